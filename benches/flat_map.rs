@@ -7,7 +7,8 @@ extern crate flat_map;
 
 use std::collections::BTreeMap;
 use std::iter::FromIterator;
-use flat_map::FlatMap;
+use flat_map::{BinarySearch, BranchlessSearch, EytzingerFlatMap, FlatMap, InterpolationSearch,
+               Lookup, StagedFlatMap, UnsortedLinear};
 use test::Bencher;
 use rand::Rng;
 use rand::distributions::{IndependentSample, Range};
@@ -66,7 +67,37 @@ fn bench_flat_map_from_iter(b: &mut Bencher) {
         key_vals.push((i as Key, value))
     }
     b.iter(|| {
-        let map = FlatMap::from_iter(key_vals.clone());
+        let map = FlatMap::<_, _>::from_iter(key_vals.clone());
+        map
+    })
+}
+
+#[bench]
+fn bench_flat_map_from_sorted_iter(b: &mut Bencher) {
+    let num_items = 1 << 20;
+    let mut rng = rand::IsaacRng::new_unseeded();
+    let source: BTreeMap<Key, Value> = (0..num_items)
+        .map(|_| (rng.gen::<Key>(), rng.gen::<Value>()))
+        .collect();
+    b.iter(|| {
+        let map = FlatMap::<_, _>::from_iter(source.clone().into_iter());
+        map
+    })
+}
+
+#[bench]
+fn bench_flat_map_extend(b: &mut Bencher) {
+    let num_items = 1 << 16;
+    let mut rng = rand::IsaacRng::new_unseeded();
+    let base: FlatMap<Key, Value> = (0..num_items)
+        .map(|_| (rng.gen::<Key>(), rng.gen::<Value>()))
+        .collect();
+    let extra: Vec<(Key, Value)> = (0..num_items)
+        .map(|_| (rng.gen::<Key>(), rng.gen::<Value>()))
+        .collect();
+    b.iter(|| {
+        let mut map = base.clone();
+        map.extend(extra.iter().cloned());
         map
     })
 }
@@ -82,6 +113,97 @@ fn bench_flat_map_insert(b: &mut Bencher) {
     })
 }
 
+#[bench]
+fn bench_flat_map_insert_ascending(b: &mut Bencher) {
+    b.iter(|| {
+        let mut map = FlatMap::new();
+        for k in 0..(1 << 16) as Key {
+            map.insert(k, k);
+        }
+        map
+    })
+}
+
+#[bench]
+fn bench_flat_map_insert_hint_ascending(b: &mut Bencher) {
+    b.iter(|| {
+        let mut map = FlatMap::new();
+        for k in 0..(1 << 16) as Key {
+            let hint = map.last_insert_index().map_or(0, |i| i + 1);
+            map.insert_hint(hint, k, k);
+        }
+        map
+    })
+}
+
+// An unsorted map of 100k entries and 10k random keys to remove from it.
+fn random_inserts_setup() -> (FlatMap<Key, Value>, Vec<Key>) {
+    let mut rng = rand::IsaacRng::new_unseeded();
+    let map = (0..100_000).map(|_| (rng.gen::<Key>(), rng.gen::<Value>())).collect();
+    let inserted = (0..10_000).map(|_| rng.gen::<Key>()).collect();
+    (map, inserted)
+}
+
+#[bench]
+fn bench_flat_map_insert_random_batch(b: &mut Bencher) {
+    let (map, inserted) = random_inserts_setup();
+    b.iter(|| {
+        let mut map = map.clone();
+        for &k in &inserted {
+            map.insert(k, k);
+        }
+        map
+    })
+}
+
+#[bench]
+fn bench_staged_flat_map_insert_random_batch(b: &mut Bencher) {
+    let (map, inserted) = random_inserts_setup();
+    b.iter(|| {
+        let mut map = StagedFlatMap::from(map.clone());
+        for &k in &inserted {
+            map.insert(k, k);
+        }
+        map.into_flat_map()
+    })
+}
+
+fn remove_setup() -> (FlatMap<Key, Value, UnsortedLinear>, Vec<Key>) {
+    let mut rng = rand::IsaacRng::new_unseeded();
+    let mut map = FlatMap::with_lookup(UnsortedLinear);
+    for _ in 0..100_000 {
+        map.insert(rng.gen::<Key>(), rng.gen::<Value>());
+    }
+    let keys: Vec<Key> = map.keys().cloned().collect();
+    let between = Range::new(0, keys.len());
+    let removed = (0..10_000).map(|_| keys[between.ind_sample(&mut rng)]).collect();
+    (map, removed)
+}
+
+#[bench]
+fn bench_flat_map_remove_unsorted(b: &mut Bencher) {
+    let (map, removed) = remove_setup();
+    b.iter(|| {
+        let mut map = map.clone();
+        for k in &removed {
+            map.remove(k);
+        }
+        map
+    })
+}
+
+#[bench]
+fn bench_flat_map_remove_unordered(b: &mut Bencher) {
+    let (map, removed) = remove_setup();
+    b.iter(|| {
+        let mut map = map.clone();
+        for k in &removed {
+            map.remove_unordered(k);
+        }
+        map
+    })
+}
+
 #[bench]
 fn bench_flat_map_get(b: &mut Bencher) {
     let (map, keys) = flat_map_setup();
@@ -93,6 +215,51 @@ fn bench_flat_map_get(b: &mut Bencher) {
     })
 }
 
+fn random_keys_setup<L: Lookup<Key, Value, Key> + Default>() -> (FlatMap<Key, Value, L>, Vec<Key>) {
+    let mut rng = rand::IsaacRng::new_unseeded();
+    let key_vals: Vec<_> = (0..1 << 20).map(|_| (rng.gen::<Key>(), rng.gen::<Value>())).collect();
+    let keys = key_vals.iter().map(|&(k, _)| k).collect();
+    let map = key_vals.into_iter().collect();
+    (map, keys)
+}
+
+fn bench_get_random_keys<L: Lookup<Key, Value, Key> + Default>(b: &mut Bencher) {
+    let (map, keys) = random_keys_setup::<L>();
+    let mut rng = rand::IsaacRng::new_unseeded();
+    let between = Range::new(0, keys.len());
+    b.iter(|| {
+        let i = between.ind_sample(&mut rng);
+        map.get(&keys[i])
+    })
+}
+
+#[bench]
+fn bench_flat_map_get_random_binary_search(b: &mut Bencher) {
+    bench_get_random_keys::<BinarySearch>(b);
+}
+
+#[bench]
+fn bench_flat_map_get_random_branchless_search(b: &mut Bencher) {
+    bench_get_random_keys::<BranchlessSearch>(b);
+}
+
+#[bench]
+fn bench_eytzinger_flat_map_get_random(b: &mut Bencher) {
+    let (map, keys) = random_keys_setup::<BinarySearch>();
+    let map = EytzingerFlatMap::from(map);
+    let mut rng = rand::IsaacRng::new_unseeded();
+    let between = Range::new(0, keys.len());
+    b.iter(|| {
+        let i = between.ind_sample(&mut rng);
+        map.get(&keys[i])
+    })
+}
+
+#[bench]
+fn bench_flat_map_get_random_interpolation_search(b: &mut Bencher) {
+    bench_get_random_keys::<InterpolationSearch>(b);
+}
+
 #[bench]
 fn bench_btree_map_from_iter(b: &mut Bencher) {
     let num_keys = 1 << 10;
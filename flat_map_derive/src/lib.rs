@@ -0,0 +1,347 @@
+//! `#[derive(FlatMapKey)]` for key types stored in a `flat_map::FlatMap`.
+//!
+//! For a newtype such as `struct UserId(u64);` the derive generates
+//! `Borrow<u64>`, so a `FlatMap<UserId, V>` can be queried with a bare
+//! `&u64`. The newtype should also derive `PartialEq`, `Eq`, `PartialOrd` and
+//! `Ord` so that its ordering matches that of the inner type.
+//!
+//! For any other struct, a `#[key(order = (field, ...))]` attribute lists the
+//! fields that make up the key, most significant first. The derive generates
+//! `PartialEq`, `Eq`, `PartialOrd` and `Ord` comparing exactly those fields in
+//! that order; fields not listed take no part in comparisons. When the order
+//! names a single field, `Borrow` of that field's type is generated as well,
+//! which is only consistent with `Ord` in that case.
+//!
+//! A longer order has no field to borrow as, so each prefix of it is a query
+//! instead: a tuple of the types of its first fields implements
+//! `flat_map::Comparable` and `flat_map::Equivalent` for the struct. A tuple
+//! of all of them finds one key; a shorter one matches every key that starts
+//! with those fields, and a lookup may return any of them.
+//!
+//! ```
+//! extern crate flat_map;
+//! extern crate flat_map_derive;
+//!
+//! use flat_map::FlatMap;
+//! use flat_map_derive::FlatMapKey;
+//!
+//! #[derive(FlatMapKey)]
+//! #[key(order = (timestamp, id))]
+//! struct Event {
+//!     id: u32,
+//!     timestamp: u64,
+//!     payload: String,
+//! }
+//!
+//! # fn main() {
+//! let mut events = FlatMap::new();
+//! events.insert(Event { id: 7, timestamp: 100, payload: "start".into() }, ());
+//! assert!(events.contains_key(&(100u64, 7u32)));
+//! assert!(events.contains_key(&(100u64,)));
+//! assert!(!events.contains_key(&(100u64, 8u32)));
+//! # }
+//! ```
+//!
+//! This crate has no dependencies and parses its input by hand, so generic
+//! structs are not supported.
+//!
+//! # Errors
+//!
+//! Input the derive can't handle is reported with `compile_error!`. That
+//! covers enums and unions:
+//!
+//! ```compile_fail
+//! # use flat_map_derive::FlatMapKey;
+//! #[derive(FlatMapKey)]
+//! enum Kind { A, B }
+//! # fn main() {}
+//! ```
+//!
+//! generic structs:
+//!
+//! ```compile_fail
+//! # use flat_map_derive::FlatMapKey;
+//! #[derive(FlatMapKey)]
+//! struct Id<T>(T);
+//! # fn main() {}
+//! ```
+//!
+//! an order naming a field the struct doesn't have:
+//!
+//! ```compile_fail
+//! # use flat_map_derive::FlatMapKey;
+//! #[derive(FlatMapKey)]
+//! #[key(order = (name))]
+//! struct User { id: u32 }
+//! # fn main() {}
+//! ```
+//!
+//! an empty order:
+//!
+//! ```compile_fail
+//! # use flat_map_derive::FlatMapKey;
+//! #[derive(FlatMapKey)]
+//! #[key(order = ())]
+//! struct User { id: u32 }
+//! # fn main() {}
+//! ```
+//!
+//! and a struct that is not a newtype without the attribute:
+//!
+//! ```compile_fail
+//! # use flat_map_derive::FlatMapKey;
+//! #[derive(FlatMapKey)]
+//! struct User { id: u32 }
+//! # fn main() {}
+//! ```
+
+extern crate proc_macro;
+
+use proc_macro::{Delimiter, Spacing, TokenStream, TokenTree};
+
+#[proc_macro_derive(FlatMapKey, attributes(key))]
+pub fn derive_flat_map_key(input: TokenStream) -> TokenStream {
+    let out = match parse(input) {
+        Ok(item) => expand(&item),
+        Err(msg) => format!("compile_error!({:?});", msg),
+    };
+    out.parse().expect("generated code does not parse")
+}
+
+struct Field {
+    name: String,
+    ty: String,
+}
+
+struct Item {
+    name: String,
+    fields: Vec<Field>,
+    order: Option<Vec<String>>,
+}
+
+fn parse(input: TokenStream) -> Result<Item, String> {
+    let mut tokens = input.into_iter();
+    let mut order = None;
+
+    loop {
+        match tokens.next() {
+            Some(TokenTree::Punct(ref p)) if p.as_char() == '#' => {
+                if let Some(TokenTree::Group(g)) = tokens.next() {
+                    if let Some(o) = parse_key_attr(g.stream())? {
+                        order = Some(o);
+                    }
+                }
+            }
+            Some(TokenTree::Ident(ref i)) if i.to_string() == "struct" => break,
+            Some(TokenTree::Ident(ref i)) if i.to_string() == "enum" || i.to_string() == "union" => {
+                return Err("FlatMapKey can only be derived for structs".to_string());
+            }
+            Some(_) => {}
+            None => return Err("expected a struct".to_string()),
+        }
+    }
+
+    let name = match tokens.next() {
+        Some(TokenTree::Ident(i)) => i.to_string(),
+        _ => return Err("expected a struct name".to_string()),
+    };
+
+    let body = loop {
+        match tokens.next() {
+            Some(TokenTree::Punct(ref p)) if p.as_char() == '<' => {
+                return Err("FlatMapKey does not support generic structs".to_string());
+            }
+            Some(TokenTree::Group(g)) => break g,
+            Some(_) => {}
+            None => return Err("FlatMapKey does not support unit structs".to_string()),
+        }
+    };
+
+    let tuple = body.delimiter() == Delimiter::Parenthesis;
+    let mut fields = Vec::new();
+    for (index, field) in split_commas(body.stream()).into_iter().enumerate() {
+        let field = strip_attrs_and_vis(field);
+        if tuple {
+            fields.push(Field { name: index.to_string(), ty: to_string(&field) });
+        } else {
+            let name = match field.first() {
+                Some(TokenTree::Ident(i)) => i.to_string(),
+                _ => return Err("expected a field name".to_string()),
+            };
+            fields.push(Field { name, ty: to_string(&field[2..]) });
+        }
+    }
+
+    if let Some(ref order) = order {
+        if order.is_empty() {
+            return Err("#[key(order = (...))] must name at least one field".to_string());
+        }
+        for o in order {
+            if !fields.iter().any(|f| f.name == *o) {
+                return Err(format!("#[key(order = (...))] names unknown field `{}`", o));
+            }
+        }
+    } else if !tuple || fields.len() != 1 {
+        return Err("FlatMapKey needs either a newtype or a #[key(order = (...))] attribute"
+                       .to_string());
+    }
+
+    Ok(Item { name, fields, order })
+}
+
+// Parses the contents of `#[key(order = (a, b))]`, returning `None` for any
+// other attribute.
+fn parse_key_attr(attr: TokenStream) -> Result<Option<Vec<String>>, String> {
+    let mut tokens = attr.into_iter();
+    match tokens.next() {
+        Some(TokenTree::Ident(ref i)) if i.to_string() == "key" => {}
+        _ => return Ok(None),
+    }
+    let args = match tokens.next() {
+        Some(TokenTree::Group(g)) => g.stream().into_iter().collect::<Vec<_>>(),
+        _ => return Err("expected #[key(order = (...))]".to_string()),
+    };
+    match (args.first(), args.get(1), args.get(2)) {
+        (Some(TokenTree::Ident(i)), Some(TokenTree::Punct(p)), Some(TokenTree::Group(g)))
+            if i.to_string() == "order" && p.as_char() == '=' => {
+            Ok(Some(split_commas(g.stream()).iter().map(|f| to_string(f)).collect()))
+        }
+        _ => Err("expected #[key(order = (...))]".to_string()),
+    }
+}
+
+// Splits a token stream at commas that are not nested inside angle brackets.
+fn split_commas(stream: TokenStream) -> Vec<Vec<TokenTree>> {
+    let mut parts = Vec::new();
+    let mut current = Vec::new();
+    let mut depth = 0usize;
+    let mut after_dash = false;
+    for token in stream {
+        let mut dash = false;
+        if let TokenTree::Punct(ref p) = token {
+            match p.as_char() {
+                ',' if depth == 0 => {
+                    parts.push(current);
+                    current = Vec::new();
+                    continue;
+                }
+                '<' => depth += 1,
+                '>' if !after_dash => depth = depth.saturating_sub(1),
+                '-' => dash = p.spacing() == Spacing::Joint,
+                _ => {}
+            }
+        }
+        after_dash = dash;
+        current.push(token);
+    }
+    if !current.is_empty() {
+        parts.push(current);
+    }
+    parts
+}
+
+fn strip_attrs_and_vis(field: Vec<TokenTree>) -> Vec<TokenTree> {
+    let mut rest = &field[..];
+    loop {
+        match rest {
+            [TokenTree::Punct(p), TokenTree::Group(_), tail @ ..] if p.as_char() == '#' => rest = tail,
+            [TokenTree::Ident(i), TokenTree::Group(g), tail @ ..]
+                if i.to_string() == "pub" && g.delimiter() == Delimiter::Parenthesis => rest = tail,
+            [TokenTree::Ident(i), tail @ ..] if i.to_string() == "pub" => rest = tail,
+            _ => return rest.to_vec(),
+        }
+    }
+}
+
+fn to_string(tokens: &[TokenTree]) -> String {
+    tokens.iter().cloned().collect::<TokenStream>().to_string()
+}
+
+fn expand(item: &Item) -> String {
+    let name = &item.name;
+    let order = match item.order {
+        Some(ref order) => order,
+        None => return borrow_impl(name, &item.fields[0]),
+    };
+
+    let cmp = order.iter()
+        .map(|f| format!("::flat_map::__private::Ord::cmp(&self.{0}, &other.{0})", f))
+        .collect::<Vec<_>>()
+        .join(".then_with(|| ");
+    let cmp = format!("{}{}", cmp, ")".repeat(order.len() - 1));
+
+    let mut out = format!(r#"
+        impl ::flat_map::__private::PartialEq for {name} {{
+            fn eq(&self, other: &Self) -> bool {{
+                ::flat_map::__private::Ord::cmp(self, other) == ::flat_map::__private::Ordering::Equal
+            }}
+        }}
+
+        impl ::flat_map::__private::Eq for {name} {{}}
+
+        impl ::flat_map::__private::PartialOrd for {name} {{
+            fn partial_cmp(&self, other: &Self) -> ::flat_map::__private::Option<::flat_map::__private::Ordering> {{
+                ::flat_map::__private::Option::Some(::flat_map::__private::Ord::cmp(self, other))
+            }}
+        }}
+
+        impl ::flat_map::__private::Ord for {name} {{
+            fn cmp(&self, other: &Self) -> ::flat_map::__private::Ordering {{
+                {cmp}
+            }}
+        }}
+    "#, name = name, cmp = cmp);
+
+    let fields = order.iter()
+        .map(|o| item.fields.iter().find(|f| f.name == *o).unwrap())
+        .collect::<Vec<_>>();
+    if fields.len() == 1 {
+        out.push_str(&borrow_impl(name, fields[0]));
+    } else {
+        for len in 1..=fields.len() {
+            out.push_str(&prefix_impl(name, &fields[..len]));
+        }
+    }
+    out
+}
+
+// Lets a tuple of the types of the first fields of the order be used as a
+// query, through `Equivalent` and `Comparable` since a `Borrow` impl would
+// have to return a tuple the struct doesn't hold.
+fn prefix_impl(name: &str, fields: &[&Field]) -> String {
+    let ty = fields.iter().map(|f| format!("{},", f.ty)).collect::<String>();
+    let eq = fields.iter()
+        .enumerate()
+        .map(|(i, f)| format!("self.{} == key.{}", i, f.name))
+        .collect::<Vec<_>>()
+        .join(" && ");
+    let cmp = fields.iter()
+        .enumerate()
+        .map(|(i, f)| format!("::flat_map::__private::Ord::cmp(&self.{}, &key.{})", i, f.name))
+        .collect::<Vec<_>>()
+        .join(".then_with(|| ");
+    let cmp = format!("{}{}", cmp, ")".repeat(fields.len() - 1));
+    format!(r#"
+        impl ::flat_map::__private::Equivalent<{name}> for ({ty}) {{
+            fn equivalent(&self, key: &{name}) -> bool {{
+                {eq}
+            }}
+        }}
+
+        impl ::flat_map::__private::Comparable<{name}> for ({ty}) {{
+            fn compare(&self, key: &{name}) -> ::flat_map::__private::Ordering {{
+                {cmp}
+            }}
+        }}
+    "#, name = name, ty = ty, eq = eq, cmp = cmp)
+}
+
+fn borrow_impl(name: &str, field: &Field) -> String {
+    format!(r#"
+        impl ::flat_map::__private::Borrow<{ty}> for {name} {{
+            fn borrow(&self) -> &{ty} {{
+                &self.{field}
+            }}
+        }}
+    "#, ty = field.ty, name = name, field = field.name)
+}
@@ -0,0 +1,52 @@
+//! Exercises the core, entry and iterator APIs of `flat_map` from a
+//! `#![no_std]` crate. There is nothing to run; compiling is the check.
+
+#![no_std]
+
+extern crate alloc;
+extern crate flat_map;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use flat_map::{FlatMap, FlatSet, LinearBack, Occupied, Vacant};
+
+pub fn core_api() -> Option<u32> {
+    let mut map: FlatMap<u32, u32> = FlatMap::new();
+    map.insert(2, 20);
+    map.insert(1, 10);
+    map.extend(Vec::from([(3, 30), (4, 40)]));
+    map.retain(|&k, _| k != 4);
+    let mut right = map.split_off(&3);
+    right.remove(&3);
+    map.get(&1).copied()
+}
+
+pub fn entry_api(words: &[&str]) -> FlatMap<String, usize, LinearBack> {
+    let mut counts = FlatMap::with_lookup(LinearBack);
+    for &w in words {
+        *counts.entry(String::from(w)).or_default() += 1;
+    }
+    match counts.entry(String::from("total")) {
+        Occupied(e) => {
+            e.remove();
+        }
+        Vacant(e) => {
+            e.insert(words.len());
+        }
+    }
+    counts
+}
+
+pub fn iterators(map: &mut FlatMap<u32, u32>) -> u32 {
+    let mut sum: u32 = map.iter().map(|(k, v)| k + v).sum();
+    sum += map.keys().rev().sum::<u32>();
+    for v in map.values_mut() {
+        *v += 1;
+    }
+    for (_, v) in map.range_mut(1..3) {
+        *v += 1;
+    }
+    sum += map.range(..).len() as u32;
+    let drained: FlatSet<u32> = map.drain().map(|(k, _)| k).collect();
+    sum + drained.into_iter().sum::<u32>()
+}
@@ -0,0 +1,199 @@
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::fmt;
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use errors::CapacityError;
+use flat_map::FlatMap;
+
+/// A `FlatMap` that never holds more than `MAX` entries.
+///
+/// The bound makes the serialized size of the map statically known, which
+/// non-self-describing formats such as postcard can use to size buffers at
+/// compile time. All the read-only `FlatMap` methods are available through
+/// `Deref`.
+#[derive(Clone)]
+pub struct BoundedFlatMap<K, V, const MAX: usize> {
+    map: FlatMap<K, V>,
+}
+
+impl<K, V, const MAX: usize> BoundedFlatMap<K, V, MAX> {
+    pub const fn new() -> Self {
+        BoundedFlatMap { map: FlatMap::new() }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.map.len() >= MAX
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear()
+    }
+
+    pub fn into_inner(self) -> FlatMap<K, V> {
+        self.map
+    }
+}
+
+impl<K: Ord, V, const MAX: usize> BoundedFlatMap<K, V, MAX> {
+    /// Inserts a key-value pair, returning the previous value for the key.
+    /// Fails and hands the pair back if the key is new and the map is full.
+    pub fn try_insert(&mut self, key: K, value: V) -> Result<Option<V>, CapacityError<(K, V)>> {
+        if self.is_full() && !self.map.contains_key(&key) {
+            return Err(CapacityError::new((key, value)));
+        }
+        Ok(self.map.insert(key, value))
+    }
+
+    pub fn get_mut<Q>(&mut self, q: &Q) -> Option<&mut V>
+        where K: Borrow<Q>,
+              Q: ?Sized + Ord
+    {
+        self.map.get_mut(q)
+    }
+
+    pub fn remove<Q>(&mut self, q: &Q) -> Option<V>
+        where K: Borrow<Q>,
+              Q: ?Sized + Ord
+    {
+        self.map.remove(q)
+    }
+
+    /// Adopts `map` if it has at most `MAX` entries, otherwise hands it back.
+    pub fn try_from_map(map: FlatMap<K, V>) -> Result<Self, CapacityError<FlatMap<K, V>>> {
+        if map.len() > MAX {
+            Err(CapacityError::new(map))
+        } else {
+            Ok(BoundedFlatMap { map })
+        }
+    }
+}
+
+impl<K, V, const MAX: usize> Deref for BoundedFlatMap<K, V, MAX> {
+    type Target = FlatMap<K, V>;
+
+    fn deref(&self) -> &FlatMap<K, V> {
+        &self.map
+    }
+}
+
+impl<K, V, const MAX: usize> Default for BoundedFlatMap<K, V, MAX> {
+    fn default() -> Self {
+        BoundedFlatMap::new()
+    }
+}
+
+impl<K: Ord, V: PartialEq, const MAX: usize> PartialEq for BoundedFlatMap<K, V, MAX> {
+    fn eq(&self, other: &Self) -> bool {
+        self.map == other.map
+    }
+}
+
+impl<K: Ord, V: Eq, const MAX: usize> Eq for BoundedFlatMap<K, V, MAX> {}
+
+impl<K: Ord, V: PartialOrd, const MAX: usize> PartialOrd for BoundedFlatMap<K, V, MAX> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.map.partial_cmp(&other.map)
+    }
+}
+
+impl<K: Ord, V: Ord, const MAX: usize> Ord for BoundedFlatMap<K, V, MAX> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.map.cmp(&other.map)
+    }
+}
+
+impl<K: Hash, V: Hash, const MAX: usize> Hash for BoundedFlatMap<K, V, MAX> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.map.hash(state)
+    }
+}
+
+impl<K: Debug, V: Debug, const MAX: usize> Debug for BoundedFlatMap<K, V, MAX> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.map.fmt(f)
+    }
+}
+
+#[cfg(feature = "serde1")]
+mod serde_impl {
+    use serde::de::{Deserialize, Deserializer, Error};
+    use serde::{Serialize, Serializer};
+    use flat_map::FlatMap;
+    use super::BoundedFlatMap;
+
+    impl<K, V, const MAX: usize> Serialize for BoundedFlatMap<K, V, MAX>
+        where K: Ord + Serialize,
+              V: Serialize
+    {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where S: Serializer
+        {
+            self.map.serialize(serializer)
+        }
+    }
+
+    impl<'de, K, V, const MAX: usize> Deserialize<'de> for BoundedFlatMap<K, V, MAX>
+        where K: Ord + Deserialize<'de>,
+              V: Deserialize<'de>
+    {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where D: Deserializer<'de>
+        {
+            let map = FlatMap::deserialize(deserializer)?;
+            BoundedFlatMap::try_from_map(map)
+                .map_err(|_| D::Error::custom(format_args!("more than {} entries", MAX)))
+        }
+    }
+}
+
+// postcard encodes a map as a varint length followed by the key-value pairs,
+// which is byte-for-byte the same as a sequence of pairs, so the bound below
+// holds whichever of the two the serde impls emit.
+#[cfg(feature = "postcard")]
+mod postcard_impl {
+    use postcard::experimental::max_size::MaxSize;
+    use super::BoundedFlatMap;
+
+    // the number of bytes postcard uses to encode `n` as a varint
+    const fn varint_size(mut n: usize) -> usize {
+        let mut size = 1;
+        while n >= 0x80 {
+            n >>= 7;
+            size += 1;
+        }
+        size
+    }
+
+    impl<K: MaxSize, V: MaxSize, const MAX: usize> MaxSize for BoundedFlatMap<K, V, MAX> {
+        const POSTCARD_MAX_SIZE: usize =
+            varint_size(MAX) + MAX * (K::POSTCARD_MAX_SIZE + V::POSTCARD_MAX_SIZE);
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+mod arbitrary_impl {
+    // takes at most `MAX` entries from the input, so the bound always holds
+    use arbitrary::{Arbitrary, Result, Unstructured};
+    use super::BoundedFlatMap;
+
+    impl<'a, K, V, const MAX: usize> Arbitrary<'a> for BoundedFlatMap<K, V, MAX>
+        where K: Arbitrary<'a> + Ord,
+              V: Arbitrary<'a>
+    {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            let map = u.arbitrary_iter()?.take(MAX).collect::<Result<_>>()?;
+            Ok(BoundedFlatMap { map })
+        }
+
+        fn arbitrary_take_rest(u: Unstructured<'a>) -> Result<Self> {
+            let map = u.arbitrary_take_rest_iter()?.take(MAX).collect::<Result<_>>()?;
+            Ok(BoundedFlatMap { map })
+        }
+
+        fn size_hint(_depth: usize) -> (usize, Option<usize>) {
+            (0, None)
+        }
+    }
+}
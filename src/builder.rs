@@ -0,0 +1,105 @@
+use std::fmt;
+use std::fmt::Debug;
+use std::vec::Vec;
+use flat_map::FlatMap;
+use lookup::{Arrange, BinarySearch};
+
+/// Collects entries in any order without looking them up, then sorts them
+/// into a `FlatMap` in one go.
+///
+/// Of entries with equal keys, the first pushed is kept unless `keep_last`
+/// is set, matching `collect` and repeated inserts respectively.
+///
+/// ```
+/// use flat_map::FlatMapBuilder;
+///
+/// let mut builder = FlatMapBuilder::new().keep_last(true);
+/// builder.push("b", 1);
+/// builder.push("a", 2);
+/// builder.push("b", 3);
+/// let map = builder.build();
+/// assert_eq!(map.into_inner(), [("a", 2), ("b", 3)]);
+/// ```
+pub struct FlatMapBuilder<K, V, L = BinarySearch> {
+    v: Vec<(K, V)>,
+    l: L,
+    keep_last: bool,
+}
+
+impl<K, V> FlatMapBuilder<K, V> {
+    pub const fn new() -> Self {
+        FlatMapBuilder::with_lookup(BinarySearch)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        FlatMapBuilder { v: Vec::with_capacity(capacity), l: BinarySearch, keep_last: false }
+    }
+}
+
+impl<K, V, L> FlatMapBuilder<K, V, L> {
+    /// Creates a builder for a map that finds keys using `l`.
+    pub const fn with_lookup(l: L) -> Self {
+        FlatMapBuilder { v: Vec::new(), l, keep_last: false }
+    }
+
+    /// Sets whether the last of several entries with equal keys is kept,
+    /// rather than the first.
+    pub fn keep_last(mut self, keep_last: bool) -> Self {
+        self.keep_last = keep_last;
+        self
+    }
+
+    /// The number of entries pushed, counting repeated keys.
+    pub fn len(&self) -> usize {
+        self.v.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.v.is_empty()
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        self.v.reserve(additional);
+    }
+
+    /// Adds an entry, in O(1) amortized time.
+    pub fn push(&mut self, key: K, value: V) {
+        self.v.push((key, value));
+    }
+
+    /// Sorts the entries and drops repeated keys, producing the map.
+    pub fn build(self) -> FlatMap<K, V, L>
+        where L: Arrange<K>
+    {
+        let FlatMapBuilder { mut v, l, keep_last } = self;
+        l.arrange(&mut v, keep_last);
+        FlatMap::from_arranged(v, l)
+    }
+}
+
+impl<K, V, L> Extend<(K, V)> for FlatMapBuilder<K, V, L> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        self.v.extend(iter);
+    }
+}
+
+impl<K, V, L: Default> Default for FlatMapBuilder<K, V, L> {
+    fn default() -> Self {
+        FlatMapBuilder::with_lookup(L::default())
+    }
+}
+
+impl<K: Clone, V: Clone, L: Clone> Clone for FlatMapBuilder<K, V, L> {
+    fn clone(&self) -> Self {
+        FlatMapBuilder { v: self.v.clone(), l: self.l.clone(), keep_last: self.keep_last }
+    }
+}
+
+impl<K: Debug, V: Debug, L> Debug for FlatMapBuilder<K, V, L> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FlatMapBuilder")
+            .field("entries", &self.v)
+            .field("keep_last", &self.keep_last)
+            .finish()
+    }
+}
@@ -0,0 +1,227 @@
+//! Error types returned by the fallible `FlatMap` APIs.
+//!
+//! Every error that rejects owned data hands it back, so the caller can
+//! recover the key, value or input that was refused.
+
+use std::fmt;
+use std::fmt::Debug;
+use flat_map::OccupiedEntry;
+
+/// The input contained the same key more than once.
+pub struct DuplicateKeyError<K> {
+    key: K,
+}
+
+impl<K> DuplicateKeyError<K> {
+    pub fn new(key: K) -> Self {
+        DuplicateKeyError { key }
+    }
+
+    /// The key that appeared more than once.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Recovers the rejected key.
+    pub fn into_key(self) -> K {
+        self.key
+    }
+}
+
+impl<K: Debug> Debug for DuplicateKeyError<K> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DuplicateKeyError").field("key", &self.key).finish()
+    }
+}
+
+impl<K: Debug> fmt::Display for DuplicateKeyError<K> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "duplicate key {:?}", self.key)
+    }
+}
+
+/// The input was expected to be strictly sorted by key, but the entry at
+/// `index` is not greater than its predecessor.
+///
+/// `T` is the rejected input, if the operation took ownership of one.
+pub struct SortedInputError<T = ()> {
+    index: usize,
+    input: T,
+}
+
+impl SortedInputError {
+    pub fn new(index: usize) -> Self {
+        SortedInputError { index, input: () }
+    }
+}
+
+impl<T> SortedInputError<T> {
+    pub fn with_input(index: usize, input: T) -> Self {
+        SortedInputError { index, input }
+    }
+
+    /// Index of the first entry that breaks the ordering.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Recovers the rejected input.
+    pub fn into_input(self) -> T {
+        self.input
+    }
+
+    /// Drops the rejected input, keeping only the position.
+    pub fn without_input(self) -> SortedInputError {
+        SortedInputError::new(self.index)
+    }
+}
+
+impl<T> Debug for SortedInputError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SortedInputError").field("index", &self.index).finish()
+    }
+}
+
+impl<T> fmt::Display for SortedInputError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "input is not strictly sorted by key at index {}", self.index)
+    }
+}
+
+/// A map's entries break the invariant of its lookup strategy: the entry at
+/// `index` repeats an earlier key, or comes before its predecessor when the
+/// strategy keeps the entries sorted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvariantError {
+    index: usize,
+    duplicate: bool,
+}
+
+impl InvariantError {
+    pub fn out_of_order(index: usize) -> Self {
+        InvariantError { index, duplicate: false }
+    }
+
+    pub fn duplicate(index: usize) -> Self {
+        InvariantError { index, duplicate: true }
+    }
+
+    /// Index of the first entry that breaks the invariant.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Returns true if the entry repeats an earlier key, false if it is out
+    /// of order.
+    pub fn is_duplicate(&self) -> bool {
+        self.duplicate
+    }
+}
+
+impl fmt::Display for InvariantError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.duplicate {
+            write!(f, "duplicate key at index {}", self.index)
+        } else {
+            write!(f, "key out of order at index {}", self.index)
+        }
+    }
+}
+
+/// An insert was refused because the key is already present.
+///
+/// Holds the entry for the existing key along with the key and value that
+/// were not inserted.
+pub struct OccupiedError<'a, K: 'a, V: 'a> {
+    pub entry: OccupiedEntry<'a, K, V>,
+    pub key: K,
+    pub value: V,
+}
+
+impl<'a, K: 'a, V: 'a> OccupiedError<'a, K, V> {
+    /// Recovers the rejected key and value.
+    pub fn into_inner(self) -> (K, V) {
+        (self.key, self.value)
+    }
+}
+
+impl<'a, K: Debug, V: Debug> Debug for OccupiedError<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OccupiedError")
+            .field("key", self.entry.key())
+            .field("old_value", self.entry.get())
+            .field("new_value", &self.value)
+            .finish()
+    }
+}
+
+impl<'a, K: Debug, V: Debug> fmt::Display for OccupiedError<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "failed to insert {:?}, key {:?} already exists with value {:?}",
+               self.value,
+               self.entry.key(),
+               self.entry.get())
+    }
+}
+
+impl<'a, K: 'a, V: 'a> From<OccupiedError<'a, K, V>> for DuplicateKeyError<K> {
+    fn from(err: OccupiedError<'a, K, V>) -> Self {
+        DuplicateKeyError::new(err.key)
+    }
+}
+
+/// A fixed-capacity container had no room for `element`.
+pub struct CapacityError<T = ()> {
+    element: T,
+}
+
+impl<T> CapacityError<T> {
+    pub fn new(element: T) -> Self {
+        CapacityError { element }
+    }
+
+    /// The element that did not fit.
+    pub fn element(&self) -> &T {
+        &self.element
+    }
+
+    /// Recovers the element that did not fit.
+    pub fn into_inner(self) -> T {
+        self.element
+    }
+
+    /// Drops the element, keeping only the fact that the container was full.
+    pub fn simplify(self) -> CapacityError {
+        CapacityError::new(())
+    }
+}
+
+impl<T> Debug for CapacityError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("CapacityError: insufficient capacity")
+    }
+}
+
+impl<T> fmt::Display for CapacityError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("insufficient capacity")
+    }
+}
+
+#[cfg(feature = "std")]
+mod error_impls {
+    use std::error::Error;
+    use std::fmt::Debug;
+    use super::{CapacityError, DuplicateKeyError, InvariantError, OccupiedError, SortedInputError};
+
+    impl<K: Debug> Error for DuplicateKeyError<K> {}
+
+    impl<T> Error for SortedInputError<T> {}
+
+    impl Error for InvariantError {}
+
+    impl<'a, K: Debug, V: Debug> Error for OccupiedError<'a, K, V> {}
+
+    impl<T> Error for CapacityError<T> {}
+}
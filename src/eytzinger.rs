@@ -0,0 +1,299 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::fmt::Debug;
+use std::iter::{FromIterator, FusedIterator};
+use std::vec;
+use std::vec::Vec;
+use flat_map::FlatMap;
+use lookup::{prefetch, BinarySearch, Comparable, Sorted};
+
+/// A read-mostly map whose entries are stored in Eytzinger order: the
+/// levels of a complete binary search tree laid out one after another, so
+/// the children of the entry at index i are at 2i + 1 and 2i + 2.
+///
+/// A search walks down the tree without branching on the comparisons, and
+/// the few entries it reads next share cache lines that can be prefetched,
+/// so lookups in big maps beat binary search over a sorted vector. Keys
+/// cannot be added or removed in place: build the map from a `FlatMap` and
+/// convert it back to change its keys. Iteration is still in ascending
+/// order of key.
+///
+/// ```
+/// use flat_map::{EytzingerFlatMap, FlatMap};
+///
+/// let map: FlatMap<u32, char> = FlatMap::from([(3, 'c'), (1, 'a'), (2, 'b')]);
+/// let mut frozen = EytzingerFlatMap::from(map);
+/// assert_eq!(frozen.get(&2), Some(&'b'));
+/// *frozen.get_mut(&3).unwrap() = 'C';
+/// assert!(frozen.iter().map(|(k, v)| (*k, *v)).eq([(1, 'a'), (2, 'b'), (3, 'C')]));
+///
+/// let mut map = FlatMap::from(frozen);
+/// map.insert(4, 'd');
+/// assert_eq!(map.len(), 4);
+/// ```
+pub struct EytzingerFlatMap<K, V> {
+    v: Vec<(K, V)>,
+}
+
+/// Iterates over the entries of an `EytzingerFlatMap` in ascending order of
+/// key, by walking its tree in order.
+pub struct Iter<'a, K: 'a, V: 'a> {
+    v: &'a [(K, V)],
+    // index of the next entry, or v.len() once done
+    next: usize,
+    remaining: usize,
+}
+
+// Index of the first entry of a tree of `n` entries in order, or `n`.
+fn first_in_order(n: usize) -> usize {
+    let mut i = 0;
+    while 2 * i + 1 < n {
+        i = 2 * i + 1;
+    }
+    i.min(n)
+}
+
+// Index of the entry after `i` in order, or `n` after the last one.
+fn next_in_order(mut i: usize, n: usize) -> usize {
+    if 2 * i + 2 < n {
+        // leftmost entry of the right subtree
+        i = 2 * i + 2;
+        while 2 * i + 1 < n {
+            i = 2 * i + 1;
+        }
+        return i;
+    }
+    // climb out of right subtrees; the parent of a left child comes next
+    while i > 0 && i & 1 == 0 {
+        i = (i - 1) / 2;
+    }
+    if i == 0 { n } else { (i - 1) / 2 }
+}
+
+impl<K, V> EytzingerFlatMap<K, V> {
+    /// Creates an empty map without allocating.
+    pub const fn new() -> EytzingerFlatMap<K, V> {
+        EytzingerFlatMap { v: Vec::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.v.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.v.is_empty()
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter { v: &self.v, next: first_in_order(self.v.len()), remaining: self.v.len() }
+    }
+
+    /// Takes the entries, sorted by key.
+    pub fn into_sorted_vec(self) -> Vec<(K, V)> {
+        let n = self.v.len();
+        let mut ranks = vec![0; n];
+        let mut i = first_in_order(n);
+        for rank in 0..n {
+            ranks[i] = rank;
+            i = next_in_order(i, n);
+        }
+        let mut ranked: Vec<(usize, (K, V))> = ranks.into_iter().zip(self.v).collect();
+        ranked.sort_unstable_by_key(|&(rank, _)| rank);
+        ranked.into_iter().map(|(_, entry)| entry).collect()
+    }
+
+    // Lays out entries sorted by key in Eytzinger order.
+    fn from_sorted_vec(sorted: Vec<(K, V)>) -> EytzingerFlatMap<K, V> {
+        let n = sorted.len();
+        let mut slots = Vec::with_capacity(n);
+        let mut i = first_in_order(n);
+        for entry in sorted {
+            slots.push((i, entry));
+            i = next_in_order(i, n);
+        }
+        slots.sort_unstable_by_key(|&(slot, _)| slot);
+        EytzingerFlatMap { v: slots.into_iter().map(|(_, entry)| entry).collect() }
+    }
+}
+
+impl<K: Ord, V> EytzingerFlatMap<K, V> {
+    // Index of the entry with the smallest key not less than `q`.
+    fn lower_bound<Q>(&self, q: &Q) -> Option<usize>
+        where Q: ?Sized + Comparable<K>
+    {
+        // k counts from 1, so that the children of k are 2k and 2k + 1
+        let n = self.v.len();
+        let mut k = 1;
+        while k <= n {
+            // the eight great-grandchildren of k are adjacent
+            prefetch(self.v.as_ptr().wrapping_add(8 * k - 1));
+            let right = q.compare(&self.v[k - 1].0) == Ordering::Greater;
+            k = 2 * k + right as usize;
+        }
+        // undo the turns right after the last turn left, which was at the
+        // answer; if there was no turn left, every key is less than `q`
+        k >>= (!k).trailing_zeros() + 1;
+        k.checked_sub(1)
+    }
+
+    fn find<Q>(&self, q: &Q) -> Option<usize>
+        where Q: ?Sized + Comparable<K>
+    {
+        self.lower_bound(q).filter(|&i| q.equivalent(&self.v[i].0))
+    }
+
+    pub fn get<Q>(&self, q: &Q) -> Option<&V>
+        where Q: ?Sized + Comparable<K>
+    {
+        self.find(q).map(|i| &self.v[i].1)
+    }
+
+    pub fn get_key_value<Q>(&self, q: &Q) -> Option<(&K, &V)>
+        where Q: ?Sized + Comparable<K>
+    {
+        self.find(q).map(|i| (&self.v[i].0, &self.v[i].1))
+    }
+
+    pub fn get_mut<Q>(&mut self, q: &Q) -> Option<&mut V>
+        where Q: ?Sized + Comparable<K>
+    {
+        self.find(q).map(move |i| &mut self.v[i].1)
+    }
+
+    pub fn contains_key<Q>(&self, q: &Q) -> bool
+        where Q: ?Sized + Comparable<K>
+    {
+        self.find(q).is_some()
+    }
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        let (k, v) = self.v.get(self.next)?;
+        self.next = next_in_order(self.next, self.v.len());
+        self.remaining -= 1;
+        Some((k, v))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, K, V> Clone for Iter<'a, K, V> {
+    fn clone(&self) -> Iter<'a, K, V> {
+        Iter { v: self.v, next: self.next, remaining: self.remaining }
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for Iter<'a, K, V> {}
+impl<'a, K, V> FusedIterator for Iter<'a, K, V> {}
+
+impl<'a, K, V> IntoIterator for &'a EytzingerFlatMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+// Sorted like a `FlatMap`, so of entries with equal keys the first is kept.
+impl<K: Ord, V> FromIterator<(K, V)> for EytzingerFlatMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> EytzingerFlatMap<K, V> {
+        iter.into_iter().collect::<FlatMap<K, V>>().into()
+    }
+}
+
+impl<K, V, L: Sorted> From<FlatMap<K, V, L>> for EytzingerFlatMap<K, V> {
+    fn from(map: FlatMap<K, V, L>) -> EytzingerFlatMap<K, V> {
+        EytzingerFlatMap::from_sorted_vec(map.into_inner())
+    }
+}
+
+impl<K: Ord, V> From<EytzingerFlatMap<K, V>> for FlatMap<K, V, BinarySearch> {
+    fn from(map: EytzingerFlatMap<K, V>) -> FlatMap<K, V> {
+        FlatMap::from_sorted_vec_unchecked(map.into_sorted_vec())
+    }
+}
+
+impl<K: Clone, V: Clone> Clone for EytzingerFlatMap<K, V> {
+    fn clone(&self) -> EytzingerFlatMap<K, V> {
+        EytzingerFlatMap { v: self.v.clone() }
+    }
+}
+
+impl<K, V> Default for EytzingerFlatMap<K, V> {
+    fn default() -> EytzingerFlatMap<K, V> {
+        EytzingerFlatMap::new()
+    }
+}
+
+// maps with the same entries have the same layout
+impl<K: PartialEq, V: PartialEq> PartialEq for EytzingerFlatMap<K, V> {
+    fn eq(&self, other: &EytzingerFlatMap<K, V>) -> bool {
+        self.v == other.v
+    }
+}
+
+impl<K: Eq, V: Eq> Eq for EytzingerFlatMap<K, V> {}
+
+impl<K: Debug, V: Debug> Debug for EytzingerFlatMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(feature = "serde1")]
+mod serde_impl {
+    // the same representation as `FlatMap`, in ascending order of key
+    use serde::de::{Deserialize, Deserializer};
+    use serde::{Serialize, Serializer};
+    use flat_map::serde_impl::serialize_entries;
+    use flat_map::FlatMap;
+    use super::EytzingerFlatMap;
+
+    impl<K: Serialize, V: Serialize> Serialize for EytzingerFlatMap<K, V> {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where S: Serializer
+        {
+            serialize_entries(self.iter(), serializer)
+        }
+    }
+
+    impl<'de, K, V> Deserialize<'de> for EytzingerFlatMap<K, V>
+        where K: Ord + Deserialize<'de>,
+              V: Deserialize<'de>
+    {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where D: Deserializer<'de>
+        {
+            FlatMap::<K, V>::deserialize(deserializer).map(EytzingerFlatMap::from)
+        }
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+mod arbitrary_impl {
+    // collected like `FlatMap`, so always sorted and free of duplicate keys
+    use arbitrary::{Arbitrary, Result, Unstructured};
+    use super::EytzingerFlatMap;
+
+    impl<'a, K, V> Arbitrary<'a> for EytzingerFlatMap<K, V>
+        where K: Arbitrary<'a> + Ord,
+              V: Arbitrary<'a>
+    {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            u.arbitrary_iter()?.collect()
+        }
+
+        fn arbitrary_take_rest(u: Unstructured<'a>) -> Result<Self> {
+            u.arbitrary_take_rest_iter()?.collect()
+        }
+
+        fn size_hint(_depth: usize) -> (usize, Option<usize>) {
+            (0, None)
+        }
+    }
+}
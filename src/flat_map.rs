@@ -1,18 +1,138 @@
 use self::Entry::*;
+use errors::{DuplicateKeyError, InvariantError, OccupiedError, SortedInputError};
+use flat_slice::{FlatSlice, FlatSliceMut};
+use lookup::{merged_capacity, Arrange, BinarySearch, Descending, Lookup, Order, OrderBy, Ordered,
+             Sorted};
+use std::collections::btree_map;
+use std::collections::BTreeMap;
+use std::collections::TryReserveError;
 use std::vec;
 use std::vec::Vec;
 use std::borrow::Borrow;
+use std::cmp;
 use std::cmp::Ordering;
 use std::fmt;
 use std::fmt::Debug;
 use std::hash::{Hash, Hasher};
-use std::iter::{Map, FromIterator};
+use std::iter::{Map, FromIterator, FusedIterator, Peekable};
+use std::mem;
 use std::mem::swap;
-use std::ops::Index;
+#[cfg(feature = "unstable")]
+use std::num::NonZeroUsize;
+use std::ops::{Index, IndexMut, RangeBounds, Sub};
+use std::ops::Bound::{Excluded, Included, Unbounded};
 use std::slice;
+/// A map stored as a vector of key-value pairs sorted by key.
+///
+/// `L` is the strategy used to find keys; see the `lookup` module. With
+/// `UnsortedLinear` the pairs are kept in insertion order instead, and the
+/// positional methods such as `first_key_value` follow that order.
 #[derive(Clone)]
-pub struct FlatMap<K, V> {
+pub struct FlatMap<K, V, L = BinarySearch> {
     v: Vec<(K, V)>,
+    policy: Policy,
+    l: L,
+    // where the most recent insert put its entry, as a hint for the next one
+    last: Option<usize>,
+}
+
+/// How a `FlatMap` grows its allocation when it runs out of room.
+///
+/// The policy is consulted by `insert`, `try_insert` and `entry`, and by the
+/// bulk operations such as `extend`, `append`, `merge_with` and
+/// `insert_many`: under `Amortized` they grow the map at least as `Vec`
+/// would, and under `Exact` they leave no spare room when they grow it.
+/// Deserializing into an existing map with serde's `deserialize_in_place`
+/// keeps its policy, while plain deserialization starts from the default.
+/// Changing the policy never changes the current capacity; it only affects
+/// how the map grows from then on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub enum GrowthPolicy {
+    /// Grow like `Vec`, roughly doubling the capacity each time. Inserts are
+    /// amortized O(1) in allocations, at the cost of up to 2x slack.
+    #[default]
+    Amortized,
+    /// Grow by exactly one entry for each insert that needs room. Capacity
+    /// then equals length after every growth, at the cost of a reallocation
+    /// per growing insert.
+    Exact,
+}
+
+/// Whether a `FlatMap` gives memory back after entries are removed.
+///
+/// The policy is checked at the end of `remove`, `remove_index`, `retain`,
+/// `retain_range`, `retain_keys`, `remove_keys`, `remove_range`,
+/// `truncate_before`, `truncate_after`, `pop_first`, `pop_last`, `clear`,
+/// `split_off`, `OccupiedEntry::remove` and `CursorMut::remove_current`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub enum ShrinkPolicy {
+    /// Never shrink automatically.
+    #[default]
+    Never,
+    /// Shrink once `len / capacity < numerator / denominator`, to twice the
+    /// current length but never below `min_capacity`.
+    ///
+    /// Shrinking only happens when the capacity exceeds twice the length, so
+    /// thresholds above one half behave like one half, and alternating
+    /// inserts and removals around the threshold do not reallocate on every
+    /// call.
+    WhenBelow {
+        numerator: usize,
+        denominator: usize,
+        min_capacity: usize,
+    },
+}
+
+/// Which key `FlatMap::nearest_with` returns when `q` lies exactly halfway
+/// between two keys.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub enum TiePolicy {
+    /// Return the smaller key.
+    #[default]
+    PreferLower,
+    /// Return the larger key.
+    PreferHigher,
+}
+
+#[derive(Clone, Copy)]
+struct Policy {
+    growth: GrowthPolicy,
+    shrink: ShrinkPolicy,
+}
+
+impl Policy {
+    const DEFAULT: Policy = Policy {
+        growth: GrowthPolicy::Amortized,
+        shrink: ShrinkPolicy::Never,
+    };
+
+    fn reserve<T>(self, v: &mut Vec<T>, additional: usize) {
+        match self.growth {
+            GrowthPolicy::Amortized => v.reserve(additional),
+            GrowthPolicy::Exact => v.reserve_exact(additional),
+        }
+    }
+
+    // Called after a bulk operation that may have grown `v` past `capacity`
+    // in one step, which `Exact` then trims back to the length.
+    fn grown<T>(self, v: &mut Vec<T>, capacity: usize) {
+        if self.growth == GrowthPolicy::Exact && v.capacity() > capacity {
+            v.shrink_to_fit();
+        }
+    }
+
+    fn shrink<T>(self, v: &mut Vec<T>) {
+        if let ShrinkPolicy::WhenBelow { numerator, denominator, min_capacity } = self.shrink {
+            let (len, cap) = (v.len(), v.capacity());
+            if len.saturating_mul(denominator) < cap.saturating_mul(numerator) &&
+               len.saturating_mul(2) < cap {
+                let target = cmp::max(len.saturating_mul(2), min_capacity);
+                if target < cap {
+                    v.shrink_to(target);
+                }
+            }
+        }
+    }
 }
 
 pub enum Entry<'a, K: 'a, V: 'a> {
@@ -24,19 +144,100 @@ pub struct VacantEntry<'a, K: 'a, V: 'a> {
     v: &'a mut Vec<(K, V)>,
     key: K,
     index: usize,
+    policy: Policy,
 }
 
 pub struct OccupiedEntry<'a, K: 'a, V: 'a> {
     v: &'a mut Vec<(K, V)>,
     index: usize,
+    policy: Policy,
+    // whether the entries must stay in order when one is removed
+    sorted: bool,
+}
+
+/// A read-only cursor over the entries of a sorted map, returned by
+/// `FlatMap::lower_bound_cursor` and `FlatMap::upper_bound_cursor`.
+///
+/// The cursor points at an entry or at a "ghost" position past the last
+/// one. Moving forward from the ghost goes to the first entry, and moving
+/// back from the first entry goes to the ghost, as with the cursors of
+/// `LinkedList`.
+pub struct Cursor<'a, K: 'a, V: 'a> {
+    v: &'a [(K, V)],
+    index: usize,
+}
+
+/// A cursor over the entries of a sorted map that can remove the current
+/// entry and insert new ones next to it, returned by
+/// `FlatMap::lower_bound_cursor_mut` and `FlatMap::upper_bound_cursor_mut`.
+pub struct CursorMut<'a, K: 'a, V: 'a> {
+    v: &'a mut Vec<(K, V)>,
+    index: usize,
+    policy: Policy,
 }
 
 pub struct IntoIter<K, V> {
     inner: vec::IntoIter<(K, V)>,
 }
 
+pub struct IntoKeys<K, V> {
+    inner: IntoIter<K, V>,
+}
+
+pub struct IntoValues<K, V> {
+    inner: IntoIter<K, V>,
+}
+
+// Drops the rejected entries `[write, read)` once compaction ends, even if the
+// predicate panics, so that the map stays sorted.
+struct RetainGuard<'a, K: 'a, V: 'a> {
+    v: &'a mut Vec<(K, V)>,
+    read: usize,
+    write: usize,
+}
+
+impl<'a, K, V> Drop for RetainGuard<'a, K, V> {
+    fn drop(&mut self) {
+        self.v.drain(self.write..self.read);
+    }
+}
+
+// Restores the strategy's order after the entries were handed out mutably,
+// even if the caller panicked.
+struct ArrangeGuard<'a, K: 'a, V: 'a, L: 'a + Arrange<K>> {
+    v: &'a mut Vec<(K, V)>,
+    l: &'a L,
+}
+
+impl<'a, K, V, L: Arrange<K>> Drop for ArrangeGuard<'a, K, V, L> {
+    fn drop(&mut self) {
+        self.l.arrange(self.v, false);
+    }
+}
+
+pub struct Drain<'a, K: 'a, V: 'a> {
+    inner: vec::Drain<'a, (K, V)>,
+}
+
+/// Iterator returned by `FlatMap::drain_range`.
+pub struct DrainRange<'a, K: 'a, V: 'a> {
+    inner: vec::Drain<'a, (K, V)>,
+}
+
+/// Iterator returned by `FlatMap::extract_if`. `F` is the predicate adapted
+/// to the map's entries.
+pub struct ExtractIf<'a, K: 'a, V: 'a, F>
+    where F: FnMut(&mut (K, V)) -> bool
+{
+    inner: vec::ExtractIf<'a, (K, V), F>,
+}
+
+pub struct Splice<'a, K: 'a, V: 'a> {
+    inner: vec::Splice<'a, vec::IntoIter<(K, V)>>,
+}
+
 pub struct IterMut<'a, K: 'a, V: 'a> {
-    inner: slice::IterMut<'a, (K, V)>,
+    pub(crate) inner: slice::IterMut<'a, (K, V)>,
 }
 
 pub struct ValuesMut<'a, K: 'a, V: 'a> {
@@ -44,7 +245,15 @@ pub struct ValuesMut<'a, K: 'a, V: 'a> {
 }
 
 pub struct Iter<'a, K: 'a, V: 'a> {
-    inner: slice::Iter<'a, (K, V)>,
+    pub(crate) inner: slice::Iter<'a, (K, V)>,
+}
+
+pub struct Range<'a, K: 'a, V: 'a> {
+    pub(crate) inner: slice::Iter<'a, (K, V)>,
+}
+
+pub struct RangeMut<'a, K: 'a, V: 'a> {
+    inner: slice::IterMut<'a, (K, V)>,
 }
 
 pub struct Keys<'a, K: 'a, V: 'a> {
@@ -55,14 +264,147 @@ pub struct Values<'a, K: 'a, V: 'a> {
     inner: Map<Iter<'a, K, V>, fn((&'a K, &'a V)) -> &'a V>,
 }
 
-impl<K, V> FlatMap<K, V> {
+/// Iterator over the maximal runs of consecutive entries with equal values,
+/// returned by `FlatMap::runs` and `FlatMap::runs_by`.
+pub struct Runs<'a, K: 'a, V: 'a, F = fn(&V, &V) -> bool> {
+    v: &'a [(K, V)],
+    same: F,
+}
+
+/// A non-empty run of consecutive entries whose values compare equal.
+pub struct Run<'a, K: 'a, V: 'a> {
+    entries: &'a [(K, V)],
+}
 
-    pub fn new() -> FlatMap<K, V> {
-        FlatMap { v: vec![] }
+/// Entries whose keys are in either map, in ascending order of key. A key in
+/// both maps is yielded once, with the value from the first map.
+pub struct Union<'a, K: 'a, V: 'a> {
+    a: Peekable<Iter<'a, K, V>>,
+    b: Peekable<Iter<'a, K, V>>,
+}
+
+/// Entries of the first map whose keys are also in the second, in ascending
+/// order of key.
+pub struct Intersection<'a, K: 'a, V: 'a> {
+    a: Peekable<Iter<'a, K, V>>,
+    b: Peekable<Iter<'a, K, V>>,
+}
+
+/// Entries of the first map whose keys are not in the second, in ascending
+/// order of key.
+pub struct Difference<'a, K: 'a, V: 'a> {
+    a: Peekable<Iter<'a, K, V>>,
+    b: Peekable<Iter<'a, K, V>>,
+}
+
+/// Entries whose keys are in exactly one of the maps, in ascending order of
+/// key.
+pub struct SymmetricDifference<'a, K: 'a, V: 'a> {
+    a: Peekable<Iter<'a, K, V>>,
+    b: Peekable<Iter<'a, K, V>>,
+}
+
+/// Entries whose keys are in both maps, with the values from each, in
+/// ascending order of key.
+pub struct JoinInner<'a, K: 'a, V: 'a, V2: 'a> {
+    a: Peekable<Iter<'a, K, V>>,
+    b: Peekable<Iter<'a, K, V2>>,
+}
+
+/// Every entry of the first map, with the value for the same key in the
+/// second map if there is one, in ascending order of key.
+pub struct JoinLeft<'a, K: 'a, V: 'a, V2: 'a> {
+    a: Peekable<Iter<'a, K, V>>,
+    b: Peekable<Iter<'a, K, V2>>,
+}
+
+/// Every key in either map, with the value from each map that has it, in
+/// ascending order of key.
+pub struct JoinOuter<'a, K: 'a, V: 'a, V2: 'a> {
+    a: Peekable<Iter<'a, K, V>>,
+    b: Peekable<Iter<'a, K, V2>>,
+}
+
+/// Iterator over the changes from one map to another, returned by
+/// `FlatMap::diff`.
+pub struct Diff<'a, K: 'a, V: 'a> {
+    a: Peekable<Iter<'a, K, V>>,
+    b: Peekable<Iter<'a, K, V>>,
+}
+
+/// A change from one map to another, yielded by `Diff`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiffItem<'a, K: 'a, V: 'a> {
+    /// A key only in the new map, with its value.
+    Added(&'a K, &'a V),
+    /// A key only in the old map, with its value.
+    Removed(&'a K, &'a V),
+    /// A key in both maps whose value differs, with the old and new values.
+    Changed(&'a K, &'a V, &'a V),
+}
+
+impl<K, V> FlatMap<K, V> {
+    /// Creates an empty map without allocating.
+    ///
+    /// This is a `const fn`, so a map can be used in a `static` directly,
+    /// where `Default::default()` can't be called:
+    ///
+    /// ```
+    /// use std::sync::Mutex;
+    /// use flat_map::FlatMap;
+    ///
+    /// static REGISTRY: Mutex<FlatMap<u32, &str>> = Mutex::new(FlatMap::new());
+    /// ```
+    pub const fn new() -> FlatMap<K, V> {
+        FlatMap::with_lookup(BinarySearch)
     }
 
     pub fn with_capacity(capacity: usize) -> FlatMap<K, V> {
-        FlatMap { v: Vec::with_capacity(capacity) }
+        FlatMap::with_capacity_and_lookup(capacity, BinarySearch)
+    }
+}
+
+impl<K, V> FlatMap<K, V, OrderBy<Descending>> {
+    /// Creates an empty map that keeps its keys in descending order, so that
+    /// iteration and the positional methods run from the largest key down.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let mut scores = FlatMap::new_desc();
+    /// scores.insert(70, "c");
+    /// scores.insert(95, "a");
+    /// scores.insert(80, "b");
+    /// assert_eq!(scores.first_key_value(), Some((&95, &"a")));
+    /// assert_eq!(scores.keys().cloned().collect::<Vec<_>>(), [95, 80, 70]);
+    /// ```
+    pub const fn new_desc() -> Self {
+        FlatMap::with_lookup(OrderBy(Descending))
+    }
+}
+
+impl<K, V, L> FlatMap<K, V, L> {
+    /// Creates an empty map that finds keys using `l`.
+    ///
+    /// ```
+    /// use flat_map::{FlatMap, LinearBack};
+    ///
+    /// let mut log = FlatMap::with_lookup(LinearBack);
+    /// log.insert(1, "start");
+    /// log.insert(2, "stop");
+    /// assert_eq!(log.get(&2), Some(&"stop"));
+    /// ```
+    pub const fn with_lookup(l: L) -> FlatMap<K, V, L> {
+        FlatMap { v: Vec::new(), policy: Policy::DEFAULT, l, last: None }
+    }
+
+    pub fn with_capacity_and_lookup(capacity: usize, l: L) -> FlatMap<K, V, L> {
+        FlatMap { v: Vec::with_capacity(capacity), policy: Policy::DEFAULT, l, last: None }
+    }
+
+    // Adopts entries that are already in the order `l` keeps them in.
+    pub(crate) fn from_arranged(v: Vec<(K, V)>, l: L) -> FlatMap<K, V, L> {
+        FlatMap { v, policy: Policy::DEFAULT, l, last: None }
     }
 
     /// Returns the number of elements the `VecMap` can hold without
@@ -87,10 +429,79 @@ impl<K, V> FlatMap<K, V> {
         self.v.reserve_exact(additional)
     }
 
+    /// Reserves room for at least `additional` more entries like `reserve`,
+    /// but returns an error instead of aborting if the allocation fails or
+    /// the capacity would overflow. The map is unchanged on failure.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let mut map: FlatMap<u64, u64> = FlatMap::new();
+    /// map.try_reserve(100).unwrap();
+    /// assert!(map.capacity() >= 100);
+    /// assert!(map.try_reserve(usize::MAX).is_err());
+    /// ```
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        self.v.try_reserve(additional)
+    }
+
+    pub fn try_reserve_exact(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        self.v.try_reserve_exact(additional)
+    }
+
     pub fn shrink_to_fit(&mut self) {
         self.v.shrink_to_fit()
     }
 
+    /// The strategy the map finds keys with.
+    pub fn lookup(&self) -> &L {
+        &self.l
+    }
+
+    pub fn growth_policy(&self) -> GrowthPolicy {
+        self.policy.growth
+    }
+
+    /// Sets how the map grows from now on. The current capacity is kept.
+    pub fn set_growth_policy(&mut self, policy: GrowthPolicy) {
+        self.policy.growth = policy;
+    }
+
+    pub fn shrink_policy(&self) -> ShrinkPolicy {
+        self.policy.shrink
+    }
+
+    /// Sets whether the map shrinks after removals from now on. The policy is
+    /// first checked by the next removal, not by this call.
+    pub fn set_shrink_policy(&mut self, policy: ShrinkPolicy) {
+        self.policy.shrink = policy;
+    }
+
+    /// Returns the number of bytes used by the map: the map itself plus its
+    /// allocation, including unused capacity. Heap memory owned by the keys
+    /// and values is not included.
+    pub fn memory_usage(&self) -> usize {
+        mem::size_of::<Self>() + self.v.capacity() * mem::size_of::<(K, V)>()
+    }
+
+    /// Returns `memory_usage()` plus the heap memory owned by the keys and
+    /// values, which `f` reports for each entry.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let mut names = FlatMap::new();
+    /// names.insert(1, String::from("one"));
+    /// names.insert(3, String::with_capacity(64));
+    /// let deep = names.memory_usage_with(|_, name| name.capacity());
+    /// assert!(deep >= names.memory_usage() + 67);
+    /// ```
+    pub fn memory_usage_with<F>(&self, mut f: F) -> usize
+        where F: FnMut(&K, &V) -> usize
+    {
+        self.memory_usage() + self.v.iter().map(|(k, v)| f(k, v)).sum::<usize>()
+    }
+
     pub fn len(&self) -> usize {
         self.v.len()
     }
@@ -139,264 +550,2987 @@ impl<K, V> FlatMap<K, V> {
         Values { inner: self.iter().map(second) }
     }
 
-    pub fn clear(&mut self) {
-        self.v.clear()
+    /// Returns the entry at quantile `q` of the map's entries, using the
+    /// nearest-rank method: the entry at 1-based rank `ceil(q * len)`, or the
+    /// first entry when `q` is 0. Returns `None` if the map is empty.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `q` is not in `0.0..=1.0`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let map: FlatMap<_, _> = (1..11).map(|k| (k * 10, ())).collect();
+    /// assert_eq!(map.quantile_key(0.5), Some((&50, &())));
+    /// assert_eq!(map.quantile_key(0.95), Some((&100, &())));
+    /// ```
+    pub fn quantile_key(&self, q: f64) -> Option<(&K, &V)> {
+        let rank = nearest_rank(q, self.v.len() as u64);
+        self.v.get(rank as usize - 1).map(|(k, v)| (k, v))
     }
 
-    pub fn into_inner(self) -> Vec<(K, V)> {
-        self.v
+    /// Returns the entry at which the cumulative weight of the entries, in
+    /// key order, first reaches quantile `q` of the total weight, using the
+    /// same nearest-rank rule as `quantile_key`. Entries weighing 0 are never
+    /// selected. Returns `None` if the total weight is 0.
+    ///
+    /// This scans the map, so it takes O(n) time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `q` is not in `0.0..=1.0`, or if the total weight overflows
+    /// `u64`.
+    pub fn quantile_key_by_weight<F>(&self, q: f64, weight: F) -> Option<(&K, &V)>
+        where F: Fn(&V) -> u64
+    {
+        let total = self.v.iter().fold(0u64, |sum, (_, v)| {
+            sum.checked_add(weight(v)).expect("total weight overflows u64")
+        });
+        let rank = nearest_rank(q, total);
+        let mut sum = 0;
+        self.v.iter()
+            .find(|(_, v)| {
+                sum += weight(v);
+                sum >= rank
+            })
+            .map(|(k, v)| (k, v))
     }
-}
 
-impl<K: Ord, V> FlatMap<K, V> {
-    pub fn insert(&mut self, key: K, mut v: V) -> Option<V> {
-        match self.v[..].binary_search_by(|&(ref k, _)| k.cmp(&key)) {
-            Err(i) => {
-                self.v.insert(i, (key, v));
-                None
-            }
-            Ok(i) => {
-                let &mut (_, ref mut value) = &mut self.v[i];
-                swap(value, &mut v);
-                Some(v)
-            }
-        }
+    /// Returns an iterator over the maximal runs of consecutive entries with
+    /// equal values, in key order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let map: FlatMap<_, _> = vec![(1, 'a'), (2, 'a'), (3, 'b'), (5, 'a')].into_iter().collect();
+    /// let runs: Vec<_> = map.runs().map(|r| (*r.first_key(), *r.last_key(), *r.value())).collect();
+    /// assert_eq!(runs, [(1, 2, 'a'), (3, 3, 'b'), (5, 5, 'a')]);
+    /// ```
+    pub fn runs(&self) -> Runs<'_, K, V>
+        where V: PartialEq
+    {
+        self.runs_by(PartialEq::eq)
     }
 
-    pub fn append(&mut self, other: &mut Self) {
-        self.v.reserve(other.len());
-        for (k, v) in other.v.drain(..) {
-            self.insert(k, v);
-        }
+    /// Like `runs`, but two neighbouring values belong to the same run when
+    /// `same` returns true for them. Each value is only compared with its
+    /// neighbours, so with a tolerance such as `|a, b| (a - b).abs() <= 1`,
+    /// the values 1, 2 and 3 form a single run.
+    pub fn runs_by<F>(&self, same: F) -> Runs<'_, K, V, F>
+        where F: FnMut(&V, &V) -> bool
+    {
+        Runs { v: &self.v, same }
     }
 
-    pub fn split_off(&mut self, key: &K) -> Self {
-        match self.v[..].binary_search_by(|&(ref k, _)| k.cmp(key)) {
-            Err(_) => Self::new(),
-            Ok(at) => {
-                let v = self.v.split_off(at);
-                FlatMap { v: v }
-            }
-        }
-
+    /// Retains only the entries for which `f` returns true, in a single pass
+    /// that visits every entry once in key order. Order is preserved.
+    pub fn retain<F>(&mut self, mut f: F)
+        where F: FnMut(&K, &mut V) -> bool
+    {
+        self.v.retain_mut(|(k, v)| f(k, v));
+        self.policy.shrink(&mut self.v);
     }
 
-    pub fn get<Q: ?Sized>(&self, q: &Q) -> Option<&V>
-        where K: Borrow<Q>,
-              Q: Ord
-    {
-        match self.v[..].binary_search_by(|&(ref k, _)| k.borrow().cmp(q)) {
-            Err(_) => None,
-            Ok(idx) => {
-                let (_, ref v) = self.v[idx];
-                Some(v)
-            }
-        }
+    /// Returns the entry at `index` in key order.
+    pub fn get_index(&self, index: usize) -> Option<(&K, &V)> {
+        self.v.get(index).map(|(k, v)| (k, v))
     }
 
-    pub fn contains_key<Q: ?Sized>(&self, k: &Q) -> bool
-        where K: Borrow<Q>,
-              Q: Ord
-    {
-        self.get(k).is_some()
+    pub fn get_index_mut(&mut self, index: usize) -> Option<(&K, &mut V)> {
+        self.v.get_mut(index).map(|(k, v)| (&*k, v))
     }
 
-    /// Return Option<&mut V>.
-    ///
-    /// # Example
-    ///
-    /// ```
-    /// use flat_map::FlatMap;
-    ///
-    /// let mut m = FlatMap::new();
-    /// m.insert(1, "foo".to_string());
-    /// m.get_mut(&1).unwrap().push_str("bar");
-    /// assert_eq!("foobar", m.get_mut(&1).unwrap());
-    /// ```
-    pub fn get_mut<Q: ?Sized>(&mut self, q: &Q) -> Option<&mut V>
-        where K: Borrow<Q>,
-              Q: Ord
+    /// Returns the entry at `index` for in-place manipulation, or `None` if
+    /// `index` is out of bounds.
+    pub fn entry_at_index(&mut self, index: usize) -> Option<OccupiedEntry<'_, K, V>>
+        where L: Order
     {
-        match self.v[..].binary_search_by(|&(ref k, _)| k.borrow().cmp(q)) {
-            Err(_) => None,
-            Ok(idx) => {
-                match self.v.get_mut(idx) {
-                    Some(&mut (_, ref mut v)) => Some(v),
-                    _ => None,
-                }
-            }
+        if index < self.v.len() {
+            let sorted = L::SORTED;
+            Some(OccupiedEntry { v: &mut self.v, index, policy: self.policy, sorted })
+        } else {
+            None
         }
     }
 
-    pub fn entry(&mut self, key: K) -> Entry<K, V> {
-        match self.v[..].binary_search_by(|&(ref k, _)| k.cmp(&key)) {
-            Err(i) => {
-                Vacant(VacantEntry {
-                           v: &mut self.v,
-                           key: key,
-                           index: i,
-                       })
-            }
-            Ok(i) => {
-                Occupied(OccupiedEntry {
-                             v: &mut self.v,
-                             index: i,
-                         })
-            }
+    /// Removes and returns the entry at `index`, shifting the entries after
+    /// it. Returns `None` if `index` is out of bounds.
+    pub fn remove_index(&mut self, index: usize) -> Option<(K, V)> {
+        if index >= self.v.len() {
+            return None;
         }
+        let entry = self.v.remove(index);
+        self.policy.shrink(&mut self.v);
+        Some(entry)
     }
 
-    pub fn remove<Q: ?Sized>(&mut self, q: &Q) -> Option<V>
-        where K: Borrow<Q>,
-              Q: Ord
-    {
-        match self.v[..].binary_search_by(|&(ref k, _)| k.borrow().cmp(q)) {
-            Err(_) => None,
-            Ok(i) => {
-                let (_, value) = self.v.remove(i);
-                Some(value)
-            }
-        }
+    /// Returns the entry with the smallest key.
+    pub fn first_key_value(&self) -> Option<(&K, &V)> {
+        self.v.first().map(|(k, v)| (k, v))
+    }
+
+    /// Returns the entry with the largest key.
+    pub fn last_key_value(&self) -> Option<(&K, &V)> {
+        self.v.last().map(|(k, v)| (k, v))
     }
 
+    /// Returns the value of the entry with the smallest key.
+    pub fn first_value_mut(&mut self) -> Option<&mut V> {
+        self.v.first_mut().map(|(_, v)| v)
+    }
 
-}
+    /// Returns the value of the entry with the largest key.
+    pub fn last_value_mut(&mut self) -> Option<&mut V> {
+        self.v.last_mut().map(|(_, v)| v)
+    }
 
-impl<'a, K: Ord, V> Entry<'a, K, V> {
-    pub fn or_insert(self, default: V) -> &'a mut V {
-        match self {
-            Occupied(entry) => entry.into_mut(),
-            Vacant(entry) => entry.insert(default),
+    // These do not know whether the strategy is sorted, so `remove_unordered`
+    // on their entries keeps the order.
+    pub fn first_entry(&mut self) -> Option<OccupiedEntry<'_, K, V>> {
+        if self.v.is_empty() {
+            None
+        } else {
+            Some(OccupiedEntry { v: &mut self.v, index: 0, policy: self.policy, sorted: true })
         }
     }
 
-    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
-        match self {
-            Occupied(entry) => entry.into_mut(),
-            Vacant(entry) => entry.insert(default()),
+    pub fn last_entry(&mut self) -> Option<OccupiedEntry<'_, K, V>> {
+        match self.v.len() {
+            0 => None,
+            len => {
+                let index = len - 1;
+                Some(OccupiedEntry { v: &mut self.v, index, policy: self.policy, sorted: true })
+            }
         }
     }
-}
 
-impl<'a, K: Ord, V> VacantEntry<'a, K, V> {
-    pub fn insert(self, value: V) -> &'a mut V {
-        self.v.insert(self.index, (self.key, value));
-        let &mut (_, ref mut value) = &mut self.v[self.index];
-        value
+    /// Removes and returns the entry with the smallest key. This shifts the
+    /// rest of the map, so it takes O(n) time.
+    pub fn pop_first(&mut self) -> Option<(K, V)> {
+        if self.v.is_empty() {
+            return None;
+        }
+        let first = self.v.remove(0);
+        self.policy.shrink(&mut self.v);
+        Some(first)
     }
-}
 
-impl<'a, K: Ord, V> OccupiedEntry<'a, K, V> {
-    pub fn key(&self) -> &K {
-        let (ref key, _) = self.v[self.index];
-        key
+    /// Removes and returns the entry with the largest key, in O(1) time.
+    pub fn pop_last(&mut self) -> Option<(K, V)> {
+        let last = self.v.pop();
+        self.policy.shrink(&mut self.v);
+        last
+    }
+
+    /// Removes all entries from the map, returning them in key order. The
+    /// capacity of the map is kept.
+    ///
+    /// As with `Vec::drain`, the map is emptied when the iterator is dropped,
+    /// even if it was not fully consumed.
+    pub fn drain(&mut self) -> Drain<'_, K, V> {
+        Drain { inner: self.v.drain(..) }
+    }
+
+    /// Returns an iterator that removes and yields, in key order, the
+    /// entries for which `pred` returns true. The other entries stay in the
+    /// map, in order.
+    ///
+    /// The predicate is only called as the iterator is advanced. As with
+    /// `Vec::extract_if`, entries not yet visited when the iterator is
+    /// dropped stay in the map, whatever `pred` would have returned for
+    /// them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let mut map: FlatMap<_, _> = (0..8).map(|k| (k, k)).collect();
+    /// let evens: FlatMap<_, _> = map.extract_if(|k, _| k % 2 == 0).collect();
+    /// assert_eq!(evens.keys().cloned().collect::<Vec<_>>(), [0, 2, 4, 6]);
+    /// assert_eq!(map.keys().cloned().collect::<Vec<_>>(), [1, 3, 5, 7]);
+    /// ```
+    pub fn extract_if<F>(&mut self, mut pred: F) -> ExtractIf<'_, K, V, impl FnMut(&mut (K, V)) -> bool>
+        where F: FnMut(&K, &mut V) -> bool
+    {
+        ExtractIf { inner: self.v.extract_if(.., move |(k, v)| pred(k, v)) }
+    }
+
+    pub fn clear(&mut self) {
+        self.v.clear();
+        self.last = None;
+        self.policy.shrink(&mut self.v);
+    }
+
+    /// Returns the index at which the most recent `insert` or `insert_hint`
+    /// put or updated its entry, or `try_insert` or `push_max` added one, or
+    /// `None` if there was none since the map was created or cleared.
+    ///
+    /// Other changes to the map do not update it, so the index may be out of
+    /// date; as a hint for `insert_hint` that is harmless.
+    pub fn last_insert_index(&self) -> Option<usize> {
+        self.last
+    }
+
+    /// Divides the map into two read-only views at an index. The first view
+    /// holds the entries in `[0, mid)`, the second those in `[mid, len)`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `mid > len`.
+    pub fn split_at(&self, mid: usize) -> (FlatSlice<'_, K, V, L>, FlatSlice<'_, K, V, L>)
+        where L: Clone
+    {
+        self.as_flat_slice().split_at(mid)
+    }
+
+    /// Divides the map into two mutable views at an index. The first view
+    /// holds the entries in `[0, mid)`, the second those in `[mid, len)`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `mid > len`.
+    pub fn split_at_mut(&mut self, mid: usize)
+        -> (FlatSliceMut<'_, K, V, L>, FlatSliceMut<'_, K, V, L>)
+        where L: Clone
+    {
+        let (left, right) = self.v.split_at_mut(mid);
+        let l = self.l.clone();
+        (FlatSliceMut::with_lookup(left, l.clone()), FlatSliceMut::with_lookup(right, l))
+    }
+
+    pub fn into_inner(self) -> Vec<(K, V)> {
+        self.v
+    }
+
+    /// Takes the entries, in the map's order, and the lookup strategy.
+    pub fn into_parts(self) -> (Vec<(K, V)>, L) {
+        (self.v, self.l)
+    }
+
+    /// Rebinds the map to the lookup strategy `l2`, keeping its entries and
+    /// policies. Entries already in the order `l2` keeps, as when switching
+    /// between strategies sorted by `Ord` or to an unsorted one, are kept
+    /// in place in O(n) time; otherwise they are sorted.
+    ///
+    /// ```
+    /// use flat_map::{FlatMap, LinearFront, UnsortedLinear};
+    ///
+    /// let map: FlatMap<_, _> = vec![(2, 'b'), (1, 'a')].into_iter().collect();
+    /// let mut map = map.set_lookup(LinearFront);
+    /// assert_eq!(map.get(&1), Some(&'a'));
+    ///
+    /// let mut unsorted = map.set_lookup(UnsortedLinear);
+    /// unsorted.insert(0, 'z');
+    /// let sorted = unsorted.set_lookup(LinearFront);
+    /// assert_eq!(sorted.into_inner(), [(0, 'z'), (1, 'a'), (2, 'b')]);
+    /// ```
+    pub fn set_lookup<L2: Arrange<K>>(self, l2: L2) -> FlatMap<K, V, L2> {
+        let mut v = self.v;
+        // the keys are already distinct, so only their order can be wrong
+        if L2::SORTED {
+            l2.arrange(&mut v, false);
+        }
+        FlatMap { v, policy: self.policy, l: l2, last: None }
+    }
+
+    /// Returns the entries as a contiguous slice, in the map's order.
+    pub fn as_slice(&self) -> &[(K, V)] {
+        &self.v
+    }
+
+    /// Checks that the entries are in the order the lookup strategy expects,
+    /// reporting the first entry that is not.
+    ///
+    /// Sorted strategies need keys in strictly ascending order, or strictly
+    /// in the comparator's order for `OrderBy`, and `UnsortedLinear` needs
+    /// distinct keys. The map keeps to this by itself; it can only break
+    /// through `from_sorted_vec_unchecked`, a key whose ordering changes
+    /// while in the map, or a faulty custom strategy. Takes O(n) time for
+    /// sorted strategies and O(n^2) otherwise, like the operations of
+    /// `UnsortedLinear` maps.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let map: FlatMap<_, _> = vec![(3, 'c'), (1, 'a'), (2, 'b')].into_iter().collect();
+    /// assert!(map.check_invariants().is_ok());
+    /// ```
+    pub fn check_invariants(&self) -> Result<(), InvariantError>
+        where L: Arrange<K>
+    {
+        if L::SORTED {
+            let order = |w: &[(K, V)]| self.l.compare_keys(&w[0].0, &w[1].0);
+            return match self.v.windows(2).map(order).position(|o| o != Ordering::Less) {
+                Some(i) if order(&self.v[i..]) == Ordering::Equal => {
+                    Err(InvariantError::duplicate(i + 1))
+                }
+                Some(i) => Err(InvariantError::out_of_order(i + 1)),
+                None => Ok(()),
+            };
+        }
+        match first_repeat(&self.l, &self.v) {
+            Some(i) => Err(InvariantError::duplicate(i)),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns a read-only view of the map that can be searched like it.
+    pub fn as_flat_slice(&self) -> FlatSlice<'_, K, V, L>
+        where L: Clone
+    {
+        FlatSlice::with_lookup(&self.v, self.l.clone())
+    }
+
+    /// Calls `f` with the entries as a mutable slice, then restores the
+    /// map's order. Keys may be changed freely; if that leaves several
+    /// entries with equal keys, the first of them in the slice is kept.
+    ///
+    /// The order is restored even if `f` panics.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let mut map: FlatMap<_, _> = vec![(1, 'a'), (2, 'b'), (3, 'c')].into_iter().collect();
+    /// map.with_mut_slice(|entries| {
+    ///     for entry in entries.iter_mut() {
+    ///         entry.0 = 10 - entry.0;
+    ///     }
+    /// });
+    /// assert_eq!(map.into_inner(), [(7, 'c'), (8, 'b'), (9, 'a')]);
+    /// ```
+    pub fn with_mut_slice<R, F>(&mut self, f: F) -> R
+        where F: FnOnce(&mut [(K, V)]) -> R,
+              L: Arrange<K>
+    {
+        let guard = ArrangeGuard { v: &mut self.v, l: &self.l };
+        f(guard.v)
+    }
+
+    /// Consumes the map, returning its keys in order.
+    pub fn into_keys(self) -> IntoKeys<K, V> {
+        IntoKeys { inner: IntoIter { inner: self.v.into_iter() } }
+    }
+
+    /// Consumes the map, returning its values in key order.
+    pub fn into_values(self) -> IntoValues<K, V> {
+        IntoValues { inner: IntoIter { inner: self.v.into_iter() } }
+    }
+
+    /// Returns true if both maps contain exactly the same keys, whatever
+    /// their values.
+    pub fn keys_eq<V2, L2>(&self, other: &FlatMap<K, V2, L2>) -> bool
+        where K: PartialEq,
+              L: Arrange<K>,
+              L2: Arrange<K>
+    {
+        self.v.len() == other.v.len() &&
+        same_entries(&self.l, &other.l, &self.v, &other.v, |a, b| a.0 == b.0)
+    }
+
+    /// Compares the key sequences of both maps lexicographically, ignoring
+    /// their values.
+    pub fn keys_cmp<V2, L2>(&self, other: &FlatMap<K, V2, L2>) -> Ordering
+        where K: Ord
+    {
+        self.keys().cmp(other.keys())
+    }
+
+    /// Returns the index of the partition point according to the given
+    /// predicate (the index of the first entry of the second partition).
+    ///
+    /// The map is assumed to be partitioned according to the given predicate.
+    /// This means that all entries for which the predicate returns true are
+    /// at the start of the map and all entries for which the predicate
+    /// returns false are at the end. For example, `[7, 15, 3, 5, 4, 12, 6]` is
+    /// partitioned under the predicate `x % 2 != 0` (all odd numbers are at
+    /// the start, all even at the end).
+    ///
+    /// If the map is not partitioned, the returned result is unspecified and
+    /// meaningless, as this method performs a kind of binary search.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let map: FlatMap<_, _> = (1..6).map(|k| (k, k * 10)).collect();
+    /// assert_eq!(map.partition_point(|_, &v| v < 35), 3);
+    /// ```
+    pub fn partition_point<F>(&self, mut pred: F) -> usize
+        where F: FnMut(&K, &V) -> bool
+    {
+        self.v.partition_point(|(k, v)| pred(k, v))
+    }
+
+    /// Returns the first entry for which `pred` returns true, where `pred`
+    /// returns false for some prefix of the map and true for the rest.
+    ///
+    /// As with `partition_point`, the result is unspecified if the map is not
+    /// partitioned this way.
+    pub fn first_entry_where<F>(&self, mut pred: F) -> Option<(&K, &V)>
+        where F: FnMut(&K, &V) -> bool
+    {
+        let i = self.partition_point(|k, v| !pred(k, v));
+        self.v.get(i).map(|(k, v)| (k, v))
+    }
+
+    /// Returns the last entry for which `pred` returns true, where `pred`
+    /// returns true for some prefix of the map and false for the rest.
+    ///
+    /// As with `partition_point`, the result is unspecified if the map is not
+    /// partitioned this way.
+    pub fn last_entry_where<F>(&self, pred: F) -> Option<(&K, &V)>
+        where F: FnMut(&K, &V) -> bool
+    {
+        match self.partition_point(pred) {
+            0 => None,
+            i => self.v.get(i - 1).map(|(k, v)| (k, v)),
+        }
+    }
+}
+
+// The 1-based nearest rank `max(ceil(q * n), 1)`, without `f64::ceil`, which
+// isn't available without std.
+fn nearest_rank(q: f64, n: u64) -> u64 {
+    assert!((0.0..=1.0).contains(&q), "quantile {} is not in 0.0..=1.0", q);
+    let x = q * n as f64;
+    let rank = x as u64;
+    let rank = if (rank as f64) < x { rank + 1 } else { rank };
+    cmp::max(cmp::min(rank, n), 1)
+}
+
+impl<K, V, L: Arrange<K> + Default> FlatMap<K, V, L> {
+    /// Adopts a vector that is already strictly sorted by key, in O(n) and
+    /// without reallocating. This is the counterpart of `into_inner`.
+    ///
+    /// Fails if a key does not come after the one before it in the
+    /// strategy's order, handing the vector back along with the index of
+    /// that key. The order is ascending, except for `OrderBy` maps.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let map: FlatMap<_, _> = FlatMap::from_sorted_vec(vec![(1, 'a'), (2, 'b')]).unwrap();
+    /// assert_eq!(map.into_inner(), [(1, 'a'), (2, 'b')]);
+    ///
+    /// let err = FlatMap::<_, _>::from_sorted_vec(vec![(1, 'a'), (1, 'b')]).unwrap_err();
+    /// assert_eq!(err.index(), 1);
+    /// ```
+    pub fn from_sorted_vec(v: Vec<(K, V)>) -> Result<Self, SortedInputError<Vec<(K, V)>>>
+        where L: Ordered
+    {
+        let l = L::default();
+        match v.windows(2).position(|w| l.compare_keys(&w[0].0, &w[1].0) != Ordering::Less) {
+            Some(i) => Err(SortedInputError::with_input(i + 1, v)),
+            None => Ok(FlatMap::from_arranged(v, l)),
+        }
+    }
+
+    /// Adopts a vector that the caller guarantees is strictly sorted by key,
+    /// without checking it in release builds.
+    ///
+    /// If the vector is not strictly sorted, lookups and inserts give
+    /// unspecified results, but never undefined behaviour.
+    pub fn from_sorted_vec_unchecked(v: Vec<(K, V)>) -> Self
+        where L: Ordered
+    {
+        let l = L::default();
+        debug_assert!(v.windows(2).all(|w| l.compare_keys(&w[0].0, &w[1].0) == Ordering::Less),
+                      "from_sorted_vec_unchecked input is not strictly sorted by key");
+        FlatMap::from_arranged(v, l)
+    }
+
+    /// Collects the entries of `iter` into a map, failing with the first key
+    /// that repeats an earlier one, in the order they arrive.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let config = vec![("port", 80), ("host", 1), ("port", 8080)];
+    /// let err = FlatMap::<_, _>::try_from_iter(config).unwrap_err();
+    /// assert_eq!(*err.key(), "port");
+    /// ```
+    pub fn try_from_iter<I>(iter: I) -> Result<Self, DuplicateKeyError<K>>
+        where I: IntoIterator<Item = (K, V)>
+    {
+        let mut v: Vec<(K, V)> = iter.into_iter().collect();
+        let l = L::default();
+        if let Some(i) = first_repeat(&l, &v) {
+            return Err(DuplicateKeyError::new(v.swap_remove(i).0));
+        }
+        l.arrange(&mut v, false);
+        Ok(FlatMap::from_arranged(v, l))
+    }
+
+    /// Builds a map from an iterator that the caller guarantees yields keys
+    /// in strictly sorted order, pushing each entry without searching or
+    /// sorting. As with `from_sorted_vec_unchecked`, the order is only
+    /// checked in debug builds.
+    ///
+    /// ```
+    /// use std::collections::BTreeMap;
+    /// use flat_map::FlatMap;
+    ///
+    /// let source: BTreeMap<_, _> = vec![(2, 'b'), (1, 'a')].into_iter().collect();
+    /// let map: FlatMap<_, _> = FlatMap::from_sorted_iter(source);
+    /// assert_eq!(map.into_inner(), [(1, 'a'), (2, 'b')]);
+    /// ```
+    pub fn from_sorted_iter<I>(iter: I) -> Self
+        where I: IntoIterator<Item = (K, V)>,
+              L: Ordered
+    {
+        let l = L::default();
+        let iter = iter.into_iter();
+        let mut v: Vec<(K, V)> = Vec::with_capacity(iter.size_hint().0);
+        for (key, value) in iter {
+            debug_assert!(v.last().is_none_or(|e| l.compare_keys(&e.0, &key) == Ordering::Less),
+                          "from_sorted_iter input is not strictly sorted by key");
+            v.push((key, value));
+        }
+        FlatMap::from_arranged(v, l)
+    }
+}
+
+impl<K, V, L: Arrange<K> + Default> FlatMap<K, V, L> {
+    /// Collects the entries of `iter` into a map, keeping the first of
+    /// entries with equal keys. This is what `collect` does.
+    pub fn from_iter_keep_first<I>(iter: I) -> Self
+        where I: IntoIterator<Item = (K, V)>
+    {
+        iter.into_iter().collect()
+    }
+
+    /// Collects the entries of `iter` into a map, keeping the last of
+    /// entries with equal keys, as repeated inserts would.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let map: FlatMap<_, _> = FlatMap::from_iter_keep_last(vec![("a", 1), ("b", 2), ("a", 3)]);
+    /// assert_eq!(map.into_inner(), [("a", 3), ("b", 2)]);
+    /// ```
+    pub fn from_iter_keep_last<I>(iter: I) -> Self
+        where I: IntoIterator<Item = (K, V)>
+    {
+        FlatMap::from(iter.into_iter().collect::<Vec<_>>())
+    }
+}
+
+impl<K, V, L> FlatMap<K, V, L> {
+    /// Inserts a key-value pair, returning the old value if the key was
+    /// already present. The stored key is kept in that case.
+    ///
+    /// With the sorted strategies the new entry goes to its place in key
+    /// order, shifting the entries after it. With `UnsortedLinear` it is
+    /// appended, so the map keeps insertion order.
+    pub fn insert(&mut self, key: K, v: V) -> Option<V>
+        where L: Lookup<K, V, K>
+    {
+        let found = self.l.lookup(&self.v, &key);
+        self.insert_at(found, key, v)
+    }
+
+    /// Inserts a key-value pair like `insert`, and also returns the index
+    /// of the entry in the map.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let mut map = FlatMap::new();
+    /// assert_eq!(map.insert_full("b", 1), (0, None));
+    /// assert_eq!(map.insert_full("a", 2), (0, None));
+    /// assert_eq!(map.insert_full("b", 3), (1, Some(1)));
+    /// ```
+    pub fn insert_full(&mut self, key: K, value: V) -> (usize, Option<V>)
+        where L: Lookup<K, V, K>
+    {
+        let found = self.l.lookup(&self.v, &key);
+        let index = found.unwrap_or_else(|i| i);
+        (index, self.insert_at(found, key, value))
+    }
+
+    // Inserts at the position a lookup returned, remembering it.
+    fn insert_at(&mut self, found: Result<usize, usize>, key: K, mut v: V) -> Option<V> {
+        match found {
+            Err(i) => {
+                self.policy.reserve(&mut self.v, 1);
+                self.v.insert(i, (key, v));
+                self.last = Some(i);
+                None
+            }
+            Ok(i) => {
+                let &mut (_, ref mut value) = &mut self.v[i];
+                swap(value, &mut v);
+                self.last = Some(i);
+                Some(v)
+            }
+        }
+    }
+
+    /// Inserts a key-value pair if the key is not present yet, returning a
+    /// reference to the new value. Unlike `insert`, an existing entry is
+    /// never overwritten: the error holds that entry along with the
+    /// rejected key and value.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let mut plugins = FlatMap::new();
+    /// assert_eq!(*plugins.try_insert("gzip", 1).unwrap(), 1);
+    /// let err = plugins.try_insert("gzip", 2).unwrap_err();
+    /// assert_eq!(err.entry.get(), &1);
+    /// assert_eq!(err.value, 2);
+    /// ```
+    pub fn try_insert(&mut self, key: K, value: V) -> Result<&mut V, OccupiedError<'_, K, V>>
+        where L: Lookup<K, V, K>
+    {
+        match self.l.lookup(&self.v, &key) {
+            Ok(index) => {
+                let sorted = L::SORTED;
+                let entry = OccupiedEntry { v: &mut self.v, index, policy: self.policy, sorted };
+                Err(OccupiedError { entry, key, value })
+            }
+            Err(index) => {
+                self.policy.reserve(&mut self.v, 1);
+                self.v.insert(index, (key, value));
+                self.last = Some(index);
+                Ok(&mut self.v[index].1)
+            }
+        }
+    }
+
+    /// Replaces the contents of the map with the entries of `iter`, reusing
+    /// the existing allocation.
+    ///
+    /// The result is the same as `iter.into_iter().collect()`: the entries
+    /// are sorted in one go and, of entries with equal keys, the first one
+    /// is kept.
+    pub fn collect_into<I>(&mut self, iter: I) -> &mut Self
+        where I: IntoIterator<Item = (K, V)>,
+              L: Arrange<K>
+    {
+        let capacity = self.v.capacity();
+        self.v.clear();
+        self.v.extend(iter);
+        self.l.arrange(&mut self.v, false);
+        self.policy.grown(&mut self.v, capacity);
+        self
+    }
+
+    /// Moves all entries of `other` into the map, leaving `other` empty.
+    /// Values from `other` replace existing values. Takes O(n + m) time.
+    pub fn append<L2>(&mut self, other: &mut FlatMap<K, V, L2>)
+        where L: Arrange<K>,
+              L2: Order
+    {
+        let other = self.rearrange::<L2>(mem::take(&mut other.v));
+        self.merge_entries(other, |_, _, new| new);
+    }
+
+    /// Merges `other` into the map in a single O(n + m) pass. For keys
+    /// present in both maps, `f` is called once with the key, the existing
+    /// value and the value from `other`, in that order, and its result is
+    /// kept. The existing key is kept too.
+    ///
+    /// If `f` panics, the map is left empty.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let mut counts: FlatMap<_, _> = vec![("a", 1), ("b", 2)].into_iter().collect();
+    /// let more: FlatMap<_, _> = vec![("b", 10), ("c", 3)].into_iter().collect();
+    /// counts.merge_with(more, |_, old, new| old + new);
+    /// assert_eq!(counts.into_inner(), [("a", 1), ("b", 12), ("c", 3)]);
+    /// ```
+    pub fn merge_with<L2, F>(&mut self, other: FlatMap<K, V, L2>, f: F)
+        where F: FnMut(&K, V, V) -> V,
+              L: Arrange<K>,
+              L2: Order
+    {
+        let other = self.rearrange::<L2>(other.v);
+        self.merge_entries(other, f);
+    }
+
+    // Merges entries in the strategy's order into the map, keeping to the
+    // growth policy.
+    fn merge_entries<I, F>(&mut self, other: I, f: F)
+        where I: IntoIterator<Item = (K, V)>,
+              F: FnMut(&K, V, V) -> V,
+              L: Arrange<K>
+    {
+        let capacity = self.v.capacity();
+        self.l.merge(&mut self.v, other, f);
+        self.policy.grown(&mut self.v, capacity);
+    }
+
+    // Brings the entries of a map with strategy `L2` into this map's order,
+    // which only takes work when an unsorted map is merged into a sorted one.
+    fn rearrange<L2: Order>(&self, mut entries: Vec<(K, V)>) -> Vec<(K, V)>
+        where L: Arrange<K>
+    {
+        if L::SORTED && !L2::SORTED {
+            self.l.arrange(&mut entries, false);
+        }
+        entries
+    }
+
+    pub fn get<Q: ?Sized>(&self, q: &Q) -> Option<&V>
+        where L: Lookup<K, V, Q>
+    {
+        match self.l.lookup(&self.v, q) {
+            Err(_) => None,
+            Ok(idx) => {
+                let (_, ref v) = self.v[idx];
+                Some(v)
+            }
+        }
+    }
+
+    /// Looks up `q` like `get`, searching outward from position `hint`
+    /// where the lookup strategy supports it. For keys looked up in
+    /// ascending order, passing the index of the previous one makes each
+    /// lookup cost O(log d) for a distance d between them. A wrong hint
+    /// only makes the search slower.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let map: FlatMap<u32, u32> = (0..1000).map(|k| (k * 3, k)).collect();
+    /// assert_eq!(map.get_hint(600, &1800), Some(&600));
+    /// assert_eq!(map.get_hint(0, &1803), Some(&601));
+    /// assert_eq!(map.get_hint(5000, &1), None);
+    /// ```
+    pub fn get_hint<Q>(&self, hint: usize, q: &Q) -> Option<&V>
+        where Q: ?Sized,
+              L: Lookup<K, V, Q>
+    {
+        self.l.lookup_hint(&self.v, q, hint).ok().map(|i| &self.v[i].1)
+    }
+
+    /// Returns the stored key along with the value, which is useful when
+    /// equal keys can still be told apart, such as interned strings.
+    pub fn get_key_value<Q>(&self, q: &Q) -> Option<(&K, &V)>
+        where Q: ?Sized,
+              L: Lookup<K, V, Q>
+    {
+        match self.l.lookup(&self.v, q) {
+            Err(_) => None,
+            Ok(idx) => {
+                let (ref k, ref v) = self.v[idx];
+                Some((k, v))
+            }
+        }
+    }
+
+    /// Returns the index of the entry for `q`, or the index at which it
+    /// would be inserted, as found by the map's lookup strategy.
+    ///
+    /// Together with `get_index`, this finds the neighbours of a key. For
+    /// example, the entry with the largest key not greater than `q`:
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let map: FlatMap<_, _> = vec![(0, "low"), (10, "mid"), (20, "high")].into_iter().collect();
+    /// let floor = match map.binary_search_key(&15) {
+    ///     Ok(i) => map.get_index(i),
+    ///     Err(0) => None,
+    ///     Err(i) => map.get_index(i - 1),
+    /// };
+    /// assert_eq!(floor, Some((&10, &"mid")));
+    /// ```
+    pub fn binary_search_key<Q>(&self, q: &Q) -> Result<usize, usize>
+        where Q: ?Sized,
+              L: Lookup<K, V, Q>
+    {
+        self.l.lookup(&self.v, q)
+    }
+
+    /// Returns the index of the entry for `q`, for use with `get_index` and
+    /// `remove_index`.
+    pub fn get_index_of<Q>(&self, q: &Q) -> Option<usize>
+        where Q: ?Sized,
+              L: Lookup<K, V, Q>
+    {
+        self.l.lookup(&self.v, q).ok()
+    }
+
+    /// Returns the index of the first entry whose key is not less than `q`.
+    /// This is the number of keys less than `q`, its rank in the map.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let map: FlatMap<_, _> = vec![(10, 'a'), (20, 'b'), (30, 'c')].into_iter().collect();
+    /// assert_eq!(map.lower_bound(&20), 1);
+    /// assert_eq!(map.upper_bound(&20), 2);
+    /// assert_eq!(map.lower_bound(&25), 2);
+    /// assert_eq!(map.upper_bound(&25), 2);
+    /// ```
+    pub fn lower_bound<Q>(&self, q: &Q) -> usize
+        where K: Borrow<Q>,
+              Q: ?Sized,
+              L: Lookup<K, V, Q> + Ordered
+    {
+        match self.l.lookup(&self.v, q) {
+            Ok(i) | Err(i) => i,
+        }
+    }
+
+    /// Returns the index of the first entry whose key is greater than `q`.
+    /// This is the number of keys less than or equal to `q`.
+    pub fn upper_bound<Q>(&self, q: &Q) -> usize
+        where K: Borrow<Q>,
+              Q: ?Sized,
+              L: Lookup<K, V, Q> + Ordered
+    {
+        match self.l.lookup(&self.v, q) {
+            Ok(i) => i + 1,
+            Err(i) => i,
+        }
+    }
+
+    /// Returns the entry with the greatest key less than or equal to `q`.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let samples: FlatMap<_, _> = vec![(100, 1.5), (200, 2.5), (300, 3.5)].into_iter().collect();
+    /// assert_eq!(samples.get_floor(&250), Some((&200, &2.5)));
+    /// assert_eq!(samples.get_floor(&200), Some((&200, &2.5)));
+    /// assert_eq!(samples.get_lower(&200), Some((&100, &1.5)));
+    /// assert_eq!(samples.get_ceiling(&250), Some((&300, &3.5)));
+    /// assert_eq!(samples.get_higher(&300), None);
+    /// ```
+    pub fn get_floor<Q>(&self, q: &Q) -> Option<(&K, &V)>
+        where K: Borrow<Q>,
+              Q: ?Sized,
+              L: Lookup<K, V, Q> + Ordered
+    {
+        peek(&self.v, self.upper_bound(q).checked_sub(1))
+    }
+
+    /// Returns the entry with the least key greater than or equal to `q`.
+    pub fn get_ceiling<Q>(&self, q: &Q) -> Option<(&K, &V)>
+        where K: Borrow<Q>,
+              Q: ?Sized,
+              L: Lookup<K, V, Q> + Ordered
+    {
+        peek(&self.v, Some(self.lower_bound(q)))
+    }
+
+    /// Returns the entry with the greatest key strictly less than `q`.
+    pub fn get_lower<Q>(&self, q: &Q) -> Option<(&K, &V)>
+        where K: Borrow<Q>,
+              Q: ?Sized,
+              L: Lookup<K, V, Q> + Ordered
+    {
+        peek(&self.v, self.lower_bound(q).checked_sub(1))
+    }
+
+    /// Returns the entry with the least key strictly greater than `q`.
+    pub fn get_higher<Q>(&self, q: &Q) -> Option<(&K, &V)>
+        where K: Borrow<Q>,
+              Q: ?Sized,
+              L: Lookup<K, V, Q> + Ordered
+    {
+        peek(&self.v, Some(self.upper_bound(q)))
+    }
+
+    /// Splits the map in two at `q`. Returns the entries with keys greater
+    /// than or equal to `q`, leaving the smaller ones in `self`, whether or
+    /// not `q` itself is present.
+    pub fn split_off<Q>(&mut self, q: &Q) -> Self
+        where K: Borrow<Q>,
+              Q: ?Sized,
+              L: Lookup<K, V, Q> + Ordered + Clone
+    {
+        let v = self.v.split_off(self.lower_bound(q));
+        self.policy.shrink(&mut self.v);
+        FlatMap { v, policy: self.policy, l: self.l.clone(), last: None }
+    }
+
+    /// Returns the entry whose key is closest to `q`, measuring distance by
+    /// subtracting keys, in one search. A tie goes to the smaller key.
+    ///
+    /// ```
+    /// use flat_map::{FlatMap, TiePolicy};
+    ///
+    /// let points: FlatMap<u32, _> = vec![(0, "zero"), (10, "ten"), (20, "twenty")].into_iter().collect();
+    /// assert_eq!(points.nearest(&13), Some((&10, &"ten")));
+    /// assert_eq!(points.nearest(&15), Some((&10, &"ten")));
+    /// assert_eq!(points.nearest_with(&15, TiePolicy::PreferHigher), Some((&20, &"twenty")));
+    /// assert_eq!(points.nearest(&99), Some((&20, &"twenty")));
+    /// ```
+    pub fn nearest<D>(&self, q: &K) -> Option<(&K, &V)>
+        where for<'k> &'k K: Sub<&'k K, Output = D>,
+              D: Ord,
+              L: Lookup<K, V, K> + Sorted
+    {
+        self.nearest_with(q, TiePolicy::PreferLower)
+    }
+
+    /// Like `nearest`, but resolves a tie according to `tie`.
+    pub fn nearest_with<D>(&self, q: &K, tie: TiePolicy) -> Option<(&K, &V)>
+        where for<'k> &'k K: Sub<&'k K, Output = D>,
+              D: Ord,
+              L: Lookup<K, V, K> + Sorted
+    {
+        // every key below `i` is at most `q`, and every key from `i` on is
+        // greater, so both differences are non-negative
+        let i = self.upper_bound(q);
+        let lower = peek(&self.v, i.checked_sub(1));
+        let higher = peek(&self.v, Some(i));
+        match (lower, higher) {
+            (Some(lower), Some(higher)) => {
+                match (q - lower.0).cmp(&(higher.0 - q)) {
+                    Ordering::Less => Some(lower),
+                    Ordering::Greater => Some(higher),
+                    Ordering::Equal => {
+                        match tie {
+                            TiePolicy::PreferLower => Some(lower),
+                            TiePolicy::PreferHigher => Some(higher),
+                        }
+                    }
+                }
+            }
+            (lower, higher) => lower.or(higher),
+        }
+    }
+
+    /// Returns a cursor at the first entry whose key is not less than `q`,
+    /// or at the ghost position if there is none.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let map: FlatMap<_, _> = vec![(1, 'a'), (3, 'c'), (5, 'e')].into_iter().collect();
+    /// let mut cursor = map.lower_bound_cursor(&2);
+    /// assert_eq!(cursor.key_value(), Some((&3, &'c')));
+    /// cursor.move_prev();
+    /// assert_eq!(cursor.key_value(), Some((&1, &'a')));
+    /// cursor.move_prev();
+    /// assert_eq!(cursor.key_value(), None);
+    /// ```
+    pub fn lower_bound_cursor<Q>(&self, q: &Q) -> Cursor<'_, K, V>
+        where K: Borrow<Q>,
+              Q: ?Sized,
+              L: Lookup<K, V, Q> + Sorted
+    {
+        Cursor { v: &self.v, index: self.lower_bound(q) }
+    }
+
+    /// Returns a cursor at the first entry whose key is greater than `q`,
+    /// or at the ghost position if there is none.
+    pub fn upper_bound_cursor<Q>(&self, q: &Q) -> Cursor<'_, K, V>
+        where K: Borrow<Q>,
+              Q: ?Sized,
+              L: Lookup<K, V, Q> + Sorted
+    {
+        Cursor { v: &self.v, index: self.upper_bound(q) }
+    }
+
+    /// Like `lower_bound_cursor`, but the cursor can edit the map.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let mut map: FlatMap<_, _> = vec![(1, 'a'), (3, 'c'), (5, 'e')].into_iter().collect();
+    /// let mut cursor = map.lower_bound_cursor_mut(&3);
+    /// cursor.insert_before(2, 'b').unwrap();
+    /// assert_eq!(cursor.remove_current(), Some((3, 'c')));
+    /// assert_eq!(cursor.key(), Some(&5));
+    /// assert!(cursor.insert_after(4, 'd').is_err());
+    /// assert_eq!(map.into_inner(), [(1, 'a'), (2, 'b'), (5, 'e')]);
+    /// ```
+    pub fn lower_bound_cursor_mut<Q>(&mut self, q: &Q) -> CursorMut<'_, K, V>
+        where K: Borrow<Q>,
+              Q: ?Sized,
+              L: Lookup<K, V, Q> + Sorted
+    {
+        let index = self.lower_bound(q);
+        CursorMut { v: &mut self.v, index, policy: self.policy }
+    }
+
+    /// Like `upper_bound_cursor`, but the cursor can edit the map.
+    pub fn upper_bound_cursor_mut<Q>(&mut self, q: &Q) -> CursorMut<'_, K, V>
+        where K: Borrow<Q>,
+              Q: ?Sized,
+              L: Lookup<K, V, Q> + Sorted
+    {
+        let index = self.upper_bound(q);
+        CursorMut { v: &mut self.v, index, policy: self.policy }
+    }
+
+    pub fn contains_key<Q: ?Sized>(&self, k: &Q) -> bool
+        where L: Lookup<K, V, Q>
+    {
+        self.get(k).is_some()
+    }
+
+    /// Return Option<&mut V>.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let mut m = FlatMap::new();
+    /// m.insert(1, "foo".to_string());
+    /// m.get_mut(&1).unwrap().push_str("bar");
+    /// assert_eq!("foobar", m.get_mut(&1).unwrap());
+    /// ```
+    pub fn get_mut<Q: ?Sized>(&mut self, q: &Q) -> Option<&mut V>
+        where L: Lookup<K, V, Q>
+    {
+        match self.l.lookup(&self.v, q) {
+            Err(_) => None,
+            Ok(idx) => {
+                match self.v.get_mut(idx) {
+                    Some(&mut (_, ref mut v)) => Some(v),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    /// Like indexing with `map[q]`, but the panic message for a missing key
+    /// includes the key.
+    ///
+    /// # Panics
+    ///
+    /// If there is no entry for `q`.
+    pub fn must_get<Q>(&self, q: &Q) -> &V
+        where Q: ?Sized + Debug,
+              L: Lookup<K, V, Q>
+    {
+        match self.get(q) {
+            Some(value) => value,
+            None => panic!("no entry found for key {:?}", q),
+        }
+    }
+
+    /// Like `must_get`, for a mutable reference.
+    ///
+    /// # Panics
+    ///
+    /// If there is no entry for `q`.
+    pub fn must_get_mut<Q>(&mut self, q: &Q) -> &mut V
+        where Q: ?Sized + Debug,
+              L: Lookup<K, V, Q>
+    {
+        match self.get_mut(q) {
+            Some(value) => value,
+            None => panic!("no entry found for key {:?}", q),
+        }
+    }
+
+    /// Returns mutable references to the values of `N` distinct keys at once.
+    ///
+    /// Returns `None` if any key is missing, or if two of the keys are equal,
+    /// since the references would then alias.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let mut balances: FlatMap<_, _> = vec![(1, 100), (2, 50)].into_iter().collect();
+    /// if let Some([from, to]) = balances.get_many_mut([&1, &2]) {
+    ///     *from -= 30;
+    ///     *to += 30;
+    /// }
+    /// assert_eq!(balances.get(&1), Some(&70));
+    /// assert_eq!(balances.get(&2), Some(&80));
+    /// assert!(balances.get_many_mut([&1, &1]).is_none());
+    /// ```
+    pub fn get_many_mut<Q, const N: usize>(&mut self, keys: [&Q; N]) -> Option<[&mut V; N]>
+        where Q: ?Sized,
+              L: Lookup<K, V, Q>
+    {
+        let mut indices = [0; N];
+        for (index, q) in indices.iter_mut().zip(keys) {
+            *index = self.l.lookup(&self.v, q).ok()?;
+        }
+        let entries = self.v.get_disjoint_mut(indices).ok()?;
+        Some(entries.map(|(_, v)| v))
+    }
+
+    /// Gets the entry for `key` for in-place manipulation. Inserting into a
+    /// vacant entry puts the new entry where `insert` would.
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V>
+        where L: Lookup<K, V, K>
+    {
+        match self.l.lookup(&self.v, &key) {
+            Err(i) => {
+                Vacant(VacantEntry {
+                           v: &mut self.v,
+                           key,
+                           index: i,
+                           policy: self.policy,
+                       })
+            }
+            Ok(i) => {
+                Occupied(OccupiedEntry {
+                             v: &mut self.v,
+                             index: i,
+                             policy: self.policy,
+                             sorted: L::SORTED,
+                         })
+            }
+        }
+    }
+
+    /// Returns the stored key and its value, first inserting the value
+    /// `default` gives for `key` if the key is not present. `default` is only
+    /// called in that case, and the map is searched once either way.
+    ///
+    /// The returned key is the one in the map, which for an existing entry is
+    /// not `key` itself.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let mut lengths = FlatMap::new();
+    /// let (key, len) = lengths.get_or_insert_with("flat".to_string(), |k| k.len());
+    /// assert_eq!((key.as_str(), *len), ("flat", 4));
+    /// ```
+    pub fn get_or_insert_with<F>(&mut self, key: K, default: F) -> (&K, &mut V)
+        where L: Lookup<K, V, K>,
+              F: FnOnce(&K) -> V
+    {
+        self.entry(key).get_or_insert_with(default)
+    }
+
+    pub fn remove<Q: ?Sized>(&mut self, q: &Q) -> Option<V>
+        where L: Lookup<K, V, Q>
+    {
+        self.remove_entry(q).map(|(_, value)| value)
+    }
+
+    /// Removes the entry for `q`, returning the stored key along with the
+    /// value.
+    pub fn remove_entry<Q>(&mut self, q: &Q) -> Option<(K, V)>
+        where Q: ?Sized,
+              L: Lookup<K, V, Q>
+    {
+        match self.l.lookup(&self.v, q) {
+            Err(_) => None,
+            Ok(i) => {
+                let entry = self.v.remove(i);
+                self.policy.shrink(&mut self.v);
+                Some(entry)
+            }
+        }
+    }
+
+    /// Removes the entry for `q` in O(1) after the lookup if the strategy is
+    /// unsorted, by moving the last entry into its place, which changes the
+    /// order of the remaining entries. The sorted strategies cannot move
+    /// entries without breaking their order, so for them this is `remove`.
+    ///
+    /// ```
+    /// use flat_map::{FlatMap, UnsortedLinear};
+    ///
+    /// let mut map: FlatMap<_, _, UnsortedLinear> = FlatMap::from([("a", 1), ("b", 2), ("c", 3)]);
+    /// assert_eq!(map.remove_unordered(&"a"), Some(1));
+    /// assert_eq!(map.into_inner(), [("c", 3), ("b", 2)]);
+    /// ```
+    pub fn remove_unordered<Q: ?Sized>(&mut self, q: &Q) -> Option<V>
+        where L: Lookup<K, V, Q>
+    {
+        match self.l.lookup(&self.v, q) {
+            Err(_) => None,
+            Ok(index) => {
+                let sorted = L::SORTED;
+                let entry = OccupiedEntry { v: &mut self.v, index, policy: self.policy, sorted };
+                Some(entry.remove_unordered())
+            }
+        }
+    }
+}
+
+impl<K: Ord, V, L: Sorted> FlatMap<K, V, L> {
+    /// Inserts a key-value pair like `insert`, but tries `hint` as the
+    /// position of `key` first. Checking the hint takes at most two
+    /// comparisons; only if it is wrong does the map search, outward from
+    /// the hint where its lookup strategy supports that, as in `get_hint`.
+    ///
+    /// Inserting keys in ascending order with the hint `len()`, or one past
+    /// `last_insert_index()`, appends each entry without searching.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let mut map = FlatMap::new();
+    /// for k in 0..100 {
+    ///     let hint = map.len();
+    ///     map.insert_hint(hint, k, k * 2);
+    /// }
+    /// assert_eq!(map.last_insert_index(), Some(99));
+    /// // a wrong hint is still correct, just slower
+    /// assert_eq!(map.insert_hint(0, 50, 0), Some(100));
+    /// ```
+    pub fn insert_hint(&mut self, hint: usize, key: K, value: V) -> Option<V>
+        where L: Lookup<K, V, K>
+    {
+        let found = match self.check_hint(hint, &key) {
+            Some(found) => found,
+            None => self.l.lookup_hint(&self.v, &key, hint),
+        };
+        self.insert_at(found, key, value)
+    }
+
+    // Returns what a lookup of `key` would, if `hint` is its position.
+    fn check_hint(&self, hint: usize, key: &K) -> Option<Result<usize, usize>> {
+        if hint > self.v.len() || hint > 0 && self.v[hint - 1].0 >= *key {
+            return None;
+        }
+        match self.v.get(hint) {
+            None => Some(Err(hint)),
+            Some((k, _)) => {
+                match key.cmp(k) {
+                    Ordering::Less => Some(Err(hint)),
+                    Ordering::Equal => Some(Ok(hint)),
+                    Ordering::Greater => None,
+                }
+            }
+        }
+    }
+
+    /// Appends an entry in O(1) amortized time if its key is greater than
+    /// every key in the map. Otherwise fails without searching, handing the
+    /// entry back; the error's index is the map's length.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let mut samples = FlatMap::new();
+    /// samples.push_max(100, 0.5).unwrap();
+    /// samples.push_max(105, 0.7).unwrap();
+    /// assert_eq!(samples.push_max(105, 0.9).unwrap_err().into_input(), (105, 0.9));
+    /// ```
+    pub fn push_max(&mut self, key: K, value: V) -> Result<(), SortedInputError<(K, V)>> {
+        match self.v.last() {
+            Some(last) if last.0 >= key => {
+                Err(SortedInputError::with_input(self.v.len(), (key, value)))
+            }
+            _ => {
+                self.policy.reserve(&mut self.v, 1);
+                self.last = Some(self.v.len());
+                self.v.push((key, value));
+                Ok(())
+            }
+        }
+    }
+
+    /// Inserts the entries of a stream whose keys mostly arrive in
+    /// ascending order. An entry whose key is not less than the current
+    /// largest key is appended, or replaces the value of that key, without
+    /// searching; any other entry is inserted with `insert`.
+    ///
+    /// Returns how many entries took the slow path, which is zero for a
+    /// sorted stream. Space for the stream's lower size bound is reserved up
+    /// front.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let mut log = FlatMap::new();
+    /// assert_eq!(log.append_sorted(vec![(1, 'a'), (2, 'b'), (2, 'c'), (4, 'd')]), 0);
+    /// assert_eq!(log.append_sorted(vec![(5, 'e'), (3, 'x'), (6, 'f')]), 1);
+    /// assert_eq!(log.keys().cloned().collect::<Vec<_>>(), [1, 2, 3, 4, 5, 6]);
+    /// assert_eq!(log[&2], 'c');
+    /// ```
+    pub fn append_sorted<I>(&mut self, iter: I) -> usize
+        where I: IntoIterator<Item = (K, V)>,
+              L: Lookup<K, V, K>
+    {
+        let iter = iter.into_iter();
+        self.policy.reserve(&mut self.v, iter.size_hint().0);
+        let mut out_of_order = 0;
+        for (key, value) in iter {
+            match self.v.last_mut().map(|last| (key.cmp(&last.0), last)) {
+                Some((Ordering::Less, _)) => {
+                    out_of_order += 1;
+                    self.insert(key, value);
+                }
+                Some((Ordering::Equal, last)) => last.1 = value,
+                Some((Ordering::Greater, _)) | None => self.v.push((key, value)),
+            }
+        }
+        out_of_order
+    }
+
+    /// Inserts a batch of entries in a single merge pass, taking
+    /// O(n + m log m) time. For a key that is already in the map or repeats
+    /// in the batch, `f` is called with the key, the value so far and the
+    /// next value from the batch, in batch order, and its result is kept.
+    /// The first key stored for each entry is kept.
+    ///
+    /// If `f` panics, the map is left empty.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let mut hits: FlatMap<_, _> = vec![("/", 3)].into_iter().collect();
+    /// hits.insert_many(vec![("/a", 1), ("/", 1), ("/a", 1)], |_, old, new| old + new);
+    /// assert_eq!(hits.into_inner(), [("/", 4), ("/a", 2)]);
+    /// ```
+    pub fn insert_many<I, F>(&mut self, iter: I, mut f: F)
+        where I: IntoIterator<Item = (K, V)>,
+              F: FnMut(&K, V, V) -> V
+    {
+        let mut batch: Vec<(K, V)> = iter.into_iter().collect();
+        // the sort is stable, so equal keys stay in batch order
+        batch.sort_by(|a, b| a.0.cmp(&b.0));
+        let capacity = self.v.capacity();
+        let mut merged = Vec::with_capacity(merged_capacity(&self.v, batch.len()));
+        let mut old = mem::take(&mut self.v).into_iter().peekable();
+        for (key, value) in batch {
+            while let Some(entry) = old.next_if(|e| e.0 <= key) {
+                merged.push(entry);
+            }
+            match merged.pop() {
+                Some((last, acc)) if last == key => {
+                    let value = f(&last, acc, value);
+                    merged.push((last, value));
+                }
+                Some(last) => merged.extend([last, (key, value)]),
+                None => merged.push((key, value)),
+            }
+        }
+        merged.extend(old);
+        self.v = merged;
+        self.policy.grown(&mut self.v, capacity);
+    }
+
+    /// Collects the entries of `iter` into a map, combining the values of
+    /// entries with equal keys with `f`, in the order they arrive. The first
+    /// key of each run is kept.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let words = "to be or not to be".split(' ').map(|w| (w, 1));
+    /// let counts: FlatMap<_, _> = FlatMap::from_iter_merge(words, |_, a, b| a + b);
+    /// assert_eq!(counts.into_inner(), [("be", 2), ("not", 1), ("or", 1), ("to", 2)]);
+    /// ```
+    pub fn from_iter_merge<I, F>(iter: I, f: F) -> Self
+        where I: IntoIterator<Item = (K, V)>,
+              F: FnMut(&K, V, V) -> V,
+              L: Default
+    {
+        let mut map = FlatMap::with_lookup(L::default());
+        map.insert_many(iter, f);
+        map
+    }
+
+    /// Inserts all entries of an iterator that is known to yield strictly
+    /// ascending keys, merging them with the map in a single O(n + m) pass.
+    /// Values from the iterator replace existing values, as with `insert`.
+    ///
+    /// Only maps kept in ascending key order take sorted input, so a
+    /// descending map has to use `extend`:
+    ///
+    /// ```compile_fail
+    /// use flat_map::FlatMap;
+    /// use std::collections::BTreeMap;
+    ///
+    /// let source: BTreeMap<u32, u32> = (0..3).map(|k| (k, k)).collect();
+    /// let mut desc = FlatMap::new_desc();
+    /// desc.extend_sorted(source);
+    /// ```
+    pub fn extend_sorted<I>(&mut self, iter: I)
+        where I: IntoIterator<Item = (K, V)>,
+              I::IntoIter: SortedByKey
+    {
+        self.merge_ascending(iter, |_, _, new| new);
+    }
+
+    /// Merges a stream with strictly ascending keys into the map in a single
+    /// O(n + m) pass. For keys present in both, `f` is called with the key,
+    /// the existing value and the value from the stream, and its result is
+    /// kept.
+    ///
+    /// If `f` panics, the map is left empty.
+    pub fn merge_sorted<I, F>(&mut self, iter: I, f: F)
+        where I: IntoIterator<Item = (K, V)>,
+              I::IntoIter: SortedByKey,
+              F: FnMut(&K, V, V) -> V
+    {
+        self.merge_ascending(iter, f);
+    }
+
+    // Merges a `SortedByKey` stream, checking its order first: iterators
+    // drained from a map with a descending or unsorted strategy carry the
+    // marker too, and their entries go through `insert_many` instead.
+    fn merge_ascending<I, F>(&mut self, iter: I, f: F)
+        where I: IntoIterator<Item = (K, V)>,
+              F: FnMut(&K, V, V) -> V
+    {
+        let batch: Vec<(K, V)> = iter.into_iter().collect();
+        if batch.windows(2).all(|w| w[0].0 < w[1].0) {
+            self.merge_entries(batch, f);
+        } else {
+            self.insert_many(batch, f);
+        }
+    }
+
+    /// Removes the entries for all of `keys` in a single compaction pass,
+    /// rather than shifting the tail once per key. The keys are sorted
+    /// first, which is cheap if they already are. Returns the number of
+    /// entries removed.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let mut map: FlatMap<_, _> = (0..10).map(|k| (k, k * k)).collect();
+    /// assert_eq!(map.remove_keys(vec![7, 2, 11, 5]), 3);
+    /// assert_eq!(map.keys().cloned().collect::<Vec<_>>(), [0, 1, 3, 4, 6, 8, 9]);
+    /// ```
+    pub fn remove_keys<Q, I>(&mut self, keys: I) -> usize
+        where K: Borrow<Q>,
+              Q: Ord,
+              I: IntoIterator<Item = Q>
+    {
+        self.retain_by_keys(keys, false)
+    }
+
+    /// Keeps only the entries for `keys`, in a single compaction pass.
+    /// Returns the number of entries removed.
+    pub fn retain_keys<Q, I>(&mut self, keys: I) -> usize
+        where K: Borrow<Q>,
+              Q: Ord,
+              I: IntoIterator<Item = Q>
+    {
+        self.retain_by_keys(keys, true)
+    }
+
+    fn retain_by_keys<Q, I>(&mut self, keys: I, keep_listed: bool) -> usize
+        where K: Borrow<Q>,
+              Q: Ord,
+              I: IntoIterator<Item = Q>
+    {
+        let mut keys: Vec<Q> = keys.into_iter().collect();
+        keys.sort_unstable();
+        let mut keys = keys.iter().peekable();
+        let len = self.v.len();
+        self.v.retain(|(k, _)| {
+            let k = k.borrow();
+            while keys.next_if(|&q| q < k).is_some() {}
+            (keys.peek() == Some(&k)) == keep_listed
+        });
+        self.policy.shrink(&mut self.v);
+        len - self.v.len()
+    }
+
+    /// Divides the map into two read-only views at a key boundary. The first
+    /// view holds the entries less than `q`, the second the rest.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let map: FlatMap<_, _> = (0..10).map(|k| (k, k * k)).collect();
+    /// let (low, high) = map.split_by_key(&4);
+    /// assert_eq!(low.last_key_value(), Some((&3, &9)));
+    /// assert_eq!(high.get(&9), Some(&81));
+    /// ```
+    pub fn split_by_key<Q>(&self, q: &Q) -> (FlatSlice<'_, K, V, L>, FlatSlice<'_, K, V, L>)
+        where K: Borrow<Q>,
+              Q: ?Sized + Ord,
+              L: Lookup<K, V, Q> + Clone
+    {
+        self.as_flat_slice().split_by_key(q)
+    }
+
+    /// Divides the map into two mutable views at a key boundary. The first
+    /// view holds the entries less than `q`, the second the rest.
+    pub fn split_by_key_mut<Q>(&mut self, q: &Q)
+        -> (FlatSliceMut<'_, K, V, L>, FlatSliceMut<'_, K, V, L>)
+        where K: Borrow<Q>,
+              Q: ?Sized + Ord,
+              L: Lookup<K, V, Q> + Clone
+    {
+        let mid = match self.l.lookup(&self.v, q) {
+            Ok(i) | Err(i) => i,
+        };
+        self.split_at_mut(mid)
+    }
+
+    /// Returns an iterator over the entries whose keys fall within `range`,
+    /// in key order. Finding the ends of the range takes two lookups.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range start is greater than the range end, or if both
+    /// are equal and excluded, like `BTreeMap::range`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let map: FlatMap<_, _> = (1..10).map(|k| (k, k * 10)).collect();
+    /// let window: Vec<_> = map.range(3..=5).map(|(&k, &v)| (k, v)).collect();
+    /// assert_eq!(window, [(3, 30), (4, 40), (5, 50)]);
+    /// ```
+    pub fn range<T, R>(&self, range: R) -> Range<'_, K, V>
+        where K: Borrow<T>,
+              T: ?Sized + Ord,
+              R: RangeBounds<T>,
+              L: Lookup<K, V, T>
+    {
+        let (start, end) = self.range_indices(&range);
+        Range { inner: self.v[start..end].iter() }
+    }
+
+    /// Returns the entries whose keys start with `prefix`, found with two
+    /// binary searches.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let words: FlatMap<_, _> = vec!["car", "cart", "cat", "ca"].into_iter().map(|w| (w, ())).collect();
+    /// let car: Vec<_> = words.range_prefix("car").map(|(&w, _)| w).collect();
+    /// assert_eq!(car, ["car", "cart"]);
+    /// ```
+    pub fn range_prefix(&self, prefix: &str) -> Range<'_, K, V>
+        where K: Borrow<str>,
+              L: Lookup<K, V, str> + Sorted
+    {
+        let start = self.lower_bound(prefix);
+        let len = self.v[start..].partition_point(|(k, _)| k.borrow().starts_with(prefix));
+        Range { inner: self.v[start..start + len].iter() }
+    }
+
+    /// Like `range_prefix`, for keys that are byte strings.
+    pub fn range_prefix_bytes(&self, prefix: &[u8]) -> Range<'_, K, V>
+        where K: Borrow<[u8]>,
+              L: Lookup<K, V, [u8]> + Sorted
+    {
+        let start = self.lower_bound(prefix);
+        let len = self.v[start..].partition_point(|(k, _)| k.borrow().starts_with(prefix));
+        Range { inner: self.v[start..start + len].iter() }
+    }
+
+    /// Returns the entry whose key is the longest prefix of `q`, as in a
+    /// routing table.
+    ///
+    /// Each step searches for the greatest key not greater than the query,
+    /// and if that is not a prefix cuts the query down to the part they
+    /// share, so there are at most `q.len()` searches.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let routes: FlatMap<_, _> = vec![("/", 0), ("/api", 1), ("/api/v2", 2)].into_iter().collect();
+    /// assert_eq!(routes.get_longest_prefix("/api/v2/users"), Some((&"/api/v2", &2)));
+    /// assert_eq!(routes.get_longest_prefix("/api/v1/users"), Some((&"/api", &1)));
+    /// assert_eq!(routes.get_longest_prefix("/static"), Some((&"/", &0)));
+    /// assert_eq!(routes.get_longest_prefix("api"), None);
+    /// ```
+    pub fn get_longest_prefix(&self, q: &str) -> Option<(&K, &V)>
+        where K: Borrow<str>,
+              L: Lookup<K, V, str> + Sorted
+    {
+        let mut q = q;
+        loop {
+            let i = self.upper_bound(q).checked_sub(1)?;
+            let k: &str = self.v[i].0.borrow();
+            if q.starts_with(k) {
+                return peek(&self.v, Some(i));
+            }
+            let mut shared = common_prefix_len(k.as_bytes(), q.as_bytes());
+            while !q.is_char_boundary(shared) {
+                shared -= 1;
+            }
+            q = &q[..shared];
+        }
+    }
+
+    /// Like `get_longest_prefix`, for keys that are byte strings.
+    pub fn get_longest_prefix_bytes(&self, q: &[u8]) -> Option<(&K, &V)>
+        where K: Borrow<[u8]>,
+              L: Lookup<K, V, [u8]> + Sorted
+    {
+        let mut q = q;
+        loop {
+            let i = self.upper_bound(q).checked_sub(1)?;
+            let k: &[u8] = self.v[i].0.borrow();
+            if q.starts_with(k) {
+                return peek(&self.v, Some(i));
+            }
+            q = &q[..common_prefix_len(k, q)];
+        }
+    }
+
+    /// Like `range`, but the values are mutable.
+    ///
+    /// # Panics
+    ///
+    /// Panics on the same ranges as `range`.
+    pub fn range_mut<T, R>(&mut self, range: R) -> RangeMut<'_, K, V>
+        where K: Borrow<T>,
+              T: ?Sized + Ord,
+              R: RangeBounds<T>,
+              L: Lookup<K, V, T>
+    {
+        let (start, end) = self.range_indices(&range);
+        RangeMut { inner: self.v[start..end].iter_mut() }
+    }
+
+    /// Removes the entries whose keys fall within `range`, moving the tail
+    /// of the map once. Returns the number of entries removed.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let mut map: FlatMap<_, _> = (0..10).map(|k| (k, ())).collect();
+    /// assert_eq!(map.remove_range(3..7), 4);
+    /// assert_eq!(map.keys().cloned().collect::<Vec<_>>(), [0, 1, 2, 7, 8, 9]);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics on the same ranges as `range`.
+    pub fn remove_range<T, R>(&mut self, range: R) -> usize
+        where K: Borrow<T>,
+              T: ?Sized + Ord,
+              R: RangeBounds<T>,
+              L: Lookup<K, V, T>
+    {
+        let (start, end) = self.range_indices(&range);
+        self.v.drain(start..end);
+        self.policy.shrink(&mut self.v);
+        end - start
+    }
+
+    /// Removes the entries with keys less than `q`, keeping `q` itself.
+    /// Returns the number of entries removed.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let mut window: FlatMap<_, _> = (0..10).map(|t| (t, ())).collect();
+    /// assert_eq!(window.truncate_before(&4), 4);
+    /// assert_eq!(window.truncate_after(&6), 3);
+    /// assert_eq!(window.keys().cloned().collect::<Vec<_>>(), [4, 5, 6]);
+    /// ```
+    pub fn truncate_before<Q>(&mut self, q: &Q) -> usize
+        where K: Borrow<Q>,
+              Q: ?Sized + Ord,
+              L: Lookup<K, V, Q>
+    {
+        self.remove_range::<Q, _>((Unbounded, Excluded(q)))
+    }
+
+    /// Removes the entries with keys greater than `q`, keeping `q` itself.
+    /// Returns the number of entries removed.
+    pub fn truncate_after<Q>(&mut self, q: &Q) -> usize
+        where K: Borrow<Q>,
+              Q: ?Sized + Ord,
+              L: Lookup<K, V, Q>
+    {
+        self.remove_range::<Q, _>((Excluded(q), Unbounded))
+    }
+
+    /// Removes the entries whose keys fall within `range`, returning them in
+    /// key order.
+    ///
+    /// As with `Vec::drain`, the whole range is removed when the iterator is
+    /// dropped, even if it was not fully consumed.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let mut map: FlatMap<_, _> = (0..10).map(|k| (k, k * 10)).collect();
+    /// let evicted: Vec<_> = map.drain_range(..3).collect();
+    /// assert_eq!(evicted, [(0, 0), (1, 10), (2, 20)]);
+    /// assert_eq!(map.len(), 7);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics on the same ranges as `range`.
+    pub fn drain_range<T, R>(&mut self, range: R) -> DrainRange<'_, K, V>
+        where K: Borrow<T>,
+              T: ?Sized + Ord,
+              R: RangeBounds<T>,
+              L: Lookup<K, V, T>
+    {
+        let (start, end) = self.range_indices(&range);
+        DrainRange { inner: self.v.drain(start..end) }
+    }
+
+    /// Replaces the entries whose keys fall within `range` with the entries
+    /// of `replacement`, returning an iterator over the removed entries.
+    ///
+    /// The tail of the map is moved at most once. As with `Vec::splice`, the
+    /// removal happens when the returned iterator is dropped, even if it is
+    /// not consumed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the keys of `replacement` are not strictly ascending, if
+    /// any of them falls outside `range`, or if the range is inverted. The
+    /// map is left unchanged in that case.
+    pub fn splice<T, R, I>(&mut self, range: R, replacement: I) -> Splice<'_, K, V>
+        where K: Borrow<T>,
+              T: ?Sized + Ord,
+              R: RangeBounds<T>,
+              I: IntoIterator<Item = (K, V)>,
+              L: Lookup<K, V, T>
+    {
+        let (start, end) = self.range_indices(&range);
+        let replacement: Vec<(K, V)> = replacement.into_iter().collect();
+        for (i, (k, _)) in replacement.iter().enumerate() {
+            assert!(range.contains(k.borrow()),
+                    "splice replacement key is outside the spliced range");
+            assert!(i == 0 || replacement[i - 1].0 < *k,
+                    "splice replacement is not strictly sorted by key");
+        }
+        Splice { inner: self.v.splice(start..end, replacement) }
+    }
+
+    /// Retains only the entries within `range` for which `pred` returns
+    /// true, visiting them in key order. Entries outside `range` are left
+    /// untouched and the tail of the map is shifted at most once.
+    pub fn retain_range<T, R, F>(&mut self, range: R, mut pred: F)
+        where K: Borrow<T>,
+              T: ?Sized + Ord,
+              R: RangeBounds<T>,
+              F: FnMut(&K, &mut V) -> bool,
+              L: Lookup<K, V, T>
+    {
+        let (start, end) = self.range_indices(&range);
+        {
+            let mut guard = RetainGuard { v: &mut self.v, read: start, write: start };
+            while guard.read < end {
+                let keep = {
+                    let (ref k, ref mut v) = guard.v[guard.read];
+                    pred(k, v)
+                };
+                if keep {
+                    guard.v.swap(guard.read, guard.write);
+                    guard.write += 1;
+                }
+                guard.read += 1;
+            }
+        }
+        self.policy.shrink(&mut self.v);
+    }
+
+    // Returns the half-open index range of the entries whose keys fall within
+    // `range`.
+    fn range_indices<T, R>(&self, range: &R) -> (usize, usize)
+        where K: Borrow<T>,
+              T: ?Sized + Ord,
+              R: RangeBounds<T>,
+              L: Lookup<K, V, T>
+    {
+        range_indices(&self.v, &self.l, range)
+    }
+}
+
+// Returns the index of the first entry whose key is the same, to `l`, as
+// that of an earlier entry in `v`. Takes O(n log n) time for sorted
+// strategies, and O(n^2) for the others, which have no order to sort by.
+fn first_repeat<K, V, L: Arrange<K>>(l: &L, v: &[(K, V)]) -> Option<usize> {
+    let same = |a: usize, b: usize| l.compare_keys(&v[a].0, &v[b].0) == Ordering::Equal;
+    if !L::SORTED {
+        return (1..v.len()).find(|&i| (0..i).any(|j| same(j, i)));
+    }
+    // a stable sort keeps equal keys in slice order, so the second of each
+    // run of equal keys is the first repeat of that key
+    let mut order: Vec<usize> = (0..v.len()).collect();
+    order.sort_by(|&a, &b| l.compare_keys(&v[a].0, &v[b].0));
+    order.windows(2).filter(|w| same(w[0], w[1])).map(|w| w[1]).min()
+}
+
+// Returns the half-open index range of the entries of a sorted slice whose
+// keys fall within `range`, panicking on inverted ranges like BTreeMap::range
+// does.
+pub(crate) fn range_indices<K, V, T, R, L>(v: &[(K, V)], l: &L, range: &R) -> (usize, usize)
+    where K: Borrow<T>,
+          T: ?Sized + Ord,
+          R: RangeBounds<T>,
+          L: Lookup<K, V, T>
+{
+    match (range.start_bound(), range.end_bound()) {
+        (Excluded(s), Excluded(e)) if s == e => {
+            panic!("range start and end are equal and excluded in FlatMap")
+        }
+        (Included(s), Included(e)) |
+        (Included(s), Excluded(e)) |
+        (Excluded(s), Included(e)) |
+        (Excluded(s), Excluded(e)) if s > e => {
+            panic!("range start is greater than range end in FlatMap")
+        }
+        _ => {}
+    }
+    let search = |q: &T| l.lookup(v, q);
+    let start = match range.start_bound() {
+        Included(q) => search(q).unwrap_or_else(|i| i),
+        Excluded(q) => search(q).map(|i| i + 1).unwrap_or_else(|i| i),
+        Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Included(q) => search(q).map(|i| i + 1).unwrap_or_else(|i| i),
+        Excluded(q) => search(q).unwrap_or_else(|i| i),
+        Unbounded => v.len(),
+    };
+    (start, end)
+}
+
+// The set operations walk both maps in key order, so both have to be sorted.
+impl<K: Ord, V, L: Sorted> FlatMap<K, V, L> {
+    /// Iterates over the entries of both maps by key, without allocating.
+    /// Where both maps have a key, the entry from `self` is yielded.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let a: FlatMap<_, _> = vec![(1, 'a'), (2, 'b')].into_iter().collect();
+    /// let b: FlatMap<_, _> = vec![(2, 'x'), (3, 'y')].into_iter().collect();
+    /// let entries: Vec<_> = a.union(&b).collect();
+    /// assert_eq!(entries, [(&1, &'a'), (&2, &'b'), (&3, &'y')]);
+    /// ```
+    pub fn union<'a, L2: Sorted>(&'a self, other: &'a FlatMap<K, V, L2>) -> Union<'a, K, V> {
+        Union { a: self.iter().peekable(), b: other.iter().peekable() }
+    }
+
+    pub fn intersection<'a, L2: Sorted>(&'a self,
+                                        other: &'a FlatMap<K, V, L2>)
+                                        -> Intersection<'a, K, V> {
+        Intersection { a: self.iter().peekable(), b: other.iter().peekable() }
+    }
+
+    pub fn difference<'a, L2: Sorted>(&'a self,
+                                      other: &'a FlatMap<K, V, L2>)
+                                      -> Difference<'a, K, V> {
+        Difference { a: self.iter().peekable(), b: other.iter().peekable() }
+    }
+
+    pub fn symmetric_difference<'a, L2: Sorted>(&'a self,
+                                                other: &'a FlatMap<K, V, L2>)
+                                                -> SymmetricDifference<'a, K, V> {
+        SymmetricDifference { a: self.iter().peekable(), b: other.iter().peekable() }
+    }
+
+    /// Pairs up the values of keys in both maps, which may hold different
+    /// value types, in one pass over each.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let names: FlatMap<_, _> = vec![(1, "one"), (2, "two"), (3, "three")].into_iter().collect();
+    /// let squares: FlatMap<_, _> = vec![(2, 4), (3, 9), (4, 16)].into_iter().collect();
+    /// let inner: Vec<_> = names.join_inner(&squares).collect();
+    /// assert_eq!(inner, [(&2, &"two", &4), (&3, &"three", &9)]);
+    /// let left: Vec<_> = names.join_left(&squares).map(|(k, _, s)| (*k, s.copied())).collect();
+    /// assert_eq!(left, [(1, None), (2, Some(4)), (3, Some(9))]);
+    /// let outer: Vec<_> = names.join_outer(&squares).map(|(k, n, _)| (*k, n.copied())).collect();
+    /// assert_eq!(outer, [(1, Some("one")), (2, Some("two")), (3, Some("three")), (4, None)]);
+    /// ```
+    pub fn join_inner<'a, V2, L2: Sorted>(&'a self,
+                                          other: &'a FlatMap<K, V2, L2>)
+                                          -> JoinInner<'a, K, V, V2> {
+        JoinInner { a: self.iter().peekable(), b: other.iter().peekable() }
+    }
+
+    pub fn join_left<'a, V2, L2: Sorted>(&'a self,
+                                         other: &'a FlatMap<K, V2, L2>)
+                                         -> JoinLeft<'a, K, V, V2> {
+        JoinLeft { a: self.iter().peekable(), b: other.iter().peekable() }
+    }
+
+    pub fn join_outer<'a, V2, L2: Sorted>(&'a self,
+                                          other: &'a FlatMap<K, V2, L2>)
+                                          -> JoinOuter<'a, K, V, V2> {
+        JoinOuter { a: self.iter().peekable(), b: other.iter().peekable() }
+    }
+
+    /// Compares `self`, the old map, with `other`, the new one, yielding
+    /// the keys that were added, removed or given a different value, in
+    /// ascending order of key. Takes a single pass over both maps.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    /// use flat_map::flat_map::DiffItem::*;
+    ///
+    /// let old: FlatMap<_, _> = vec![("port", 80), ("tls", 0)].into_iter().collect();
+    /// let new: FlatMap<_, _> = vec![("port", 443), ("user", 1)].into_iter().collect();
+    /// let changes: Vec<_> = old.diff(&new).collect();
+    /// assert_eq!(changes, [Changed(&"port", &80, &443), Removed(&"tls", &0), Added(&"user", &1)]);
+    /// ```
+    pub fn diff<'a, L2: Sorted>(&'a self, other: &'a FlatMap<K, V, L2>) -> Diff<'a, K, V>
+        where V: PartialEq
+    {
+        Diff { a: self.iter().peekable(), b: other.iter().peekable() }
+    }
+
+    /// Applies changes such as those from `diff`, in one merge pass over the
+    /// map. `Added` and `Changed` set the key to the new value whether or not
+    /// it is present, and `Removed` removes it if it is. Where the patch has
+    /// several changes to a key, the last one wins.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let old: FlatMap<_, _> = vec![(1, 'a'), (2, 'b')].into_iter().collect();
+    /// let new: FlatMap<_, _> = vec![(2, 'c'), (3, 'd')].into_iter().collect();
+    /// let mut replica = old.clone();
+    /// replica.apply(old.diff(&new));
+    /// assert_eq!(replica, new);
+    /// ```
+    pub fn apply<'a, I>(&mut self, patch: I)
+        where I: IntoIterator<Item = DiffItem<'a, K, V>>,
+              K: Clone + 'a,
+              V: Clone + 'a
+    {
+        let mut patch: Vec<_> = patch.into_iter().collect();
+        // the sort is stable, so reversing first puts the last change to each
+        // key ahead of the others, which `dedup_by` then drops
+        patch.reverse();
+        patch.sort_by(|a, b| a.key().cmp(b.key()));
+        patch.dedup_by(|a, b| a.key() == b.key());
+
+        let capacity = self.v.capacity();
+        let mut v = Vec::with_capacity(merged_capacity(&self.v, patch.len()));
+        let old = mem::take(&mut self.v);
+        let mut patch = patch.into_iter().peekable();
+        for (key, value) in old {
+            while let Some(item) = patch.next_if(|item| *item.key() < key) {
+                if let Some(new) = item.new_value() {
+                    v.push((item.key().clone(), new.clone()));
+                }
+            }
+            match patch.next_if(|item| *item.key() == key) {
+                Some(item) => {
+                    if let Some(new) = item.new_value() {
+                        v.push((key, new.clone()));
+                    }
+                }
+                None => v.push((key, value)),
+            }
+        }
+        for item in patch {
+            if let Some(new) = item.new_value() {
+                v.push((item.key().clone(), new.clone()));
+            }
+        }
+        self.v = v;
+        self.policy.grown(&mut self.v, capacity);
+    }
+}
+
+impl<K: Ord, V, L: Sorted + Default> FlatMap<K, Vec<V>, L> {
+    /// Collects the entries of `iter` into a map from each key to all of
+    /// its values, in the order they arrive. Sorts the entries once.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let words = vec!["apple", "bean", "avocado", "beet", "corn"];
+    /// let by_len: FlatMap<_, _> = FlatMap::group_from_iter(words.iter().map(|&w| (w.len(), w)));
+    /// assert_eq!(by_len[&4], ["bean", "beet", "corn"]);
+    /// assert_eq!(by_len.len(), 3);
+    /// ```
+    pub fn group_from_iter<I>(iter: I) -> Self
+        where I: IntoIterator<Item = (K, V)>
+    {
+        let mut entries: Vec<(K, V)> = iter.into_iter().collect();
+        // the sort is stable, so each key's values stay in arrival order
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut v: Vec<(K, Vec<V>)> = Vec::new();
+        for (key, value) in entries {
+            match v.last_mut() {
+                Some(last) if last.0 == key => last.1.push(value),
+                _ => v.push((key, vec![value])),
+            }
+        }
+        FlatMap { v, policy: Policy::DEFAULT, l: L::default(), last: None }
+    }
+}
+
+impl<K: Ord, L: Sorted + Default> FlatMap<K, usize, L> {
+    /// Counts how many times each key occurs in `iter`, sorting the keys
+    /// once. The first of equal keys is kept.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let counts: FlatMap<_, _> = FlatMap::counts("mississippi".chars());
+    /// assert_eq!(counts.into_inner(), [('i', 4), ('m', 1), ('p', 2), ('s', 4)]);
+    /// ```
+    pub fn counts<I>(iter: I) -> Self
+        where I: IntoIterator<Item = K>
+    {
+        let mut keys: Vec<K> = iter.into_iter().collect();
+        keys.sort();
+        let mut v: Vec<(K, usize)> = Vec::new();
+        for key in keys {
+            match v.last_mut() {
+                Some(last) if last.0 == key => last.1 += 1,
+                _ => v.push((key, 1)),
+            }
+        }
+        FlatMap { v, policy: Policy::DEFAULT, l: L::default(), last: None }
+    }
+}
+
+impl<K: Clone, V: Clone, L: Clone> FlatMap<K, V, L> {
+    /// Returns a copy of the map with `key` set to `value`, leaving `self`
+    /// untouched. The copy is built in a single pass with no spare capacity.
+    /// As with `insert`, the stored key of an existing entry is kept.
+    pub fn inserted(&self, key: K, value: V) -> Self
+        where L: Lookup<K, V, K>
+    {
+        let (key, before, after) = match self.l.lookup(&self.v, &key) {
+            Ok(i) => (self.v[i].0.clone(), &self.v[..i], &self.v[i + 1..]),
+            Err(i) => (key, &self.v[..i], &self.v[i..]),
+        };
+        let mut v = Vec::with_capacity(before.len() + 1 + after.len());
+        v.extend_from_slice(before);
+        v.push((key, value));
+        v.extend_from_slice(after);
+        FlatMap { v, policy: self.policy, l: self.l.clone(), last: None }
+    }
+
+    /// Returns a copy of the map without the entry for `q`, leaving `self`
+    /// untouched. The copy is built in a single pass with no spare capacity.
+    pub fn removed<Q>(&self, q: &Q) -> Self
+        where K: Borrow<Q>,
+              Q: ?Sized,
+              L: Lookup<K, V, Q>
+    {
+        match self.l.lookup(&self.v, q) {
+            Ok(i) => {
+                let mut v = Vec::with_capacity(self.v.len() - 1);
+                v.extend_from_slice(&self.v[..i]);
+                v.extend_from_slice(&self.v[i + 1..]);
+                FlatMap { v, policy: self.policy, l: self.l.clone(), last: None }
+            }
+            Err(_) => self.clone(),
+        }
+    }
+
+    /// Returns a copy of the map with `f` applied to the value for `q`,
+    /// leaving `self` untouched. If `q` is not present the copy is identical.
+    pub fn updated<Q, F>(&self, q: &Q, f: F) -> Self
+        where K: Borrow<Q>,
+              Q: ?Sized,
+              F: FnOnce(&mut V),
+              L: Lookup<K, V, Q>
+    {
+        let mut copy = self.clone();
+        if let Ok(i) = copy.l.lookup(&copy.v, q) {
+            f(&mut copy.v[i].1);
+        }
+        copy
+    }
+}
+
+impl<'a, K, V> Entry<'a, K, V> {
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        match self {
+            Occupied(entry) => entry.into_mut(),
+            Vacant(entry) => entry.insert(default),
+        }
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Occupied(entry) => entry.into_mut(),
+            Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    /// Like `or_insert_with`, but `default` is given the key.
+    pub fn or_insert_with_key<F: FnOnce(&K) -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Occupied(entry) => entry.into_mut(),
+            Vacant(entry) => {
+                let value = default(&entry.key);
+                entry.insert(value)
+            }
+        }
+    }
+
+    pub fn or_default(self) -> &'a mut V
+        where V: Default
+    {
+        self.or_insert_with(V::default)
+    }
+
+    /// Like `or_insert_with_key`, but returns the stored key along with the
+    /// value.
+    pub fn get_or_insert_with<F: FnOnce(&K) -> V>(self, default: F) -> (&'a K, &'a mut V) {
+        let (v, index) = match self {
+            Occupied(entry) => (entry.v, entry.index),
+            Vacant(entry) => {
+                let value = default(&entry.key);
+                entry.policy.reserve(entry.v, 1);
+                entry.v.insert(entry.index, (entry.key, value));
+                (entry.v, entry.index)
+            }
+        };
+        let &mut (ref key, ref mut value) = &mut v[index];
+        (key, value)
+    }
+
+    /// Calls `f` on the value if the entry is occupied, then returns the
+    /// entry for further chaining.
+    ///
+    /// ```
+    /// use flat_map::FlatMap;
+    ///
+    /// let mut counts = FlatMap::new();
+    /// for word in "a b a c a".split(' ') {
+    ///     counts.entry(word).and_modify(|c| *c += 1).or_insert(1);
+    /// }
+    /// assert_eq!(counts[&"a"], 3);
+    /// ```
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+        match self {
+            Occupied(mut entry) => {
+                f(entry.get_mut());
+                Occupied(entry)
+            }
+            Vacant(entry) => Vacant(entry),
+        }
+    }
+}
+
+impl<'a, K, V> Entry<'a, K, V> {
+    pub fn key(&self) -> &K {
+        match *self {
+            Occupied(ref entry) => entry.key(),
+            Vacant(ref entry) => entry.key(),
+        }
+    }
+}
+
+impl<'a, K, V> VacantEntry<'a, K, V> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Takes back the key without inserting anything.
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    /// The index at which the entry will be inserted.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The entry that will come just before this one, if any. With a sorted
+    /// strategy it holds the greatest key less than this one.
+    pub fn peek_prev(&self) -> Option<(&K, &V)> {
+        peek(self.v, self.index.checked_sub(1))
+    }
+
+    /// The entry that will come just after this one, if any. With a sorted
+    /// strategy it holds the least key greater than this one.
+    pub fn peek_next(&self) -> Option<(&K, &V)> {
+        peek(self.v, Some(self.index))
+    }
+}
+
+impl<'a, K, V> VacantEntry<'a, K, V> {
+    pub fn insert(self, value: V) -> &'a mut V {
+        self.policy.reserve(self.v, 1);
+        self.v.insert(self.index, (self.key, value));
+        let &mut (_, ref mut value) = &mut self.v[self.index];
+        value
+    }
+}
+
+impl<'a, K, V> OccupiedEntry<'a, K, V> {
+    pub fn key(&self) -> &K {
+        let (ref key, _) = self.v[self.index];
+        key
+    }
+
+    pub fn get(&self) -> &V {
+        let (_, ref value) = self.v[self.index];
+        value
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        let (_, ref mut value) = self.v[self.index];
+        value
+    }
+
+    pub fn into_mut(self) -> &'a mut V {
+        let &mut (_, ref mut value) = &mut self.v[self.index];
+        value
+    }
+
+    /// The index of the entry in the map.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The entry just before this one, if any. With a sorted strategy it
+    /// holds the greatest key less than this one.
+    ///
+    /// ```
+    /// use flat_map::{FlatMap, Occupied};
+    ///
+    /// let mut map: FlatMap<_, _> = FlatMap::from([(1, 'a'), (3, 'c'), (5, 'e')]);
+    /// if let Occupied(entry) = map.entry(3) {
+    ///     assert_eq!(entry.index(), 1);
+    ///     assert_eq!(entry.peek_prev(), Some((&1, &'a')));
+    ///     assert_eq!(entry.peek_next(), Some((&5, &'e')));
+    /// }
+    /// ```
+    pub fn peek_prev(&self) -> Option<(&K, &V)> {
+        peek(self.v, self.index.checked_sub(1))
+    }
+
+    /// The entry just after this one, if any. With a sorted strategy it
+    /// holds the least key greater than this one.
+    pub fn peek_next(&self) -> Option<(&K, &V)> {
+        peek(self.v, Some(self.index + 1))
+    }
+
+    pub fn insert(&mut self, mut value: V) -> V {
+        let &mut (_, ref mut old_value) = &mut self.v[self.index];
+        swap(old_value, &mut value);
+        value
+    }
+
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+
+    /// Removes the entry, returning the stored key along with the value.
+    pub fn remove_entry(self) -> (K, V) {
+        let entry = self.v.remove(self.index);
+        self.policy.shrink(self.v);
+        entry
+    }
+
+    /// Removes the entry like `remove`, but with an unsorted strategy moves
+    /// the last entry into its place instead of shifting all the entries
+    /// after it. With a sorted strategy this is the same as `remove`.
+    pub fn remove_unordered(self) -> V {
+        let (_, value) = if self.sorted {
+            self.v.remove(self.index)
+        } else {
+            self.v.swap_remove(self.index)
+        };
+        self.policy.shrink(self.v);
+        value
+    }
+}
+
+impl<'a, K, V> Cursor<'a, K, V> {
+    /// The index of the current entry, or `None` at the ghost position.
+    pub fn index(&self) -> Option<usize> {
+        if self.index < self.v.len() { Some(self.index) } else { None }
+    }
+
+    pub fn key(&self) -> Option<&'a K> {
+        self.key_value().map(|(k, _)| k)
+    }
+
+    pub fn value(&self) -> Option<&'a V> {
+        self.key_value().map(|(_, v)| v)
+    }
+
+    pub fn key_value(&self) -> Option<(&'a K, &'a V)> {
+        peek(self.v, Some(self.index))
+    }
+
+    /// Moves to the next entry, from the last entry to the ghost position,
+    /// or from the ghost position to the first entry.
+    pub fn move_next(&mut self) {
+        self.index = if self.index < self.v.len() { self.index + 1 } else { 0 };
+    }
+
+    /// Moves to the previous entry, from the first entry to the ghost
+    /// position, or from the ghost position to the last entry.
+    pub fn move_prev(&mut self) {
+        self.index = if self.index == 0 { self.v.len() } else { self.index - 1 };
+    }
+
+    /// The entry `move_next` would move to.
+    pub fn peek_next(&self) -> Option<(&'a K, &'a V)> {
+        peek(self.v, Some(if self.index < self.v.len() { self.index + 1 } else { 0 }))
+    }
+
+    /// The entry `move_prev` would move to.
+    pub fn peek_prev(&self) -> Option<(&'a K, &'a V)> {
+        peek(self.v, self.index.checked_sub(1))
+    }
+}
+
+impl<'a, K, V> Clone for Cursor<'a, K, V> {
+    fn clone(&self) -> Cursor<'a, K, V> {
+        Cursor { v: self.v, index: self.index }
+    }
+}
+
+impl<'a, K, V> CursorMut<'a, K, V> {
+    /// The index of the current entry, or `None` at the ghost position.
+    pub fn index(&self) -> Option<usize> {
+        self.as_cursor().index()
+    }
+
+    pub fn key(&self) -> Option<&K> {
+        self.as_cursor().key()
+    }
+
+    pub fn value_mut(&mut self) -> Option<&mut V> {
+        self.v.get_mut(self.index).map(|(_, v)| v)
+    }
+
+    pub fn key_value(&self) -> Option<(&K, &V)> {
+        self.as_cursor().key_value()
+    }
+
+    /// A read-only view of the cursor, at the same position.
+    pub fn as_cursor(&self) -> Cursor<'_, K, V> {
+        Cursor { v: self.v, index: self.index }
+    }
+
+    pub fn move_next(&mut self) {
+        self.index = if self.index < self.v.len() { self.index + 1 } else { 0 };
+    }
+
+    pub fn move_prev(&mut self) {
+        self.index = if self.index == 0 { self.v.len() } else { self.index - 1 };
+    }
+
+    pub fn peek_next(&self) -> Option<(&K, &V)> {
+        self.as_cursor().peek_next()
+    }
+
+    pub fn peek_prev(&self) -> Option<(&K, &V)> {
+        self.as_cursor().peek_prev()
+    }
+
+    /// Removes the current entry, moving the cursor to the entry after it.
+    /// Does nothing at the ghost position.
+    pub fn remove_current(&mut self) -> Option<(K, V)> {
+        if self.index < self.v.len() {
+            let entry = self.v.remove(self.index);
+            self.policy.shrink(self.v);
+            Some(entry)
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a, K: Ord, V> CursorMut<'a, K, V> {
+    /// Inserts an entry just before the current one, or at the end of the
+    /// map at the ghost position. The cursor stays on the current entry.
+    ///
+    /// Fails, handing the entry back, unless its key falls strictly between
+    /// the keys of the previous and current entries.
+    pub fn insert_before(&mut self, key: K, value: V) -> Result<(), SortedInputError<(K, V)>> {
+        self.insert_at(self.index, key, value)?;
+        self.index += 1;
+        Ok(())
+    }
+
+    /// Inserts an entry just after the current one, or at the start of the
+    /// map at the ghost position. The cursor stays on the current entry.
+    ///
+    /// Fails, handing the entry back, unless its key falls strictly between
+    /// the keys of the current and next entries.
+    pub fn insert_after(&mut self, key: K, value: V) -> Result<(), SortedInputError<(K, V)>> {
+        if self.index < self.v.len() {
+            self.insert_at(self.index + 1, key, value)
+        } else {
+            self.insert_at(0, key, value)?;
+            self.index = self.v.len();
+            Ok(())
+        }
+    }
+
+    fn insert_at(&mut self, at: usize, key: K, value: V) -> Result<(), SortedInputError<(K, V)>> {
+        let after_prev = at == 0 || self.v[at - 1].0 < key;
+        let before_next = self.v.get(at).is_none_or(|next| key < next.0);
+        if after_prev && before_next {
+            self.policy.reserve(self.v, 1);
+            self.v.insert(at, (key, value));
+            Ok(())
+        } else {
+            Err(SortedInputError::with_input(at, (key, value)))
+        }
+    }
+}
+
+fn common_prefix_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
+}
+
+fn peek<K, V>(v: &[(K, V)], index: Option<usize>) -> Option<(&K, &V)> {
+    index.and_then(|i| v.get(i)).map(|(k, v)| (k, v))
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        match self.inner.next() {
+            Some(&(ref k, ref v)) => Some((k, v)),
+            None => None,
+        }
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+
+    #[cfg(feature = "unstable")]
+    fn advance_by(&mut self, n: usize) -> Result<(), NonZeroUsize> {
+        self.inner.advance_by(n)
+    }
+}
+
+impl<'a, K, V> Clone for Iter<'a, K, V> {
+    fn clone(&self) -> Iter<'a, K, V> {
+        Iter { inner: self.inner.clone() }
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for Iter<'a, K, V> {
+    fn next_back(&mut self) -> Option<(&'a K, &'a V)> {
+        match self.inner.next_back() {
+            Some(&(ref k, ref v)) => Some((k, v)),
+            None => None,
+        }
+    }
+
+    #[cfg(feature = "unstable")]
+    fn advance_back_by(&mut self, n: usize) -> Result<(), NonZeroUsize> {
+        self.inner.advance_back_by(n)
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for Iter<'a, K, V> {}
+
+impl<'a, K, V> Iterator for IterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a mut V)> {
+        match self.inner.next() {
+            Some(&mut (ref k, ref mut v)) => Some((k, v)),
+            None => None,
+        }
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+
+    #[cfg(feature = "unstable")]
+    fn advance_by(&mut self, n: usize) -> Result<(), NonZeroUsize> {
+        self.inner.advance_by(n)
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for IterMut<'a, K, V> {
+    fn next_back(&mut self) -> Option<(&'a K, &'a mut V)> {
+        match self.inner.next_back() {
+            Some(&mut (ref k, ref mut v)) => Some((k, v)),
+            None => None,
+        }
+    }
+
+    #[cfg(feature = "unstable")]
+    fn advance_back_by(&mut self, n: usize) -> Result<(), NonZeroUsize> {
+        self.inner.advance_back_by(n)
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for IterMut<'a, K, V> {}
+
+impl<'a, K, V> Iterator for Range<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        self.inner.next().map(|(k, v)| (k, v))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, K, V> Clone for Range<'a, K, V> {
+    fn clone(&self) -> Range<'a, K, V> {
+        Range { inner: self.inner.clone() }
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for Range<'a, K, V> {
+    fn next_back(&mut self) -> Option<(&'a K, &'a V)> {
+        self.inner.next_back().map(|(k, v)| (k, v))
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for Range<'a, K, V> {}
+
+impl<'a, K, V> Iterator for RangeMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a mut V)> {
+        self.inner.next().map(|(k, v)| (&*k, v))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for RangeMut<'a, K, V> {
+    fn next_back(&mut self) -> Option<(&'a K, &'a mut V)> {
+        self.inner.next_back().map(|(k, v)| (&*k, v))
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for RangeMut<'a, K, V> {}
+
+impl<K, V> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        self.inner.next()
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+
+    #[cfg(feature = "unstable")]
+    fn advance_by(&mut self, n: usize) -> Result<(), NonZeroUsize> {
+        self.inner.advance_by(n)
+    }
+}
+
+impl<K, V> DoubleEndedIterator for IntoIter<K, V> {
+    fn next_back(&mut self) -> Option<(K, V)> {
+        self.inner.next_back()
+    }
+
+    #[cfg(feature = "unstable")]
+    fn advance_back_by(&mut self, n: usize) -> Result<(), NonZeroUsize> {
+        self.inner.advance_back_by(n)
+    }
+}
+
+impl<K, V> ExactSizeIterator for IntoIter<K, V> {}
+
+impl<K, V> Iterator for IntoKeys<K, V> {
+    type Item = K;
+
+    fn next(&mut self) -> Option<K> {
+        self.inner.next().map(|(k, _)| k)
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K, V> DoubleEndedIterator for IntoKeys<K, V> {
+    fn next_back(&mut self) -> Option<K> {
+        self.inner.next_back().map(|(k, _)| k)
+    }
+}
+
+impl<K, V> ExactSizeIterator for IntoKeys<K, V> {}
+
+impl<K: Debug, V> Debug for IntoKeys<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.inner.inner.as_slice().iter().map(|(k, _)| k)).finish()
+    }
+}
+
+impl<K, V> Iterator for IntoValues<K, V> {
+    type Item = V;
+
+    fn next(&mut self) -> Option<V> {
+        self.inner.next().map(|(_, v)| v)
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K, V> DoubleEndedIterator for IntoValues<K, V> {
+    fn next_back(&mut self) -> Option<V> {
+        self.inner.next_back().map(|(_, v)| v)
+    }
+}
+
+impl<K, V> ExactSizeIterator for IntoValues<K, V> {}
+
+impl<K, V: Debug> Debug for IntoValues<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.inner.inner.as_slice().iter().map(|(_, v)| v)).finish()
+    }
+}
+
+impl<'a, K, V> Iterator for Drain<'a, K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        self.inner.next()
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for Drain<'a, K, V> {
+    fn next_back(&mut self) -> Option<(K, V)> {
+        self.inner.next_back()
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for Drain<'a, K, V> {}
+
+impl<'a, K, V> Iterator for DrainRange<'a, K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        self.inner.next()
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for DrainRange<'a, K, V> {
+    fn next_back(&mut self) -> Option<(K, V)> {
+        self.inner.next_back()
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for DrainRange<'a, K, V> {}
+
+impl<'a, K, V, F> Iterator for ExtractIf<'a, K, V, F>
+    where F: FnMut(&mut (K, V)) -> bool
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        self.inner.next()
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, K, V> Iterator for Splice<'a, K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        self.inner.next()
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for Splice<'a, K, V> {
+    fn next_back(&mut self) -> Option<(K, V)> {
+        self.inner.next_back()
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for Splice<'a, K, V> {}
+
+impl<'a, K, V, F> Iterator for Runs<'a, K, V, F>
+    where F: FnMut(&V, &V) -> bool
+{
+    type Item = Run<'a, K, V>;
+
+    fn next(&mut self) -> Option<Run<'a, K, V>> {
+        if self.v.is_empty() {
+            return None;
+        }
+        // each value is compared with the one before it, not with the first
+        // of the run, so `same` need not be transitive
+        let same = &mut self.same;
+        let len = 1 + self.v.windows(2).take_while(|w| same(&w[0].1, &w[1].1)).count();
+        let (entries, rest) = self.v.split_at(len);
+        self.v = rest;
+        Some(Run { entries })
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (cmp::min(self.v.len(), 1), Some(self.v.len()))
+    }
+}
+
+impl<'a, K, V, F> DoubleEndedIterator for Runs<'a, K, V, F>
+    where F: FnMut(&V, &V) -> bool
+{
+    fn next_back(&mut self) -> Option<Run<'a, K, V>> {
+        if self.v.is_empty() {
+            return None;
+        }
+        let same = &mut self.same;
+        let len = 1 + self.v.windows(2).rev().take_while(|w| same(&w[0].1, &w[1].1)).count();
+        let (rest, entries) = self.v.split_at(self.v.len() - len);
+        self.v = rest;
+        Some(Run { entries })
+    }
+}
+
+impl<'a, K, V> Run<'a, K, V> {
+    pub fn first_key(&self) -> &'a K {
+        &self.entries[0].0
+    }
+
+    pub fn last_key(&self) -> &'a K {
+        &self.entries[self.entries.len() - 1].0
     }
 
-    pub fn get(&self) -> &V {
-        let (_, ref value) = self.v[self.index];
-        value
+    /// The value of the first entry in the run.
+    pub fn value(&self) -> &'a V {
+        &self.entries[0].1
     }
 
-    pub fn get_mut(&mut self) -> &mut V {
-        let (_, ref mut value) = self.v[self.index];
-        value
+    /// The number of entries in the run, which is never zero.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.entries.len()
     }
 
-    pub fn into_mut(self) -> &'a mut V {
-        let &mut (_, ref mut value) = &mut self.v[self.index];
-        value
+    pub fn entries(&self) -> &'a [(K, V)] {
+        self.entries
     }
 
-    pub fn insert(&mut self, mut value: V) -> V {
-        let &mut (_, ref mut old_value) = &mut self.v[self.index];
-        swap(old_value, &mut value);
-        value
+    pub fn iter(&self) -> Iter<'a, K, V> {
+        Iter { inner: self.entries.iter() }
     }
+}
 
-    pub fn remove(self) -> V {
-        let (_, value) = self.v.remove(self.index);
-        value
+// Compares the keys at the heads of two sorted iterators, treating an
+// exhausted iterator as greater than any key so the other one drains first.
+fn cmp_heads<K: Ord, V, V2>(a: Option<&(&K, &V)>, b: Option<&(&K, &V2)>) -> Option<Ordering> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.0.cmp(b.0)),
+        (Some(_), None) => Some(Ordering::Less),
+        (None, Some(_)) => Some(Ordering::Greater),
+        (None, None) => None,
     }
+}
 
+impl<'a, K: Ord, V> Iterator for Union<'a, K, V> {
+    type Item = (&'a K, &'a V);
 
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        match cmp_heads(self.a.peek(), self.b.peek())? {
+            Ordering::Less => self.a.next(),
+            Ordering::Greater => self.b.next(),
+            Ordering::Equal => {
+                self.b.next();
+                self.a.next()
+            }
+        }
+    }
 }
 
-impl<'a, K, V> Iterator for Iter<'a, K, V> {
+impl<'a, K: Ord, V> Iterator for Intersection<'a, K, V> {
     type Item = (&'a K, &'a V);
 
     fn next(&mut self) -> Option<(&'a K, &'a V)> {
-        match self.inner.next() {
-            Some(&(ref k, ref v)) => Some((k, v)),
-            None => None,
+        loop {
+            match cmp_heads(Some(self.a.peek()?), Some(self.b.peek()?))? {
+                Ordering::Less => {
+                    self.a.next();
+                }
+                Ordering::Greater => {
+                    self.b.next();
+                }
+                Ordering::Equal => {
+                    self.b.next();
+                    return self.a.next();
+                }
+            }
         }
     }
-    fn size_hint(&self) -> (usize, Option<usize>) {
-        self.inner.size_hint()
-    }
 }
 
-impl<'a, K, V> Clone for Iter<'a, K, V> {
-    fn clone(&self) -> Iter<'a, K, V> {
-        Iter { inner: self.inner.clone() }
+impl<'a, K: Ord, V> Iterator for Difference<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        loop {
+            match cmp_heads(Some(self.a.peek()?), self.b.peek())? {
+                Ordering::Less => return self.a.next(),
+                Ordering::Greater => {
+                    self.b.next();
+                }
+                Ordering::Equal => {
+                    self.a.next();
+                    self.b.next();
+                }
+            }
+        }
     }
 }
 
-impl<'a, K, V> DoubleEndedIterator for Iter<'a, K, V> {
-    fn next_back(&mut self) -> Option<(&'a K, &'a V)> {
-        match self.inner.next_back() {
-            Some(&(ref k, ref v)) => Some((k, v)),
-            None => None,
+impl<'a, K: Ord, V> Iterator for SymmetricDifference<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        loop {
+            match cmp_heads(self.a.peek(), self.b.peek())? {
+                Ordering::Less => return self.a.next(),
+                Ordering::Greater => return self.b.next(),
+                Ordering::Equal => {
+                    self.a.next();
+                    self.b.next();
+                }
+            }
         }
     }
 }
 
-impl<'a, K, V> ExactSizeIterator for Iter<'a, K, V> {}
+impl<'a, K: Ord, V, V2> Iterator for JoinInner<'a, K, V, V2> {
+    type Item = (&'a K, &'a V, &'a V2);
 
-impl<'a, K, V> Iterator for IterMut<'a, K, V> {
-    type Item = (&'a K, &'a mut V);
+    fn next(&mut self) -> Option<(&'a K, &'a V, &'a V2)> {
+        loop {
+            match cmp_heads(Some(self.a.peek()?), Some(self.b.peek()?))? {
+                Ordering::Less => {
+                    self.a.next();
+                }
+                Ordering::Greater => {
+                    self.b.next();
+                }
+                Ordering::Equal => {
+                    let (k, v) = self.a.next()?;
+                    let (_, v2) = self.b.next()?;
+                    return Some((k, v, v2));
+                }
+            }
+        }
+    }
+}
 
-    fn next(&mut self) -> Option<(&'a K, &'a mut V)> {
-        match self.inner.next() {
-            Some(&mut (ref k, ref mut v)) => Some((k, v)),
-            None => None,
+impl<'a, K: Ord, V, V2> Iterator for JoinLeft<'a, K, V, V2> {
+    type Item = (&'a K, &'a V, Option<&'a V2>);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V, Option<&'a V2>)> {
+        loop {
+            match cmp_heads(Some(self.a.peek()?), self.b.peek())? {
+                Ordering::Less => return self.a.next().map(|(k, v)| (k, v, None)),
+                Ordering::Greater => {
+                    self.b.next();
+                }
+                Ordering::Equal => {
+                    let (k, v) = self.a.next()?;
+                    return Some((k, v, self.b.next().map(|(_, v2)| v2)));
+                }
+            }
         }
     }
     fn size_hint(&self) -> (usize, Option<usize>) {
-        self.inner.size_hint()
+        self.a.size_hint()
     }
 }
 
-impl<'a, K, V> DoubleEndedIterator for IterMut<'a, K, V> {
-    fn next_back(&mut self) -> Option<(&'a K, &'a mut V)> {
-        match self.inner.next_back() {
-            Some(&mut (ref k, ref mut v)) => Some((k, v)),
-            None => None,
+impl<'a, K: Ord, V, V2> Iterator for JoinOuter<'a, K, V, V2> {
+    type Item = (&'a K, Option<&'a V>, Option<&'a V2>);
+
+    fn next(&mut self) -> Option<(&'a K, Option<&'a V>, Option<&'a V2>)> {
+        match cmp_heads(self.a.peek(), self.b.peek())? {
+            Ordering::Less => self.a.next().map(|(k, v)| (k, Some(v), None)),
+            Ordering::Greater => self.b.next().map(|(k, v2)| (k, None, Some(v2))),
+            Ordering::Equal => {
+                let (k, v) = self.a.next()?;
+                Some((k, Some(v), self.b.next().map(|(_, v2)| v2)))
+            }
         }
     }
 }
 
-impl<'a, K, V> ExactSizeIterator for IterMut<'a, K, V> {}
+impl<'a, K, V> DiffItem<'a, K, V> {
+    pub fn key(&self) -> &'a K {
+        match *self {
+            DiffItem::Added(k, _) | DiffItem::Removed(k, _) | DiffItem::Changed(k, _, _) => k,
+        }
+    }
 
-impl<K, V> Iterator for IntoIter<K, V> {
-    type Item = (K, V);
+    /// The value the key has after the change, or `None` if it was removed.
+    pub fn new_value(&self) -> Option<&'a V> {
+        match *self {
+            DiffItem::Added(_, v) | DiffItem::Changed(_, _, v) => Some(v),
+            DiffItem::Removed(_, _) => None,
+        }
+    }
+}
 
-    fn next(&mut self) -> Option<(K, V)> {
-        self.inner.next()
+impl<'a, K: Ord, V: PartialEq> Iterator for Diff<'a, K, V> {
+    type Item = DiffItem<'a, K, V>;
+
+    fn next(&mut self) -> Option<DiffItem<'a, K, V>> {
+        loop {
+            match cmp_heads(self.a.peek(), self.b.peek())? {
+                Ordering::Less => return self.a.next().map(|(k, v)| DiffItem::Removed(k, v)),
+                Ordering::Greater => return self.b.next().map(|(k, v)| DiffItem::Added(k, v)),
+                Ordering::Equal => {
+                    let (k, old) = self.a.next()?;
+                    let (_, new) = self.b.next()?;
+                    if old != new {
+                        return Some(DiffItem::Changed(k, old, new));
+                    }
+                }
+            }
+        }
     }
-    fn size_hint(&self) -> (usize, Option<usize>) {
-        self.inner.size_hint()
+}
+
+impl<'a, K, V> Clone for Run<'a, K, V> {
+    fn clone(&self) -> Run<'a, K, V> {
+        Run { entries: self.entries }
     }
 }
 
-impl<K, V> DoubleEndedIterator for IntoIter<K, V> {
-    fn next_back(&mut self) -> Option<(K, V)> {
-        self.inner.next_back()
+impl<'a, K: Debug, V: Debug> Debug for Run<'a, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
     }
 }
 
-impl<K, V> ExactSizeIterator for IntoIter<K, V> {}
+/// Marker for iterators that yield `(K, V)` pairs with strictly ascending
+/// keys.
+///
+/// `FlatMap::extend_sorted` and `FlatMap::merge_sorted` take it as a promise
+/// that the input needs no sorting. The iterators of a map implement it
+/// whatever the map's strategy, so the merge still checks the order in one
+/// pass and sorts input from a descending or unsorted map first.
+///
+/// # Safety
+///
+/// Implement this only for iterators that yield strictly ascending keys.
+/// One that yields keys out of order or more than once costs the merge a
+/// sort, but does not break the ordering invariant of the map.
+pub unsafe trait SortedByKey {}
+
+unsafe impl<K, V> SortedByKey for IntoIter<K, V> {}
+unsafe impl<'a, K, V> SortedByKey for Drain<'a, K, V> {}
+unsafe impl<'a, K, V> SortedByKey for DrainRange<'a, K, V> {}
+unsafe impl<'a, K, V, F> SortedByKey for ExtractIf<'a, K, V, F>
+    where F: FnMut(&mut (K, V)) -> bool {}
+unsafe impl<'a, K, V> SortedByKey for Splice<'a, K, V> {}
+unsafe impl<K, V> SortedByKey for btree_map::IntoIter<K, V> {}
 
-impl<K, V> IntoIterator for FlatMap<K, V> {
+impl<K, V, L> IntoIterator for FlatMap<K, V, L> {
     type Item = (K, V);
     type IntoIter = IntoIter<K, V>;
 
@@ -405,7 +3539,7 @@ impl<K, V> IntoIterator for FlatMap<K, V> {
     }
 }
 
-impl<'a, K, V> IntoIterator for &'a FlatMap<K, V> {
+impl<'a, K, V, L> IntoIterator for &'a FlatMap<K, V, L> {
     type Item = (&'a K, &'a V);
     type IntoIter = Iter<'a, K, V>;
 
@@ -414,7 +3548,7 @@ impl<'a, K, V> IntoIterator for &'a FlatMap<K, V> {
     }
 }
 
-impl<'a, K, V> IntoIterator for &'a mut FlatMap<K, V> {
+impl<'a, K, V, L> IntoIterator for &'a mut FlatMap<K, V, L> {
     type Item = (&'a K, &'a mut V);
     type IntoIter = IterMut<'a, K, V>;
 
@@ -473,32 +3607,48 @@ impl<'a, K, V> DoubleEndedIterator for Values<'a, K, V> {
 
 impl<'a, K, V> ExactSizeIterator for Values<'a, K, V> {}
 
-impl<K: Ord, V> FromIterator<(K, V)> for FlatMap<K, V> {
-    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> FlatMap<K, V> {
+// Of entries with equal keys, the first one is kept.
+impl<K, V, L: Arrange<K> + Default> FromIterator<(K, V)> for FlatMap<K, V, L> {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> FlatMap<K, V, L> {
         let mut vec: Vec<_> = iter.into_iter().collect();
-        vec.sort_by(|kv1, kv2| kv1.0.cmp(&kv2.0));
-        vec.dedup_by(|kv1, kv2| kv1.0 == kv2.0);
+        let l = L::default();
+        l.arrange(&mut vec, false);
         Self {
-            v: vec
+            v: vec,
+            policy: Policy::DEFAULT,
+            l,
+            last: None,
         }
     }
 }
 
-impl<K: Ord, V> Extend<(K, V)> for FlatMap<K, V> {
+// Arranges the new entries once and merges them with the map in a single
+// pass, rather than shifting the tail of the map for every entry. As with
+// repeated `insert`, the last value for each key wins.
+impl<K, V, L: Lookup<K, V, K>> Extend<(K, V)> for FlatMap<K, V, L> {
     fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
-        for (k, v) in iter {
-            self.insert(k, v);
+        let mut new: Vec<(K, V)> = iter.into_iter().collect();
+        if new.len() <= 1 {
+            if let Some((k, v)) = new.pop() {
+                self.insert(k, v);
+            }
+            return;
         }
+        self.l.arrange(&mut new, true);
+        self.merge_entries(new, |_, _, new| new);
     }
 }
 
-impl<'a, K: Ord + Copy, V: Copy> Extend<(&'a K, &'a V)> for FlatMap<K, V> {
+impl<'a, K: Copy, V: Copy, L: Lookup<K, V, K>> Extend<(&'a K, &'a V)> for FlatMap<K, V, L> {
     fn extend<I: IntoIterator<Item = (&'a K, &'a V)>>(&mut self, iter: I) {
         self.extend(iter.into_iter().map(|(&key, &value)| (key, value)));
     }
 }
 
-impl<K: Hash, V: Hash> Hash for FlatMap<K, V> {
+// Equal sorted maps hold their entries in the same order whatever their
+// strategies, so they hash the same. Unsorted maps would not, so they do not
+// implement Hash.
+impl<K: Hash, V: Hash, L: Sorted> Hash for FlatMap<K, V, L> {
     fn hash<H: Hasher>(&self, state: &mut H) {
         for elt in self {
             elt.hash(state);
@@ -506,41 +3656,117 @@ impl<K: Hash, V: Hash> Hash for FlatMap<K, V> {
     }
 }
 
-impl<K: Ord, V> Default for FlatMap<K, V> {
-    fn default() -> FlatMap<K, V> {
-        FlatMap::new()
+impl<K, V, L: Default> Default for FlatMap<K, V, L> {
+    fn default() -> FlatMap<K, V, L> {
+        FlatMap::with_lookup(L::default())
+    }
+}
+
+// A `BTreeMap` iterates in key order with distinct keys, so its entries are
+// adopted as they come, without sorting.
+impl<K, V> From<BTreeMap<K, V>> for FlatMap<K, V> {
+    fn from(map: BTreeMap<K, V>) -> FlatMap<K, V> {
+        FlatMap {
+            v: map.into_iter().collect(),
+            policy: Policy::DEFAULT,
+            l: BinarySearch,
+            last: None,
+        }
+    }
+}
+
+impl<K: Ord, V, L> From<FlatMap<K, V, L>> for BTreeMap<K, V> {
+    fn from(map: FlatMap<K, V, L>) -> BTreeMap<K, V> {
+        map.v.into_iter().collect()
+    }
+}
+
+// Unlike `collect`, and like `BTreeMap::from`, the conversions from a vector
+// or an array keep the last of entries with equal keys. The vector's buffer
+// is reused.
+impl<K, V, L: Arrange<K> + Default> From<Vec<(K, V)>> for FlatMap<K, V, L> {
+    fn from(mut v: Vec<(K, V)>) -> FlatMap<K, V, L> {
+        let l = L::default();
+        l.arrange(&mut v, true);
+        FlatMap { v, policy: Policy::DEFAULT, l, last: None }
+    }
+}
+
+impl<K, V, L: Arrange<K> + Default, const N: usize> From<[(K, V); N]> for FlatMap<K, V, L> {
+    fn from(entries: [(K, V); N]) -> FlatMap<K, V, L> {
+        FlatMap::from(Vec::from(entries))
     }
 }
 
-impl<K: Ord, V: Ord> Ord for FlatMap<K, V> {
-    fn cmp(&self, other: &FlatMap<K, V>) -> Ordering {
+impl<K: Ord, V: Ord, L: Sorted> Ord for FlatMap<K, V, L> {
+    fn cmp(&self, other: &FlatMap<K, V, L>) -> Ordering {
         self.iter().cmp(other.iter())
     }
 }
 
-impl<K: PartialEq, V: PartialEq> PartialEq for FlatMap<K, V> {
-    fn eq(&self, other: &FlatMap<K, V>) -> bool {
-        self.len() == other.len() && self.iter().zip(other).all(|(a, b)| a == b)
+// Maps are equal if they hold the same entries, whatever their strategies
+// and, for unsorted maps, whatever order the entries were inserted in. Only
+// maps that are both sorted by `Ord` can be compared position by position.
+impl<K, V, L1, L2> PartialEq<FlatMap<K, V, L2>> for FlatMap<K, V, L1>
+    where K: PartialEq,
+          V: PartialEq,
+          L1: Arrange<K>,
+          L2: Arrange<K>
+{
+    fn eq(&self, other: &FlatMap<K, V, L2>) -> bool {
+        self.len() == other.len() &&
+        same_entries(&self.l, &other.l, &self.v, &other.v, |a, b| a == b)
     }
 }
 
-impl<K: Eq, V: Eq> Eq for FlatMap<K, V> {}
+impl<K: Eq, V: Eq, L: Arrange<K>> Eq for FlatMap<K, V, L> {}
 
-impl<K: PartialOrd, V: PartialOrd> PartialOrd for FlatMap<K, V> {
-    fn partial_cmp(&self, other: &FlatMap<K, V>) -> Option<Ordering> {
+impl<K: Ord, V: PartialOrd, L: Sorted> PartialOrd for FlatMap<K, V, L> {
+    fn partial_cmp(&self, other: &FlatMap<K, V, L>) -> Option<Ordering> {
         self.iter().partial_cmp(other.iter())
     }
 }
 
-impl<K: Debug, V: Debug> Debug for FlatMap<K, V> {
+// Checks that every entry of `a` has a matching entry in `b`, for two
+// slices of entries with distinct keys and the same length, kept by `l1` and
+// `l2`. Matching entries at the same positions settle it, and are the only
+// way to match when both slices are sorted by `Ord`. Otherwise each entry is
+// looked up in a side that is sorted, with that side's comparator, and only
+// two unsorted slices need every pair tried.
+pub(crate) fn same_entries<K, V, V2, L1, L2, F>(l1: &L1, l2: &L2, a: &[(K, V)], b: &[(K, V2)],
+                                                same: F)
+    -> bool
+    where L1: Arrange<K>,
+          L2: Arrange<K>,
+          F: Fn(&(K, V), &(K, V2)) -> bool
+{
+    if a.iter().zip(b).all(|(x, y)| same(x, y)) {
+        true
+    } else if L1::BY_ORD && L2::BY_ORD {
+        false
+    } else if L1::SORTED {
+        b.iter().all(|y| {
+            a.binary_search_by(|x| l1.compare_keys(&x.0, &y.0))
+                .is_ok_and(|i| same(&a[i], y))
+        })
+    } else if L2::SORTED {
+        a.iter().all(|x| {
+            b.binary_search_by(|y| l2.compare_keys(&y.0, &x.0))
+                .is_ok_and(|i| same(x, &b[i]))
+        })
+    } else {
+        a.iter().all(|x| b.iter().any(|y| same(x, y)))
+    }
+}
+
+impl<K: Debug, V: Debug, L> Debug for FlatMap<K, V, L> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_map().entries(self.iter()).finish()
     }
 }
 
-impl<'a, K: Ord, Q: ?Sized, V> Index<&'a Q> for FlatMap<K, V>
-    where K: Borrow<Q>,
-          Q: Ord
+impl<K, Q: ?Sized, V, L> Index<&Q> for FlatMap<K, V, L>
+    where L: Lookup<K, V, Q>
 {
     type Output = V;
 
@@ -549,6 +3775,17 @@ impl<'a, K: Ord, Q: ?Sized, V> Index<&'a Q> for FlatMap<K, V>
     }
 }
 
+// Like `Index`, but unlike `BTreeMap`, the map can be assigned through with
+// `map[&k] = v`. Only existing entries can be assigned; use `insert` to add
+// one.
+impl<K, Q: ?Sized, V, L> IndexMut<&Q> for FlatMap<K, V, L>
+    where L: Lookup<K, V, Q>
+{
+    fn index_mut(&mut self, key: &Q) -> &mut V {
+        self.get_mut(key).expect("no entry found for key")
+    }
+}
+
 impl<'a, K, V> Iterator for ValuesMut<'a, K, V> {
     type Item = &'a mut V;
 
@@ -573,9 +3810,126 @@ impl<'a, K, V> ExactSizeIterator for ValuesMut<'a, K, V> {
     }
 }
 
+// all of the iterators wrap slice or vec iterators, which are fused
+impl<'a, K, V> FusedIterator for Iter<'a, K, V> {}
+impl<'a, K, V> FusedIterator for IterMut<'a, K, V> {}
+impl<K, V> FusedIterator for IntoIter<K, V> {}
+impl<K, V> FusedIterator for IntoKeys<K, V> {}
+impl<K, V> FusedIterator for IntoValues<K, V> {}
+impl<'a, K, V> FusedIterator for Keys<'a, K, V> {}
+impl<'a, K, V> FusedIterator for Values<'a, K, V> {}
+impl<'a, K, V> FusedIterator for ValuesMut<'a, K, V> {}
+impl<'a, K, V> FusedIterator for Range<'a, K, V> {}
+impl<'a, K, V> FusedIterator for RangeMut<'a, K, V> {}
+impl<'a, K, V> FusedIterator for Drain<'a, K, V> {}
+impl<'a, K, V> FusedIterator for DrainRange<'a, K, V> {}
+impl<'a, K, V> FusedIterator for Splice<'a, K, V> {}
+impl<'a, K: Ord, V> FusedIterator for Union<'a, K, V> {}
+impl<'a, K: Ord, V> FusedIterator for Intersection<'a, K, V> {}
+impl<'a, K: Ord, V> FusedIterator for Difference<'a, K, V> {}
+impl<'a, K: Ord, V> FusedIterator for SymmetricDifference<'a, K, V> {}
+impl<'a, K: Ord, V, V2> FusedIterator for JoinInner<'a, K, V, V2> {}
+impl<'a, K: Ord, V, V2> FusedIterator for JoinLeft<'a, K, V, V2> {}
+impl<'a, K: Ord, V, V2> FusedIterator for JoinOuter<'a, K, V, V2> {}
+impl<'a, K: Ord, V: PartialEq> FusedIterator for Diff<'a, K, V> {}
+
+// all of the iterators wrap slice or vec iterators, so their size hints are
+// exact.
+#[cfg(feature = "unstable")]
+mod trusted_len_impls {
+    use std::iter::TrustedLen;
+    use super::{Drain, DrainRange, IntoIter, IntoKeys, IntoValues, Iter, IterMut, Keys, Range,
+                RangeMut, Values, ValuesMut};
+
+    unsafe impl<'a, K, V> TrustedLen for Iter<'a, K, V> {}
+    unsafe impl<'a, K, V> TrustedLen for IterMut<'a, K, V> {}
+    unsafe impl<K, V> TrustedLen for IntoIter<K, V> {}
+    unsafe impl<'a, K, V> TrustedLen for Drain<'a, K, V> {}
+    unsafe impl<'a, K, V> TrustedLen for DrainRange<'a, K, V> {}
+    unsafe impl<K, V> TrustedLen for IntoKeys<K, V> {}
+    unsafe impl<K, V> TrustedLen for IntoValues<K, V> {}
+    unsafe impl<'a, K, V> TrustedLen for Range<'a, K, V> {}
+    unsafe impl<'a, K, V> TrustedLen for RangeMut<'a, K, V> {}
+    unsafe impl<'a, K, V> TrustedLen for Keys<'a, K, V> {}
+    unsafe impl<'a, K, V> TrustedLen for Values<'a, K, V> {}
+    unsafe impl<'a, K, V> TrustedLen for ValuesMut<'a, K, V> {}
+}
+
+// a little-endian `u64` entry count followed by the entries in key order,
+// each written and read back by the caller's function
+#[cfg(feature = "std")]
+mod io_impl {
+    use std::cmp;
+    use std::convert::TryFrom;
+    use std::io::{self, Read, Write};
+    use std::vec::Vec;
+    use lookup::{Arrange, Ordered};
+    use super::FlatMap;
+
+    impl<K, V, L> FlatMap<K, V, L> {
+        /// Writes the map to `w` as its length followed by the entries in
+        /// key order, with `f` writing each entry.
+        ///
+        /// ```
+        /// use std::io::{Read, Write};
+        /// use flat_map::FlatMap;
+        ///
+        /// let map: FlatMap<u32, u16> = vec![(1, 10), (2, 20)].into_iter().collect();
+        /// let mut bytes = Vec::new();
+        /// map.write_to(&mut bytes, |w, k, v| {
+        ///     w.write_all(&k.to_le_bytes())?;
+        ///     w.write_all(&v.to_le_bytes())
+        /// }).unwrap();
+        /// assert_eq!(bytes.len(), 8 + 2 * 6);
+        ///
+        /// let back = FlatMap::<u32, u16>::read_from(&bytes[..], |r| {
+        ///     let (mut k, mut v) = ([0; 4], [0; 2]);
+        ///     r.read_exact(&mut k)?;
+        ///     r.read_exact(&mut v)?;
+        ///     Ok((u32::from_le_bytes(k), u16::from_le_bytes(v)))
+        /// }).unwrap();
+        /// assert_eq!(back, map);
+        /// ```
+        pub fn write_to<W, F>(&self, mut w: W, mut f: F) -> io::Result<()>
+            where W: Write,
+                  F: FnMut(&mut W, &K, &V) -> io::Result<()>
+        {
+            w.write_all(&(self.len() as u64).to_le_bytes())?;
+            for (k, v) in self.iter() {
+                f(&mut w, k, v)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl<K, V, L: Arrange<K> + Default> FlatMap<K, V, L> {
+        /// Reads a map written by `write_to`, with `f` reading each entry.
+        ///
+        /// Fails with `InvalidData` if the keys are not strictly sorted in the
+        /// strategy's order, and with whatever error `f` returns, such as
+        /// `UnexpectedEof` for truncated input.
+        pub fn read_from<R, F>(mut r: R, mut f: F) -> io::Result<Self>
+            where R: Read,
+                  F: FnMut(&mut R) -> io::Result<(K, V)>,
+                  L: Ordered
+        {
+            let mut len = [0; 8];
+            r.read_exact(&mut len)?;
+            let len = usize::try_from(u64::from_le_bytes(len))
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            // don't let a corrupt length allocate before the entries are read
+            let mut v = Vec::with_capacity(cmp::min(len, 4096));
+            for _ in 0..len {
+                v.push(f(&mut r)?);
+            }
+            FlatMap::from_sorted_vec(v)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+        }
+    }
+}
 
 #[cfg(feature = "serde1")]
-mod serde_impl
+pub(crate) mod serde_impl
 {
     // the serde serialization/deserialization is manually handled to
     // serialize the FlatMap as a classic map
@@ -584,35 +3938,58 @@ mod serde_impl
     // { "k1": "v1", "k2": "v2" }
     // and not
     // {"v": [["k1", "v1"],["k2", "v2"]]}
+    //
+    // binary formats get a length-prefixed sequence of (key, value) pairs
+    // instead, as `serde_seq` writes, which skips any map framing
 
 
     use std::marker::PhantomData;
-    use serde::de::{Deserialize, Deserializer, Visitor, MapAccess};
+    use std::vec::Vec;
+    use serde::de::{Deserialize, Deserializer, Visitor, MapAccess, SeqAccess};
     use serde::{Serialize, Serializer};
     use serde::ser::SerializeMap;
-    use super::FlatMap;
+    use lookup::Arrange;
+    use super::{FlatMap, Policy};
     use std::fmt;
 
-    impl<K, V> Serialize for FlatMap<K, V>
+    impl<K, V, L> Serialize for FlatMap<K, V, L>
     where K: Ord + Serialize, V: Serialize {
         fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
         where
             S: Serializer,
         {
-            let mut map = serializer.serialize_map(Some(self.len()))?;
-            for (k, v) in self {
-                map.serialize_entry(k, v)?;
-            }
-            map.end()
+            serialize_entries(self.iter(), serializer)
+        }
+    }
+
+    // Writes entries as `FlatMap` does, for the other map types.
+    pub(crate) fn serialize_entries<'a, K, V, I, S>(entries: I, serializer: S)
+        -> Result<S::Ok, S::Error>
+        where K: Serialize + 'a,
+              V: Serialize + 'a,
+              I: ExactSizeIterator<Item = (&'a K, &'a V)>,
+              S: Serializer
+    {
+        if !serializer.is_human_readable() {
+            return serializer.collect_seq(entries);
+        }
+        let mut map = serializer.serialize_map(Some(entries.len()))?;
+        for (k, v) in entries {
+            map.serialize_entry(k, v)?;
         }
+        map.end()
     }
 
 
-    struct FlatMapVisitor<K, V> {
-        marker: PhantomData<fn() -> FlatMap<K, V>>
+    // the map the visitor builds, behind `fn` so that it is `Send` and
+    // `Sync` whatever the entries
+    type Builds<K, V, L> = fn() -> FlatMap<K, V, L>;
+
+    struct FlatMapVisitor<K, V, L> {
+        marker: PhantomData<Builds<K, V, L>>
     }
 
-    impl<K, V> FlatMapVisitor<K, V> {
+    impl<K, V, L> FlatMapVisitor<K, V, L> {
         fn new() -> Self {
             FlatMapVisitor {
                 marker: PhantomData
@@ -620,11 +3997,21 @@ mod serde_impl
         }
     }
 
-    impl<'de, K: Ord, V> Visitor<'de> for FlatMapVisitor<K, V>
+    // Both visitors collect the entries first and arrange them once, which is
+    // linear for input that is already in key order. Later duplicates win, as
+    // if the entries had been inserted one by one.
+    fn from_entries<K, V, L: Arrange<K> + Default>(mut v: Vec<(K, V)>) -> FlatMap<K, V, L> {
+        let l = L::default();
+        l.arrange(&mut v, true);
+        FlatMap { v, policy: Policy::DEFAULT, l, last: None }
+    }
+
+    impl<'de, K, V, L> Visitor<'de> for FlatMapVisitor<K, V, L>
         where K: Deserialize<'de>,
-            V: Deserialize<'de>
+            V: Deserialize<'de>,
+            L: Arrange<K> + Default
     {
-        type Value = FlatMap<K, V>;
+        type Value = FlatMap<K, V, L>;
 
         fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
             formatter.write_str("a flat_map")
@@ -633,22 +4020,329 @@ mod serde_impl
         fn visit_map<M>(self, mut access: M) -> Result<Self::Value, M::Error>
             where M: MapAccess<'de>
         {
-            let mut map = FlatMap::with_capacity(access.size_hint().unwrap_or(0));
-            while let Some((key, value)) = access.next_entry()? {
-                map.insert(key, value);
+            let mut v = Vec::with_capacity(access.size_hint().unwrap_or(0));
+            while let Some(entry) = access.next_entry()? {
+                v.push(entry);
+            }
+            Ok(from_entries(v))
+        }
+
+        // accepts maps written by other tools as a sequence of pairs
+        fn visit_seq<A>(self, mut access: A) -> Result<Self::Value, A::Error>
+            where A: SeqAccess<'de>
+        {
+            let mut v = Vec::with_capacity(access.size_hint().unwrap_or(0));
+            while let Some(entry) = access.next_element()? {
+                v.push(entry);
             }
-            Ok(map)
+            Ok(from_entries(v))
         }
     }
 
-    impl<'de, K: Ord, V> Deserialize<'de> for FlatMap<K, V>
+    impl<'de, K, V, L> Deserialize<'de> for FlatMap<K, V, L>
         where K: Deserialize<'de>,
-            V: Deserialize<'de>
+            V: Deserialize<'de>,
+            L: Arrange<K> + Default
     {
         fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
             where D: Deserializer<'de>
         {
-            deserializer.deserialize_map(FlatMapVisitor::new())
+            // human-readable formats are self-describing, so let the input
+            // decide between a map and a sequence of pairs; binary formats
+            // such as postcard need to be told what to expect, which is the
+            // sequence that `serialize` writes for them
+            if deserializer.is_human_readable() {
+                deserializer.deserialize_any(FlatMapVisitor::new())
+            } else {
+                deserializer.deserialize_seq(FlatMapVisitor::new())
+            }
+        }
+
+        // replaces the entries of an existing map but keeps its policies, so
+        // `Exact` maps stay exactly sized
+        fn deserialize_in_place<D>(deserializer: D, place: &mut Self) -> Result<(), D::Error>
+            where D: Deserializer<'de>
+        {
+            let map = Self::deserialize(deserializer)?;
+            let capacity = place.v.capacity();
+            place.v.clear();
+            place.v.extend(map.v);
+            place.l = map.l;
+            place.last = None;
+            place.policy.grown(&mut place.v, capacity);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "rayon")]
+pub use self::rayon_impl::{IntoParIter, ParIter, ParIterMut, ParKeys, ParValues, ParValuesMut};
+
+#[cfg(feature = "rayon")]
+mod rayon_impl
+{
+    // the entries are a single contiguous slice, so the parallel iterators
+    // are rayon's own slice and vec iterators with the entries reshaped,
+    // and they split as evenly as the slice does
+
+    use std::vec::Vec;
+    use rayon::iter::plumbing::{Consumer, ProducerCallback, UnindexedConsumer};
+    use rayon::iter::{FromParallelIterator, IndexedParallelIterator, IntoParallelIterator,
+                      IntoParallelRefIterator, IntoParallelRefMutIterator, ParallelExtend,
+                      ParallelIterator};
+    use rayon::slice::ParallelSliceMut;
+    use rayon::{iter, slice, vec};
+    use lookup::Arrange;
+    use super::{FlatMap, Policy};
+
+    // rayon's slice iterators with each entry mapped to `T`
+    type Entries<'a, K, V, T> = iter::Map<slice::Iter<'a, (K, V)>, fn(&'a (K, V)) -> T>;
+    type EntriesMut<'a, K, V, T> = iter::Map<slice::IterMut<'a, (K, V)>, fn(&'a mut (K, V)) -> T>;
+
+    /// A parallel iterator over the entries of a `FlatMap`, by value.
+    pub struct IntoParIter<K, V> {
+        inner: vec::IntoIter<(K, V)>,
+    }
+
+    /// A parallel iterator over the entries of a `FlatMap`.
+    pub struct ParIter<'a, K: 'a, V: 'a> {
+        inner: Entries<'a, K, V, (&'a K, &'a V)>,
+    }
+
+    /// A parallel iterator over the entries of a `FlatMap`, with mutable
+    /// references to the values.
+    pub struct ParIterMut<'a, K: 'a, V: 'a> {
+        inner: EntriesMut<'a, K, V, (&'a K, &'a mut V)>,
+    }
+
+    /// A parallel iterator over the keys of a `FlatMap`.
+    pub struct ParKeys<'a, K: 'a, V: 'a> {
+        inner: Entries<'a, K, V, &'a K>,
+    }
+
+    /// A parallel iterator over the values of a `FlatMap`.
+    pub struct ParValues<'a, K: 'a, V: 'a> {
+        inner: Entries<'a, K, V, &'a V>,
+    }
+
+    /// A parallel iterator over mutable references to the values of a
+    /// `FlatMap`.
+    pub struct ParValuesMut<'a, K: 'a, V: 'a> {
+        inner: EntriesMut<'a, K, V, &'a mut V>,
+    }
+
+    // every iterator forwards to its inner rayon iterator
+    macro_rules! indexed_parallel_iterator {
+        ($name:ident<$($lt:lifetime,)* K, V>, $item:ty, $($bounds:tt)*) => {
+            impl<$($lt,)* K, V> ParallelIterator for $name<$($lt,)* K, V>
+                where $($bounds)*
+            {
+                type Item = $item;
+
+                fn drive_unindexed<C>(self, consumer: C) -> C::Result
+                    where C: UnindexedConsumer<Self::Item>
+                {
+                    self.inner.drive_unindexed(consumer)
+                }
+
+                fn opt_len(&self) -> Option<usize> {
+                    Some(self.inner.len())
+                }
+            }
+
+            impl<$($lt,)* K, V> IndexedParallelIterator for $name<$($lt,)* K, V>
+                where $($bounds)*
+            {
+                fn drive<C>(self, consumer: C) -> C::Result
+                    where C: Consumer<Self::Item>
+                {
+                    self.inner.drive(consumer)
+                }
+
+                fn len(&self) -> usize {
+                    self.inner.len()
+                }
+
+                fn with_producer<CB>(self, callback: CB) -> CB::Output
+                    where CB: ProducerCallback<Self::Item>
+                {
+                    self.inner.with_producer(callback)
+                }
+            }
+        }
+    }
+
+    indexed_parallel_iterator!(IntoParIter<K, V>, (K, V), K: Send, V: Send);
+    indexed_parallel_iterator!(ParIter<'a, K, V>, (&'a K, &'a V), K: Sync, V: Sync);
+    indexed_parallel_iterator!(ParIterMut<'a, K, V>, (&'a K, &'a mut V), K: Send + Sync, V: Send);
+    indexed_parallel_iterator!(ParKeys<'a, K, V>, &'a K, K: Sync, V: Sync);
+    indexed_parallel_iterator!(ParValues<'a, K, V>, &'a V, K: Sync, V: Sync);
+    indexed_parallel_iterator!(ParValuesMut<'a, K, V>, &'a mut V, K: Send, V: Send);
+
+    impl<K: Send, V: Send, L> IntoParallelIterator for FlatMap<K, V, L> {
+        type Item = (K, V);
+        type Iter = IntoParIter<K, V>;
+
+        fn into_par_iter(self) -> Self::Iter {
+            IntoParIter { inner: self.v.into_par_iter() }
+        }
+    }
+
+    impl<'a, K: Sync, V: Sync, L> IntoParallelIterator for &'a FlatMap<K, V, L> {
+        type Item = (&'a K, &'a V);
+        type Iter = ParIter<'a, K, V>;
+
+        fn into_par_iter(self) -> Self::Iter {
+            self.par_iter()
+        }
+    }
+
+    impl<'a, K: Send + Sync, V: Send, L> IntoParallelIterator for &'a mut FlatMap<K, V, L> {
+        type Item = (&'a K, &'a mut V);
+        type Iter = ParIterMut<'a, K, V>;
+
+        fn into_par_iter(self) -> Self::Iter {
+            self.par_iter_mut()
+        }
+    }
+
+    impl<K, V, L> FlatMap<K, V, L> {
+        /// Returns a parallel iterator over the entries, in key order.
+        ///
+        /// Requires the `rayon` feature.
+        pub fn par_iter(&self) -> ParIter<'_, K, V>
+            where K: Sync, V: Sync
+        {
+            fn split<K, V>((k, v): &(K, V)) -> (&K, &V) {
+                (k, v)
+            }
+            let split: fn(&(K, V)) -> (&K, &V) = split;
+            ParIter { inner: self.v.par_iter().map(split) }
+        }
+
+        /// Returns a parallel iterator over the entries, in key order, with
+        /// mutable references to the values.
+        ///
+        /// Requires the `rayon` feature.
+        pub fn par_iter_mut(&mut self) -> ParIterMut<'_, K, V>
+            where K: Send + Sync, V: Send
+        {
+            fn split<K, V>((k, v): &mut (K, V)) -> (&K, &mut V) {
+                (k, v)
+            }
+            let split: fn(&mut (K, V)) -> (&K, &mut V) = split;
+            ParIterMut { inner: self.v.par_iter_mut().map(split) }
+        }
+
+        /// Returns a parallel iterator over the keys, in order.
+        ///
+        /// Requires the `rayon` feature.
+        pub fn par_keys(&self) -> ParKeys<'_, K, V>
+            where K: Sync, V: Sync
+        {
+            fn first<K, V>((k, _): &(K, V)) -> &K {
+                k
+            }
+            let first: fn(&(K, V)) -> &K = first;
+            ParKeys { inner: self.v.par_iter().map(first) }
+        }
+
+        /// Returns a parallel iterator over the values, in key order.
+        ///
+        /// Requires the `rayon` feature.
+        pub fn par_values(&self) -> ParValues<'_, K, V>
+            where K: Sync, V: Sync
+        {
+            fn second<K, V>((_, v): &(K, V)) -> &V {
+                v
+            }
+            let second: fn(&(K, V)) -> &V = second;
+            ParValues { inner: self.v.par_iter().map(second) }
+        }
+
+        /// Returns a parallel iterator over mutable references to the values,
+        /// in key order.
+        ///
+        /// Requires the `rayon` feature.
+        pub fn par_values_mut(&mut self) -> ParValuesMut<'_, K, V>
+            where K: Send, V: Send
+        {
+            fn second<K, V>((_, v): &mut (K, V)) -> &mut V {
+                v
+            }
+            let second: fn(&mut (K, V)) -> &mut V = second;
+            ParValuesMut { inner: self.v.par_iter_mut().map(second) }
+        }
+    }
+
+    // Sorts in parallel, in the strategy's order, and keeps the last of any
+    // entries with equal keys, like `Extend`.
+    impl<K, V, L> FromParallelIterator<(K, V)> for FlatMap<K, V, L>
+        where K: Send,
+              V: Send,
+              L: Arrange<K> + Default + Sync
+    {
+        fn from_par_iter<I>(iter: I) -> Self
+            where I: IntoParallelIterator<Item = (K, V)>
+        {
+            let mut v: Vec<(K, V)> = iter.into_par_iter().collect();
+            let l = L::default();
+            // the parallel sort is stable too, so arranging only has to dedup
+            if L::SORTED {
+                v.par_sort_by(|kv1, kv2| l.compare_keys(&kv1.0, &kv2.0));
+            }
+            l.arrange(&mut v, true);
+            FlatMap { v, policy: Policy::DEFAULT, l, last: None }
+        }
+    }
+
+    // Collects and sorts the new entries in parallel, then merges them in
+    // one sequential pass. As with `Extend`, the last value for a key wins.
+    impl<K, V, L> ParallelExtend<(K, V)> for FlatMap<K, V, L>
+        where K: Send,
+              V: Send,
+              L: Arrange<K> + Sync
+    {
+        fn par_extend<I>(&mut self, iter: I)
+            where I: IntoParallelIterator<Item = (K, V)>
+        {
+            let mut batch: Vec<(K, V)> = iter.into_par_iter().collect();
+            if L::SORTED {
+                let l = &self.l;
+                batch.par_sort_by(|kv1, kv2| l.compare_keys(&kv1.0, &kv2.0));
+            }
+            self.l.arrange(&mut batch, true);
+            self.merge_entries(batch, |_, _, new| new);
+            self.last = None;
+        }
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+mod arbitrary_impl
+{
+    // generated maps go through `FromIterator`, so they are always sorted and
+    // free of duplicate keys, however the fuzzer's bytes turn out
+
+    use arbitrary::{Arbitrary, Result, Unstructured};
+    use lookup::Arrange;
+    use super::FlatMap;
+
+    impl<'a, K, V, L> Arbitrary<'a> for FlatMap<K, V, L>
+        where K: Arbitrary<'a>,
+              V: Arbitrary<'a>,
+              L: Arrange<K> + Default
+    {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            u.arbitrary_iter()?.collect()
+        }
+
+        fn arbitrary_take_rest(u: Unstructured<'a>) -> Result<Self> {
+            u.arbitrary_take_rest_iter()?.collect()
+        }
+
+        fn size_hint(_depth: usize) -> (usize, Option<usize>) {
+            (0, None)
         }
     }
 }
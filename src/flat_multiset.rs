@@ -0,0 +1,331 @@
+use std::borrow::Borrow;
+use std::fmt;
+use std::fmt::Debug;
+use std::iter::{FromIterator, FusedIterator};
+use flat_map::{self, FlatMap};
+use lookup::{Arrange, BinarySearch, Lookup};
+
+/// A multiset stored as a vector of distinct values with their counts,
+/// sorted by value.
+///
+/// Each value is stored once however many times it was inserted, so the set
+/// suits histograms over small domains. `L` is the strategy used to find
+/// values; see the `lookup` module.
+///
+/// ```
+/// use flat_map::FlatMultiSet;
+///
+/// let mut words: FlatMultiSet<&str> = "a b a c a".split(' ').collect();
+/// assert_eq!(words.count(&"a"), 3);
+/// assert!(words.remove_one(&"a"));
+/// assert_eq!(words.counts().collect::<Vec<_>>(), [(&"a", 2), (&"b", 1), (&"c", 1)]);
+/// assert_eq!(words.iter().collect::<Vec<_>>(), [&"a", &"a", &"b", &"c"]);
+/// ```
+pub struct FlatMultiSet<T, L = BinarySearch> {
+    map: FlatMap<T, usize, L>,
+    // the sum of the counts
+    len: usize,
+}
+
+/// Every value of a multiset as many times as it occurs, in the set's
+/// order.
+pub struct Iter<'a, T: 'a> {
+    inner: flat_map::Iter<'a, T, usize>,
+    current: Option<(&'a T, usize)>,
+    remaining: usize,
+}
+
+/// The distinct values of a multiset with their counts, in the set's order.
+pub struct Counts<'a, T: 'a> {
+    inner: flat_map::Iter<'a, T, usize>,
+}
+
+impl<T> FlatMultiSet<T> {
+    /// Creates an empty multiset without allocating.
+    pub const fn new() -> FlatMultiSet<T> {
+        FlatMultiSet { map: FlatMap::new(), len: 0 }
+    }
+}
+
+impl<T, L> FlatMultiSet<T, L> {
+    /// Creates an empty multiset that finds values using `l`.
+    pub const fn with_lookup(l: L) -> FlatMultiSet<T, L> {
+        FlatMultiSet { map: FlatMap::with_lookup(l), len: 0 }
+    }
+
+    /// The number of values in the multiset, counting repeats.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// The number of distinct values in the multiset.
+    pub fn distinct_len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { inner: self.map.iter(), current: None, remaining: self.len }
+    }
+
+    pub fn counts(&self) -> Counts<'_, T> {
+        Counts { inner: self.map.iter() }
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.len = 0;
+    }
+
+    /// Adds one occurrence of `value`, returning its new count. An equal
+    /// value already in the set is kept and `value` is dropped.
+    pub fn insert(&mut self, value: T) -> usize
+        where L: Lookup<T, usize, T>
+    {
+        self.insert_many(value, 1)
+    }
+
+    /// Adds `n` occurrences of `value`, returning its new count. Adding
+    /// none of a value that is not in the set leaves the set unchanged.
+    pub fn insert_many(&mut self, value: T, n: usize) -> usize
+        where L: Lookup<T, usize, T>
+    {
+        if n == 0 {
+            return self.count(&value);
+        }
+        let count = self.map.entry(value).or_insert(0);
+        *count += n;
+        self.len += n;
+        *count
+    }
+
+    /// Removes one occurrence of `q`, returning true if it was present.
+    pub fn remove_one<Q>(&mut self, q: &Q) -> bool
+        where T: Borrow<Q>,
+              Q: ?Sized,
+              L: Lookup<T, usize, Q>
+    {
+        match self.map.get_mut(q) {
+            None => false,
+            Some(count) if *count > 1 => {
+                *count -= 1;
+                self.len -= 1;
+                true
+            }
+            Some(_) => {
+                self.map.remove(q);
+                self.len -= 1;
+                true
+            }
+        }
+    }
+
+    /// Removes every occurrence of `q`, returning how many there were.
+    pub fn remove_all<Q>(&mut self, q: &Q) -> usize
+        where T: Borrow<Q>,
+              Q: ?Sized,
+              L: Lookup<T, usize, Q>
+    {
+        let count = self.map.remove(q).unwrap_or(0);
+        self.len -= count;
+        count
+    }
+
+    /// The number of occurrences of `q`, zero if it is not in the set.
+    pub fn count<Q>(&self, q: &Q) -> usize
+        where T: Borrow<Q>,
+              Q: ?Sized,
+              L: Lookup<T, usize, Q>
+    {
+        self.map.get(q).cloned().unwrap_or(0)
+    }
+
+    pub fn contains<Q>(&self, q: &Q) -> bool
+        where T: Borrow<Q>,
+              Q: ?Sized,
+              L: Lookup<T, usize, Q>
+    {
+        self.map.contains_key(q)
+    }
+
+    /// The distinct values with their counts, in the set's order.
+    pub fn into_counts(self) -> FlatMap<T, usize, L> {
+        self.map
+    }
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        match self.current {
+            Some((t, n)) if n > 0 => {
+                self.current = Some((t, n - 1));
+                self.remaining -= 1;
+                Some(t)
+            }
+            _ => {
+                let (t, &n) = self.inner.next()?;
+                self.current = Some((t, n));
+                self.next()
+            }
+        }
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, T> Clone for Iter<'a, T> {
+    fn clone(&self) -> Iter<'a, T> {
+        Iter { inner: self.inner.clone(), current: self.current, remaining: self.remaining }
+    }
+}
+
+impl<'a, T> ExactSizeIterator for Iter<'a, T> {}
+impl<'a, T> FusedIterator for Iter<'a, T> {}
+
+impl<'a, T> Iterator for Counts<'a, T> {
+    type Item = (&'a T, usize);
+
+    fn next(&mut self) -> Option<(&'a T, usize)> {
+        self.inner.next().map(|(t, &n)| (t, n))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, T> Clone for Counts<'a, T> {
+    fn clone(&self) -> Counts<'a, T> {
+        Counts { inner: self.inner.clone() }
+    }
+}
+
+impl<'a, T> DoubleEndedIterator for Counts<'a, T> {
+    fn next_back(&mut self) -> Option<(&'a T, usize)> {
+        self.inner.next_back().map(|(t, &n)| (t, n))
+    }
+}
+
+impl<'a, T> ExactSizeIterator for Counts<'a, T> {}
+impl<'a, T> FusedIterator for Counts<'a, T> {}
+
+impl<'a, T, L> IntoIterator for &'a FlatMultiSet<T, L> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T, L: Lookup<T, usize, T> + Default> FromIterator<T> for FlatMultiSet<T, L> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> FlatMultiSet<T, L> {
+        let mut set = FlatMultiSet::default();
+        set.extend(iter);
+        set
+    }
+}
+
+impl<T, L: Lookup<T, usize, T>> Extend<T> for FlatMultiSet<T, L> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for t in iter {
+            self.insert(t);
+        }
+    }
+}
+
+impl<T: Clone, L: Clone> Clone for FlatMultiSet<T, L> {
+    fn clone(&self) -> FlatMultiSet<T, L> {
+        FlatMultiSet { map: self.map.clone(), len: self.len }
+    }
+}
+
+impl<T, L: Default> Default for FlatMultiSet<T, L> {
+    fn default() -> FlatMultiSet<T, L> {
+        FlatMultiSet { map: FlatMap::default(), len: 0 }
+    }
+}
+
+impl<T, L1, L2> PartialEq<FlatMultiSet<T, L2>> for FlatMultiSet<T, L1>
+    where T: PartialEq,
+          L1: Arrange<T>,
+          L2: Arrange<T>
+{
+    fn eq(&self, other: &FlatMultiSet<T, L2>) -> bool {
+        self.len == other.len && self.map == other.map
+    }
+}
+
+impl<T: Eq, L: Arrange<T>> Eq for FlatMultiSet<T, L> {}
+
+// shown as a map from values to counts
+impl<T: Debug, L> Debug for FlatMultiSet<T, L> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.map.fmt(f)
+    }
+}
+
+#[cfg(feature = "serde1")]
+mod serde_impl {
+    // serialized as a map from each distinct value to its count
+    use serde::de::{Deserialize, Deserializer, Error};
+    use serde::{Serialize, Serializer};
+    use flat_map::serde_impl::serialize_entries;
+    use flat_map::FlatMap;
+    use lookup::Arrange;
+    use super::FlatMultiSet;
+
+    impl<T: Serialize, L> Serialize for FlatMultiSet<T, L> {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where S: Serializer
+        {
+            serialize_entries(self.map.iter(), serializer)
+        }
+    }
+
+    // values with a count of zero are dropped
+    impl<'de, T, L> Deserialize<'de> for FlatMultiSet<T, L>
+        where T: Deserialize<'de>,
+              L: Arrange<T> + Default
+    {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where D: Deserializer<'de>
+        {
+            let mut map = FlatMap::<T, usize, L>::deserialize(deserializer)?;
+            map.retain(|_, n| *n > 0);
+            let len = map.values().try_fold(0usize, |len, &n| len.checked_add(n))
+                .ok_or_else(|| D::Error::custom("multiset counts overflow usize"))?;
+            Ok(FlatMultiSet { map, len })
+        }
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+mod arbitrary_impl {
+    // repeated values are counted, so every count is at least one
+    use arbitrary::{Arbitrary, Result, Unstructured};
+    use lookup::Lookup;
+    use super::FlatMultiSet;
+
+    impl<'a, T, L> Arbitrary<'a> for FlatMultiSet<T, L>
+        where T: Arbitrary<'a>,
+              L: Lookup<T, usize, T> + Default
+    {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            u.arbitrary_iter()?.collect()
+        }
+
+        fn arbitrary_take_rest(u: Unstructured<'a>) -> Result<Self> {
+            u.arbitrary_take_rest_iter()?.collect()
+        }
+
+        fn size_hint(_depth: usize) -> (usize, Option<usize>) {
+            (0, None)
+        }
+    }
+}
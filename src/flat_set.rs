@@ -0,0 +1,486 @@
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::fmt;
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::iter::{FromIterator, FusedIterator, Peekable};
+use flat_map::{self, FlatMap, Keys};
+use flat_map::Entry::{Occupied, Vacant};
+use lookup::{Arrange, BinarySearch, Lookup, Sorted};
+
+/// A set stored as a vector of values sorted in ascending order.
+///
+/// The set is a `FlatMap` with `()` values, like `BTreeSet` is a `BTreeMap`.
+/// `(T, ())` has the same layout as `T`, so this costs nothing over a plain
+/// sorted `Vec<T>`. `L` is the strategy used to find values; see the
+/// `lookup` module.
+pub struct FlatSet<T, L = BinarySearch> {
+    map: FlatMap<T, (), L>,
+}
+
+pub struct Iter<'a, T: 'a> {
+    inner: Keys<'a, T, ()>,
+}
+
+pub struct IntoIter<T> {
+    inner: flat_map::IntoIter<T, ()>,
+}
+
+/// Values in either set, in ascending order, each yielded once.
+pub struct Union<'a, T: 'a> {
+    a: Peekable<Iter<'a, T>>,
+    b: Peekable<Iter<'a, T>>,
+}
+
+/// Values in both sets, in ascending order.
+pub struct Intersection<'a, T: 'a> {
+    a: Peekable<Iter<'a, T>>,
+    b: Peekable<Iter<'a, T>>,
+}
+
+/// Values in the first set but not the second, in ascending order.
+pub struct Difference<'a, T: 'a> {
+    a: Peekable<Iter<'a, T>>,
+    b: Peekable<Iter<'a, T>>,
+}
+
+/// Values in exactly one of the sets, in ascending order.
+pub struct SymmetricDifference<'a, T: 'a> {
+    a: Peekable<Iter<'a, T>>,
+    b: Peekable<Iter<'a, T>>,
+}
+
+impl<T> FlatSet<T> {
+    /// Creates an empty set without allocating.
+    pub const fn new() -> FlatSet<T> {
+        FlatSet { map: FlatMap::new() }
+    }
+
+    pub fn with_capacity(capacity: usize) -> FlatSet<T> {
+        FlatSet { map: FlatMap::with_capacity(capacity) }
+    }
+}
+
+impl<T, L> FlatSet<T, L> {
+    /// Creates an empty set that finds values using `l`.
+    pub const fn with_lookup(l: L) -> FlatSet<T, L> {
+        FlatSet { map: FlatMap::with_lookup(l) }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.map.capacity()
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { inner: self.map.keys() }
+    }
+
+    pub fn first(&self) -> Option<&T> {
+        self.map.first_key_value().map(|(t, _)| t)
+    }
+
+    pub fn last(&self) -> Option<&T> {
+        self.map.last_key_value().map(|(t, _)| t)
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear()
+    }
+}
+
+impl<T, L> FlatSet<T, L> {
+    /// Adds a value to the set, returning true if it was not already
+    /// present. An equal value already in the set is left in place.
+    pub fn insert(&mut self, value: T) -> bool
+        where L: Lookup<T, (), T>
+    {
+        match self.map.entry(value) {
+            Vacant(entry) => {
+                entry.insert(());
+                true
+            }
+            Occupied(_) => false,
+        }
+    }
+
+    /// Removes a value from the set, returning true if it was present.
+    pub fn remove<Q>(&mut self, q: &Q) -> bool
+        where T: Borrow<Q>,
+              Q: ?Sized,
+              L: Lookup<T, (), Q>
+    {
+        self.map.remove(q).is_some()
+    }
+
+    /// Removes and returns the value equal to `q`.
+    pub fn take<Q>(&mut self, q: &Q) -> Option<T>
+        where T: Borrow<Q>,
+              Q: ?Sized,
+              L: Lookup<T, (), Q>
+    {
+        self.map.remove_entry(q).map(|(t, _)| t)
+    }
+
+    pub fn contains<Q>(&self, q: &Q) -> bool
+        where T: Borrow<Q>,
+              Q: ?Sized,
+              L: Lookup<T, (), Q>
+    {
+        self.map.contains_key(q)
+    }
+
+    /// Returns the stored value equal to `q`.
+    pub fn get<Q>(&self, q: &Q) -> Option<&T>
+        where T: Borrow<Q>,
+              Q: ?Sized,
+              L: Lookup<T, (), Q>
+    {
+        self.map.get_key_value(q).map(|(t, _)| t)
+    }
+
+}
+
+// The set operations walk both sets in order, so both have to be sorted.
+impl<T: Ord, L: Sorted> FlatSet<T, L> {
+    pub fn union<'a, L2: Sorted>(&'a self, other: &'a FlatSet<T, L2>) -> Union<'a, T> {
+        Union { a: self.iter().peekable(), b: other.iter().peekable() }
+    }
+
+    pub fn intersection<'a, L2: Sorted>(&'a self, other: &'a FlatSet<T, L2>) -> Intersection<'a, T> {
+        Intersection { a: self.iter().peekable(), b: other.iter().peekable() }
+    }
+
+    pub fn difference<'a, L2: Sorted>(&'a self, other: &'a FlatSet<T, L2>) -> Difference<'a, T> {
+        Difference { a: self.iter().peekable(), b: other.iter().peekable() }
+    }
+
+    pub fn symmetric_difference<'a, L2: Sorted>(&'a self,
+                                        other: &'a FlatSet<T, L2>)
+                                        -> SymmetricDifference<'a, T> {
+        SymmetricDifference { a: self.iter().peekable(), b: other.iter().peekable() }
+    }
+
+    /// Returns true if every value of `self` is also in `other`. Takes a
+    /// single pass over both sets.
+    pub fn is_subset<L2: Sorted>(&self, other: &FlatSet<T, L2>) -> bool {
+        self.len() <= other.len() && self.difference(other).next().is_none()
+    }
+
+    pub fn is_superset<L2: Sorted>(&self, other: &FlatSet<T, L2>) -> bool {
+        other.is_subset(self)
+    }
+
+    pub fn is_disjoint<L2: Sorted>(&self, other: &FlatSet<T, L2>) -> bool {
+        self.intersection(other).next().is_none()
+    }
+}
+
+// Compares the heads of two sorted iterators, treating an exhausted iterator
+// as greater than any value so the other one drains first.
+fn cmp_heads<T: Ord>(a: Option<&&T>, b: Option<&&T>) -> Option<Ordering> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.cmp(b)),
+        (Some(_), None) => Some(Ordering::Less),
+        (None, Some(_)) => Some(Ordering::Greater),
+        (None, None) => None,
+    }
+}
+
+impl<'a, T: Ord> Iterator for Union<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        match cmp_heads(self.a.peek(), self.b.peek())? {
+            Ordering::Less => self.a.next(),
+            Ordering::Greater => self.b.next(),
+            Ordering::Equal => {
+                self.b.next();
+                self.a.next()
+            }
+        }
+    }
+}
+
+impl<'a, T: Ord> Iterator for Intersection<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        loop {
+            match self.a.peek()?.cmp(self.b.peek()?) {
+                Ordering::Less => {
+                    self.a.next();
+                }
+                Ordering::Greater => {
+                    self.b.next();
+                }
+                Ordering::Equal => {
+                    self.b.next();
+                    return self.a.next();
+                }
+            }
+        }
+    }
+}
+
+impl<'a, T: Ord> Iterator for Difference<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        loop {
+            match cmp_heads(Some(self.a.peek()?), self.b.peek())? {
+                Ordering::Less => return self.a.next(),
+                Ordering::Greater => {
+                    self.b.next();
+                }
+                Ordering::Equal => {
+                    self.a.next();
+                    self.b.next();
+                }
+            }
+        }
+    }
+}
+
+impl<'a, T: Ord> Iterator for SymmetricDifference<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        loop {
+            match cmp_heads(self.a.peek(), self.b.peek())? {
+                Ordering::Less => return self.a.next(),
+                Ordering::Greater => return self.b.next(),
+                Ordering::Equal => {
+                    self.a.next();
+                    self.b.next();
+                }
+            }
+        }
+    }
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        self.inner.next()
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, T> Clone for Iter<'a, T> {
+    fn clone(&self) -> Iter<'a, T> {
+        Iter { inner: self.inner.clone() }
+    }
+}
+
+impl<'a, T> DoubleEndedIterator for Iter<'a, T> {
+    fn next_back(&mut self) -> Option<&'a T> {
+        self.inner.next_back()
+    }
+}
+
+impl<'a, T> ExactSizeIterator for Iter<'a, T> {}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.inner.next().map(|(t, _)| t)
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<T> DoubleEndedIterator for IntoIter<T> {
+    fn next_back(&mut self) -> Option<T> {
+        self.inner.next_back().map(|(t, _)| t)
+    }
+}
+
+impl<T> ExactSizeIterator for IntoIter<T> {}
+
+impl<'a, T> FusedIterator for Iter<'a, T> {}
+impl<T> FusedIterator for IntoIter<T> {}
+impl<'a, T: Ord> FusedIterator for Union<'a, T> {}
+impl<'a, T: Ord> FusedIterator for Intersection<'a, T> {}
+impl<'a, T: Ord> FusedIterator for Difference<'a, T> {}
+impl<'a, T: Ord> FusedIterator for SymmetricDifference<'a, T> {}
+
+impl<T, L> IntoIterator for FlatSet<T, L> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter { inner: self.map.into_iter() }
+    }
+}
+
+impl<'a, T, L> IntoIterator for &'a FlatSet<T, L> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T, L: Arrange<T> + Default> FromIterator<T> for FlatSet<T, L> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> FlatSet<T, L> {
+        FlatSet { map: iter.into_iter().map(|t| (t, ())).collect() }
+    }
+}
+
+impl<T, L: Lookup<T, (), T>> Extend<T> for FlatSet<T, L> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.map.extend(iter.into_iter().map(|t| (t, ())));
+    }
+}
+
+impl<'a, T: Copy, L: Lookup<T, (), T>> Extend<&'a T> for FlatSet<T, L> {
+    fn extend<I: IntoIterator<Item = &'a T>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().cloned());
+    }
+}
+
+impl<T: Clone, L: Clone> Clone for FlatSet<T, L> {
+    fn clone(&self) -> FlatSet<T, L> {
+        FlatSet { map: self.map.clone() }
+    }
+}
+
+impl<T, L: Default> Default for FlatSet<T, L> {
+    fn default() -> FlatSet<T, L> {
+        FlatSet { map: FlatMap::default() }
+    }
+}
+
+impl<T: Hash, L: Sorted> Hash for FlatSet<T, L> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for t in self {
+            t.hash(state);
+        }
+    }
+}
+
+impl<T: PartialEq, L1: Arrange<T>, L2: Arrange<T>> PartialEq<FlatSet<T, L2>> for FlatSet<T, L1> {
+    fn eq(&self, other: &FlatSet<T, L2>) -> bool {
+        self.map == other.map
+    }
+}
+
+impl<T: Eq, L: Arrange<T>> Eq for FlatSet<T, L> {}
+
+impl<T: Ord, L: Sorted> PartialOrd for FlatSet<T, L> {
+    fn partial_cmp(&self, other: &FlatSet<T, L>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: Ord, L: Sorted> Ord for FlatSet<T, L> {
+    fn cmp(&self, other: &FlatSet<T, L>) -> Ordering {
+        self.iter().cmp(other.iter())
+    }
+}
+
+impl<T: Debug, L> Debug for FlatSet<T, L> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(feature = "serde1")]
+mod serde_impl {
+    // serialized as a plain sequence of values, like BTreeSet
+    use std::fmt;
+    use std::marker::PhantomData;
+    use std::vec::Vec;
+    use serde::de::{Deserialize, Deserializer, SeqAccess, Visitor};
+    use serde::ser::SerializeSeq;
+    use serde::{Serialize, Serializer};
+    use lookup::Arrange;
+    use super::FlatSet;
+
+    impl<T: Serialize, L> Serialize for FlatSet<T, L> {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where S: Serializer
+        {
+            let mut seq = serializer.serialize_seq(Some(self.len()))?;
+            for t in self {
+                seq.serialize_element(t)?;
+            }
+            seq.end()
+        }
+    }
+
+    struct FlatSetVisitor<T, L> {
+        marker: PhantomData<fn() -> FlatSet<T, L>>,
+    }
+
+    impl<'de, T, L> Visitor<'de> for FlatSetVisitor<T, L>
+        where T: Deserialize<'de>,
+              L: Arrange<T> + Default
+    {
+        type Value = FlatSet<T, L>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a flat_set")
+        }
+
+        fn visit_seq<A>(self, mut access: A) -> Result<Self::Value, A::Error>
+            where A: SeqAccess<'de>
+        {
+            let mut v = Vec::with_capacity(access.size_hint().unwrap_or(0));
+            while let Some(t) = access.next_element()? {
+                v.push(t);
+            }
+            Ok(v.into_iter().collect())
+        }
+    }
+
+    impl<'de, T, L> Deserialize<'de> for FlatSet<T, L>
+        where T: Deserialize<'de>,
+              L: Arrange<T> + Default
+    {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where D: Deserializer<'de>
+        {
+            deserializer.deserialize_seq(FlatSetVisitor { marker: PhantomData })
+        }
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+mod arbitrary_impl {
+    // collected like `FlatMap`, so always sorted and free of duplicates
+    use arbitrary::{Arbitrary, Result, Unstructured};
+    use lookup::Arrange;
+    use super::FlatSet;
+
+    impl<'a, T, L> Arbitrary<'a> for FlatSet<T, L>
+        where T: Arbitrary<'a>,
+              L: Arrange<T> + Default
+    {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            u.arbitrary_iter()?.collect()
+        }
+
+        fn arbitrary_take_rest(u: Unstructured<'a>) -> Result<Self> {
+            u.arbitrary_take_rest_iter()?.collect()
+        }
+
+        fn size_hint(_depth: usize) -> (usize, Option<usize>) {
+            (0, None)
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use std::borrow::Borrow;
+use std::fmt;
+use std::fmt::Debug;
+use std::ops::RangeBounds;
+use errors::SortedInputError;
+use flat_map::{range_indices, same_entries, Iter, IterMut, Range};
+use lookup::{Arrange, BinarySearch, Lookup};
+
+/// A read-only view over a slice of entries sorted by key, with the query
+/// methods of `FlatMap`.
+///
+/// The slice is searched with the lookup strategy `L`, so it must be in the
+/// order that strategy expects. The view can be built in a `const`, which
+/// suits tables kept in statics.
+///
+/// ```
+/// use flat_map::FlatSlice;
+///
+/// static PORTS: FlatSlice<&str, u16> =
+///     FlatSlice::from_sorted_slice_unchecked(&[("http", 80), ("https", 443), ("ssh", 22)]);
+///
+/// assert_eq!(PORTS.get("https"), Some(&443));
+/// assert_eq!(PORTS.range("h".."i").count(), 2);
+/// ```
+pub struct FlatSlice<'a, K: 'a, V: 'a, L = BinarySearch> {
+    v: &'a [(K, V)],
+    l: L,
+}
+
+/// A mutable view over a contiguous part of a `FlatMap`.
+///
+/// Keys can't be changed through the view, so the part stays in the map's
+/// order and can still be searched by key with the map's lookup strategy
+/// `L`. Obtained from `FlatMap::split_at_mut` and
+/// `FlatMap::split_by_key_mut`.
+pub struct FlatSliceMut<'a, K: 'a, V: 'a, L = BinarySearch> {
+    v: &'a mut [(K, V)],
+    l: L,
+}
+
+impl<'a, K: 'a, V: 'a, L> FlatSliceMut<'a, K, V, L> {
+    pub(crate) fn with_lookup(v: &'a mut [(K, V)], l: L) -> Self {
+        FlatSliceMut { v, l }
+    }
+
+    pub fn len(&self) -> usize {
+        self.v.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.v.is_empty()
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter { inner: self.v.iter() }
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        IterMut { inner: self.v.iter_mut() }
+    }
+
+    /// Consumes the view, returning an iterator that borrows for the
+    /// lifetime of the original map.
+    pub fn into_iter_mut(self) -> IterMut<'a, K, V> {
+        IterMut { inner: self.v.iter_mut() }
+    }
+
+    pub fn get<Q: ?Sized>(&self, q: &Q) -> Option<&V>
+        where K: Borrow<Q>,
+              L: Lookup<K, V, Q>
+    {
+        match self.l.lookup(self.v, q) {
+            Ok(i) => Some(&self.v[i].1),
+            Err(_) => None,
+        }
+    }
+
+    pub fn get_mut<Q: ?Sized>(&mut self, q: &Q) -> Option<&mut V>
+        where K: Borrow<Q>,
+              L: Lookup<K, V, Q>
+    {
+        match self.l.lookup(self.v, q) {
+            Ok(i) => Some(&mut self.v[i].1),
+            Err(_) => None,
+        }
+    }
+
+    pub fn contains_key<Q: ?Sized>(&self, q: &Q) -> bool
+        where K: Borrow<Q>,
+              L: Lookup<K, V, Q>
+    {
+        self.l.lookup(self.v, q).is_ok()
+    }
+}
+
+impl<'a, K: Debug, V: Debug, L> Debug for FlatSliceMut<'a, K, V, L> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<'a, K, V, L> IntoIterator for FlatSliceMut<'a, K, V, L> {
+    type Item = (&'a K, &'a mut V);
+    type IntoIter = IterMut<'a, K, V>;
+
+    fn into_iter(self) -> IterMut<'a, K, V> {
+        self.into_iter_mut()
+    }
+}
+
+impl<'a, K, V> FlatSlice<'a, K, V> {
+    /// Views a slice that the caller guarantees is strictly sorted by key,
+    /// without checking it.
+    ///
+    /// If the slice is not strictly sorted, lookups give unspecified
+    /// results, but never undefined behaviour.
+    pub const fn from_sorted_slice_unchecked(v: &'a [(K, V)]) -> Self {
+        FlatSlice { v, l: BinarySearch }
+    }
+
+    /// Views a slice, checking that it is strictly sorted by key.
+    pub fn from_sorted_slice(v: &'a [(K, V)]) -> Result<Self, SortedInputError<&'a [(K, V)]>>
+        where K: Ord
+    {
+        match v.windows(2).position(|w| w[0].0 >= w[1].0) {
+            Some(i) => Err(SortedInputError::with_input(i + 1, v)),
+            None => Ok(FlatSlice::from_sorted_slice_unchecked(v)),
+        }
+    }
+}
+
+impl<'a, K, V, L> FlatSlice<'a, K, V, L> {
+    pub(crate) fn with_lookup(v: &'a [(K, V)], l: L) -> Self {
+        FlatSlice { v, l }
+    }
+
+    pub fn len(&self) -> usize {
+        self.v.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.v.is_empty()
+    }
+
+    /// Returns the entries, borrowed for as long as the underlying slice.
+    pub fn as_slice(&self) -> &'a [(K, V)] {
+        self.v
+    }
+
+    pub fn iter(&self) -> Iter<'a, K, V> {
+        Iter { inner: self.v.iter() }
+    }
+
+    /// Returns the entry with the smallest key.
+    pub fn first_key_value(&self) -> Option<(&'a K, &'a V)> {
+        self.v.first().map(|(k, v)| (k, v))
+    }
+
+    /// Returns the entry with the largest key.
+    pub fn last_key_value(&self) -> Option<(&'a K, &'a V)> {
+        self.v.last().map(|(k, v)| (k, v))
+    }
+
+    pub fn get<Q: ?Sized>(&self, q: &Q) -> Option<&'a V>
+        where K: Borrow<Q>,
+              L: Lookup<K, V, Q>
+    {
+        self.get_key_value(q).map(|(_, v)| v)
+    }
+
+    pub fn get_key_value<Q: ?Sized>(&self, q: &Q) -> Option<(&'a K, &'a V)>
+        where K: Borrow<Q>,
+              L: Lookup<K, V, Q>
+    {
+        match self.l.lookup(self.v, q) {
+            Ok(i) => Some((&self.v[i].0, &self.v[i].1)),
+            Err(_) => None,
+        }
+    }
+
+    pub fn contains_key<Q: ?Sized>(&self, q: &Q) -> bool
+        where K: Borrow<Q>,
+              L: Lookup<K, V, Q>
+    {
+        self.l.lookup(self.v, q).is_ok()
+    }
+
+    /// Divides the view into two at an index, as `FlatMap::split_at` does.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `mid > len`.
+    pub fn split_at(&self, mid: usize) -> (Self, Self)
+        where L: Clone
+    {
+        let (left, right) = self.v.split_at(mid);
+        (FlatSlice::with_lookup(left, self.l.clone()), FlatSlice::with_lookup(right, self.l.clone()))
+    }
+
+    /// Divides the view into two at a key boundary, as
+    /// `FlatMap::split_by_key` does.
+    pub fn split_by_key<Q>(&self, q: &Q) -> (Self, Self)
+        where K: Borrow<Q>,
+              Q: ?Sized + Ord,
+              L: Lookup<K, V, Q> + Clone
+    {
+        let mid = match self.l.lookup(self.v, q) {
+            Ok(i) | Err(i) => i,
+        };
+        self.split_at(mid)
+    }
+
+    /// Returns an iterator over the entries whose keys fall within `range`,
+    /// in key order.
+    ///
+    /// # Panics
+    ///
+    /// Panics on the same ranges as `FlatMap::range`.
+    pub fn range<T, R>(&self, range: R) -> Range<'a, K, V>
+        where K: Borrow<T>,
+              T: ?Sized + Ord,
+              R: RangeBounds<T>,
+              L: Lookup<K, V, T>
+    {
+        let (start, end) = range_indices(self.v, &self.l, &range);
+        Range { inner: self.v[start..end].iter() }
+    }
+}
+
+impl<'a, K, V, L: Clone> Clone for FlatSlice<'a, K, V, L> {
+    fn clone(&self) -> Self {
+        FlatSlice { v: self.v, l: self.l.clone() }
+    }
+}
+
+impl<'a, K, V, L: Copy> Copy for FlatSlice<'a, K, V, L> {}
+
+impl<'a, K: Debug, V: Debug, L> Debug for FlatSlice<'a, K, V, L> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<'a, 'b, K, V, L1, L2> PartialEq<FlatSlice<'b, K, V, L2>> for FlatSlice<'a, K, V, L1>
+    where K: PartialEq,
+          V: PartialEq,
+          L1: Arrange<K>,
+          L2: Arrange<K>
+{
+    fn eq(&self, other: &FlatSlice<'b, K, V, L2>) -> bool {
+        self.len() == other.len() &&
+        same_entries(&self.l, &other.l, self.v, other.v, |a, b| a == b)
+    }
+}
+
+impl<'a, K: Eq, V: Eq, L: Arrange<K>> Eq for FlatSlice<'a, K, V, L> {}
+
+impl<'a, K, V, L> IntoIterator for FlatSlice<'a, K, V, L> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+impl<'a, K, V, L> IntoIterator for &FlatSlice<'a, K, V, L> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
@@ -1,20 +1,83 @@
 #![cfg_attr(not(feature = "std"), no_std)]
-#![cfg_attr(not(feature = "std"), feature(alloc))]
+#![cfg_attr(feature = "unstable", feature(trusted_len, iter_advance_by))]
 
 #[cfg(feature = "serde1")]
 extern crate serde;
 
+#[cfg(feature = "derive")]
+extern crate flat_map_derive;
+
+#[cfg(feature = "postcard")]
+extern crate postcard;
+
+#[cfg(feature = "rayon")]
+extern crate rayon;
+
+#[cfg(feature = "arbitrary")]
+extern crate arbitrary;
+
+#[cfg(feature = "proptest")]
+extern crate proptest;
+
 #[cfg(not(feature = "std"))]
-#[macro_use]
 pub extern crate alloc;
 
+// lets the rest of the crate import from `std` whether or not it is enabled;
+// some of these are only used by optional features
 #[cfg(not(feature = "std"))]
+#[allow(unused_imports)]
 mod std {
-    pub use core::{ops, hash, fmt, cmp, mem, slice, iter, borrow, marker};
+    pub use core::{arch, ops, hash, fmt, cmp, mem, slice, iter, borrow, marker, num, option, ptr,
+                   cell, sync};
     pub use alloc::*;
 }
 
 
+pub mod bounded;
+pub mod builder;
+pub mod errors;
+pub mod eytzinger;
 pub mod flat_map;
+pub mod flat_multiset;
+pub mod flat_set;
+pub mod flat_slice;
+pub mod lookup;
+#[cfg(feature = "proptest")]
+pub mod prop;
+pub mod range_map;
+#[cfg(feature = "serde1")]
+pub mod serde_dup;
+#[cfg(feature = "serde1")]
+pub mod serde_seq;
+pub mod small;
+pub mod soa;
+pub mod staged;
+pub use bounded::BoundedFlatMap;
+pub use builder::FlatMapBuilder;
+pub use eytzinger::EytzingerFlatMap;
 pub use flat_map::Entry::*;
-pub use flat_map::FlatMap;
+pub use flat_map::{FlatMap, GrowthPolicy, ShrinkPolicy, SortedByKey, TiePolicy};
+pub use flat_multiset::FlatMultiSet;
+pub use flat_set::FlatSet;
+pub use flat_slice::{FlatSlice, FlatSliceMut};
+pub use range_map::RangeMap;
+pub use small::{SliceFlatMap, SmallFlatMap, SpillFlatMap};
+pub use soa::SoaFlatMap;
+pub use staged::StagedFlatMap;
+pub use lookup::{Adaptive, Arrange, AsciiCaseInsensitive, BinarySearch, BoxedLookup,
+                 BranchlessSearch, CachedLookup, CaseInsensitive, Comparable, Compare, Descending,
+                 DynLookup, Equivalent, ExponentialSearch, Instrumented, InterpolationKey,
+                 InterpolationSearch, LinearBack, LinearFront, Lookup, LookupStats, Order, OrderBy,
+                 Ordered, Sorted, UnsortedLinear};
+
+#[cfg(feature = "derive")]
+pub use flat_map_derive::FlatMapKey;
+
+// used by the code generated by `#[derive(FlatMapKey)]`
+#[doc(hidden)]
+pub mod __private {
+    pub use std::borrow::Borrow;
+    pub use std::cmp::{Eq, Ord, Ordering, PartialEq, PartialOrd};
+    pub use std::option::Option;
+    pub use lookup::{Comparable, Equivalent};
+}
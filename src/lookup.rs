@@ -0,0 +1,1150 @@
+//! Strategies a `FlatMap` uses to find keys in its entries.
+//!
+//! Every strategy returns the same kind of result as
+//! `slice::binary_search_by`: the index of the matching entry, or the index
+//! at which the key would have to be inserted. The sorted strategies keep
+//! the entries sorted by key and differ only in how they search, which
+//! matters for small maps and for skewed access patterns. `UnsortedLinear`
+//! keeps the entries in insertion order instead, and only needs `PartialEq`
+//! keys.
+//!
+//! A strategy is described by three traits: `Order` says whether the entries
+//! are sorted, `Arrange` builds and merges whole vectors of entries, and
+//! `Lookup` finds a single key. The key bounds of a map's methods come from
+//! these impls, so a map with an unsorted strategy never asks for `Ord`.
+//! `DynLookup` is the object-safe part of `Lookup`, for strategies picked
+//! at runtime.
+//!
+//! `OrderBy` keeps the entries sorted by a comparator instead of `Ord`, for
+//! keys such as floats, or to order keys other than by their `Ord` impl.
+
+use std::borrow::Borrow;
+use std::boxed::Box;
+use std::cell::Cell;
+use std::cmp;
+use std::cmp::Ordering;
+use std::fmt;
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::vec::Vec;
+
+/// Whether a strategy keeps the entries sorted by key.
+pub trait Order {
+    /// True if the entries are sorted by key, false if they are kept in
+    /// insertion order.
+    const SORTED: bool;
+
+    /// True if the entries are sorted by the keys' `Ord` impl, as the
+    /// `Sorted` strategies keep them. Two maps whose strategies both are
+    /// hold equal entries at the same positions.
+    const BY_ORD: bool = false;
+}
+
+/// Marks the strategies that keep the entries sorted by key. The range,
+/// splitting and ordering operations of a map need one of these. Their
+/// `Order` impls set `BY_ORD`.
+pub trait Sorted: Order {}
+
+/// Marks the strategies that keep the entries sorted in an order of their
+/// own, which for the `Sorted` strategies is that of `Ord`. The operations
+/// that only need positions from the strategy, such as `split_off` and
+/// `lower_bound`, need one of these.
+pub trait Ordered: Order {}
+
+/// The whole-map operations of a strategy: arranging freshly collected
+/// entries, and merging two arrangements.
+pub trait Arrange<K>: Order {
+    /// Puts `entries` in the order the strategy expects and removes
+    /// duplicate keys. For each key the value of the first entry is kept,
+    /// or of the last one if `keep_last` is set, as repeated inserts would.
+    fn arrange<V>(&self, entries: &mut Vec<(K, V)>, keep_last: bool);
+
+    /// Compares two keys in the order the strategy keeps them in. Strategies
+    /// that keep insertion order only tell equal keys from the rest, giving
+    /// `Equal` for those and `Less` otherwise.
+    fn compare_keys(&self, a: &K, b: &K) -> Ordering;
+
+    /// Merges `other`, which holds distinct keys in the strategy's order,
+    /// into `entries`. For keys present in both, `f(key, old, new)` gives
+    /// the value; the existing key and its position are kept.
+    ///
+    /// If `f` panics, `entries` is left empty.
+    fn merge<V, I, F>(&self, entries: &mut Vec<(K, V)>, other: I, f: F)
+        where I: IntoIterator<Item = (K, V)>,
+              F: FnMut(&K, V, V) -> V;
+}
+
+/// Searches the entries of a map for `q`.
+///
+/// Returns `Ok(index)` of the entry whose key equals `q`, or `Err(index)` of
+/// the position where such an entry would be inserted.
+///
+/// The sorted strategies return the same result as `slice::binary_search_by`
+/// for any sorted slice, with one exception: if several entries compare
+/// equal to `q`, which a map's distinct keys only allow when `Comparable`
+/// does not order `Q` like `K`, any of them may be returned. `LinearFront`
+/// gives the first, `LinearBack` the last. `Err` results never differ.
+pub trait Lookup<K, V, Q: ?Sized>: Arrange<K> {
+    fn lookup(&self, slice: &[(K, V)], q: &Q) -> Result<usize, usize>;
+
+    /// Searches like `lookup`, starting near `hint`, which is cheap if `q`
+    /// is at or close to that position. Any `hint` gives the same result.
+    /// The sorted strategies for `Ord` keys gallop out from the hint as
+    /// `ExponentialSearch::lookup_from` does; the others ignore it.
+    fn lookup_hint(&self, slice: &[(K, V)], q: &Q, hint: usize) -> Result<usize, usize> {
+        let _ = hint;
+        self.lookup(slice, q)
+    }
+}
+
+/// The searching half of `Lookup`, which unlike `Lookup` can be used as a
+/// trait object. Implemented for every sorted strategy that is `Clone`.
+///
+/// A map whose strategy is a `BoxedLookup` picks how to search at runtime,
+/// for instance from configuration, without its callers being generic over
+/// the strategy. The strategy must keep the entries sorted by `Ord`, since
+/// whether they are sorted cannot change at runtime.
+///
+/// ```
+/// use flat_map::{BinarySearch, BoxedLookup, FlatMap, LinearFront};
+///
+/// fn strategy(expected_len: usize) -> BoxedLookup<u32, &'static str> {
+///     if expected_len < 16 { Box::new(LinearFront) } else { Box::new(BinarySearch) }
+/// }
+///
+/// let mut map = FlatMap::with_lookup(strategy(4));
+/// map.insert(2, "b");
+/// map.insert(1, "a");
+/// assert_eq!(map.get(&2), Some(&"b"));
+/// assert_eq!(map.into_inner(), [(1, "a"), (2, "b")]);
+/// ```
+pub trait DynLookup<K, V, Q: ?Sized = K> {
+    fn dyn_lookup(&self, slice: &[(K, V)], q: &Q) -> Result<usize, usize>;
+
+    fn dyn_lookup_hint(&self, slice: &[(K, V)], q: &Q, hint: usize) -> Result<usize, usize>;
+
+    fn clone_box(&self) -> BoxedLookup<K, V, Q>;
+}
+
+/// A strategy chosen at runtime; see `DynLookup`.
+pub type BoxedLookup<K, V, Q = K> = Box<dyn DynLookup<K, V, Q> + Send + Sync>;
+
+/// A query that can be matched against keys of type `K` without being a
+/// borrowed form of them. Implemented for every `Q: PartialEq` that `K`
+/// borrows as, so that `UnsortedLinear` maps of keys such as `f64` can be
+/// searched; implement it for other query types by hand.
+///
+/// This has the same shape as the trait of the `equivalent` crate.
+pub trait Equivalent<K: ?Sized> {
+    fn equivalent(&self, key: &K) -> bool;
+}
+
+/// A query that can be ordered against keys of type `K`, which is how the
+/// sorted strategies search. Implemented for every `Q: Ord` that `K`
+/// borrows as. A hand-written impl must order queries consistently with the
+/// keys' `Ord`.
+///
+/// This lets a map find keys by a query that is cheaper to build than a
+/// key, and which `Borrow` cannot express:
+///
+/// ```
+/// use std::cmp::Ordering;
+/// use flat_map::{Comparable, Equivalent, FlatMap};
+///
+/// // looks up (String, u32) keys without allocating a String
+/// struct Query<'a>(&'a str, u32);
+///
+/// impl<'a> Equivalent<(String, u32)> for Query<'a> {
+///     fn equivalent(&self, key: &(String, u32)) -> bool {
+///         self.0 == key.0 && self.1 == key.1
+///     }
+/// }
+///
+/// impl<'a> Comparable<(String, u32)> for Query<'a> {
+///     fn compare(&self, key: &(String, u32)) -> Ordering {
+///         self.0.cmp(key.0.as_str()).then(self.1.cmp(&key.1))
+///     }
+/// }
+///
+/// let mut map = FlatMap::new();
+/// map.insert(("eth".to_string(), 0), "up");
+/// map.insert(("eth".to_string(), 1), "down");
+/// assert_eq!(map.get(&Query("eth", 1)), Some(&"down"));
+/// assert_eq!(map.remove(&Query("eth", 0)), Some("up"));
+/// ```
+pub trait Comparable<K: ?Sized>: Equivalent<K> {
+    fn compare(&self, key: &K) -> Ordering;
+}
+
+impl<Q, K> Equivalent<K> for Q
+    where Q: ?Sized + PartialEq,
+          K: ?Sized + Borrow<Q>
+{
+    fn equivalent(&self, key: &K) -> bool {
+        *self == *key.borrow()
+    }
+}
+
+impl<Q, K> Comparable<K> for Q
+    where Q: ?Sized + Ord,
+          K: ?Sized + Borrow<Q>
+{
+    fn compare(&self, key: &K) -> Ordering {
+        self.cmp(key.borrow())
+    }
+}
+
+/// Binary search, O(log n). The default strategy.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct BinarySearch;
+
+/// Linear scan from the smallest key, O(n). Fast for very small maps and for
+/// maps whose lookups mostly hit the first few keys.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct LinearFront;
+
+/// Linear scan from the largest key, O(n). Fast for very small maps and for
+/// maps that mostly see lookups and inserts of keys at or past the end, such
+/// as timestamps.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct LinearBack;
+
+/// Linear scan from the smallest key while the map holds at most
+/// `threshold` entries, binary search once it holds more. This suits maps
+/// whose size is not known up front, without choosing a strategy per type.
+///
+/// ```
+/// use flat_map::{Adaptive, FlatMap};
+///
+/// let mut map = FlatMap::with_lookup(Adaptive::new(4));
+/// map.extend((0..10).map(|k| (k, k * k)));
+/// assert_eq!(map.get(&7), Some(&49));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Adaptive {
+    /// The largest number of entries that are scanned linearly.
+    pub threshold: usize,
+}
+
+impl Adaptive {
+    /// The threshold of `Adaptive::default()`, below which a scan usually
+    /// beats binary search for small keys.
+    pub const DEFAULT_THRESHOLD: usize = 16;
+
+    pub const fn new(threshold: usize) -> Adaptive {
+        Adaptive { threshold }
+    }
+}
+
+impl Default for Adaptive {
+    fn default() -> Adaptive {
+        Adaptive::new(Adaptive::DEFAULT_THRESHOLD)
+    }
+}
+
+/// Wraps a sorted strategy, remembering where the last lookup ended. A
+/// lookup first tries that entry and its neighbours, and only searches with
+/// the wrapped strategy if none of them has the key. Repeated lookups of the
+/// same or adjacent keys are then O(1).
+///
+/// The position is kept in an atomic, so maps stay `Sync`; concurrent
+/// readers may overwrite each other's position, which only costs a search.
+///
+/// ```
+/// use flat_map::{BinarySearch, CachedLookup, FlatMap};
+///
+/// let mut map = FlatMap::with_lookup(CachedLookup::new(BinarySearch));
+/// map.extend((0..100).map(|k| (k, k * 2)));
+/// for k in 40..60 {
+///     // after the first, each of these hits a neighbour of the last
+///     assert_eq!(map.get(&k), Some(&(k * 2)));
+/// }
+/// ```
+pub struct CachedLookup<L = BinarySearch> {
+    inner: L,
+    last: AtomicUsize,
+}
+
+impl<L> CachedLookup<L> {
+    pub const fn new(inner: L) -> CachedLookup<L> {
+        CachedLookup { inner, last: AtomicUsize::new(0) }
+    }
+
+    /// The wrapped strategy.
+    pub fn inner(&self) -> &L {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> L {
+        self.inner
+    }
+}
+
+impl<L: Clone> Clone for CachedLookup<L> {
+    fn clone(&self) -> CachedLookup<L> {
+        let last = self.last.load(AtomicOrdering::Relaxed);
+        CachedLookup { inner: self.inner.clone(), last: AtomicUsize::new(last) }
+    }
+}
+
+impl<L: Default> Default for CachedLookup<L> {
+    fn default() -> CachedLookup<L> {
+        CachedLookup::new(L::default())
+    }
+}
+
+impl<L: fmt::Debug> fmt::Debug for CachedLookup<L> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CachedLookup")
+            .field("inner", &self.inner)
+            .field("last", &self.last.load(AtomicOrdering::Relaxed))
+            .finish()
+    }
+}
+
+/// Wraps a sorted strategy and counts its lookups, the hits and misses
+/// among them, and the keys they compared the query with. Use it to
+/// compare strategies on a real workload; `FlatMap::lookup` gives access to
+/// the counts.
+///
+/// Only lookups are counted, not the sorting done by bulk operations such
+/// as `extend`. The counters are atomic, so maps stay `Sync`.
+///
+/// ```
+/// use flat_map::{BinarySearch, FlatMap, Instrumented, LinearFront};
+///
+/// let mut by_scan = FlatMap::with_lookup(Instrumented::new(LinearFront));
+/// let mut by_halves = FlatMap::with_lookup(Instrumented::new(BinarySearch));
+/// by_scan.extend((0..100).map(|k| (k, ())));
+/// by_halves.extend((0..100).map(|k| (k, ())));
+/// for k in 90..110 {
+///     by_scan.get(&k);
+///     by_halves.get(&k);
+/// }
+/// let (scan, halves) = (by_scan.lookup().stats(), by_halves.lookup().stats());
+/// assert_eq!((scan.lookups, scan.hits, scan.misses), (20, 10, 10));
+/// assert!(halves.comparisons < scan.comparisons);
+/// ```
+#[derive(Debug, Default)]
+pub struct Instrumented<L = BinarySearch> {
+    inner: L,
+    lookups: AtomicUsize,
+    hits: AtomicUsize,
+    comparisons: AtomicUsize,
+}
+
+/// Counts taken by an `Instrumented` strategy.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct LookupStats {
+    pub lookups: usize,
+    /// Lookups that found the key.
+    pub hits: usize,
+    pub misses: usize,
+    /// Keys compared with queries, across all lookups.
+    pub comparisons: usize,
+}
+
+/// A query that counts the keys it is compared with, which is how
+/// `Instrumented` counts comparisons.
+pub struct Counted<'c, Q: ?Sized + 'c> {
+    q: &'c Q,
+    n: &'c Cell<usize>,
+}
+
+impl<L> Instrumented<L> {
+    pub const fn new(inner: L) -> Instrumented<L> {
+        Instrumented {
+            inner,
+            lookups: AtomicUsize::new(0),
+            hits: AtomicUsize::new(0),
+            comparisons: AtomicUsize::new(0),
+        }
+    }
+
+    /// The wrapped strategy.
+    pub fn inner(&self) -> &L {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> L {
+        self.inner
+    }
+
+    /// The counts taken since the strategy was created or last reset.
+    pub fn stats(&self) -> LookupStats {
+        let lookups = self.lookups.load(AtomicOrdering::Relaxed);
+        let hits = self.hits.load(AtomicOrdering::Relaxed);
+        LookupStats {
+            lookups,
+            hits,
+            misses: lookups.saturating_sub(hits),
+            comparisons: self.comparisons.load(AtomicOrdering::Relaxed),
+        }
+    }
+
+    pub fn reset_stats(&self) {
+        self.lookups.store(0, AtomicOrdering::Relaxed);
+        self.hits.store(0, AtomicOrdering::Relaxed);
+        self.comparisons.store(0, AtomicOrdering::Relaxed);
+    }
+
+    fn record(&self, found: Result<usize, usize>, comparisons: usize) -> Result<usize, usize> {
+        self.lookups.fetch_add(1, AtomicOrdering::Relaxed);
+        self.hits.fetch_add(found.is_ok() as usize, AtomicOrdering::Relaxed);
+        self.comparisons.fetch_add(comparisons, AtomicOrdering::Relaxed);
+        found
+    }
+}
+
+// A copy starts from the counts of the original.
+impl<L: Clone> Clone for Instrumented<L> {
+    fn clone(&self) -> Instrumented<L> {
+        let copy = Instrumented::new(self.inner.clone());
+        copy.lookups.store(self.lookups.load(AtomicOrdering::Relaxed), AtomicOrdering::Relaxed);
+        copy.hits.store(self.hits.load(AtomicOrdering::Relaxed), AtomicOrdering::Relaxed);
+        let comparisons = self.comparisons.load(AtomicOrdering::Relaxed);
+        copy.comparisons.store(comparisons, AtomicOrdering::Relaxed);
+        copy
+    }
+}
+
+impl<'c, K: ?Sized, Q: ?Sized + Equivalent<K>> Equivalent<K> for Counted<'c, Q> {
+    fn equivalent(&self, key: &K) -> bool {
+        self.n.set(self.n.get() + 1);
+        self.q.equivalent(key)
+    }
+}
+
+impl<'c, K: ?Sized, Q: ?Sized + Comparable<K>> Comparable<K> for Counted<'c, Q> {
+    fn compare(&self, key: &K) -> Ordering {
+        self.n.set(self.n.get() + 1);
+        self.q.compare(key)
+    }
+}
+
+/// Binary search that halves the range with a conditional move instead of a
+/// branch, and prefetches both entries the next step might read. Still
+/// O(log n), and never mispredicts a branch, at the cost of always taking
+/// the full number of steps. Recent versions of `slice::binary_search_by`,
+/// which `BinarySearch` uses, avoid the branch too, so measure before
+/// picking this over it; the prefetching helps most on big maps.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct BranchlessSearch;
+
+/// Exponential (galloping) search from the smallest key: probes at offsets
+/// 1, 2, 4, ... until it passes the key, then binary searches the last
+/// window. O(log i) for a key at index i, so cheap for keys near the front
+/// without the O(n) worst case of `LinearFront`. `lookup_from` gallops from
+/// any position instead, which suits runs of queries clustered together.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ExponentialSearch;
+
+/// Interpolation search, which guesses where a key is from its value and
+/// those of the keys at either end of the range still being searched. For
+/// keys spread evenly, such as random or sequential integers, this takes
+/// O(log log n) probes; after a few probes that fail to find the key it
+/// falls back to binary search, so skewed keys cost O(log n) plus the
+/// probes. Needs keys that implement `InterpolationKey`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct InterpolationSearch;
+
+/// Maps keys to `u64` for `InterpolationSearch`, in a way that never
+/// decreases as the keys increase. Implemented for the primitive integer
+/// types and `char`.
+pub trait InterpolationKey {
+    fn interpolation_key(&self) -> u64;
+}
+
+macro_rules! unsigned_interpolation_key {
+    ($($t:ty)*) => {$(
+        impl InterpolationKey for $t {
+            fn interpolation_key(&self) -> u64 {
+                *self as u64
+            }
+        }
+    )*}
+}
+
+macro_rules! signed_interpolation_key {
+    ($($t:ty)*) => {$(
+        impl InterpolationKey for $t {
+            // flipping the sign bit orders negative numbers before positive
+            fn interpolation_key(&self) -> u64 {
+                (*self as i64 as u64) ^ (1 << 63)
+            }
+        }
+    )*}
+}
+
+unsigned_interpolation_key!(u8 u16 u32 u64 usize char);
+signed_interpolation_key!(i8 i16 i32 i64 isize);
+
+impl InterpolationKey for u128 {
+    fn interpolation_key(&self) -> u64 {
+        (*self >> 64) as u64
+    }
+}
+
+impl InterpolationKey for i128 {
+    fn interpolation_key(&self) -> u64 {
+        ((*self >> 64) as i64 as u64) ^ (1 << 63)
+    }
+}
+
+impl<T: ?Sized + InterpolationKey> InterpolationKey for &T {
+    fn interpolation_key(&self) -> u64 {
+        (**self).interpolation_key()
+    }
+}
+
+/// Linear scan of entries kept in insertion order, O(n). New keys are pushed
+/// at the end. For tiny maps whose keys are only `PartialEq`, or where
+/// keeping the entries sorted costs more than it saves.
+///
+/// Keys are matched with `==`, and other queries through `Equivalent`, so a
+/// key that is not equal to itself, such as a NaN float, can never be found
+/// again once inserted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct UnsortedLinear;
+
+/// Binary search over entries sorted by the comparator `C` rather than by
+/// `Ord`, O(log n). Keys that compare equal are the same key.
+///
+/// Inserts, lookups, `entry`, `split_off` and collecting all follow the
+/// comparator, and keys need no `Ord` impl. A lookup by a borrowed form `Q`
+/// of the keys needs a comparator for `Q` as well, which must order it as
+/// it orders the keys; a closure only compares keys.
+///
+/// The strategy is `Ordered` but not `Sorted`, so the range and set
+/// operations, which compare keys with `Ord`, are not available. Where the
+/// docs of a map method speak of lesser and greater keys, as `lower_bound`
+/// and `get_floor` do, they mean earlier and later in the comparator's
+/// order.
+///
+/// ```
+/// use flat_map::{FlatMap, OrderBy};
+///
+/// let mut map = FlatMap::with_lookup(OrderBy(|a: &f64, b: &f64| a.total_cmp(b)));
+/// map.insert(2.5, 'b');
+/// map.insert(-1.0, 'a');
+/// map.insert(f64::NAN, 'z');
+/// assert_eq!(map.get(&2.5), Some(&'b'));
+/// assert_eq!(map.keys().next(), Some(&-1.0));
+/// assert!(map.get(&f64::NAN).is_some());
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct OrderBy<C>(pub C);
+
+/// A total order on keys, for `OrderBy`. Implemented by closures and
+/// functions taking two keys.
+pub trait Compare<K: ?Sized> {
+    fn compare(&self, a: &K, b: &K) -> Ordering;
+}
+
+impl<K: ?Sized, F> Compare<K> for F
+    where F: Fn(&K, &K) -> Ordering
+{
+    fn compare(&self, a: &K, b: &K) -> Ordering {
+        self(a, b)
+    }
+}
+
+/// Orders strings by their lowercase forms, so that keys differing only in
+/// case are the same key. The first spelling inserted is the one kept.
+///
+/// ```
+/// use flat_map::{CaseInsensitive, FlatMap, OrderBy};
+///
+/// let mut headers: FlatMap<String, &str, OrderBy<CaseInsensitive>> = FlatMap::default();
+/// headers.insert("Content-Type".to_string(), "text/plain");
+/// headers.insert("content-type".to_string(), "text/html");
+/// assert_eq!(headers.get("CONTENT-TYPE"), Some(&"text/html"));
+/// assert_eq!(headers.keys().next().unwrap(), "Content-Type");
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CaseInsensitive;
+
+/// Like `CaseInsensitive`, but only folds ASCII letters, which is cheaper
+/// and enough for protocol names such as HTTP headers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct AsciiCaseInsensitive;
+
+impl<K: ?Sized + AsRef<str>> Compare<K> for CaseInsensitive {
+    fn compare(&self, a: &K, b: &K) -> Ordering {
+        let a = a.as_ref().chars().flat_map(char::to_lowercase);
+        let b = b.as_ref().chars().flat_map(char::to_lowercase);
+        a.cmp(b)
+    }
+}
+
+impl<K: ?Sized + AsRef<str>> Compare<K> for AsciiCaseInsensitive {
+    fn compare(&self, a: &K, b: &K) -> Ordering {
+        let a = a.as_ref().bytes().map(|b| b.to_ascii_lowercase());
+        let b = b.as_ref().bytes().map(|b| b.to_ascii_lowercase());
+        a.cmp(b)
+    }
+}
+
+/// Orders keys from largest to smallest by their `Ord` impl, for
+/// `OrderBy<Descending>` maps such as `FlatMap::new_desc`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Descending;
+
+impl<K: ?Sized + Ord> Compare<K> for Descending {
+    fn compare(&self, a: &K, b: &K) -> Ordering {
+        b.cmp(a)
+    }
+}
+
+impl Order for BinarySearch {
+    const SORTED: bool = true;
+    const BY_ORD: bool = true;
+}
+
+impl Order for LinearFront {
+    const SORTED: bool = true;
+    const BY_ORD: bool = true;
+}
+
+impl Order for LinearBack {
+    const SORTED: bool = true;
+    const BY_ORD: bool = true;
+}
+
+impl Order for Adaptive {
+    const SORTED: bool = true;
+    const BY_ORD: bool = true;
+}
+
+impl<L: Sorted> Order for CachedLookup<L> {
+    const SORTED: bool = true;
+    const BY_ORD: bool = true;
+}
+
+impl<L: Sorted> Order for Instrumented<L> {
+    const SORTED: bool = true;
+    const BY_ORD: bool = true;
+}
+
+impl Order for BranchlessSearch {
+    const SORTED: bool = true;
+    const BY_ORD: bool = true;
+}
+
+impl Order for ExponentialSearch {
+    const SORTED: bool = true;
+    const BY_ORD: bool = true;
+}
+
+impl Order for InterpolationSearch {
+    const SORTED: bool = true;
+    const BY_ORD: bool = true;
+}
+
+impl Order for UnsortedLinear {
+    const SORTED: bool = false;
+}
+
+impl<C> Order for OrderBy<C> {
+    const SORTED: bool = true;
+}
+
+impl Sorted for BinarySearch {}
+impl Sorted for LinearFront {}
+impl Sorted for LinearBack {}
+impl Sorted for Adaptive {}
+impl Sorted for BranchlessSearch {}
+impl<L: Sorted> Sorted for CachedLookup<L> {}
+impl<L: Sorted> Sorted for Instrumented<L> {}
+impl Sorted for ExponentialSearch {}
+impl Sorted for InterpolationSearch {}
+
+impl<S: Sorted> Ordered for S {}
+impl<C> Ordered for OrderBy<C> {}
+
+impl<K: Ord, S: Sorted> Arrange<K> for S {
+    fn arrange<V>(&self, entries: &mut Vec<(K, V)>, keep_last: bool) {
+        arrange_by(entries, keep_last, K::cmp);
+    }
+
+    fn compare_keys(&self, a: &K, b: &K) -> Ordering {
+        a.cmp(b)
+    }
+
+    fn merge<V, I, F>(&self, entries: &mut Vec<(K, V)>, other: I, f: F)
+        where I: IntoIterator<Item = (K, V)>,
+              F: FnMut(&K, V, V) -> V
+    {
+        merge_by(entries, other, f, K::cmp);
+    }
+}
+
+impl<K, C: Compare<K>> Arrange<K> for OrderBy<C> {
+    fn arrange<V>(&self, entries: &mut Vec<(K, V)>, keep_last: bool) {
+        arrange_by(entries, keep_last, |a, b| self.0.compare(a, b));
+    }
+
+    fn compare_keys(&self, a: &K, b: &K) -> Ordering {
+        self.0.compare(a, b)
+    }
+
+    fn merge<V, I, F>(&self, entries: &mut Vec<(K, V)>, other: I, f: F)
+        where I: IntoIterator<Item = (K, V)>,
+              F: FnMut(&K, V, V) -> V
+    {
+        merge_by(entries, other, f, |a, b| self.0.compare(a, b));
+    }
+}
+
+// Sorts `entries` by key with `cmp` and removes duplicate keys, keeping the
+// first or last of each.
+fn arrange_by<K, V, C>(entries: &mut Vec<(K, V)>, keep_last: bool, mut cmp: C)
+    where C: FnMut(&K, &K) -> Ordering
+{
+    // input that comes out of another sorted structure needs no sorting
+    if !entries.windows(2).all(|w| cmp(&w[0].0, &w[1].0) != Ordering::Greater) {
+        entries.sort_by(|kv1, kv2| cmp(&kv1.0, &kv2.0));
+    }
+    // the sort is stable, so the last of each run of equal keys is the
+    // one that came last
+    entries.dedup_by(|later, earlier| {
+        if cmp(&later.0, &earlier.0) == Ordering::Equal {
+            if keep_last {
+                mem::swap(later, earlier);
+            }
+            true
+        } else {
+            false
+        }
+    });
+}
+
+// The capacity for a fresh allocation holding the entries of `v` and
+// `additional` more: the spare room `v` had if they fit in it, or else grown
+// like `Vec` would, so that merging batch after batch is amortized.
+pub(crate) fn merged_capacity<T>(v: &Vec<T>, additional: usize) -> usize {
+    let needed = v.len().saturating_add(additional);
+    if needed <= v.capacity() {
+        v.capacity()
+    } else {
+        cmp::max(needed, v.capacity().saturating_mul(2))
+    }
+}
+
+// Merges the sorted, distinct entries of `other` into `entries`, which are
+// sorted by `cmp` too.
+fn merge_by<K, V, I, F, C>(entries: &mut Vec<(K, V)>, other: I, mut f: F, mut cmp: C)
+    where I: IntoIterator<Item = (K, V)>,
+          F: FnMut(&K, V, V) -> V,
+          C: FnMut(&K, &K) -> Ordering
+{
+    let old = mem::take(entries);
+    let other = other.into_iter();
+    let mut merged = Vec::with_capacity(merged_capacity(&old, other.size_hint().0));
+    let mut a = old.into_iter().peekable();
+    let mut b = other.peekable();
+    loop {
+        let ord = match (a.peek(), b.peek()) {
+            (Some(x), Some(y)) => cmp(&x.0, &y.0),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => break,
+        };
+        match ord {
+            Ordering::Less => merged.extend(a.next()),
+            Ordering::Greater => merged.extend(b.next()),
+            Ordering::Equal => {
+                if let (Some((k, old)), Some((_, new))) = (a.next(), b.next()) {
+                    let v = f(&k, old, new);
+                    merged.push((k, v));
+                }
+            }
+        }
+    }
+    *entries = merged;
+}
+
+impl<K: PartialEq> Arrange<K> for UnsortedLinear {
+    fn arrange<V>(&self, entries: &mut Vec<(K, V)>, keep_last: bool) {
+        let all = mem::replace(entries, Vec::with_capacity(entries.len()));
+        for (k, v) in all {
+            match entries.iter().position(|e| e.0 == k) {
+                Some(i) if keep_last => entries[i].1 = v,
+                Some(_) => {}
+                None => entries.push((k, v)),
+            }
+        }
+    }
+
+    fn compare_keys(&self, a: &K, b: &K) -> Ordering {
+        if a == b { Ordering::Equal } else { Ordering::Less }
+    }
+
+    fn merge<V, I, F>(&self, entries: &mut Vec<(K, V)>, other: I, mut f: F)
+        where I: IntoIterator<Item = (K, V)>,
+              F: FnMut(&K, V, V) -> V
+    {
+        let mut v = mem::take(entries);
+        for (k, new) in other {
+            match v.iter().position(|e| e.0 == k) {
+                Some(i) => {
+                    // move the old entry to the end to take its value by
+                    // value, then swap the result back into its place
+                    let (k, old) = v.swap_remove(i);
+                    let value = f(&k, old, new);
+                    v.push((k, value));
+                    let last = v.len() - 1;
+                    v.swap(i, last);
+                }
+                None => v.push((k, new)),
+            }
+        }
+        *entries = v;
+    }
+}
+
+impl<K, V, Q> Lookup<K, V, Q> for BinarySearch
+    where K: Ord,
+          Q: ?Sized + Comparable<K>
+{
+    fn lookup(&self, slice: &[(K, V)], q: &Q) -> Result<usize, usize> {
+        slice.binary_search_by(|(k, _)| q.compare(k).reverse())
+    }
+
+    fn lookup_hint(&self, slice: &[(K, V)], q: &Q, hint: usize) -> Result<usize, usize> {
+        ExponentialSearch.lookup_from(slice, q, hint)
+    }
+}
+
+impl<K, V, Q> Lookup<K, V, Q> for LinearFront
+    where K: Ord,
+          Q: ?Sized + Comparable<K>
+{
+    fn lookup(&self, slice: &[(K, V)], q: &Q) -> Result<usize, usize> {
+        for (i, (k, _)) in slice.iter().enumerate() {
+            match q.compare(k).reverse() {
+                Ordering::Less => {}
+                Ordering::Equal => return Ok(i),
+                Ordering::Greater => return Err(i),
+            }
+        }
+        Err(slice.len())
+    }
+
+    fn lookup_hint(&self, slice: &[(K, V)], q: &Q, hint: usize) -> Result<usize, usize> {
+        ExponentialSearch.lookup_from(slice, q, hint)
+    }
+}
+
+impl<K, V, Q> Lookup<K, V, Q> for Adaptive
+    where K: Ord,
+          Q: ?Sized + Comparable<K>
+{
+    fn lookup(&self, slice: &[(K, V)], q: &Q) -> Result<usize, usize> {
+        if slice.len() <= self.threshold {
+            LinearFront.lookup(slice, q)
+        } else {
+            BinarySearch.lookup(slice, q)
+        }
+    }
+
+    fn lookup_hint(&self, slice: &[(K, V)], q: &Q, hint: usize) -> Result<usize, usize> {
+        ExponentialSearch.lookup_from(slice, q, hint)
+    }
+}
+
+impl<K, V, Q, L> Lookup<K, V, Q> for CachedLookup<L>
+    where K: Ord,
+          Q: ?Sized + Comparable<K>,
+          L: Lookup<K, V, Q> + Sorted
+{
+    fn lookup(&self, slice: &[(K, V)], q: &Q) -> Result<usize, usize> {
+        let last = self.last.load(AtomicOrdering::Relaxed);
+        // the same key, then the next one, then the previous one; `last`
+        // is at most the length of some slice, so adding one cannot wrap
+        for &i in &[last, last + 1, last.wrapping_sub(1)] {
+            match slice.get(i) {
+                Some((k, _)) if q.compare(k) == Ordering::Equal => {
+                    self.last.store(i, AtomicOrdering::Relaxed);
+                    return Ok(i);
+                }
+                _ => {}
+            }
+        }
+        let found = self.inner.lookup(slice, q);
+        self.last.store(found.unwrap_or_else(|i| i), AtomicOrdering::Relaxed);
+        found
+    }
+
+    fn lookup_hint(&self, slice: &[(K, V)], q: &Q, hint: usize) -> Result<usize, usize> {
+        let found = self.inner.lookup_hint(slice, q, hint);
+        self.last.store(found.unwrap_or_else(|i| i), AtomicOrdering::Relaxed);
+        found
+    }
+}
+
+impl<K, V, Q, L> Lookup<K, V, Q> for Instrumented<L>
+    where K: Ord,
+          Q: ?Sized,
+          L: for<'c> Lookup<K, V, Counted<'c, Q>> + Sorted
+{
+    fn lookup(&self, slice: &[(K, V)], q: &Q) -> Result<usize, usize> {
+        let n = Cell::new(0);
+        let found = self.inner.lookup(slice, &Counted { q, n: &n });
+        self.record(found, n.get())
+    }
+
+    fn lookup_hint(&self, slice: &[(K, V)], q: &Q, hint: usize) -> Result<usize, usize> {
+        let n = Cell::new(0);
+        let found = self.inner.lookup_hint(slice, &Counted { q, n: &n }, hint);
+        self.record(found, n.get())
+    }
+}
+
+impl<K, V, Q> Lookup<K, V, Q> for LinearBack
+    where K: Ord,
+          Q: ?Sized + Comparable<K>
+{
+    fn lookup(&self, slice: &[(K, V)], q: &Q) -> Result<usize, usize> {
+        for (i, (k, _)) in slice.iter().enumerate().rev() {
+            match q.compare(k).reverse() {
+                Ordering::Greater => {}
+                Ordering::Equal => return Ok(i),
+                Ordering::Less => return Err(i + 1),
+            }
+        }
+        Err(0)
+    }
+
+    fn lookup_hint(&self, slice: &[(K, V)], q: &Q, hint: usize) -> Result<usize, usize> {
+        ExponentialSearch.lookup_from(slice, q, hint)
+    }
+}
+
+// Hints the cache to load the line at `p`, where the target has a way to.
+// Prefetching never faults, so `p` need not be valid.
+#[inline(always)]
+pub(crate) fn prefetch<T>(p: *const T) {
+    #[cfg(target_arch = "x86_64")]
+    {
+        use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+        // sse is part of the x86_64 baseline, and a prefetch reads nothing
+        #[allow(unused_unsafe)]
+        unsafe { _mm_prefetch::<_MM_HINT_T0>(p as *const i8) };
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    let _ = p;
+}
+
+impl<K, V, Q> Lookup<K, V, Q> for BranchlessSearch
+    where K: Ord,
+          Q: ?Sized + Comparable<K>
+{
+    fn lookup(&self, slice: &[(K, V)], q: &Q) -> Result<usize, usize> {
+        if slice.is_empty() {
+            return Err(0);
+        }
+        // `base` is the last entry whose key is at most `q`, if there is
+        // one, and stays within base..base + len
+        let mut base = 0;
+        let mut len = slice.len();
+        while len > 1 {
+            let half = len / 2;
+            let next = (len - half) / 2;
+            prefetch(slice.as_ptr().wrapping_add(base + next));
+            prefetch(slice.as_ptr().wrapping_add(base + half + next));
+            let reached = q.compare(&slice[base + half].0) != Ordering::Less;
+            base = if reached { base + half } else { base };
+            len -= half;
+        }
+        match q.compare(&slice[base].0) {
+            Ordering::Equal => Ok(base),
+            Ordering::Greater => Err(base + 1),
+            Ordering::Less => Err(base),
+        }
+    }
+}
+
+impl ExponentialSearch {
+    /// Searches `slice` like `lookup`, galloping out from `hint` in the
+    /// direction of `q` rather than from the front. Costs O(log d) where d
+    /// is the distance from `hint` to the result, so passing the result of
+    /// the previous query makes clustered queries cheap. A `hint` past the
+    /// end is treated as `slice.len()`.
+    ///
+    /// ```
+    /// use flat_map::ExponentialSearch;
+    ///
+    /// let entries: Vec<(u32, ())> = (0..100).map(|k| (k * 2, ())).collect();
+    /// let search = ExponentialSearch;
+    /// assert_eq!(search.lookup_from(&entries, &60, 25), Ok(30));
+    /// assert_eq!(search.lookup_from(&entries, &61, 40), Err(31));
+    /// assert_eq!(search.lookup_from(&entries, &500, 0), Err(100));
+    /// ```
+    pub fn lookup_from<K, V, Q>(&self, slice: &[(K, V)], q: &Q, hint: usize)
+        -> Result<usize, usize>
+        where K: Ord,
+              Q: ?Sized + Comparable<K>
+    {
+        let hint = hint.min(slice.len());
+        let (lo, hi) = match slice.get(hint).map(|(k, _)| q.compare(k)) {
+            Some(Ordering::Equal) => return Ok(hint),
+            Some(Ordering::Greater) => {
+                // the key is past the hint: gallop towards the end
+                let mut lo = hint + 1;
+                let mut step = 1;
+                loop {
+                    let i = hint + step;
+                    if i >= slice.len() {
+                        break (lo, slice.len());
+                    }
+                    match q.compare(&slice[i].0) {
+                        Ordering::Equal => return Ok(i),
+                        Ordering::Less => break (lo, i),
+                        Ordering::Greater => lo = i + 1,
+                    }
+                    step *= 2;
+                }
+            }
+            _ => {
+                // the key is before the hint: gallop towards the front
+                let mut hi = hint;
+                let mut step = 1;
+                loop {
+                    if step > hint {
+                        break (0, hi);
+                    }
+                    let i = hint - step;
+                    match q.compare(&slice[i].0) {
+                        Ordering::Equal => return Ok(i),
+                        Ordering::Greater => break (i + 1, hi),
+                        Ordering::Less => hi = i,
+                    }
+                    step *= 2;
+                }
+            }
+        };
+        match slice[lo..hi].binary_search_by(|(k, _)| q.compare(k).reverse()) {
+            Ok(i) => Ok(lo + i),
+            Err(i) => Err(lo + i),
+        }
+    }
+}
+
+impl<K, V, Q> Lookup<K, V, Q> for ExponentialSearch
+    where K: Ord,
+          Q: ?Sized + Comparable<K>
+{
+    fn lookup(&self, slice: &[(K, V)], q: &Q) -> Result<usize, usize> {
+        self.lookup_from(slice, q, 0)
+    }
+
+    fn lookup_hint(&self, slice: &[(K, V)], q: &Q, hint: usize) -> Result<usize, usize> {
+        ExponentialSearch.lookup_from(slice, q, hint)
+    }
+}
+
+impl<K, V, Q> Lookup<K, V, Q> for InterpolationSearch
+    where K: Ord + InterpolationKey,
+          Q: ?Sized + Comparable<K> + InterpolationKey
+{
+    fn lookup(&self, slice: &[(K, V)], q: &Q) -> Result<usize, usize> {
+        // how many guesses to make before falling back to binary search
+        const PROBES: usize = 8;
+
+        let x = q.interpolation_key();
+        let (mut lo, mut hi) = (0, slice.len());
+        for _ in 0..PROBES {
+            if lo == hi {
+                return Err(lo);
+            }
+            // the keys are ordered no differently, so a query outside
+            // their range can be placed without comparing it
+            let a = slice[lo].0.interpolation_key();
+            let b = slice[hi - 1].0.interpolation_key();
+            if x < a {
+                return Err(lo);
+            }
+            if x > b {
+                return Err(hi);
+            }
+            let mid = if a == b {
+                lo
+            } else {
+                let span = (hi - 1 - lo) as u128;
+                lo + ((x - a) as u128 * span / (b - a) as u128) as usize
+            };
+            match q.compare(&slice[mid].0) {
+                Ordering::Equal => return Ok(mid),
+                Ordering::Less => hi = mid,
+                Ordering::Greater => lo = mid + 1,
+            }
+        }
+        match slice[lo..hi].binary_search_by(|(k, _)| q.compare(k).reverse()) {
+            Ok(i) => Ok(lo + i),
+            Err(i) => Err(lo + i),
+        }
+    }
+}
+
+impl<K, V, Q> Lookup<K, V, Q> for UnsortedLinear
+    where K: PartialEq,
+          Q: ?Sized + Equivalent<K>
+{
+    fn lookup(&self, slice: &[(K, V)], q: &Q) -> Result<usize, usize> {
+        match slice.iter().position(|(k, _)| q.equivalent(k)) {
+            Some(i) => Ok(i),
+            None => Err(slice.len()),
+        }
+    }
+}
+
+impl<K, V, Q, C> Lookup<K, V, Q> for OrderBy<C>
+    where K: Borrow<Q>,
+          Q: ?Sized,
+          C: Compare<K> + Compare<Q>
+{
+    fn lookup(&self, slice: &[(K, V)], q: &Q) -> Result<usize, usize> {
+        slice.binary_search_by(|(k, _)| self.0.compare(k.borrow(), q))
+    }
+}
+
+impl<K, V, Q, L> DynLookup<K, V, Q> for L
+    where Q: ?Sized,
+          L: Lookup<K, V, Q> + Sorted + Clone + Send + Sync + 'static
+{
+    fn dyn_lookup(&self, slice: &[(K, V)], q: &Q) -> Result<usize, usize> {
+        self.lookup(slice, q)
+    }
+
+    fn dyn_lookup_hint(&self, slice: &[(K, V)], q: &Q, hint: usize) -> Result<usize, usize> {
+        self.lookup_hint(slice, q, hint)
+    }
+
+    fn clone_box(&self) -> BoxedLookup<K, V, Q> {
+        Box::new(self.clone())
+    }
+}
+
+impl<K, V, Q: ?Sized> Order for BoxedLookup<K, V, Q> {
+    const SORTED: bool = true;
+    const BY_ORD: bool = true;
+}
+
+impl<K, V, Q: ?Sized> Sorted for BoxedLookup<K, V, Q> {}
+
+impl<K: Ord, V, Q: ?Sized> Lookup<K, V, Q> for BoxedLookup<K, V, Q> {
+    fn lookup(&self, slice: &[(K, V)], q: &Q) -> Result<usize, usize> {
+        (**self).dyn_lookup(slice, q)
+    }
+
+    fn lookup_hint(&self, slice: &[(K, V)], q: &Q, hint: usize) -> Result<usize, usize> {
+        (**self).dyn_lookup_hint(slice, q, hint)
+    }
+}
+
+impl<K, V, Q: ?Sized> Clone for BoxedLookup<K, V, Q> {
+    fn clone(&self) -> BoxedLookup<K, V, Q> {
+        (**self).clone_box()
+    }
+}
@@ -0,0 +1,86 @@
+//! `proptest` strategies for generating maps, like those in
+//! `proptest::collection`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use proptest::arbitrary::{any_with, Arbitrary, StrategyFor};
+use proptest::collection::{btree_map, btree_set, SizeRange};
+use proptest::strategy::{Map, Strategy};
+use flat_map::FlatMap;
+use flat_set::FlatSet;
+use lookup::Arrange;
+
+/// Creates a strategy for maps with a number of entries within `size`, and
+/// keys and values drawn from `key` and `value`.
+///
+/// The maps are generated as `BTreeMap`s and converted, so they shrink the
+/// same way: towards fewer entries, then towards simpler keys and values.
+/// As with `btree_map`, fewer entries than requested are generated if `key`
+/// cannot produce enough distinct keys.
+///
+/// ```
+/// # #[macro_use] extern crate proptest;
+/// # extern crate flat_map;
+/// use flat_map::FlatMap;
+///
+/// proptest! {
+///     fn keys_are_sorted(map in flat_map::prop::flat_map(0u8..50, ".*", 0..20)) {
+///         let map: FlatMap<u8, String> = map;
+///         prop_assert!(map.keys().zip(map.keys().skip(1)).all(|(a, b)| a < b));
+///     }
+/// }
+/// # fn main() { keys_are_sorted(); }
+/// ```
+pub fn flat_map<K, V, L>(key: K,
+                         value: V,
+                         size: impl Into<SizeRange>)
+                         -> impl Strategy<Value = FlatMap<K::Value, V::Value, L>>
+    where K: Strategy,
+          V: Strategy,
+          K::Value: Ord,
+          L: Arrange<K::Value> + Default
+{
+    btree_map(key, value, size).prop_map(|m: BTreeMap<K::Value, V::Value>| m.into_iter().collect())
+}
+
+/// Creates a strategy for sets with a number of elements within `size`,
+/// drawn from `element`.
+///
+/// Like `flat_map`, the sets are generated as `BTreeSet`s and shrink the
+/// same way.
+pub fn flat_set<T, L>(element: T,
+                      size: impl Into<SizeRange>)
+                      -> impl Strategy<Value = FlatSet<T::Value, L>>
+    where T: Strategy,
+          T::Value: Ord,
+          L: Arrange<T::Value> + Default
+{
+    btree_set(element, size).prop_map(|s: BTreeSet<T::Value>| s.into_iter().collect())
+}
+
+/// `any::<FlatMap<K, V>>()` generates maps the same way as `flat_map`, with
+/// the same parameters as `BTreeMap`: a size range and the parameters for
+/// the keys and values.
+impl<K, V, L> Arbitrary for FlatMap<K, V, L>
+    where K: Arbitrary + Ord,
+          V: Arbitrary,
+          L: Arrange<K> + Default
+{
+    type Parameters = <BTreeMap<K, V> as Arbitrary>::Parameters;
+    type Strategy = Map<StrategyFor<BTreeMap<K, V>>, fn(BTreeMap<K, V>) -> Self>;
+
+    fn arbitrary_with(args: Self::Parameters) -> Self::Strategy {
+        any_with::<BTreeMap<K, V>>(args).prop_map(|m| m.into_iter().collect())
+    }
+}
+
+impl<T, L> Arbitrary for FlatSet<T, L>
+    where T: Arbitrary + Ord,
+          L: Arrange<T> + Default
+{
+    type Parameters = <BTreeSet<T> as Arbitrary>::Parameters;
+    type Strategy = Map<StrategyFor<BTreeSet<T>>, fn(BTreeSet<T>) -> Self>;
+
+    fn arbitrary_with(args: Self::Parameters) -> Self::Strategy {
+        any_with::<BTreeSet<T>>(args).prop_map(|s| s.into_iter().collect())
+    }
+}
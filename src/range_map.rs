@@ -0,0 +1,339 @@
+use std::cmp;
+use std::fmt;
+use std::fmt::Debug;
+use std::iter::{FromIterator, FusedIterator};
+use std::ops::{Range, RangeInclusive};
+use std::vec::Vec;
+use flat_map::{self, FlatMap};
+
+/// A map from half-open ranges of keys to values, stored as a `FlatMap` from
+/// the start of each range to its end and value.
+///
+/// The ranges never overlap: inserting a range overwrites whatever it
+/// covers, splitting the ranges that stick out of it. Neighbouring ranges
+/// that touch and have equal values are merged into one.
+///
+/// ```
+/// use flat_map::RangeMap;
+///
+/// let mut owners = RangeMap::new();
+/// owners.insert(0..100, "kernel");
+/// owners.insert(40..60, "driver");
+/// owners.insert(60..80, "kernel");
+/// assert_eq!(owners.get(&50), Some(&"driver"));
+/// assert_eq!(owners.get(&100), None);
+/// assert_eq!(owners.iter().collect::<Vec<_>>(), [(&0..&40, &"kernel"),
+///                                                (&40..&60, &"driver"),
+///                                                (&60..&100, &"kernel")]);
+/// ```
+pub struct RangeMap<K, V> {
+    map: FlatMap<K, (K, V)>,
+}
+
+// A range as stored in the map: its start, end and value.
+type Span<K, V> = (K, (K, V));
+
+/// The ranges of a `RangeMap` with their values, in ascending order.
+pub struct Iter<'a, K: 'a, V: 'a> {
+    inner: flat_map::Iter<'a, K, (K, V)>,
+}
+
+impl<K, V> RangeMap<K, V> {
+    /// Creates an empty map without allocating.
+    pub const fn new() -> RangeMap<K, V> {
+        RangeMap { map: FlatMap::new() }
+    }
+
+    /// The number of ranges in the map, after merging.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter { inner: self.map.iter() }
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear()
+    }
+}
+
+impl<K: Ord, V> RangeMap<K, V> {
+    /// Returns the value of the range that contains `key`.
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.get_key_value(key).map(|(_, v)| v)
+    }
+
+    /// Returns the range that contains `key`, along with its value.
+    pub fn get_key_value(&self, key: &K) -> Option<(Range<&K>, &V)> {
+        let entries = self.map.as_slice();
+        let i = entries.partition_point(|(start, _)| start <= key);
+        match i.checked_sub(1).map(|i| &entries[i]) {
+            Some((start, (end, value))) if key < end => Some((start..end, value)),
+            _ => None,
+        }
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get_key_value(key).is_some()
+    }
+}
+
+impl<K: Ord + Clone, V: PartialEq + Clone> RangeMap<K, V> {
+    /// Maps every key in `range` to `value`, replacing whatever those keys
+    /// were mapped to. Takes O(n) time, moving the tail of the map at most
+    /// twice.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range` is empty.
+    pub fn insert(&mut self, range: Range<K>, value: V) {
+        assert!(range.start < range.end, "range is empty");
+        let (mut start, mut end) = (range.start, range.end);
+        let (bound, removed) = self.take_touching(&start, &end);
+        let (mut left, mut right) = (None, None);
+        for (s, (e, v)) in removed {
+            if v == value {
+                start = cmp::min(start, s);
+                end = cmp::max(end, e);
+            } else {
+                outside((s, (e, v)), &start, &end, &mut left, &mut right);
+            }
+        }
+        let pieces = left.into_iter().chain(Some((start, (end, value)))).chain(right);
+        self.map.splice(bound, pieces);
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> RangeMap<K, V> {
+    /// Removes every key in `range` from the map, shortening or splitting
+    /// the ranges that stick out of it. An empty `range` removes nothing.
+    pub fn remove(&mut self, range: Range<K>) {
+        if range.start >= range.end {
+            return;
+        }
+        let (bound, removed) = self.take_touching(&range.start, &range.end);
+        let (mut left, mut right) = (None, None);
+        for entry in removed {
+            outside(entry, &range.start, &range.end, &mut left, &mut right);
+        }
+        self.map.splice(bound, left.into_iter().chain(right));
+    }
+
+    // Removes the ranges that overlap or touch `start..end`, returning them
+    // along with a key range that contains their starts and `start..=end`,
+    // for putting the pieces back.
+    fn take_touching(&mut self, start: &K, end: &K) -> (RangeInclusive<K>, Vec<Span<K, V>>) {
+        let entries = self.map.as_slice();
+        // the ends are sorted as well, since the ranges do not overlap
+        let lo = entries.partition_point(|(_, (e, _))| e < start);
+        let from = match entries.get(lo) {
+            Some((s, _)) if s < start => s.clone(),
+            _ => start.clone(),
+        };
+        let bound = from..=end.clone();
+        let removed = self.map.splice(bound.clone(), None).collect();
+        (bound, removed)
+    }
+}
+
+// Keeps the parts of a range that stick out of `start..end` on the left and
+// on the right, with its value. Only the first of the ranges touching
+// `start..end` can stick out on the left, and only the last on the right.
+fn outside<K, V>((s, (e, v)): Span<K, V>,
+                 start: &K,
+                 end: &K,
+                 left: &mut Option<Span<K, V>>,
+                 right: &mut Option<Span<K, V>>)
+    where K: Ord + Clone,
+          V: Clone
+{
+    if e > *end {
+        *right = Some((cmp::max(&s, end).clone(), (e, v.clone())));
+    }
+    if s < *start {
+        *left = Some((s, (start.clone(), v)));
+    }
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (Range<&'a K>, &'a V);
+
+    fn next(&mut self) -> Option<(Range<&'a K>, &'a V)> {
+        self.inner.next().map(|(start, (end, v))| (start..end, v))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, K, V> Clone for Iter<'a, K, V> {
+    fn clone(&self) -> Iter<'a, K, V> {
+        Iter { inner: self.inner.clone() }
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for Iter<'a, K, V> {
+    fn next_back(&mut self) -> Option<(Range<&'a K>, &'a V)> {
+        self.inner.next_back().map(|(start, (end, v))| (start..end, v))
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for Iter<'a, K, V> {}
+impl<'a, K, V> FusedIterator for Iter<'a, K, V> {}
+
+impl<'a, K, V> IntoIterator for &'a RangeMap<K, V> {
+    type Item = (Range<&'a K>, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+// Ranges are inserted in order, so later ones overwrite earlier ones where
+// they overlap.
+impl<K: Ord + Clone, V: PartialEq + Clone> FromIterator<(Range<K>, V)> for RangeMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (Range<K>, V)>>(iter: I) -> RangeMap<K, V> {
+        let mut map = RangeMap::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K: Ord + Clone, V: PartialEq + Clone> Extend<(Range<K>, V)> for RangeMap<K, V> {
+    fn extend<I: IntoIterator<Item = (Range<K>, V)>>(&mut self, iter: I) {
+        for (range, value) in iter {
+            self.insert(range, value);
+        }
+    }
+}
+
+impl<K: Clone, V: Clone> Clone for RangeMap<K, V> {
+    fn clone(&self) -> RangeMap<K, V> {
+        RangeMap { map: self.map.clone() }
+    }
+}
+
+impl<K, V> Default for RangeMap<K, V> {
+    fn default() -> RangeMap<K, V> {
+        RangeMap::new()
+    }
+}
+
+impl<K: Ord, V: PartialEq> PartialEq for RangeMap<K, V> {
+    fn eq(&self, other: &RangeMap<K, V>) -> bool {
+        self.map == other.map
+    }
+}
+
+impl<K: Ord, V: Eq> Eq for RangeMap<K, V> {}
+
+impl<K: Debug, V: Debug> Debug for RangeMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(feature = "serde1")]
+mod serde_impl {
+    // serialized as a sequence of (start, end, value) triples in ascending
+    // order; on the way back they are inserted in order, so later ranges
+    // overwrite earlier ones where they overlap
+    use std::fmt;
+    use std::marker::PhantomData;
+    use serde::de::{Deserialize, Deserializer, Error, SeqAccess, Visitor};
+    use serde::{Serialize, Serializer};
+    use super::RangeMap;
+
+    impl<K: Serialize, V: Serialize> Serialize for RangeMap<K, V> {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where S: Serializer
+        {
+            serializer.collect_seq(self.iter().map(|(range, v)| (range.start, range.end, v)))
+        }
+    }
+
+    struct RangeMapVisitor<K, V> {
+        marker: PhantomData<RangeMap<K, V>>,
+    }
+
+    impl<'de, K, V> Visitor<'de> for RangeMapVisitor<K, V>
+        where K: Ord + Clone + Deserialize<'de>,
+              V: PartialEq + Clone + Deserialize<'de>
+    {
+        type Value = RangeMap<K, V>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a sequence of (start, end, value) triples")
+        }
+
+        fn visit_seq<A>(self, mut access: A) -> Result<Self::Value, A::Error>
+            where A: SeqAccess<'de>
+        {
+            let mut map = RangeMap::new();
+            while let Some((start, end, value)) = access.next_element::<(K, K, V)>()? {
+                if start >= end {
+                    return Err(A::Error::custom("empty range"));
+                }
+                map.insert(start..end, value);
+            }
+            Ok(map)
+        }
+    }
+
+    impl<'de, K, V> Deserialize<'de> for RangeMap<K, V>
+        where K: Ord + Clone + Deserialize<'de>,
+              V: PartialEq + Clone + Deserialize<'de>
+    {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where D: Deserializer<'de>
+        {
+            deserializer.deserialize_seq(RangeMapVisitor { marker: PhantomData })
+        }
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+mod arbitrary_impl {
+    // generated as (start, end, value) triples, skipping the empty ranges
+    // that `insert` would reject
+    use arbitrary::{Arbitrary, Result, Unstructured};
+    use super::RangeMap;
+
+    fn insert_all<K, V, I>(triples: I) -> Result<RangeMap<K, V>>
+        where K: Ord + Clone,
+              V: PartialEq + Clone,
+              I: Iterator<Item = Result<(K, K, V)>>
+    {
+        let mut map = RangeMap::new();
+        for triple in triples {
+            let (start, end, value) = triple?;
+            if start < end {
+                map.insert(start..end, value);
+            }
+        }
+        Ok(map)
+    }
+
+    impl<'a, K, V> Arbitrary<'a> for RangeMap<K, V>
+        where K: Arbitrary<'a> + Ord + Clone,
+              V: Arbitrary<'a> + PartialEq + Clone
+    {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            insert_all(u.arbitrary_iter()?)
+        }
+
+        fn arbitrary_take_rest(u: Unstructured<'a>) -> Result<Self> {
+            insert_all(u.arbitrary_take_rest_iter()?)
+        }
+
+        fn size_hint(_depth: usize) -> (usize, Option<usize>) {
+            (0, None)
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! Controls what deserializing a `FlatMap` does with duplicate keys, for
+//! use with `#[serde(with = "...")]`. The plain `Deserialize` impl keeps the
+//! last value for a key, as repeated inserts would; `first` keeps the first
+//! one instead, and `deny` fails, which suits untrusted input. `last` is the
+//! default behaviour, for symmetry.
+//!
+//! Maps are serialized as with the plain `Serialize` impl, and accepted as
+//! a map or a sequence of pairs in the same way.
+//!
+//! ```
+//! # extern crate flat_map;
+//! # #[macro_use] extern crate serde_derive;
+//! # extern crate serde_json;
+//! use flat_map::FlatMap;
+//!
+//! #[derive(Debug, Deserialize)]
+//! struct Config {
+//!     #[serde(with = "flat_map::serde_dup::deny")]
+//!     limits: FlatMap<String, u32>,
+//! }
+//!
+//! # fn main() {
+//! let ok: Config = serde_json::from_str(r#"{"limits":{"a":1,"b":2}}"#).unwrap();
+//! assert_eq!(ok.limits["b"], 2);
+//! let err = serde_json::from_str::<Config>(r#"{"limits":{"a":1,"a":2}}"#).unwrap_err();
+//! assert!(err.to_string().contains("duplicate key"));
+//! # }
+//! ```
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::vec::Vec;
+use serde::de::{Deserializer, Error, MapAccess, SeqAccess, Visitor};
+use serde::Deserialize;
+use flat_map::FlatMap;
+use lookup::Arrange;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Duplicates {
+    KeepFirst,
+    KeepLast,
+    Deny,
+}
+
+struct DedupVisitor<K, V, L> {
+    duplicates: Duplicates,
+    marker: PhantomData<FlatMap<K, V, L>>,
+}
+
+impl<K, V, L: Arrange<K> + Default> DedupVisitor<K, V, L> {
+    fn finish<E: Error>(&self, mut v: Vec<(K, V)>) -> Result<FlatMap<K, V, L>, E> {
+        let l = L::default();
+        let len = v.len();
+        l.arrange(&mut v, self.duplicates == Duplicates::KeepLast);
+        // arranging drops an entry for every repeated key
+        if self.duplicates == Duplicates::Deny && v.len() != len {
+            return Err(E::custom("duplicate key in map"));
+        }
+        Ok(FlatMap::from_arranged(v, l))
+    }
+}
+
+impl<'de, K, V, L> Visitor<'de> for DedupVisitor<K, V, L>
+    where K: Deserialize<'de>,
+          V: Deserialize<'de>,
+          L: Arrange<K> + Default
+{
+    type Value = FlatMap<K, V, L>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a flat_map")
+    }
+
+    fn visit_map<M>(self, mut access: M) -> Result<Self::Value, M::Error>
+        where M: MapAccess<'de>
+    {
+        let mut v = Vec::with_capacity(access.size_hint().unwrap_or(0));
+        while let Some(entry) = access.next_entry()? {
+            v.push(entry);
+        }
+        self.finish(v)
+    }
+
+    fn visit_seq<A>(self, mut access: A) -> Result<Self::Value, A::Error>
+        where A: SeqAccess<'de>
+    {
+        let mut v = Vec::with_capacity(access.size_hint().unwrap_or(0));
+        while let Some(entry) = access.next_element()? {
+            v.push(entry);
+        }
+        self.finish(v)
+    }
+}
+
+fn deserialize_with<'de, K, V, L, D>(deserializer: D, duplicates: Duplicates)
+    -> Result<FlatMap<K, V, L>, D::Error>
+    where K: Deserialize<'de>,
+          V: Deserialize<'de>,
+          L: Arrange<K> + Default,
+          D: Deserializer<'de>
+{
+    let visitor = DedupVisitor { duplicates, marker: PhantomData };
+    // the same choice of representation as the plain impl
+    if deserializer.is_human_readable() {
+        deserializer.deserialize_any(visitor)
+    } else {
+        deserializer.deserialize_seq(visitor)
+    }
+}
+
+macro_rules! dedup_module {
+    ($(#[$attr:meta])* $name:ident, $duplicates:expr) => {
+        $(#[$attr])*
+        pub mod $name {
+            use serde::{Deserialize, Deserializer, Serialize, Serializer};
+            use flat_map::FlatMap;
+            use lookup::Arrange;
+            use super::Duplicates;
+
+            pub fn serialize<K, V, L, S>(map: &FlatMap<K, V, L>, serializer: S)
+                -> Result<S::Ok, S::Error>
+                where K: Ord + Serialize,
+                      V: Serialize,
+                      S: Serializer
+            {
+                map.serialize(serializer)
+            }
+
+            pub fn deserialize<'de, K, V, L, D>(deserializer: D)
+                -> Result<FlatMap<K, V, L>, D::Error>
+                where K: Deserialize<'de>,
+                      V: Deserialize<'de>,
+                      L: Arrange<K> + Default,
+                      D: Deserializer<'de>
+            {
+                super::deserialize_with(deserializer, $duplicates)
+            }
+        }
+    }
+}
+
+dedup_module!(
+    /// Keeps the first value for a repeated key.
+    first, Duplicates::KeepFirst);
+dedup_module!(
+    /// Keeps the last value for a repeated key, like the plain impl.
+    last, Duplicates::KeepLast);
+dedup_module!(
+    /// Fails on a repeated key.
+    deny, Duplicates::Deny);
@@ -0,0 +1,80 @@
+//! Serializes a `FlatMap` as a sequence of `(key, value)` pairs instead of a
+//! map, for use with `#[serde(with = "flat_map::serde_seq")]`.
+//!
+//! Formats that only allow string map keys, such as JSON, can then hold maps
+//! with any key type, and binary formats skip the map framing. Decoding
+//! accepts pairs in any order; of pairs with equal keys the last one wins,
+//! as with the map representation.
+//!
+//! ```
+//! # extern crate flat_map;
+//! # #[macro_use] extern crate serde_derive;
+//! # extern crate serde_json;
+//! use flat_map::FlatMap;
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Index {
+//!     #[serde(with = "flat_map::serde_seq")]
+//!     offsets: FlatMap<u64, u32>,
+//! }
+//!
+//! # fn main() {
+//! let index = Index { offsets: vec![(7, 1), (3, 2)].into_iter().collect() };
+//! let json = serde_json::to_string(&index).unwrap();
+//! assert_eq!(json, r#"{"offsets":[[3,2],[7,1]]}"#);
+//! # }
+//! ```
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::vec::Vec;
+use serde::de::{Deserialize, Deserializer, SeqAccess, Visitor};
+use serde::{Serialize, Serializer};
+use flat_map::FlatMap;
+use lookup::Lookup;
+
+pub fn serialize<K, V, L, S>(map: &FlatMap<K, V, L>, serializer: S) -> Result<S::Ok, S::Error>
+    where K: Serialize,
+          V: Serialize,
+          S: Serializer
+{
+    serializer.collect_seq(map.iter())
+}
+
+pub fn deserialize<'de, K, V, L, D>(deserializer: D) -> Result<FlatMap<K, V, L>, D::Error>
+    where K: Deserialize<'de>,
+          V: Deserialize<'de>,
+          L: Lookup<K, V, K> + Default,
+          D: Deserializer<'de>
+{
+    deserializer.deserialize_seq(PairsVisitor { marker: PhantomData })
+}
+
+struct PairsVisitor<K, V, L> {
+    marker: PhantomData<FlatMap<K, V, L>>,
+}
+
+impl<'de, K, V, L> Visitor<'de> for PairsVisitor<K, V, L>
+    where K: Deserialize<'de>,
+          V: Deserialize<'de>,
+          L: Lookup<K, V, K> + Default
+{
+    type Value = FlatMap<K, V, L>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a sequence of key-value pairs")
+    }
+
+    fn visit_seq<A>(self, mut access: A) -> Result<Self::Value, A::Error>
+        where A: SeqAccess<'de>
+    {
+        let mut v = Vec::with_capacity(access.size_hint().unwrap_or(0));
+        while let Some(entry) = access.next_element()? {
+            v.push(entry);
+        }
+        // extending arranges the pairs once, keeping the last of equal keys
+        let mut map = FlatMap::with_lookup(L::default());
+        map.extend(v);
+        Ok(map)
+    }
+}
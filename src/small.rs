@@ -0,0 +1,958 @@
+//! Maps with the semantics of `FlatMap` that keep their entries in a
+//! fixed-size array instead of a heap-allocated vector: `SmallFlatMap`, which
+//! holds at most `N` entries, `SpillFlatMap`, which moves to the heap when it
+//! outgrows them, and `SliceFlatMap`, which lives in a buffer supplied by the
+//! caller.
+
+use std::borrow::Borrow;
+use std::cmp;
+use std::fmt;
+use std::fmt::Debug;
+use std::iter::FromIterator;
+use std::mem;
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::slice;
+use std::vec::Vec;
+use errors::CapacityError;
+use flat_map::{same_entries, FlatMap, Iter, IterMut};
+use lookup::{Arrange, BinarySearch, LinearFront, Lookup};
+
+/// A map of at most `N` entries stored inline, for when allocation is not
+/// available or not wanted.
+///
+/// Keys are found with the lookup strategy `L`, as in `FlatMap`. The default
+/// is `LinearFront`, which suits the small sizes this map is meant for.
+/// Inserting a new key into a full map fails and hands the entry back.
+///
+/// ```
+/// use flat_map::SmallFlatMap;
+///
+/// const EMPTY: SmallFlatMap<u8, u32, 4> = SmallFlatMap::new();
+/// assert!(EMPTY.is_empty());
+///
+/// let mut regs: SmallFlatMap<u8, u32, 2> = SmallFlatMap::new();
+/// regs.insert(0x10, 7).unwrap();
+/// regs.insert(0x04, 9).unwrap();
+/// assert_eq!(regs.insert(0x20, 1).unwrap_err().into_inner(), (0x20, 1));
+/// assert_eq!(regs.as_slice(), [(0x04, 9), (0x10, 7)]);
+/// ```
+pub struct SmallFlatMap<K, V, const N: usize, L = LinearFront> {
+    // the first `len` slots are initialized
+    entries: [MaybeUninit<(K, V)>; N],
+    len: usize,
+    l: L,
+}
+
+pub enum Entry<'a, K: 'a, V: 'a, const N: usize> {
+    Vacant(VacantEntry<'a, K, V, N>),
+    Occupied(OccupiedEntry<'a, K, V, N>),
+}
+
+pub struct VacantEntry<'a, K: 'a, V: 'a, const N: usize> {
+    map: Slots<'a, K, V>,
+    key: K,
+    index: usize,
+}
+
+pub struct OccupiedEntry<'a, K: 'a, V: 'a, const N: usize> {
+    map: Slots<'a, K, V>,
+    index: usize,
+}
+
+// The storage of a map, without its lookup strategy, so that entries need
+// not carry `L`. The capacity is the number of slots.
+struct Slots<'a, K: 'a, V: 'a> {
+    entries: &'a mut [MaybeUninit<(K, V)>],
+    len: &'a mut usize,
+}
+
+impl<K, V, const N: usize> SmallFlatMap<K, V, N> {
+    pub const fn new() -> Self {
+        SmallFlatMap::with_lookup(LinearFront)
+    }
+}
+
+impl<K, V, const N: usize, L> SmallFlatMap<K, V, N, L> {
+    pub const fn with_lookup(l: L) -> Self {
+        SmallFlatMap {
+            entries: [const { MaybeUninit::uninit() }; N],
+            len: 0,
+            l,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The most entries the map can hold, `N`.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    pub fn clear(&mut self) {
+        self.slots().truncate(0);
+    }
+
+    /// The entries, in the order of the lookup strategy.
+    pub fn as_slice(&self) -> &[(K, V)] {
+        // the first `len` slots are initialized
+        unsafe { slice::from_raw_parts(self.entries.as_ptr() as *const (K, V), self.len) }
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter { inner: self.as_slice().iter() }
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        IterMut { inner: self.slots().into_mut_slice().iter_mut() }
+    }
+
+    pub fn get<Q: ?Sized>(&self, q: &Q) -> Option<&V>
+        where K: Borrow<Q>,
+              L: Lookup<K, V, Q>
+    {
+        match self.l.lookup(self.as_slice(), q) {
+            Ok(i) => Some(&self.as_slice()[i].1),
+            Err(_) => None,
+        }
+    }
+
+    pub fn get_mut<Q: ?Sized>(&mut self, q: &Q) -> Option<&mut V>
+        where K: Borrow<Q>,
+              L: Lookup<K, V, Q>
+    {
+        match self.l.lookup(self.as_slice(), q) {
+            Ok(i) => Some(&mut self.slots().into_mut_slice()[i].1),
+            Err(_) => None,
+        }
+    }
+
+    pub fn contains_key<Q: ?Sized>(&self, q: &Q) -> bool
+        where K: Borrow<Q>,
+              L: Lookup<K, V, Q>
+    {
+        self.l.lookup(self.as_slice(), q).is_ok()
+    }
+
+    /// Inserts a key-value pair, returning the old value if the key was
+    /// already present. Fails and hands the pair back if the key is new and
+    /// the map is full.
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, CapacityError<(K, V)>>
+        where L: Lookup<K, V, K>
+    {
+        match self.entry(key) {
+            Entry::Occupied(mut entry) => Ok(Some(entry.insert(value))),
+            Entry::Vacant(entry) => entry.insert(value).map(|_| None),
+        }
+    }
+
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V, N>
+        where L: Lookup<K, V, K>
+    {
+        let found = self.l.lookup(self.as_slice(), &key);
+        let map = self.slots();
+        match found {
+            Ok(index) => Entry::Occupied(OccupiedEntry { map, index }),
+            Err(index) => Entry::Vacant(VacantEntry { map, key, index }),
+        }
+    }
+
+    pub fn remove<Q: ?Sized>(&mut self, q: &Q) -> Option<V>
+        where K: Borrow<Q>,
+              L: Lookup<K, V, Q>
+    {
+        self.remove_entry(q).map(|(_, v)| v)
+    }
+
+    pub fn remove_entry<Q: ?Sized>(&mut self, q: &Q) -> Option<(K, V)>
+        where K: Borrow<Q>,
+              L: Lookup<K, V, Q>
+    {
+        match self.l.lookup(self.as_slice(), q) {
+            Ok(i) => Some(self.slots().remove(i)),
+            Err(_) => None,
+        }
+    }
+
+    /// Collects the entries of `iter` into a map. Of entries with equal keys
+    /// the first one is kept, as with `FlatMap`'s `collect`.
+    ///
+    /// Fails with the first entry with a new key that does not fit.
+    pub fn try_from_iter<I>(iter: I) -> Result<Self, CapacityError<(K, V)>>
+        where I: IntoIterator<Item = (K, V)>,
+              L: Lookup<K, V, K> + Default
+    {
+        let mut map = SmallFlatMap::with_lookup(L::default());
+        for (key, value) in iter {
+            if let Entry::Vacant(entry) = map.entry(key) {
+                entry.insert(value)?;
+            }
+        }
+        Ok(map)
+    }
+
+    fn slots(&mut self) -> Slots<'_, K, V> {
+        Slots { entries: &mut self.entries, len: &mut self.len }
+    }
+
+    // Moves the entries out into a vector with room for `capacity`, leaving
+    // the map empty.
+    fn take_entries(&mut self, capacity: usize) -> Vec<(K, V)> {
+        let mut v = Vec::with_capacity(cmp::max(capacity, self.len));
+        // forget the entries first, so that a panic leaks them rather than
+        // dropping them twice
+        let len = mem::replace(&mut self.len, 0);
+        // the first `len` slots were initialized
+        v.extend(self.entries[..len].iter().map(|e| unsafe { e.assume_init_read() }));
+        v
+    }
+}
+
+impl<'a, K, V> Slots<'a, K, V> {
+    fn as_mut_slice(&mut self) -> &mut [(K, V)] {
+        // the first `len` slots are initialized
+        unsafe { slice::from_raw_parts_mut(self.entries.as_mut_ptr() as *mut (K, V), *self.len) }
+    }
+
+    fn into_mut_slice(self) -> &'a mut [(K, V)] {
+        unsafe { slice::from_raw_parts_mut(self.entries.as_mut_ptr() as *mut (K, V), *self.len) }
+    }
+
+    // Puts `entry` at `index`, shifting the entries after it. The caller
+    // checks that there is room.
+    fn insert(&mut self, index: usize, entry: (K, V)) {
+        let len = *self.len;
+        self.entries[len] = MaybeUninit::new(entry);
+        *self.len = len + 1;
+        self.as_mut_slice()[index..].rotate_right(1);
+    }
+
+    fn remove(&mut self, index: usize) -> (K, V) {
+        self.as_mut_slice()[index..].rotate_left(1);
+        *self.len -= 1;
+        // the slot was initialized and is no longer counted in `len`
+        unsafe { self.entries[*self.len].assume_init_read() }
+    }
+
+    fn truncate(&mut self, len: usize) {
+        let tail: *mut [(K, V)] = &mut self.as_mut_slice()[len..];
+        // forget the entries before dropping them, so that a panicking
+        // destructor cannot cause a double drop
+        *self.len = len;
+        unsafe { ptr::drop_in_place(tail) }
+    }
+}
+
+impl<'a, K, V, const N: usize> Entry<'a, K, V, N> {
+    pub fn key(&self) -> &K {
+        match *self {
+            Entry::Occupied(ref entry) => entry.key(),
+            Entry::Vacant(ref entry) => entry.key(),
+        }
+    }
+
+    pub fn or_insert(self, default: V) -> Result<&'a mut V, CapacityError<(K, V)>> {
+        self.or_insert_with(|| default)
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F)
+                                            -> Result<&'a mut V, CapacityError<(K, V)>> {
+        match self {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    pub fn or_default(self) -> Result<&'a mut V, CapacityError<(K, V)>>
+        where V: Default
+    {
+        self.or_insert_with(V::default)
+    }
+}
+
+impl<'a, K, V, const N: usize> VacantEntry<'a, K, V, N> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Takes back the key without inserting anything.
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    /// The index at which the entry will be inserted.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Inserts the entry, or hands it back if the map is full.
+    pub fn insert(mut self, value: V) -> Result<&'a mut V, CapacityError<(K, V)>> {
+        if *self.map.len == self.map.entries.len() {
+            return Err(CapacityError::new((self.key, value)));
+        }
+        self.map.insert(self.index, (self.key, value));
+        Ok(&mut self.map.into_mut_slice()[self.index].1)
+    }
+}
+
+impl<'a, K, V, const N: usize> OccupiedEntry<'a, K, V, N> {
+    pub fn key(&self) -> &K {
+        &self.get_entry().0
+    }
+
+    pub fn get(&self) -> &V {
+        &self.get_entry().1
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.map.as_mut_slice()[self.index].1
+    }
+
+    pub fn into_mut(self) -> &'a mut V {
+        &mut self.map.into_mut_slice()[self.index].1
+    }
+
+    /// The index of the entry in the map.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn insert(&mut self, value: V) -> V {
+        mem::replace(self.get_mut(), value)
+    }
+
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+
+    /// Removes the entry, returning the stored key along with the value.
+    pub fn remove_entry(mut self) -> (K, V) {
+        self.map.remove(self.index)
+    }
+
+    fn get_entry(&self) -> &(K, V) {
+        // the entry's slot is initialized
+        unsafe { self.map.entries[self.index].assume_init_ref() }
+    }
+}
+
+impl<K, V, const N: usize, L> Drop for SmallFlatMap<K, V, N, L> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+impl<K: Clone, V: Clone, const N: usize, L: Clone> Clone for SmallFlatMap<K, V, N, L> {
+    fn clone(&self) -> Self {
+        let mut map = SmallFlatMap::with_lookup(self.l.clone());
+        for entry in self.as_slice() {
+            let len = map.len;
+            map.slots().insert(len, entry.clone());
+        }
+        map
+    }
+}
+
+impl<K, V, const N: usize, L: Default> Default for SmallFlatMap<K, V, N, L> {
+    fn default() -> Self {
+        SmallFlatMap::with_lookup(L::default())
+    }
+}
+
+impl<K: Debug, V: Debug, const N: usize, L> Debug for SmallFlatMap<K, V, N, L> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+// Maps are equal if they hold the same entries, whatever their capacities
+// and strategies, as for `FlatMap`.
+impl<K, V, const N: usize, const M: usize, L1, L2> PartialEq<SmallFlatMap<K, V, M, L2>>
+    for SmallFlatMap<K, V, N, L1>
+    where K: PartialEq,
+          V: PartialEq,
+          L1: Arrange<K>,
+          L2: Arrange<K>
+{
+    fn eq(&self, other: &SmallFlatMap<K, V, M, L2>) -> bool {
+        self.len() == other.len() &&
+        same_entries(&self.l, &other.l, self.as_slice(), other.as_slice(), |a, b| a == b)
+    }
+}
+
+impl<K: Eq, V: Eq, const N: usize, L: Arrange<K>> Eq for SmallFlatMap<K, V, N, L> {}
+
+impl<'a, K, V, const N: usize, L> IntoIterator for &'a SmallFlatMap<K, V, N, L> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+impl<'a, K, V, const N: usize, L> IntoIterator for &'a mut SmallFlatMap<K, V, N, L> {
+    type Item = (&'a K, &'a mut V);
+    type IntoIter = IterMut<'a, K, V>;
+
+    fn into_iter(self) -> IterMut<'a, K, V> {
+        self.iter_mut()
+    }
+}
+
+/// A map that keeps up to `N` entries inline and moves them to a `FlatMap`
+/// on the heap once it outgrows them.
+///
+/// Maps that stay small never allocate, and large ones behave like
+/// `FlatMap`. Once spilled, the map stays on the heap even if entries are
+/// removed, so a map whose size hovers around `N` does not move back and
+/// forth. The lookup strategy `L` is cloned when the map spills.
+///
+/// ```
+/// use flat_map::SpillFlatMap;
+///
+/// let mut attrs: SpillFlatMap<&str, u32, 2> = SpillFlatMap::new();
+/// attrs.insert("width", 4);
+/// attrs.insert("height", 3);
+/// assert!(!attrs.spilled());
+/// attrs.insert("depth", 2);
+/// assert!(attrs.spilled());
+/// assert_eq!(attrs.get("height"), Some(&3));
+/// ```
+pub struct SpillFlatMap<K, V, const N: usize, L = LinearFront> {
+    repr: Repr<K, V, N, L>,
+}
+
+enum Repr<K, V, const N: usize, L> {
+    Inline(SmallFlatMap<K, V, N, L>),
+    Heap(FlatMap<K, V, L>),
+}
+
+impl<K, V, const N: usize> SpillFlatMap<K, V, N> {
+    pub const fn new() -> Self {
+        SpillFlatMap::with_lookup(LinearFront)
+    }
+}
+
+impl<K, V, const N: usize, L> SpillFlatMap<K, V, N, L> {
+    pub const fn with_lookup(l: L) -> Self {
+        SpillFlatMap { repr: Repr::Inline(SmallFlatMap::with_lookup(l)) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.as_slice().is_empty()
+    }
+
+    /// The number of entries the map can hold without allocating: `N` until
+    /// it spills, then the capacity of its vector.
+    pub fn capacity(&self) -> usize {
+        match self.repr {
+            Repr::Inline(_) => N,
+            Repr::Heap(ref map) => map.capacity(),
+        }
+    }
+
+    /// Returns true if the entries have moved to the heap.
+    pub fn spilled(&self) -> bool {
+        match self.repr {
+            Repr::Inline(_) => false,
+            Repr::Heap(_) => true,
+        }
+    }
+
+    /// Removes all entries, keeping the storage in use, inline or not.
+    pub fn clear(&mut self) {
+        match self.repr {
+            Repr::Inline(ref mut map) => map.clear(),
+            Repr::Heap(ref mut map) => map.clear(),
+        }
+    }
+
+    /// The entries, in the order of the lookup strategy.
+    pub fn as_slice(&self) -> &[(K, V)] {
+        match self.repr {
+            Repr::Inline(ref map) => map.as_slice(),
+            Repr::Heap(ref map) => map.as_slice(),
+        }
+    }
+
+    fn strategy(&self) -> &L {
+        match self.repr {
+            Repr::Inline(ref map) => &map.l,
+            Repr::Heap(ref map) => map.lookup(),
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter { inner: self.as_slice().iter() }
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        match self.repr {
+            Repr::Inline(ref mut map) => map.iter_mut(),
+            Repr::Heap(ref mut map) => map.iter_mut(),
+        }
+    }
+
+    pub fn get<Q: ?Sized>(&self, q: &Q) -> Option<&V>
+        where K: Borrow<Q>,
+              L: Lookup<K, V, Q>
+    {
+        match self.repr {
+            Repr::Inline(ref map) => map.get(q),
+            Repr::Heap(ref map) => map.get(q),
+        }
+    }
+
+    pub fn get_mut<Q: ?Sized>(&mut self, q: &Q) -> Option<&mut V>
+        where K: Borrow<Q>,
+              L: Lookup<K, V, Q>
+    {
+        match self.repr {
+            Repr::Inline(ref mut map) => map.get_mut(q),
+            Repr::Heap(ref mut map) => map.get_mut(q),
+        }
+    }
+
+    pub fn contains_key<Q: ?Sized>(&self, q: &Q) -> bool
+        where K: Borrow<Q>,
+              L: Lookup<K, V, Q>
+    {
+        match self.repr {
+            Repr::Inline(ref map) => map.contains_key(q),
+            Repr::Heap(ref map) => map.contains_key(q),
+        }
+    }
+
+    /// Inserts a key-value pair, returning the old value if the key was
+    /// already present. A new key that does not fit inline moves the map to
+    /// the heap.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V>
+        where L: Lookup<K, V, K> + Clone
+    {
+        let heap = match self.repr {
+            Repr::Heap(ref mut map) => return map.insert(key, value),
+            Repr::Inline(ref mut map) => {
+                let (key, value) = match map.insert(key, value) {
+                    Ok(old) => return old,
+                    Err(full) => full.into_inner(),
+                };
+                let entries = map.take_entries(N.saturating_mul(2));
+                let mut heap = FlatMap::from_arranged(entries, map.l.clone());
+                heap.insert(key, value);
+                heap
+            }
+        };
+        self.repr = Repr::Heap(heap);
+        None
+    }
+
+    pub fn remove<Q: ?Sized>(&mut self, q: &Q) -> Option<V>
+        where K: Borrow<Q>,
+              L: Lookup<K, V, Q>
+    {
+        self.remove_entry(q).map(|(_, v)| v)
+    }
+
+    pub fn remove_entry<Q: ?Sized>(&mut self, q: &Q) -> Option<(K, V)>
+        where K: Borrow<Q>,
+              L: Lookup<K, V, Q>
+    {
+        match self.repr {
+            Repr::Inline(ref mut map) => map.remove_entry(q),
+            Repr::Heap(ref mut map) => map.remove_entry(q),
+        }
+    }
+}
+
+impl<K: Clone, V: Clone, const N: usize, L: Clone> Clone for SpillFlatMap<K, V, N, L> {
+    fn clone(&self) -> Self {
+        let repr = match self.repr {
+            Repr::Inline(ref map) => Repr::Inline(map.clone()),
+            Repr::Heap(ref map) => Repr::Heap(map.clone()),
+        };
+        SpillFlatMap { repr }
+    }
+}
+
+impl<K, V, const N: usize, L: Default> Default for SpillFlatMap<K, V, N, L> {
+    fn default() -> Self {
+        SpillFlatMap::with_lookup(L::default())
+    }
+}
+
+impl<K: Debug, V: Debug, const N: usize, L> Debug for SpillFlatMap<K, V, N, L> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+// Equal if they hold the same entries, whether spilled or not.
+impl<K, V, const N: usize, const M: usize, L1, L2> PartialEq<SpillFlatMap<K, V, M, L2>>
+    for SpillFlatMap<K, V, N, L1>
+    where K: PartialEq,
+          V: PartialEq,
+          L1: Arrange<K>,
+          L2: Arrange<K>
+{
+    fn eq(&self, other: &SpillFlatMap<K, V, M, L2>) -> bool {
+        self.len() == other.len() &&
+        same_entries(self.strategy(), other.strategy(), self.as_slice(), other.as_slice(),
+                     |a, b| a == b)
+    }
+}
+
+impl<K: Eq, V: Eq, const N: usize, L: Arrange<K>> Eq for SpillFlatMap<K, V, N, L> {}
+
+// Collected like a `FlatMap`, so of entries with equal keys the first is
+// kept, and then moved inline if they fit.
+impl<K, V, const N: usize, L> FromIterator<(K, V)> for SpillFlatMap<K, V, N, L>
+    where L: Arrange<K> + Default
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let heap: FlatMap<K, V, L> = iter.into_iter().collect();
+        if heap.len() > N {
+            return SpillFlatMap { repr: Repr::Heap(heap) };
+        }
+        let mut map = SmallFlatMap::default();
+        for (index, entry) in heap.into_iter().enumerate() {
+            map.slots().insert(index, entry);
+        }
+        SpillFlatMap { repr: Repr::Inline(map) }
+    }
+}
+
+impl<K, V, const N: usize, L: Lookup<K, V, K> + Clone> Extend<(K, V)> for SpillFlatMap<K, V, N, L> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<'a, K, V, const N: usize, L> IntoIterator for &'a SpillFlatMap<K, V, N, L> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+impl<'a, K, V, const N: usize, L> IntoIterator for &'a mut SpillFlatMap<K, V, N, L> {
+    type Item = (&'a K, &'a mut V);
+    type IntoIter = IterMut<'a, K, V>;
+
+    fn into_iter(self) -> IterMut<'a, K, V> {
+        self.iter_mut()
+    }
+}
+
+/// A map whose entries live in a buffer provided by the caller, such as a
+/// stack array or an arena allocation.
+///
+/// The map owns the entries it holds and drops them when it is dropped, but
+/// never allocates: inserting a new key into a full buffer fails and hands
+/// the entry back. Keys are found with the lookup strategy `L`, as in
+/// `FlatMap`.
+///
+/// ```
+/// use std::mem::MaybeUninit;
+/// use flat_map::SliceFlatMap;
+///
+/// let mut buf = [const { MaybeUninit::uninit() }; 2];
+/// let mut map = SliceFlatMap::new(&mut buf);
+/// map.insert("b", 2).unwrap();
+/// map.insert("a", 1).unwrap();
+/// assert!(map.insert("c", 3).is_err());
+/// assert_eq!(map.as_slice(), [("a", 1), ("b", 2)]);
+/// ```
+pub struct SliceFlatMap<'a, K: 'a, V: 'a, L = BinarySearch> {
+    // the first `len` slots are initialized
+    entries: &'a mut [MaybeUninit<(K, V)>],
+    len: usize,
+    l: L,
+}
+
+impl<'a, K, V> SliceFlatMap<'a, K, V> {
+    /// Creates an empty map that stores its entries in `buf`, whatever the
+    /// buffer held before.
+    pub fn new(buf: &'a mut [MaybeUninit<(K, V)>]) -> Self {
+        SliceFlatMap::with_lookup(buf, BinarySearch)
+    }
+}
+
+impl<'a, K, V, L> SliceFlatMap<'a, K, V, L> {
+    pub fn with_lookup(buf: &'a mut [MaybeUninit<(K, V)>], l: L) -> Self {
+        SliceFlatMap { entries: buf, len: 0, l }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The most entries the map can hold, the length of its buffer.
+    pub fn capacity(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == self.entries.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.slots().truncate(0);
+    }
+
+    /// The entries, in the order of the lookup strategy.
+    pub fn as_slice(&self) -> &[(K, V)] {
+        // the first `len` slots are initialized
+        unsafe { slice::from_raw_parts(self.entries.as_ptr() as *const (K, V), self.len) }
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter { inner: self.as_slice().iter() }
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        IterMut { inner: self.slots().into_mut_slice().iter_mut() }
+    }
+
+    pub fn get<Q: ?Sized>(&self, q: &Q) -> Option<&V>
+        where K: Borrow<Q>,
+              L: Lookup<K, V, Q>
+    {
+        match self.l.lookup(self.as_slice(), q) {
+            Ok(i) => Some(&self.as_slice()[i].1),
+            Err(_) => None,
+        }
+    }
+
+    pub fn get_mut<Q: ?Sized>(&mut self, q: &Q) -> Option<&mut V>
+        where K: Borrow<Q>,
+              L: Lookup<K, V, Q>
+    {
+        match self.l.lookup(self.as_slice(), q) {
+            Ok(i) => Some(&mut self.slots().into_mut_slice()[i].1),
+            Err(_) => None,
+        }
+    }
+
+    pub fn contains_key<Q: ?Sized>(&self, q: &Q) -> bool
+        where K: Borrow<Q>,
+              L: Lookup<K, V, Q>
+    {
+        self.l.lookup(self.as_slice(), q).is_ok()
+    }
+
+    /// Inserts a key-value pair, returning the old value if the key was
+    /// already present. Fails and hands the pair back if the key is new and
+    /// the buffer is full.
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, CapacityError<(K, V)>>
+        where L: Lookup<K, V, K>
+    {
+        match self.l.lookup(self.as_slice(), &key) {
+            Ok(i) => Ok(Some(mem::replace(&mut self.slots().into_mut_slice()[i].1, value))),
+            Err(_) if self.is_full() => Err(CapacityError::new((key, value))),
+            Err(i) => {
+                self.slots().insert(i, (key, value));
+                Ok(None)
+            }
+        }
+    }
+
+    pub fn remove<Q: ?Sized>(&mut self, q: &Q) -> Option<V>
+        where K: Borrow<Q>,
+              L: Lookup<K, V, Q>
+    {
+        self.remove_entry(q).map(|(_, v)| v)
+    }
+
+    pub fn remove_entry<Q: ?Sized>(&mut self, q: &Q) -> Option<(K, V)>
+        where K: Borrow<Q>,
+              L: Lookup<K, V, Q>
+    {
+        match self.l.lookup(self.as_slice(), q) {
+            Ok(i) => Some(self.slots().remove(i)),
+            Err(_) => None,
+        }
+    }
+
+    fn slots(&mut self) -> Slots<'_, K, V> {
+        Slots { entries: self.entries, len: &mut self.len }
+    }
+}
+
+impl<'a, K, V, L> Drop for SliceFlatMap<'a, K, V, L> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+impl<'a, K: Debug, V: Debug, L> Debug for SliceFlatMap<'a, K, V, L> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<'a, 'b, K, V, L1, L2> PartialEq<SliceFlatMap<'b, K, V, L2>> for SliceFlatMap<'a, K, V, L1>
+    where K: PartialEq,
+          V: PartialEq,
+          L1: Arrange<K>,
+          L2: Arrange<K>
+{
+    fn eq(&self, other: &SliceFlatMap<'b, K, V, L2>) -> bool {
+        self.len() == other.len() &&
+        same_entries(&self.l, &other.l, self.as_slice(), other.as_slice(), |a, b| a == b)
+    }
+}
+
+impl<'a, K: Eq, V: Eq, L: Arrange<K>> Eq for SliceFlatMap<'a, K, V, L> {}
+
+impl<'a, 'b, K, V, L> IntoIterator for &'b SliceFlatMap<'a, K, V, L> {
+    type Item = (&'b K, &'b V);
+    type IntoIter = Iter<'b, K, V>;
+
+    fn into_iter(self) -> Iter<'b, K, V> {
+        self.iter()
+    }
+}
+
+impl<'a, 'b, K, V, L> IntoIterator for &'b mut SliceFlatMap<'a, K, V, L> {
+    type Item = (&'b K, &'b mut V);
+    type IntoIter = IterMut<'b, K, V>;
+
+    fn into_iter(self) -> IterMut<'b, K, V> {
+        self.iter_mut()
+    }
+}
+
+#[cfg(feature = "serde1")]
+mod serde_impl {
+    // the same representation as `FlatMap`
+    use serde::de::{Deserialize, Deserializer, Error};
+    use serde::{Serialize, Serializer};
+    use flat_map::serde_impl::serialize_entries;
+    use flat_map::FlatMap;
+    use lookup::{Arrange, Lookup};
+    use super::{SmallFlatMap, SpillFlatMap};
+
+    impl<K, V, const N: usize, L> Serialize for SmallFlatMap<K, V, N, L>
+        where K: Serialize,
+              V: Serialize
+    {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where S: Serializer
+        {
+            serialize_entries(self.iter(), serializer)
+        }
+    }
+
+    impl<'de, K, V, const N: usize, L> Deserialize<'de> for SmallFlatMap<K, V, N, L>
+        where K: Deserialize<'de>,
+              V: Deserialize<'de>,
+              L: Lookup<K, V, K> + Default
+    {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where D: Deserializer<'de>
+        {
+            let map = FlatMap::<K, V, L>::deserialize(deserializer)?;
+            SmallFlatMap::try_from_iter(map)
+                .map_err(|_| D::Error::custom(format_args!("more than {} entries", N)))
+        }
+    }
+
+    impl<K, V, const N: usize, L> Serialize for SpillFlatMap<K, V, N, L>
+        where K: Serialize,
+              V: Serialize
+    {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where S: Serializer
+        {
+            serialize_entries(self.iter(), serializer)
+        }
+    }
+
+    impl<'de, K, V, const N: usize, L> Deserialize<'de> for SpillFlatMap<K, V, N, L>
+        where K: Deserialize<'de>,
+              V: Deserialize<'de>,
+              L: Arrange<K> + Default
+    {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where D: Deserializer<'de>
+        {
+            let map = FlatMap::<K, V, L>::deserialize(deserializer)?;
+            Ok(map.into_iter().collect())
+        }
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+mod arbitrary_impl {
+    // `SmallFlatMap` takes at most `N` entries from the input, so the result
+    // always fits
+    use arbitrary::{Arbitrary, Result, Unstructured};
+    use flat_map::FlatMap;
+    use lookup::{Arrange, Lookup};
+    use super::{SmallFlatMap, SpillFlatMap};
+
+    fn fit<K, V, const N: usize, L>(map: FlatMap<K, V, L>) -> SmallFlatMap<K, V, N, L>
+        where L: Lookup<K, V, K> + Default
+    {
+        SmallFlatMap::try_from_iter(map).unwrap_or_else(|_| unreachable!("at most N entries"))
+    }
+
+    impl<'a, K, V, const N: usize, L> Arbitrary<'a> for SmallFlatMap<K, V, N, L>
+        where K: Arbitrary<'a>,
+              V: Arbitrary<'a>,
+              L: Arrange<K> + Lookup<K, V, K> + Default
+    {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            u.arbitrary_iter()?.take(N).collect::<Result<FlatMap<K, V, L>>>().map(fit)
+        }
+
+        fn arbitrary_take_rest(u: Unstructured<'a>) -> Result<Self> {
+            u.arbitrary_take_rest_iter()?.take(N).collect::<Result<FlatMap<K, V, L>>>().map(fit)
+        }
+
+        fn size_hint(_depth: usize) -> (usize, Option<usize>) {
+            (0, None)
+        }
+    }
+
+    impl<'a, K, V, const N: usize, L> Arbitrary<'a> for SpillFlatMap<K, V, N, L>
+        where K: Arbitrary<'a>,
+              V: Arbitrary<'a>,
+              L: Arrange<K> + Default
+    {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            u.arbitrary_iter()?.collect()
+        }
+
+        fn arbitrary_take_rest(u: Unstructured<'a>) -> Result<Self> {
+            u.arbitrary_take_rest_iter()?.collect()
+        }
+
+        fn size_hint(_depth: usize) -> (usize, Option<usize>) {
+            (0, None)
+        }
+    }
+}
@@ -0,0 +1,353 @@
+use std::borrow::Borrow;
+use std::fmt;
+use std::fmt::Debug;
+use std::iter::{FromIterator, FusedIterator, Zip};
+use std::mem;
+use std::slice;
+use std::vec;
+use std::vec::Vec;
+use flat_map::FlatMap;
+use lookup::{BinarySearch, Sorted};
+
+/// A map stored as two vectors, one of keys sorted in ascending order and
+/// one of the matching values.
+///
+/// Searches only touch the keys, which keeps them in cache when the values
+/// are large, and `keys` and `values` are plain slices. Keys are always
+/// found by binary search: the `lookup` strategies work on slices of pairs.
+///
+/// ```
+/// use flat_map::SoaFlatMap;
+///
+/// let mut prices = SoaFlatMap::new();
+/// prices.insert("pear", 3);
+/// prices.insert("apple", 2);
+/// assert_eq!(prices.keys(), ["apple", "pear"]);
+/// assert_eq!(prices.values(), [2, 3]);
+/// assert_eq!(prices.get("pear"), Some(&3));
+/// ```
+pub struct SoaFlatMap<K, V> {
+    keys: Vec<K>,
+    values: Vec<V>,
+}
+
+pub struct Iter<'a, K: 'a, V: 'a> {
+    inner: Zip<slice::Iter<'a, K>, slice::Iter<'a, V>>,
+}
+
+pub struct IterMut<'a, K: 'a, V: 'a> {
+    inner: Zip<slice::Iter<'a, K>, slice::IterMut<'a, V>>,
+}
+
+pub struct IntoIter<K, V> {
+    inner: Zip<vec::IntoIter<K>, vec::IntoIter<V>>,
+}
+
+impl<K, V> SoaFlatMap<K, V> {
+    /// Creates an empty map without allocating.
+    pub const fn new() -> SoaFlatMap<K, V> {
+        SoaFlatMap { keys: Vec::new(), values: Vec::new() }
+    }
+
+    pub fn with_capacity(capacity: usize) -> SoaFlatMap<K, V> {
+        SoaFlatMap { keys: Vec::with_capacity(capacity), values: Vec::with_capacity(capacity) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// The keys, in ascending order.
+    pub fn keys(&self) -> &[K] {
+        &self.keys
+    }
+
+    /// The values, in the order of their keys.
+    pub fn values(&self) -> &[V] {
+        &self.values
+    }
+
+    pub fn values_mut(&mut self) -> &mut [V] {
+        &mut self.values
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter { inner: self.keys.iter().zip(self.values.iter()) }
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        IterMut { inner: self.keys.iter().zip(self.values.iter_mut()) }
+    }
+
+    pub fn clear(&mut self) {
+        self.keys.clear();
+        self.values.clear();
+    }
+
+    /// Takes the key and value vectors.
+    pub fn into_inner(self) -> (Vec<K>, Vec<V>) {
+        (self.keys, self.values)
+    }
+}
+
+impl<K: Ord, V> SoaFlatMap<K, V> {
+    /// Searches the keys for `q`, with the result of `slice::binary_search`.
+    pub fn binary_search_key<Q>(&self, q: &Q) -> Result<usize, usize>
+        where K: Borrow<Q>,
+              Q: ?Sized + Ord
+    {
+        self.keys.binary_search_by(|k| k.borrow().cmp(q))
+    }
+
+    pub fn get<Q>(&self, q: &Q) -> Option<&V>
+        where K: Borrow<Q>,
+              Q: ?Sized + Ord
+    {
+        self.binary_search_key(q).ok().map(|i| &self.values[i])
+    }
+
+    pub fn get_mut<Q>(&mut self, q: &Q) -> Option<&mut V>
+        where K: Borrow<Q>,
+              Q: ?Sized + Ord
+    {
+        match self.binary_search_key(q) {
+            Ok(i) => Some(&mut self.values[i]),
+            Err(_) => None,
+        }
+    }
+
+    pub fn contains_key<Q>(&self, q: &Q) -> bool
+        where K: Borrow<Q>,
+              Q: ?Sized + Ord
+    {
+        self.binary_search_key(q).is_ok()
+    }
+
+    /// Inserts a key-value pair, returning the old value if the key was
+    /// already present. The stored key is kept in that case.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        match self.binary_search_key(&key) {
+            Ok(i) => Some(mem::replace(&mut self.values[i], value)),
+            Err(i) => {
+                self.keys.insert(i, key);
+                self.values.insert(i, value);
+                None
+            }
+        }
+    }
+
+    pub fn remove<Q>(&mut self, q: &Q) -> Option<V>
+        where K: Borrow<Q>,
+              Q: ?Sized + Ord
+    {
+        self.remove_entry(q).map(|(_, v)| v)
+    }
+
+    /// Removes the entry for `q`, returning the stored key along with the
+    /// value.
+    pub fn remove_entry<Q>(&mut self, q: &Q) -> Option<(K, V)>
+        where K: Borrow<Q>,
+              Q: ?Sized + Ord
+    {
+        match self.binary_search_key(q) {
+            Ok(i) => Some((self.keys.remove(i), self.values.remove(i))),
+            Err(_) => None,
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        self.inner.next()
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, K, V> Clone for Iter<'a, K, V> {
+    fn clone(&self) -> Iter<'a, K, V> {
+        Iter { inner: self.inner.clone() }
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for Iter<'a, K, V> {
+    fn next_back(&mut self) -> Option<(&'a K, &'a V)> {
+        self.inner.next_back()
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for Iter<'a, K, V> {}
+impl<'a, K, V> FusedIterator for Iter<'a, K, V> {}
+
+impl<'a, K, V> Iterator for IterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a mut V)> {
+        self.inner.next()
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for IterMut<'a, K, V> {
+    fn next_back(&mut self) -> Option<(&'a K, &'a mut V)> {
+        self.inner.next_back()
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for IterMut<'a, K, V> {}
+impl<'a, K, V> FusedIterator for IterMut<'a, K, V> {}
+
+impl<K, V> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        self.inner.next()
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K, V> DoubleEndedIterator for IntoIter<K, V> {
+    fn next_back(&mut self) -> Option<(K, V)> {
+        self.inner.next_back()
+    }
+}
+
+impl<K, V> ExactSizeIterator for IntoIter<K, V> {}
+impl<K, V> FusedIterator for IntoIter<K, V> {}
+
+impl<K, V> IntoIterator for SoaFlatMap<K, V> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    fn into_iter(self) -> IntoIter<K, V> {
+        IntoIter { inner: self.keys.into_iter().zip(self.values) }
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a SoaFlatMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a mut SoaFlatMap<K, V> {
+    type Item = (&'a K, &'a mut V);
+    type IntoIter = IterMut<'a, K, V>;
+
+    fn into_iter(self) -> IterMut<'a, K, V> {
+        self.iter_mut()
+    }
+}
+
+// Sorted like a `FlatMap`, so of entries with equal keys the first is kept.
+impl<K: Ord, V> FromIterator<(K, V)> for SoaFlatMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> SoaFlatMap<K, V> {
+        iter.into_iter().collect::<FlatMap<K, V>>().into()
+    }
+}
+
+impl<K, V, L: Sorted> From<FlatMap<K, V, L>> for SoaFlatMap<K, V> {
+    fn from(map: FlatMap<K, V, L>) -> SoaFlatMap<K, V> {
+        let (keys, values) = map.into_iter().unzip();
+        SoaFlatMap { keys, values }
+    }
+}
+
+impl<K: Ord, V> From<SoaFlatMap<K, V>> for FlatMap<K, V, BinarySearch> {
+    fn from(map: SoaFlatMap<K, V>) -> FlatMap<K, V> {
+        FlatMap::from_sorted_vec_unchecked(map.into_iter().collect())
+    }
+}
+
+impl<K: Clone, V: Clone> Clone for SoaFlatMap<K, V> {
+    fn clone(&self) -> SoaFlatMap<K, V> {
+        SoaFlatMap { keys: self.keys.clone(), values: self.values.clone() }
+    }
+}
+
+impl<K, V> Default for SoaFlatMap<K, V> {
+    fn default() -> SoaFlatMap<K, V> {
+        SoaFlatMap::new()
+    }
+}
+
+impl<K: PartialEq, V: PartialEq> PartialEq for SoaFlatMap<K, V> {
+    fn eq(&self, other: &SoaFlatMap<K, V>) -> bool {
+        self.keys == other.keys && self.values == other.values
+    }
+}
+
+impl<K: Eq, V: Eq> Eq for SoaFlatMap<K, V> {}
+
+impl<K: Debug, V: Debug> Debug for SoaFlatMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(feature = "serde1")]
+mod serde_impl {
+    // the same representation as `FlatMap`
+    use serde::de::{Deserialize, Deserializer};
+    use serde::{Serialize, Serializer};
+    use flat_map::serde_impl::serialize_entries;
+    use flat_map::FlatMap;
+    use super::SoaFlatMap;
+
+    impl<K: Serialize, V: Serialize> Serialize for SoaFlatMap<K, V> {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where S: Serializer
+        {
+            serialize_entries(self.iter(), serializer)
+        }
+    }
+
+    impl<'de, K, V> Deserialize<'de> for SoaFlatMap<K, V>
+        where K: Ord + Deserialize<'de>,
+              V: Deserialize<'de>
+    {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where D: Deserializer<'de>
+        {
+            FlatMap::<K, V>::deserialize(deserializer).map(SoaFlatMap::from)
+        }
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+mod arbitrary_impl {
+    // collected like `FlatMap`, so always sorted and free of duplicate keys
+    use arbitrary::{Arbitrary, Result, Unstructured};
+    use super::SoaFlatMap;
+
+    impl<'a, K, V> Arbitrary<'a> for SoaFlatMap<K, V>
+        where K: Arbitrary<'a> + Ord,
+              V: Arbitrary<'a>
+    {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            u.arbitrary_iter()?.collect()
+        }
+
+        fn arbitrary_take_rest(u: Unstructured<'a>) -> Result<Self> {
+            u.arbitrary_take_rest_iter()?.collect()
+        }
+
+        fn size_hint(_depth: usize) -> (usize, Option<usize>) {
+            (0, None)
+        }
+    }
+}
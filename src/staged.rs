@@ -0,0 +1,241 @@
+use std::borrow::Borrow;
+use std::fmt;
+use std::fmt::Debug;
+use std::mem;
+use flat_map::FlatMap;
+use lookup::UnsortedLinear;
+
+/// A `FlatMap` that stages new keys in a small unsorted buffer, merging
+/// them into the sorted entries once the buffer is full.
+///
+/// Inserting a new key into a `FlatMap` shifts every entry after it. Here
+/// a new key is pushed onto the buffer instead, and a full buffer is sorted
+/// and merged in one pass, so a run of `limit` new keys costs one
+/// O(n + limit log limit) merge rather than `limit` shifts. Lookups search
+/// the buffer linearly as well as the sorted entries, so the limit should
+/// stay small. Keys already present are updated and removed in place.
+///
+/// ```
+/// use flat_map::StagedFlatMap;
+///
+/// let mut map = StagedFlatMap::with_staging_limit(2);
+/// map.insert(3, 'c');
+/// assert_eq!(map.staged_len(), 1);
+/// map.insert(1, 'a');
+/// assert_eq!(map.staged_len(), 0);
+/// map.insert(2, 'b');
+/// assert_eq!(map.staged_len(), 1);
+/// assert_eq!(map.get(&2), Some(&'b'));
+/// assert_eq!(map.flat_map().as_slice(), [(1, 'a'), (2, 'b'), (3, 'c')]);
+/// ```
+pub struct StagedFlatMap<K, V> {
+    main: FlatMap<K, V>,
+    // keys that are not in `main`, in insertion order
+    staged: FlatMap<K, V, UnsortedLinear>,
+    limit: usize,
+}
+
+impl<K, V> StagedFlatMap<K, V> {
+    /// The number of staged keys that triggers a merge, unless set with
+    /// `with_staging_limit`.
+    pub const DEFAULT_STAGING_LIMIT: usize = 32;
+
+    /// Creates an empty map without allocating.
+    pub const fn new() -> Self {
+        StagedFlatMap::with_staging_limit(Self::DEFAULT_STAGING_LIMIT)
+    }
+
+    /// Creates an empty map that merges its buffer once it holds `limit`
+    /// keys. A limit of zero or one merges every new key straight away.
+    pub const fn with_staging_limit(limit: usize) -> Self {
+        StagedFlatMap {
+            main: FlatMap::new(),
+            staged: FlatMap::with_lookup(UnsortedLinear),
+            limit,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.main.len() + self.staged.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.main.is_empty() && self.staged.is_empty()
+    }
+
+    /// The number of keys waiting in the buffer.
+    pub fn staged_len(&self) -> usize {
+        self.staged.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.main.clear();
+        self.staged.clear();
+    }
+}
+
+impl<K: Ord, V> StagedFlatMap<K, V> {
+    pub fn get<Q>(&self, q: &Q) -> Option<&V>
+        where K: Borrow<Q>,
+              Q: ?Sized + Ord
+    {
+        self.main.get(q).or_else(|| self.staged.get(q))
+    }
+
+    pub fn get_mut<Q>(&mut self, q: &Q) -> Option<&mut V>
+        where K: Borrow<Q>,
+              Q: ?Sized + Ord
+    {
+        match self.main.get_mut(q) {
+            Some(value) => Some(value),
+            None => self.staged.get_mut(q),
+        }
+    }
+
+    pub fn contains_key<Q>(&self, q: &Q) -> bool
+        where K: Borrow<Q>,
+              Q: ?Sized + Ord
+    {
+        self.main.contains_key(q) || self.staged.contains_key(q)
+    }
+
+    /// Inserts a key-value pair, returning the old value if the key was
+    /// already present. A new key is staged, and merges the buffer if that
+    /// fills it.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        if let Some(old) = self.main.get_mut(&key) {
+            return Some(mem::replace(old, value));
+        }
+        let old = self.staged.insert(key, value);
+        if self.staged.len() >= self.limit {
+            self.flush();
+        }
+        old
+    }
+
+    /// Removes a key, returning its value. A staged key is swapped out of
+    /// the buffer; any other is removed from the sorted entries, shifting
+    /// the ones after it.
+    pub fn remove<Q>(&mut self, q: &Q) -> Option<V>
+        where K: Borrow<Q>,
+              Q: ?Sized + Ord
+    {
+        match self.staged.remove_unordered(q) {
+            Some(value) => Some(value),
+            None => self.main.remove(q),
+        }
+    }
+
+    /// Merges the buffer into the sorted entries.
+    pub fn flush(&mut self) {
+        if !self.staged.is_empty() {
+            self.main.extend(self.staged.drain());
+        }
+    }
+
+    /// Merges the buffer and returns the sorted entries, for reads that need
+    /// the whole map, such as iteration and range queries.
+    pub fn flat_map(&mut self) -> &FlatMap<K, V> {
+        self.flush();
+        &self.main
+    }
+
+    pub fn into_flat_map(mut self) -> FlatMap<K, V> {
+        self.flush();
+        self.main
+    }
+}
+
+impl<K, V> From<FlatMap<K, V>> for StagedFlatMap<K, V> {
+    fn from(map: FlatMap<K, V>) -> Self {
+        StagedFlatMap { main: map, ..StagedFlatMap::new() }
+    }
+}
+
+impl<K: Ord, V> Extend<(K, V)> for StagedFlatMap<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: Clone, V: Clone> Clone for StagedFlatMap<K, V> {
+    fn clone(&self) -> Self {
+        StagedFlatMap { main: self.main.clone(), staged: self.staged.clone(), limit: self.limit }
+    }
+}
+
+impl<K, V> Default for StagedFlatMap<K, V> {
+    fn default() -> Self {
+        StagedFlatMap::new()
+    }
+}
+
+impl<K: Debug, V: Debug> Debug for StagedFlatMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StagedFlatMap")
+            .field("sorted", &self.main)
+            .field("staged", &self.staged)
+            .finish()
+    }
+}
+
+#[cfg(feature = "serde1")]
+mod serde_impl {
+    // the same representation as `FlatMap`, with the staged keys merged in
+    use std::vec::Vec;
+    use serde::de::{Deserialize, Deserializer};
+    use serde::{Serialize, Serializer};
+    use flat_map::serde_impl::serialize_entries;
+    use flat_map::FlatMap;
+    use super::StagedFlatMap;
+
+    impl<K: Ord + Serialize, V: Serialize> Serialize for StagedFlatMap<K, V> {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where S: Serializer
+        {
+            // two sorted runs once the buffer is sorted, which the stable
+            // sort merges in linear time
+            let mut entries: Vec<(&K, &V)> = self.main.iter().chain(&self.staged).collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            serialize_entries(entries.into_iter(), serializer)
+        }
+    }
+
+    impl<'de, K, V> Deserialize<'de> for StagedFlatMap<K, V>
+        where K: Ord + Deserialize<'de>,
+              V: Deserialize<'de>
+    {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where D: Deserializer<'de>
+        {
+            FlatMap::<K, V>::deserialize(deserializer).map(StagedFlatMap::from)
+        }
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+mod arbitrary_impl {
+    // everything starts out in the main map, with nothing staged
+    use arbitrary::{Arbitrary, Result, Unstructured};
+    use flat_map::FlatMap;
+    use super::StagedFlatMap;
+
+    impl<'a, K, V> Arbitrary<'a> for StagedFlatMap<K, V>
+        where K: Arbitrary<'a> + Ord,
+              V: Arbitrary<'a>
+    {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            FlatMap::arbitrary(u).map(StagedFlatMap::from)
+        }
+
+        fn arbitrary_take_rest(u: Unstructured<'a>) -> Result<Self> {
+            FlatMap::arbitrary_take_rest(u).map(StagedFlatMap::from)
+        }
+
+        fn size_hint(_depth: usize) -> (usize, Option<usize>) {
+            (0, None)
+        }
+    }
+}
@@ -0,0 +1,90 @@
+#![cfg(feature = "derive")]
+
+#[macro_use]
+extern crate flat_map;
+
+use flat_map::FlatMap;
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+
+#[derive(FlatMapKey, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct UserId(u64);
+
+#[derive(FlatMapKey, Debug)]
+#[key(order = (timestamp, id))]
+struct Event {
+    id: u32,
+    pub timestamp: u64,
+    payload: Vec<(u8, u8)>,
+}
+
+#[derive(FlatMapKey, Debug)]
+#[key(order = (name))]
+struct Named {
+    name: String,
+    #[allow(dead_code)]
+    cached_len: usize,
+}
+
+#[derive(FlatMapKey, Debug)]
+#[key(order = (1, 0))]
+struct Pair(u8, u8);
+
+#[test]
+fn test_newtype_borrow() {
+    let mut map = FlatMap::new();
+    map.insert(UserId(3), "c");
+    map.insert(UserId(1), "a");
+    assert_eq!(map.get(&1u64), Some(&"a"));
+    assert_eq!(map[&3u64], "c");
+    assert!(!map.contains_key(&2u64));
+    let id: &u64 = UserId(7).borrow();
+    assert_eq!(*id, 7);
+}
+
+#[test]
+fn test_key_order() {
+    let a = Event { id: 2, timestamp: 10, payload: vec![(1, 1)] };
+    let b = Event { id: 1, timestamp: 20, payload: vec![] };
+    let c = Event { id: 3, timestamp: 10, payload: vec![] };
+    assert_eq!(a.cmp(&b), Ordering::Less);
+    assert_eq!(a.cmp(&c), Ordering::Less);
+    assert_eq!(a, Event { id: 2, timestamp: 10, payload: vec![] });
+
+    let map: FlatMap<_, _> = vec![(b, 'b'), (c, 'c'), (a, 'a')].into_iter().collect();
+    assert_eq!(map.values().cloned().collect::<String>(), "acb");
+    assert_eq!(map.keys().next().unwrap().payload, vec![(1, 1)]);
+
+    assert!(Pair(9, 1) < Pair(0, 2));
+    assert_eq!(Pair(1, 1).partial_cmp(&Pair(1, 1)), Some(Ordering::Equal));
+}
+
+#[test]
+fn test_single_field_order_borrow() {
+    let mut map = FlatMap::new();
+    map.insert(Named { name: "b".to_string(), cached_len: 1 }, 2);
+    map.insert(Named { name: "a".to_string(), cached_len: 1 }, 1);
+    assert_eq!(map.get(&"a".to_string()), Some(&1));
+    let first = map.keys().next().unwrap();
+    let name: &String = first.borrow();
+    assert_eq!(name, "a");
+}
+
+#[test]
+fn test_prefix_queries() {
+    let map: FlatMap<_, _> = vec![
+        (Event { id: 1, timestamp: 20, payload: vec![] }, 'b'),
+        (Event { id: 2, timestamp: 10, payload: vec![] }, 'a'),
+        (Event { id: 3, timestamp: 20, payload: vec![] }, 'c'),
+    ].into_iter().collect();
+    assert_eq!(map.get(&(20u64, 3u32)), Some(&'c'));
+    assert_eq!(map.get(&(10u64, 3u32)), None);
+    assert_eq!(map.get(&(10u64,)), Some(&'a'));
+    assert!(map.contains_key(&(20u64,)));
+    assert!(!map.contains_key(&(15u64,)));
+
+    let pairs: FlatMap<_, _> = vec![(Pair(5, 1), 'x'), (Pair(0, 2), 'y')].into_iter().collect();
+    assert_eq!(pairs.get(&(2u8,)), Some(&'y'));
+    assert_eq!(pairs.get(&(1u8, 5u8)), Some(&'x'));
+    assert_eq!(pairs.get(&(1u8, 0u8)), None);
+}
@@ -1,16 +1,49 @@
+#![cfg_attr(feature = "unstable", feature(trusted_len, iter_advance_by))]
+
 #[cfg(any(feature = "serde"))]
 extern crate serde;
 #[cfg(any(feature = "serde"))]
 extern crate serde_json;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde_derive;
+
+#[cfg(feature = "postcard")]
+extern crate postcard;
+
+#[cfg(feature = "rayon")]
+extern crate rayon;
+
+#[cfg(feature = "arbitrary")]
+extern crate arbitrary;
+
+#[cfg(feature = "proptest")]
+#[macro_use]
+extern crate proptest;
 
 extern crate flat_map;
 
+use flat_map::BoundedFlatMap;
 use flat_map::FlatMap;
+use flat_map::FlatMapBuilder;
+use flat_map::FlatMultiSet;
+use flat_map::FlatSet;
+use flat_map::FlatSlice;
+use flat_map::RangeMap;
+use flat_map::{SliceFlatMap, SmallFlatMap, SpillFlatMap};
+use flat_map::SoaFlatMap;
+use flat_map::EytzingerFlatMap;
+use flat_map::StagedFlatMap;
+use flat_map::{GrowthPolicy, ShrinkPolicy};
+use flat_map::{BinarySearch, LinearBack, LinearFront, Lookup, Sorted, UnsortedLinear};
+use flat_map::errors::{CapacityError, DuplicateKeyError, InvariantError, OccupiedError, SortedInputError};
 use flat_map::Occupied;
 use flat_map::Vacant;
+use std::error::Error;
 use std::iter::FromIterator;
 
 use std::rc::Rc;
+use std::sync::Mutex;
 
 #[test]
 fn it_works() {
@@ -497,7 +530,7 @@ fn rand_data(len: usize) -> Vec<(u32, u32)> {
 fn test_split_off_empty_right() {
     let mut data = rand_data(173);
 
-    let mut map = FlatMap::from_iter(data.clone());
+    let mut map = FlatMap::<_, _>::from_iter(data.clone());
     let right = map.split_off(&(data.iter()
                                     .max()
                                     .unwrap()
@@ -512,7 +545,7 @@ fn test_split_off_empty_right() {
 fn test_split_off_empty_left() {
     let mut data = rand_data(314);
 
-    let mut map = FlatMap::from_iter(data.clone());
+    let mut map = FlatMap::<_, _>::from_iter(data.clone());
     let right = map.split_off(&data.iter()
                                    .min()
                                    .unwrap()
@@ -529,7 +562,7 @@ fn test_split_off_large_random_sorted() {
     // special case with maximum height.
     data.sort();
 
-    let mut map = FlatMap::from_iter(data.clone());
+    let mut map = FlatMap::<_, _>::from_iter(data.clone());
     let key = data[data.len() / 2].0;
     let right = map.split_off(&key);
 
@@ -537,12 +570,3896 @@ fn test_split_off_large_random_sorted() {
     assert!(right.into_iter().eq(data.into_iter().filter(|x| x.0 >= key)));
 }
 
-#[cfg(feature = "serde")]
 #[test]
-fn test_serde() {
+fn test_error_display() {
+    let err = DuplicateKeyError::new("a");
+    assert_eq!(err.to_string(), "duplicate key \"a\"");
+    assert_eq!(err.into_key(), "a");
+
+    let err = SortedInputError::with_input(3, vec![(2, 'b'), (1, 'a')]);
+    assert_eq!(err.to_string(), "input is not strictly sorted by key at index 3");
+    assert_eq!(err.index(), 3);
+    assert_eq!(err.into_input(), vec![(2, 'b'), (1, 'a')]);
+    assert_eq!(SortedInputError::new(1).to_string(),
+               "input is not strictly sorted by key at index 1");
+
+    let err = CapacityError::new((1, "one"));
+    assert_eq!(err.to_string(), "insufficient capacity");
+    assert_eq!(err.into_inner(), (1, "one"));
+
     let mut map = FlatMap::new();
-    map.insert(18, 18);
-    let json = serde_json::to_string(&map).unwrap();
-    let new_map: FlatMap<u64, u64> = serde_json::from_str(&json).unwrap();
-    assert_eq!(new_map.get(&18), map.get(&18));
+    map.insert(1, "one");
+    let entry = match map.entry(1) {
+        Occupied(entry) => entry,
+        Vacant(_) => unreachable!(),
+    };
+    let err = OccupiedError { entry, key: 1, value: "uno" };
+    assert_eq!(err.to_string(),
+               "failed to insert \"uno\", key 1 already exists with value \"one\"");
+    assert_eq!(err.into_inner(), (1, "uno"));
+}
+
+#[test]
+fn test_error_conversions() {
+    let mut map = FlatMap::new();
+    map.insert("k".to_string(), 1);
+    let entry = match map.entry("k".to_string()) {
+        Occupied(entry) => entry,
+        Vacant(_) => unreachable!(),
+    };
+    let err = OccupiedError { entry, key: "k".to_string(), value: 2 };
+    let err: DuplicateKeyError<String> = err.into();
+    assert_eq!(err.key(), "k");
+
+    fn boxed<E: Error + 'static>(err: E) -> Box<dyn Error> {
+        Box::new(err)
+    }
+    assert_eq!(boxed(DuplicateKeyError::new(7)).to_string(), "duplicate key 7");
+    assert_eq!(boxed(CapacityError::new(())).to_string(), "insufficient capacity");
+}
+
+#[test]
+fn test_split_at_mut_threads() {
+    let mut map: FlatMap<u32, u32> = (0..1000).map(|i| (i, i)).collect();
+    {
+        let (left, right) = map.split_at_mut(400);
+        assert_eq!(left.len(), 400);
+        assert_eq!(right.len(), 600);
+        std::thread::scope(|s| {
+            s.spawn(move || {
+                for (_, v) in left {
+                    *v += 1;
+                }
+            });
+            s.spawn(move || {
+                for (_, v) in right {
+                    *v *= 2;
+                }
+            });
+        });
+    }
+    for (&k, &v) in &map {
+        if k < 400 {
+            assert_eq!(v, k + 1);
+        } else {
+            assert_eq!(v, k * 2);
+        }
+    }
+}
+
+#[test]
+fn test_split_at_mut_bounds() {
+    let mut map: FlatMap<u32, u32> = (0..10).map(|i| (i, i)).collect();
+    {
+        let (left, mut right) = map.split_at_mut(0);
+        assert!(left.is_empty());
+        assert_eq!(right.len(), 10);
+        *right.get_mut(&9).unwrap() = 90;
+    }
+    {
+        let (mut left, right) = map.split_at_mut(10);
+        assert_eq!(left.len(), 10);
+        assert!(right.is_empty());
+        assert_eq!(left.get(&9), Some(&90));
+        *left.get_mut(&0).unwrap() = 100;
+    }
+    assert_eq!(map[&0], 100);
+    assert_eq!(map[&9], 90);
+
+    // the views search with the map's strategy
+    let mut desc = FlatMap::new_desc();
+    desc.extend(vec![(5, 5), (3, 3), (1, 1)]);
+    {
+        let (left, mut right) = desc.split_at_mut(1);
+        assert_eq!(left.get(&5), Some(&5));
+        assert_eq!(right.get(&3), Some(&3));
+        assert!(!right.contains_key(&5));
+        *right.get_mut(&1).unwrap() = 10;
+    }
+    assert_eq!(desc[&1], 10);
+
+    let mut unsorted: FlatMap<u32, u32, UnsortedLinear> = FlatMap::default();
+    unsorted.extend(vec![(3, 3), (1, 1), (2, 2)]);
+    let (left, right) = unsorted.split_at_mut(1);
+    assert_eq!(left.get(&3), Some(&3));
+    assert_eq!(right.get(&1), Some(&1));
+    assert_eq!(right.get(&2), Some(&2));
+    assert!(!right.contains_key(&3));
+}
+
+#[test]
+fn test_split_by_key_mut() {
+    let mut map: FlatMap<String, u32> = (0..10).map(|i| (i.to_string(), i)).collect();
+    {
+        let (mut left, mut right) = map.split_by_key_mut("5");
+        assert_eq!(left.len(), 5);
+        assert_eq!(right.len(), 5);
+        assert!(left.get_mut("5").is_none());
+        assert!(right.contains_key("5"));
+        *left.get_mut("4").unwrap() = 40;
+        *right.get_mut("5").unwrap() = 50;
+    }
+    assert_eq!(map["4"], 40);
+    assert_eq!(map["5"], 50);
+
+    let (left, right) = map.split_by_key_mut("");
+    assert!(left.is_empty());
+    assert_eq!(right.len(), 10);
+    let (left, right) = map.split_by_key_mut("a");
+    assert_eq!(left.len(), 10);
+    assert!(right.is_empty());
+}
+
+#[test]
+fn test_split_by_key() {
+    let map: FlatMap<String, u32> = (0..10).map(|i| (i.to_string(), i)).collect();
+    let (left, right) = map.split_by_key("5");
+    assert_eq!(left.len(), 5);
+    assert!(left.get("5").is_none() && right.contains_key("5"));
+    assert_eq!(right.get_key_value("9"), Some((&"9".to_string(), &9)));
+
+    let (a, b) = right.split_at(2);
+    assert_eq!(a.iter().map(|(_, &v)| v).collect::<Vec<_>>(), [5, 6]);
+    assert_eq!(b.len(), 3);
+    let (empty, all) = map.split_at(0);
+    assert!(empty.is_empty() && all == map.as_flat_slice());
+
+    let sums = std::thread::scope(|s| {
+        let l = s.spawn(|| left.iter().map(|(_, v)| v).sum::<u32>());
+        let r = s.spawn(|| right.iter().map(|(_, v)| v).sum::<u32>());
+        (l.join().unwrap(), r.join().unwrap())
+    });
+    assert_eq!(sums, (10, 35));
+}
+
+#[test]
+fn test_partition_point() {
+    let map: FlatMap<u32, Option<u32>> =
+        (0..50).map(|k| (k * 2, if k < 30 { Some(k) } else { None })).collect();
+
+    fn linear<F: FnMut(&u32, &Option<u32>) -> bool>(map: &FlatMap<u32, Option<u32>>, mut pred: F) -> usize {
+        map.iter().take_while(|&(k, v)| pred(k, v)).count()
+    }
+
+    for bound in 0..110 {
+        assert_eq!(map.partition_point(|&k, _| k < bound), linear(&map, |&k, _| k < bound));
+    }
+    for bound in 0..35 {
+        let pred = |_: &u32, v: &Option<u32>| v.is_some_and(|v| v < bound);
+        assert_eq!(map.partition_point(pred), linear(&map, pred));
+    }
+    for bound in 0..110 {
+        let pred = |&k: &u32, v: &Option<u32>| k < bound && v.is_some();
+        assert_eq!(map.partition_point(pred), linear(&map, pred));
+    }
+
+    assert_eq!(map.first_entry_where(|_, v| v.is_none()), Some((&60, &None)));
+    assert_eq!(map.last_entry_where(|_, v| v.is_some()), Some((&58, &Some(29))));
+    assert_eq!(map.first_entry_where(|&k, _| k > 1000), None);
+    assert_eq!(map.last_entry_where(|&k, _| k > 1000), None);
+    assert_eq!(map.first_entry_where(|_, _| true), Some((&0, &Some(0))));
+    assert_eq!(map.last_entry_where(|_, _| true), Some((&98, &None)));
+
+    let empty: FlatMap<u32, u32> = FlatMap::new();
+    assert_eq!(empty.partition_point(|_, _| true), 0);
+    assert_eq!(empty.first_entry_where(|_, _| true), None);
+    assert_eq!(empty.last_entry_where(|_, _| true), None);
+}
+
+fn naive_splice(map: &FlatMap<u32, u32>, lo: u32, hi: u32, replacement: &[(u32, u32)])
+                -> (FlatMap<u32, u32>, Vec<(u32, u32)>) {
+    let removed: Vec<_> = map.iter().filter(|&(&k, _)| lo <= k && k < hi).map(|(&k, &v)| (k, v)).collect();
+    let mut result: FlatMap<_, _> = map.iter().filter(|&(&k, _)| k < lo || hi <= k).map(|(&k, &v)| (k, v)).collect();
+    result.extend(replacement.iter().cloned());
+    (result, removed)
+}
+
+#[test]
+fn test_splice_differential() {
+    let mut rng = DeterministicRng::new();
+    for _ in 0..200 {
+        let map: FlatMap<u32, u32> = (0..rng.next() % 40).map(|_| (rng.next() % 100, rng.next())).collect();
+        let a = rng.next() % 110;
+        let b = rng.next() % 110;
+        let (lo, hi) = if a <= b { (a, b) } else { (b, a) };
+        let replacement: FlatMap<u32, u32> = if hi > lo {
+            (0..rng.next() % 20).map(|_| (lo + rng.next() % (hi - lo), rng.next())).collect()
+        } else {
+            FlatMap::new()
+        };
+        let replacement = replacement.into_inner();
+
+        let (expected, expected_removed) = naive_splice(&map, lo, hi, &replacement);
+        let mut actual = map.clone();
+        let removed: Vec<_> = actual.splice(lo..hi, replacement).collect();
+        assert_eq!(removed, expected_removed);
+        assert_eq!(actual, expected);
+    }
+}
+
+#[test]
+fn test_splice_edges() {
+    let map: FlatMap<u32, u32> = (0..10).map(|i| (i * 10, i)).collect();
+
+    // replacement larger than the removed range
+    let mut m = map.clone();
+    assert_eq!(m.splice(20..=30, (20..=30).map(|k| (k, 0))).count(), 2);
+    assert_eq!(m.len(), 19);
+
+    // replacement smaller than the removed range
+    let mut m = map.clone();
+    assert_eq!(m.splice(20.., vec![(55, 1)]).count(), 8);
+    assert_eq!(m.into_inner(), vec![(0, 0), (10, 1), (55, 1)]);
+
+    // empty replacement is a pure removal
+    let mut m = map.clone();
+    assert_eq!(m.splice(..50, None).collect::<Vec<_>>(), vec![(0, 0), (10, 1), (20, 2), (30, 3), (40, 4)]);
+    assert_eq!(m.len(), 5);
+
+    // no entries in the range is a pure insertion
+    let mut m = map.clone();
+    assert_eq!(m.splice(41..49, vec![(42, 42), (43, 43)]).count(), 0);
+    assert_eq!(m.len(), 12);
+    assert_eq!(m[&42], 42);
+
+    // dropping the iterator unconsumed still applies the splice
+    let mut m = map.clone();
+    m.splice(.., vec![(1, 1)]);
+    assert_eq!(m.into_inner(), vec![(1, 1)]);
+}
+
+#[test]
+#[should_panic(expected = "outside the spliced range")]
+fn test_splice_out_of_range() {
+    let mut map: FlatMap<u32, u32> = (0..10).map(|i| (i, i)).collect();
+    map.splice(2..5, vec![(3, 3), (5, 5)]);
+}
+
+#[test]
+#[should_panic(expected = "not strictly sorted")]
+fn test_splice_unsorted() {
+    let mut map: FlatMap<u32, u32> = (0..10).map(|i| (i, i)).collect();
+    map.splice(2..5, vec![(3, 3), (2, 2)]);
+}
+
+#[test]
+fn test_inserted_removed_updated() {
+    let mut original = FlatMap::with_capacity(100);
+    for i in 0..10 {
+        original.insert(i * 2, i);
+    }
+    let snapshot = original.clone();
+
+    let m = original.inserted(5, 50);
+    assert_eq!(m.len(), 11);
+    assert_eq!(m.capacity(), m.len());
+    assert_eq!(m[&5], 50);
+    assert!(m.keys().zip(m.keys().skip(1)).all(|(a, b)| a < b));
+
+    let m = original.inserted(4, 40);
+    assert_eq!(m.len(), 10);
+    assert_eq!(m.capacity(), m.len());
+    assert_eq!(m[&4], 40);
+
+    let m = original.inserted(100, 1);
+    assert_eq!(m.iter().next_back(), Some((&100, &1)));
+
+    // the stored key of an existing entry is kept, as with insert
+    let names: FlatMap<&str, u32, flat_map::OrderBy<flat_map::CaseInsensitive>> =
+        vec![("Alice", 1), ("bob", 2)].into_iter().collect();
+    let m = names.inserted("ALICE", 10);
+    assert_eq!(m.into_inner(), [("Alice", 10), ("bob", 2)]);
+    let mut expected = names.clone();
+    expected.insert("BOB", 20);
+    assert_eq!(names.inserted("BOB", 20).into_inner(), expected.into_inner());
+
+    let m = original.removed(&4);
+    assert_eq!(m.len(), 9);
+    assert_eq!(m.capacity(), m.len());
+    assert!(!m.contains_key(&4));
+
+    let m = original.removed(&5);
+    assert_eq!(m, original);
+    assert_eq!(m.capacity(), m.len());
+
+    let m = original.updated(&6, |v| *v += 100);
+    assert_eq!(m[&6], 103);
+    assert_eq!(m.capacity(), m.len());
+    let m = original.updated(&7, |_| unreachable!());
+    assert_eq!(m, original);
+
+    assert_eq!(original, snapshot);
+    assert_eq!(original.capacity(), 100);
+}
+
+#[test]
+fn test_retain_range() {
+    let mut rng = DeterministicRng::new();
+    for _ in 0..200 {
+        let map: FlatMap<u32, u32> = (0..rng.next() % 60).map(|_| (rng.next() % 100, rng.next() % 4)).collect();
+        let a = rng.next() % 110;
+        let b = rng.next() % 110;
+        let (lo, hi) = if a <= b { (a, b) } else { (b, a) };
+
+        let expected: FlatMap<_, _> = map.iter()
+            .filter(|&(k, v)| !(lo <= *k && *k <= hi) || *v != 0)
+            .map(|(&k, &v)| (k, v + if lo <= k && k <= hi { 10 } else { 0 }))
+            .collect();
+
+        let mut actual = map.clone();
+        let mut visited = Vec::new();
+        actual.retain_range(lo..=hi, |&k, v| {
+            visited.push(k);
+            *v += 10;
+            *v != 10
+        });
+        assert_eq!(actual, expected);
+        assert!(visited.iter().all(|&k| lo <= k && k <= hi));
+        assert!(visited.windows(2).all(|w| w[0] < w[1]));
+    }
+}
+
+#[test]
+fn test_retain_range_panic() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    let mut map: FlatMap<u32, u32> = (0..20).map(|i| (i, i)).collect();
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        map.retain_range(5..15, |&k, _| {
+            assert!(k != 10);
+            k % 2 == 0
+        });
+    }));
+    assert!(result.is_err());
+    let keys: Vec<_> = map.keys().cloned().collect();
+    assert!(keys.windows(2).all(|w| w[0] < w[1]));
+    assert_eq!(keys, vec![0, 1, 2, 3, 4, 6, 8, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19]);
+}
+
+#[test]
+fn test_growth_policy() {
+    let mut exact = FlatMap::new();
+    exact.set_growth_policy(GrowthPolicy::Exact);
+    assert_eq!(exact.growth_policy(), GrowthPolicy::Exact);
+    let mut amortized = FlatMap::new();
+    assert_eq!(amortized.growth_policy(), GrowthPolicy::Amortized);
+
+    let mut amortized_caps = Vec::new();
+    for i in 0..100u64 {
+        let k = (i * 37) % 100;
+        exact.insert(k, i);
+        amortized.insert(k, i);
+        assert_eq!(exact.capacity(), exact.len());
+        assert!(amortized.capacity() >= amortized.len());
+        amortized_caps.push(amortized.capacity());
+    }
+    amortized_caps.dedup();
+    assert!(amortized_caps.len() < 10);
+    assert!(exact.memory_usage() <= amortized.memory_usage());
+
+    // entry, extend and append follow the policy too
+    *exact.entry(1000).or_insert(0) += 1;
+    assert_eq!(exact.capacity(), exact.len());
+    exact.extend(vec![(2000, 1), (2001, 2)]);
+    assert_eq!(exact.capacity(), exact.len());
+    let mut other: FlatMap<_, _> = (3000..3010).map(|k| (k, k)).collect();
+    exact.append(&mut other);
+    assert_eq!(exact.capacity(), exact.len());
+
+    // switching policies keeps the current capacity
+    let mut map: FlatMap<u64, u64> = FlatMap::with_capacity(64);
+    map.insert(1, 1);
+    map.set_growth_policy(GrowthPolicy::Exact);
+    assert_eq!(map.capacity(), 64);
+    for i in 2..=64 {
+        map.insert(i, i);
+    }
+    assert_eq!(map.capacity(), 64);
+    map.insert(65, 65);
+    assert_eq!(map.capacity(), 65);
+    map.set_growth_policy(GrowthPolicy::Amortized);
+    map.insert(66, 66);
+    assert!(map.capacity() >= 66);
+}
+
+#[test]
+fn test_growth_policy_bulk() {
+    for &policy in &[GrowthPolicy::Amortized, GrowthPolicy::Exact] {
+        let mut extended: FlatMap<u32, u32> = FlatMap::new();
+        extended.set_growth_policy(policy);
+        let mut appended = extended.clone();
+        let mut inserted = extended.clone();
+        let mut caps = (Vec::new(), Vec::new(), Vec::new());
+        for batch in 0..50u32 {
+            let entries: Vec<_> = (0..4).map(|i| (i * 50 + batch, batch)).collect();
+            extended.extend(entries.clone());
+            let mut other: FlatMap<_, _> = entries.iter().cloned().collect();
+            appended.append(&mut other);
+            inserted.insert_many(entries, |_, _, new| new);
+            for map in &[&extended, &appended, &inserted] {
+                assert_eq!(map.len() as u32, (batch + 1) * 4);
+                if policy == GrowthPolicy::Exact {
+                    assert_eq!(map.capacity(), map.len());
+                } else {
+                    assert!(map.capacity() >= map.len());
+                }
+            }
+            caps.0.push(extended.capacity());
+            caps.1.push(appended.capacity());
+            caps.2.push(inserted.capacity());
+        }
+        for caps in &mut [caps.0, caps.1, caps.2] {
+            caps.dedup();
+            if policy == GrowthPolicy::Exact {
+                assert_eq!(caps.len(), 50);
+            } else {
+                assert!(caps.len() < 10);
+                assert!(caps.windows(2).all(|w| w[1] >= w[0] * 2));
+            }
+        }
+        extended.check_invariants().unwrap();
+        assert_eq!(extended, appended);
+        assert_eq!(extended, inserted);
+    }
+
+    // spare room is kept by a bulk merge that fits
+    let mut map: FlatMap<u32, u32> = FlatMap::with_capacity(64);
+    let capacity = map.capacity();
+    map.extend((0..10).map(|k| (k, k)));
+    assert_eq!(map.capacity(), capacity);
+
+    // duplicates in the batch don't leave room under `Exact`
+    let mut exact: FlatMap<u32, u32> = FlatMap::new();
+    exact.set_growth_policy(GrowthPolicy::Exact);
+    exact.extend(vec![(1, 1), (2, 2), (1, 3), (2, 4), (3, 5)]);
+    assert_eq!(exact.len(), 3);
+    assert_eq!(exact.capacity(), 3);
+}
+
+#[test]
+fn test_keys_eq() {
+    use std::cmp::Ordering;
+
+    let a: FlatMap<u32, u32> = (1..11).map(|k| (k, k)).collect();
+    let b: FlatMap<u32, String> = (1..11).map(|k| (k, k.to_string())).collect();
+    assert!(a.keys_eq(&b));
+    assert!(b.keys_eq(&a));
+    assert_eq!(a.keys_cmp(&b), Ordering::Equal);
+
+    let first: FlatMap<u32, ()> = (1..11).map(|k| (if k == 1 { 0 } else { k }, ())).collect();
+    assert!(!a.keys_eq(&first));
+    assert_eq!(a.keys_cmp(&first), Ordering::Greater);
+    let last: FlatMap<u32, ()> = (1..11).map(|k| (if k == 10 { 11 } else { k }, ())).collect();
+    assert!(!a.keys_eq(&last));
+    assert_eq!(a.keys_cmp(&last), Ordering::Less);
+    assert_eq!(last.keys_cmp(&a), Ordering::Greater);
+
+    let shorter: FlatMap<u32, ()> = (1..10).map(|k| (k, ())).collect();
+    assert!(!a.keys_eq(&shorter));
+    assert_eq!(a.keys_cmp(&shorter), Ordering::Greater);
+
+    let empty: FlatMap<u32, ()> = FlatMap::new();
+    assert!(empty.keys_eq(&FlatMap::<u32, u8>::new()));
+    assert_eq!(empty.keys_cmp(&a), Ordering::Less);
+}
+
+#[test]
+fn test_shrink_policy() {
+    let mut map: FlatMap<u32, u32> = FlatMap::with_capacity(100);
+    for i in 0..100 {
+        map.insert(i, i);
+    }
+    assert_eq!(map.shrink_policy(), ShrinkPolicy::Never);
+    for i in 0..90 {
+        map.remove(&i);
+    }
+    assert_eq!(map.capacity(), 100);
+
+    let policy = ShrinkPolicy::WhenBelow { numerator: 1, denominator: 4, min_capacity: 8 };
+    let mut map: FlatMap<u32, u32> = FlatMap::with_capacity(100);
+    map.set_shrink_policy(policy);
+    for i in 0..100 {
+        map.insert(i, i);
+    }
+    // 25 of 100 is not below a quarter yet
+    for i in 0..75 {
+        map.remove(&i);
+        assert_eq!(map.capacity(), 100);
+    }
+    map.remove(&75);
+    assert_eq!(map.capacity(), 48);
+
+    // alternating inserts and removals at the boundary don't reallocate
+    for _ in 0..10 {
+        map.insert(0, 0);
+        assert_eq!(map.capacity(), 48);
+        map.remove(&0);
+        assert_eq!(map.capacity(), 48);
+    }
+
+    // never below min_capacity
+    map.retain_range(.., |&k, _| k >= 98);
+    assert_eq!(map.len(), 2);
+    assert_eq!(map.capacity(), 8);
+    map.clear();
+    assert_eq!(map.capacity(), 8);
+
+    // entries and split_off honor the policy too
+    let mut map: FlatMap<u32, u32> = (0..64).map(|i| (i, i)).collect();
+    map.shrink_to_fit();
+    map.set_shrink_policy(ShrinkPolicy::WhenBelow { numerator: 1, denominator: 2, min_capacity: 0 });
+    let right = map.split_off(&20);
+    assert_eq!(right.len(), 44);
+    assert_eq!(map.capacity(), 40);
+    for i in 0..19 {
+        match map.entry(i) {
+            Occupied(e) => { e.remove(); }
+            Vacant(_) => unreachable!(),
+        }
+    }
+    assert_eq!(map.len(), 1);
+    assert!(map.capacity() < 4);
+}
+
+#[test]
+fn test_bounded_flat_map() {
+    let mut map: BoundedFlatMap<u8, u8, 3> = BoundedFlatMap::new();
+    assert_eq!(map.try_insert(1, 1).unwrap(), None);
+    assert_eq!(map.try_insert(2, 2).unwrap(), None);
+    assert_eq!(map.try_insert(3, 3).unwrap(), None);
+    assert!(map.is_full());
+    assert_eq!(map.try_insert(4, 4).unwrap_err().into_inner(), (4, 4));
+    assert_eq!(map.try_insert(3, 30).unwrap(), Some(3));
+    assert_eq!(map.get(&3), Some(&30));
+    assert_eq!(map.remove(&1), Some(1));
+    assert_eq!(map.try_insert(4, 4).unwrap(), None);
+    assert_eq!(map.keys().cloned().collect::<Vec<_>>(), vec![2, 3, 4]);
+
+    let big: FlatMap<u8, u8> = (0..4).map(|i| (i, i)).collect();
+    let err = BoundedFlatMap::<u8, u8, 3>::try_from_map(big.clone()).unwrap_err();
+    assert_eq!(err.into_inner(), big);
+}
+
+#[cfg(feature = "postcard")]
+#[test]
+fn test_postcard_max_size() {
+    use postcard::experimental::max_size::MaxSize;
+
+    type Map = BoundedFlatMap<u32, u64, 200>;
+    // a varint length of at most 200 takes two bytes
+    assert_eq!(Map::POSTCARD_MAX_SIZE, 2 + 200 * (5 + 10));
+
+    let mut map = Map::new();
+    for i in 0..200 {
+        map.try_insert(u32::MAX - i, u64::MAX - u64::from(i)).unwrap();
+    }
+    let mut buf = [0u8; Map::POSTCARD_MAX_SIZE];
+    let used = postcard::to_slice(&map, &mut buf).unwrap().len();
+    assert_eq!(used, Map::POSTCARD_MAX_SIZE);
+    let decoded: Map = postcard::from_bytes(&buf[..used]).unwrap();
+    assert_eq!(decoded, map);
+
+    let empty = Map::new();
+    let bytes = postcard::to_slice(&empty, &mut buf).unwrap();
+    assert_eq!(bytes, &[0]);
+}
+
+type Handler = fn(u32) -> u32;
+
+static REGISTRY: Mutex<FlatMap<u32, Handler>> = Mutex::new(FlatMap::new());
+static BOUNDED: BoundedFlatMap<u32, u32, 4> = BoundedFlatMap::new();
+
+#[test]
+fn test_const_new() {
+    const EMPTY: FlatMap<u32, String> = FlatMap::new();
+    assert!(EMPTY.is_empty());
+    assert!(BOUNDED.is_empty());
+
+    fn double(x: u32) -> u32 {
+        x * 2
+    }
+    REGISTRY.lock().unwrap().insert(1, double);
+    assert_eq!(REGISTRY.lock().unwrap()[&1](21), 42);
+}
+
+#[test]
+fn test_from_iter_sorted_input() {
+    fn reference(mut data: Vec<(u32, u32)>) -> Vec<(u32, u32)> {
+        data.sort_by_key(|a| a.0);
+        data.dedup_by(|a, b| a.0 == b.0);
+        data
+    }
+
+    let mut rng = DeterministicRng::new();
+    for len in 0..100 {
+        let random: Vec<_> = (0..len).map(|_| (rng.next() % 50, rng.next())).collect();
+        let mut sorted = random.clone();
+        sorted.sort_by_key(|a| a.0);
+        let mut strict = sorted.clone();
+        strict.dedup_by(|a, b| a.0 == b.0);
+        for data in [random, sorted, strict] {
+            let map = FlatMap::<_, _>::from_iter(data.clone());
+            assert_eq!(map.into_inner(), reference(data));
+        }
+    }
+
+    let source: std::collections::BTreeMap<_, _> = rand_data(1000).into_iter().collect();
+    let map: FlatMap<_, _> = source.clone().into_iter().collect();
+    assert!(map.into_iter().eq(source));
+}
+
+#[test]
+fn test_extend_sorted() {
+    use std::cell::Cell;
+    use std::cmp::Ordering;
+    use std::collections::BTreeMap;
+
+    thread_local!(static COMPARISONS: Cell<usize> = const { Cell::new(0) });
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    struct Counted(u32);
+
+    impl PartialOrd for Counted {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl Ord for Counted {
+        fn cmp(&self, other: &Self) -> Ordering {
+            COMPARISONS.with(|c| c.set(c.get() + 1));
+            self.0.cmp(&other.0)
+        }
+    }
+
+    let mut rng = DeterministicRng::new();
+    let a: FlatMap<_, _> = (0..1000).map(|_| (Counted(rng.next() % 3000), rng.next())).collect();
+    let b: FlatMap<_, _> = (0..1000).map(|_| (Counted(rng.next() % 3000), rng.next())).collect();
+
+    let mut expected = a.clone();
+    expected.extend(b.clone());
+
+    let mut merged = a.clone();
+    COMPARISONS.with(|c| c.set(0));
+    merged.extend_sorted(b.clone());
+    let comparisons = COMPARISONS.with(|c| c.get());
+    // one pass to check the order, one to merge
+    assert!(comparisons <= a.len() + 2 * b.len(), "{} comparisons", comparisons);
+    assert_eq!(merged, expected);
+
+    let source: BTreeMap<_, _> = b.clone().into_iter().collect();
+    let mut merged = a.clone();
+    merged.extend_sorted(source);
+    assert_eq!(merged, expected);
+
+    let mut c = b.clone();
+    let mut merged = a.clone();
+    merged.extend_sorted(c.splice(Counted(0)..Counted(1500), None));
+    let mut expected = a.clone();
+    expected.extend(b.into_iter().filter(|kv| kv.0 < Counted(1500)));
+    assert_eq!(merged, expected);
+
+    let mut empty = FlatMap::new();
+    empty.extend_sorted(a.clone());
+    assert_eq!(empty, a);
+
+    // maps of other strategies hand out their entries in their own order
+    let mut unsorted: FlatMap<u32, u32, UnsortedLinear> = FlatMap::default();
+    unsorted.extend(vec![(3, 3), (1, 1), (2, 2)]);
+    let mut merged: FlatMap<u32, u32> = vec![(0, 0), (2, 20)].into_iter().collect();
+    merged.extend_sorted(unsorted);
+    merged.check_invariants().unwrap();
+    assert_eq!(merged.into_inner(), [(0, 0), (1, 1), (2, 2), (3, 3)]);
+
+    let mut desc = FlatMap::new_desc();
+    desc.extend(vec![(5, 5), (3, 3), (1, 1)]);
+    let mut totals: FlatMap<u32, u32> = vec![(1, 10), (2, 2), (4, 4)].into_iter().collect();
+    totals.merge_sorted(desc.drain(), |_, old, new| old + new);
+    totals.check_invariants().unwrap();
+    assert_eq!(totals.into_inner(), [(1, 11), (2, 2), (3, 3), (4, 4), (5, 5)]);
+}
+
+#[test]
+fn test_runs() {
+    fn brute_force(map: &FlatMap<u32, u32>) -> Vec<(u32, u32, u32, usize)> {
+        let mut runs: Vec<(u32, u32, u32, usize)> = Vec::new();
+        for (&k, &v) in map {
+            match runs.last_mut() {
+                Some(run) if run.2 == v => {
+                    run.1 = k;
+                    run.3 += 1;
+                }
+                _ => runs.push((k, k, v, 1)),
+            }
+        }
+        runs
+    }
+
+    fn check(map: &FlatMap<u32, u32>) {
+        let expected = brute_force(map);
+        let forward: Vec<_> = map.runs().map(|r| (*r.first_key(), *r.last_key(), *r.value(), r.len())).collect();
+        assert_eq!(forward, expected);
+        let mut backward: Vec<_> = map.runs().rev().map(|r| (*r.first_key(), *r.last_key(), *r.value(), r.len())).collect();
+        backward.reverse();
+        assert_eq!(backward, expected);
+        assert_eq!(map.runs().map(|r| r.len()).sum::<usize>(), map.len());
+    }
+
+    check(&FlatMap::new());
+    check(&(0..100).map(|k| (k, k)).collect());
+    check(&(0..100).map(|k| (k, 7)).collect());
+
+    let mut rng = DeterministicRng::new();
+    let map: FlatMap<_, _> = (0..1000).map(|_| (rng.next() % 2000, rng.next() % 3)).collect();
+    check(&map);
+
+    let mut runs = map.runs();
+    let front = runs.next().unwrap();
+    let back = runs.next_back().unwrap();
+    let middle: usize = runs.map(|r| r.len()).sum();
+    assert_eq!(front.len() + middle + back.len(), map.len());
+
+    let map: FlatMap<_, _> = vec![(1, 10), (2, 11), (3, 20), (4, 29), (5, 30)].into_iter().collect();
+    let runs: Vec<_> = map.runs_by(|a, b| a / 10 == b / 10).map(|r| r.entries().to_vec()).collect();
+    assert_eq!(runs, [vec![(1, 10), (2, 11)], vec![(3, 20), (4, 29)], vec![(5, 30)]]);
+
+    // `same` need not be transitive: each value is compared with the one
+    // before it, in both directions
+    let map: FlatMap<_, _> = vec![(1, 1i32), (2, 2), (3, 3), (4, 7)].into_iter().collect();
+    let near = |a: &i32, b: &i32| (a - b).abs() <= 1;
+    let forward: Vec<_> = map.runs_by(near).map(|r| r.len()).collect();
+    assert_eq!(forward, [3, 1]);
+    let backward: Vec<_> = map.runs_by(near).rev().map(|r| r.len()).collect();
+    assert_eq!(backward, [1, 3]);
+}
+
+#[test]
+fn test_quantile_key() {
+    let empty: FlatMap<u64, u64> = FlatMap::new();
+    assert_eq!(empty.quantile_key(0.5), None);
+    assert_eq!(empty.quantile_key_by_weight(0.5, |&c| c), None);
+
+    // latency bucket -> count
+    let map: FlatMap<u64, u64> = vec![(10, 5), (20, 0), (30, 3), (40, 1), (50, 1)].into_iter().collect();
+    let key = |e: Option<(&u64, &u64)>| e.map(|(&k, _)| k);
+
+    assert_eq!(key(map.quantile_key(0.0)), Some(10));
+    assert_eq!(key(map.quantile_key(0.2)), Some(10));
+    assert_eq!(key(map.quantile_key(0.21)), Some(20));
+    assert_eq!(key(map.quantile_key(0.5)), Some(30));
+    assert_eq!(key(map.quantile_key(0.9)), Some(50));
+    assert_eq!(key(map.quantile_key(1.0)), Some(50));
+
+    // cumulative counts: 5, 5, 8, 9, 10
+    assert_eq!(key(map.quantile_key_by_weight(0.0, |&c| c)), Some(10));
+    assert_eq!(key(map.quantile_key_by_weight(0.5, |&c| c)), Some(10));
+    assert_eq!(key(map.quantile_key_by_weight(0.51, |&c| c)), Some(30));
+    assert_eq!(key(map.quantile_key_by_weight(0.8, |&c| c)), Some(30));
+    assert_eq!(key(map.quantile_key_by_weight(0.9, |&c| c)), Some(40));
+    assert_eq!(key(map.quantile_key_by_weight(0.99, |&c| c)), Some(50));
+    assert_eq!(key(map.quantile_key_by_weight(1.0, |&c| c)), Some(50));
+    assert_eq!(map.quantile_key_by_weight(0.5, |_| 0), None);
+
+    let zero_first: FlatMap<u64, u64> = vec![(1, 0), (2, 4)].into_iter().collect();
+    assert_eq!(key(zero_first.quantile_key_by_weight(0.0, |&c| c)), Some(2));
+}
+
+#[test]
+#[should_panic(expected = "not in 0.0..=1.0")]
+fn test_quantile_key_out_of_range() {
+    let map: FlatMap<u64, u64> = vec![(1, 1)].into_iter().collect();
+    map.quantile_key(1.5);
+}
+
+#[test]
+#[should_panic(expected = "not in 0.0..=1.0")]
+fn test_quantile_key_nan() {
+    let map: FlatMap<u64, u64> = FlatMap::new();
+    map.quantile_key_by_weight(f64::NAN, |&c| c);
+}
+
+#[test]
+fn test_collect_into() {
+    let mut rng = DeterministicRng::new();
+    let mut map = FlatMap::with_capacity(1000);
+    let capacity = map.capacity();
+    let mut first_key = None;
+
+    for _ in 0..10 {
+        let batch: Vec<_> = (0..1000).map(|_| (rng.next() % 500, rng.next())).collect();
+        map.collect_into(batch.iter().cloned());
+        assert_eq!(map.capacity(), capacity);
+        let ptr = map.keys().next().unwrap() as *const u32;
+        assert_eq!(*first_key.get_or_insert(ptr), ptr);
+        assert_eq!(map, batch.into_iter().collect::<FlatMap<_, _>>());
+    }
+
+    assert_eq!(map.collect_into(vec![(2, 20), (1, 10), (2, 30)]).get(&2), Some(&20));
+}
+
+#[test]
+fn test_lookup_strategies_agree() {
+    let mut rng = DeterministicRng::new();
+    for len in 0..40 {
+        let mut keys: Vec<u32> = (0..len).map(|_| rng.next() % 60).collect();
+        keys.sort();
+        keys.dedup();
+        let slice: Vec<(u32, ())> = keys.into_iter().map(|k| (k, ())).collect();
+        for q in 0..62 {
+            let expected = BinarySearch.lookup(&slice, &q);
+            assert_eq!(LinearFront.lookup(&slice, &q), expected);
+            assert_eq!(LinearBack.lookup(&slice, &q), expected);
+        }
+    }
+}
+
+#[test]
+fn test_range() {
+    use std::collections::BTreeMap;
+    use std::ops::Bound::{self, Excluded, Included, Unbounded};
+
+    fn check<L: Lookup<u32, u32, u32> + Sorted + Default>(btree: &BTreeMap<u32, u32>) {
+        let mut map: FlatMap<u32, u32, L> = btree.iter().map(|(&k, &v)| (k, v)).collect();
+        let bounds = |q| vec![Included(q), Excluded(q), Unbounded];
+        for s in 0..22 {
+            for e in s..22 {
+                for start in bounds(s) {
+                    for end in bounds(e) {
+                        if let (Excluded(a), Excluded(b)) = (start, end) {
+                            if a == b {
+                                continue;
+                            }
+                        }
+                        let range: (Bound<u32>, Bound<u32>) = (start, end);
+                        let expected: Vec<_> = btree.range(range).collect();
+                        assert_eq!(map.range(range).collect::<Vec<_>>(), expected);
+                        assert_eq!(map.range(range).len(), expected.len());
+                        let mut rev: Vec<_> = map.range(range).rev().collect();
+                        rev.reverse();
+                        assert_eq!(rev, expected);
+                        let keys: Vec<_> = map.range_mut(range).map(|(k, _)| k).cloned().collect();
+                        assert_eq!(keys, expected.iter().map(|kv| *kv.0).collect::<Vec<_>>());
+                    }
+                }
+            }
+        }
+
+        for (_, v) in map.range_mut(5..10) {
+            *v += 1000;
+        }
+        for (k, v) in &map {
+            assert_eq!(*v, btree[k] + if (5..10).contains(k) { 1000 } else { 0 });
+        }
+    }
+
+    let mut rng = DeterministicRng::new();
+    let btree: BTreeMap<u32, u32> = (0..12).map(|_| (rng.next() % 20, rng.next())).collect();
+    check::<BinarySearch>(&btree);
+    check::<LinearFront>(&btree);
+    check::<LinearBack>(&btree);
+    check::<BinarySearch>(&BTreeMap::new());
+
+    let names: FlatMap<String, u32> = vec![("a".to_string(), 1), ("b".to_string(), 2), ("c".to_string(), 3)]
+        .into_iter()
+        .collect();
+    let window: Vec<_> = names.range::<str, _>((Excluded("a"), Unbounded)).map(|(_, &v)| v).collect();
+    assert_eq!(window, [2, 3]);
+}
+
+#[test]
+#[should_panic(expected = "range start is greater than range end")]
+fn test_range_inverted() {
+    let map: FlatMap<u32, u32> = (0..10).map(|k| (k, k)).collect();
+    #[allow(clippy::reversed_empty_ranges)]
+    map.range(5..3);
+}
+
+#[test]
+#[should_panic(expected = "range start and end are equal and excluded")]
+fn test_range_mut_equal_excluded() {
+    use std::ops::Bound::Excluded;
+
+    let mut map: FlatMap<u32, u32, LinearBack> = (0..10).map(|k| (k, k)).collect();
+    map.range_mut((Excluded(4), Excluded(4)));
+}
+
+#[test]
+fn test_retain() {
+    let source: FlatMap<u32, u32> = (0..20).map(|k| (k, k * 10)).collect();
+    type Keep = fn(u32) -> bool;
+    let cases: [(&str, Keep); 5] = [
+        ("front", |k| k >= 5),
+        ("back", |k| k < 15),
+        ("middle", |k| !(5..15).contains(&k)),
+        ("everything", |_| false),
+        ("nothing", |_| true),
+    ];
+    for &(name, keep) in &cases {
+        let mut map = source.clone();
+        let mut visited = Vec::new();
+        map.retain(|&k, v| {
+            visited.push(k);
+            *v += 1;
+            keep(k)
+        });
+        assert_eq!(visited, (0..20).collect::<Vec<_>>(), "{}", name);
+        let expected: FlatMap<u32, u32> = (0..20).filter(|&k| keep(k)).map(|k| (k, k * 10 + 1)).collect();
+        assert_eq!(map, expected, "{}", name);
+    }
+}
+
+#[test]
+fn test_extend_merges() {
+    fn check(existing: &[(u32, u32)], new: &[(u32, u32)]) {
+        let mut expected: FlatMap<u32, u32> = existing.iter().cloned().collect();
+        for &(k, v) in new {
+            expected.insert(k, v);
+        }
+        let mut map: FlatMap<u32, u32> = existing.iter().cloned().collect();
+        map.extend(new.iter().cloned());
+        assert_eq!(map, expected);
+        let mut map: FlatMap<u32, u32> = existing.iter().cloned().collect();
+        map.extend(new.iter().map(|(k, v)| (k, v)));
+        assert_eq!(map, expected);
+    }
+
+    let existing: Vec<_> = (100..200).map(|k| (k * 2, k)).collect();
+    let mut rng = DeterministicRng::new();
+    // interleaved
+    check(&existing, &(0..100).map(|_| (rng.next() % 600, rng.next())).collect::<Vec<_>>());
+    // all before
+    check(&existing, &(0..100).rev().map(|k| (k, k)).collect::<Vec<_>>());
+    // all after
+    check(&existing, &(500..600).map(|k| (k, k)).collect::<Vec<_>>());
+    // duplicate heavy, both within the input and against the map
+    check(&existing, &(0..1000).map(|_| (200 + rng.next() % 10, rng.next())).collect::<Vec<_>>());
+    // edge cases
+    check(&existing, &[]);
+    check(&existing, &[(201, 1)]);
+    check(&[], &[(3, 1), (1, 1), (3, 2)]);
+}
+
+#[test]
+fn test_drain() {
+    let mut map: FlatMap<u32, String> = (0..10).map(|k| (k, k.to_string())).collect();
+    let capacity = map.capacity();
+    let mut other = FlatMap::new();
+    other.extend_sorted(map.drain());
+    assert!(map.is_empty());
+    assert_eq!(map.capacity(), capacity);
+    assert_eq!(other.len(), 10);
+    assert_eq!(other[&7], "7");
+
+    // dropping a partially consumed drain still empties the map
+    let mut drain = other.drain();
+    assert_eq!(drain.next(), Some((0, "0".to_string())));
+    assert_eq!(drain.next_back(), Some((9, "9".to_string())));
+    drop(drain);
+    assert!(other.is_empty());
+}
+
+#[test]
+fn test_extract_if() {
+    let mut rng = DeterministicRng::new();
+    let source: FlatMap<u32, u32> = (0..200).map(|_| (rng.next() % 1000, rng.next())).collect();
+
+    let mut map = source.clone();
+    let mut visited = Vec::new();
+    let extracted: Vec<_> = map.extract_if(|&k, v| {
+            visited.push(k);
+            *v = v.wrapping_add(1);
+            k % 3 == 0
+        })
+        .collect();
+    assert_eq!(visited, source.keys().cloned().collect::<Vec<_>>());
+    let expected: Vec<_> = source.iter().map(|(&k, &v)| (k, v.wrapping_add(1))).collect();
+    assert_eq!(extracted, expected.iter().cloned().filter(|kv| kv.0 % 3 == 0).collect::<Vec<_>>());
+    assert_eq!(map.into_inner(), expected.iter().cloned().filter(|kv| kv.0 % 3 != 0).collect::<Vec<_>>());
+
+    // entries not visited before the iterator is dropped stay in the map
+    let mut map = source.clone();
+    let first = map.extract_if(|_, _| true).next();
+    assert_eq!(first.as_ref(), source.iter().map(|(&k, &v)| (k, v)).next().as_ref());
+    assert_eq!(map.len(), source.len() - 1);
+    assert_eq!(map.iter().collect::<Vec<_>>(), source.iter().skip(1).collect::<Vec<_>>());
+
+    let mut map = source.clone();
+    drop(map.extract_if(|_, _| true));
+    assert_eq!(map, source);
+}
+
+#[test]
+fn test_first_last() {
+    let mut empty: FlatMap<u32, u32> = FlatMap::new();
+    assert_eq!(empty.first_key_value(), None);
+    assert_eq!(empty.last_key_value(), None);
+    assert!(empty.first_entry().is_none());
+    assert!(empty.last_entry().is_none());
+    assert_eq!(empty.first_value_mut(), None);
+    assert_eq!(empty.last_value_mut(), None);
+    assert_eq!(empty.pop_first(), None);
+    assert_eq!(empty.pop_last(), None);
+
+    // deadline -> task
+    let mut map: FlatMap<u32, &str> = vec![(30, "c"), (10, "a"), (20, "b")].into_iter().collect();
+    assert_eq!(map.first_key_value(), Some((&10, &"a")));
+    assert_eq!(map.last_key_value(), Some((&30, &"c")));
+    *map.last_value_mut().unwrap() = "C";
+    assert_eq!(map.last_key_value(), Some((&30, &"C")));
+    *map.last_value_mut().unwrap() = "c";
+
+    *map.first_entry().unwrap().get_mut() = "A";
+    assert_eq!(map.last_entry().unwrap().key(), &30);
+    assert_eq!(map.last_entry().unwrap().remove(), "c");
+
+    assert_eq!(map.first_value_mut(), Some(&mut "A"));
+    assert_eq!(map.pop_first(), Some((10, "A")));
+    assert_eq!(map.pop_last(), Some((20, "b")));
+    assert!(map.is_empty());
+
+    let mut map: FlatMap<u32, u32> = (0..100).map(|k| (k, k)).collect();
+    let mut popped = Vec::new();
+    while let Some((k, _)) = map.pop_first() {
+        popped.push(k);
+        if let Some((k, _)) = map.pop_last() {
+            popped.push(k);
+        }
+    }
+    popped.sort();
+    assert_eq!(popped, (0..100).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_entry_parity() {
+    let mut counts: FlatMap<&str, u32> = FlatMap::new();
+    for word in "the cat and the hat and the bat".split(' ') {
+        counts.entry(word).and_modify(|c| *c += 1).or_insert(1);
+    }
+    assert_eq!(counts[&"the"], 3);
+    assert_eq!(counts[&"and"], 2);
+    assert_eq!(counts[&"cat"], 1);
+
+    *counts.entry("dog").or_default() += 5;
+    assert_eq!(counts[&"dog"], 5);
+    assert_eq!(*counts.entry("cat").or_default(), 1);
+
+    let mut lengths: FlatMap<String, usize> = FlatMap::new();
+    assert_eq!(*lengths.entry("four".to_string()).or_insert_with_key(|k| k.len()), 4);
+    assert_eq!(*lengths.entry("four".to_string()).or_insert_with_key(|_| unreachable!()), 4);
+
+    match lengths.entry("five".to_string()) {
+        Occupied(_) => unreachable!(),
+        Vacant(e) => {
+            assert_eq!(e.key(), "five");
+            assert_eq!(e.into_key(), "five");
+        }
+    }
+    assert!(!lengths.contains_key("five"));
+
+    assert_eq!(lengths.entry("four".to_string()).key(), "four");
+    assert_eq!(lengths.entry("zero".to_string()).key(), "zero");
+
+    match lengths.entry("four".to_string()) {
+        Occupied(e) => assert_eq!(e.remove_entry(), ("four".to_string(), 4)),
+        Vacant(_) => unreachable!(),
+    }
+    assert!(lengths.is_empty());
+}
+
+#[test]
+fn test_get_key_value_and_remove_entry() {
+    let mut interned: FlatMap<Rc<str>, u32> = FlatMap::new();
+    let hello: Rc<str> = Rc::from("hello");
+    interned.insert(hello.clone(), 1);
+    interned.insert(Rc::from("world"), 2);
+
+    let (key, value) = interned.get_key_value("hello").unwrap();
+    assert!(Rc::ptr_eq(key, &hello));
+    assert_eq!(*value, 1);
+    assert_eq!(interned.get_key_value("nope"), None);
+
+    let (key, value) = interned.remove_entry("hello").unwrap();
+    assert!(Rc::ptr_eq(&key, &hello));
+    assert_eq!(value, 1);
+    assert_eq!(interned.remove_entry("hello"), None);
+    assert_eq!(interned.len(), 1);
+}
+
+#[test]
+fn test_split_off_absent_key() {
+    let source: FlatMap<u32, u32> = (1..6).map(|k| (k * 10, k)).collect();
+    let keys = |m: &FlatMap<u32, u32>| m.keys().cloned().collect::<Vec<_>>();
+
+    // smaller than all entries: everything moves out
+    let mut map = source.clone();
+    let right = map.split_off(&5);
+    assert!(map.is_empty());
+    assert_eq!(right, source);
+
+    // larger than all entries: nothing moves
+    let mut map = source.clone();
+    let right = map.split_off(&55);
+    assert_eq!(map, source);
+    assert!(right.is_empty());
+
+    // between two existing keys
+    let mut map = source.clone();
+    let right = map.split_off(&25);
+    assert_eq!(keys(&map), [10, 20]);
+    assert_eq!(keys(&right), [30, 40, 50]);
+
+    // equal to an existing key
+    let mut map = source.clone();
+    let right = map.split_off(&30);
+    assert_eq!(keys(&map), [10, 20]);
+    assert_eq!(keys(&right), [30, 40, 50]);
+
+    let mut names: FlatMap<String, u32> = vec![("a".to_string(), 1), ("c".to_string(), 3)].into_iter().collect();
+    let right = names.split_off("b");
+    assert_eq!(names.len(), 1);
+    assert_eq!(right["c"], 3);
+}
+
+#[test]
+fn test_flat_set() {
+    let mut set: FlatSet<u32> = FlatSet::new();
+    assert!(set.insert(3));
+    assert!(set.insert(1));
+    assert!(!set.insert(3));
+    assert!(set.insert(2));
+    assert_eq!(set.len(), 3);
+    assert!(set.contains(&2));
+    assert_eq!(set.get(&1), Some(&1));
+    assert_eq!(set.iter().cloned().collect::<Vec<_>>(), [1, 2, 3]);
+    assert_eq!((set.first(), set.last()), (Some(&1), Some(&3)));
+    assert!(set.remove(&2));
+    assert!(!set.remove(&2));
+    assert_eq!(set.take(&3), Some(3));
+    assert_eq!(format!("{:?}", set), "{1}");
+
+    let mut names: FlatSet<String, LinearBack> = FlatSet::with_lookup(LinearBack);
+    names.extend(vec!["b".to_string(), "a".to_string(), "b".to_string()]);
+    assert!(names.contains("a"));
+    assert_eq!(names.into_iter().collect::<Vec<_>>(), ["a", "b"]);
+
+    let set: FlatSet<u32> = vec![5, 1, 5, 3].into_iter().collect();
+    let copy = set.clone();
+    assert_eq!(set, copy);
+    assert_eq!(set.iter().rev().cloned().collect::<Vec<_>>(), [5, 3, 1]);
+    assert!(set < vec![5].into_iter().collect());
+}
+
+#[test]
+fn test_flat_set_algebra() {
+    use std::collections::BTreeSet;
+
+    let mut rng = DeterministicRng::new();
+    for _ in 0..50 {
+        let a: Vec<u32> = (0..rng.next() % 30).map(|_| rng.next() % 40).collect();
+        let b: Vec<u32> = (0..rng.next() % 30).map(|_| rng.next() % 40).collect();
+        let (fa, fb): (FlatSet<u32>, FlatSet<u32, LinearFront>) =
+            (a.iter().cloned().collect(), b.iter().cloned().collect());
+        let (ba, bb): (BTreeSet<u32>, BTreeSet<u32>) = (a.into_iter().collect(), b.into_iter().collect());
+
+        assert!(fa.union(&fb).eq(ba.union(&bb)));
+        assert!(fa.intersection(&fb).eq(ba.intersection(&bb)));
+        assert!(fa.difference(&fb).eq(ba.difference(&bb)));
+        assert!(fa.symmetric_difference(&fb).eq(ba.symmetric_difference(&bb)));
+        assert_eq!(fa.is_subset(&fb), ba.is_subset(&bb));
+        assert_eq!(fa.is_superset(&fb), ba.is_superset(&bb));
+        assert_eq!(fa.is_disjoint(&fb), ba.is_disjoint(&bb));
+    }
+
+    let small: FlatSet<u32> = vec![2, 4].into_iter().collect();
+    let big: FlatSet<u32> = (0..10).collect();
+    assert!(small.is_subset(&big));
+    assert!(big.is_superset(&small));
+    assert!(!big.is_subset(&small));
+    assert!(small.is_disjoint(&FlatSet::<u32>::new()));
+}
+
+#[test]
+fn test_try_insert() {
+    let mut map: FlatMap<u32, String> = FlatMap::new();
+    *map.try_insert(20, "twenty".to_string()).unwrap() += "!";
+    assert_eq!(map[&20], "twenty!");
+    assert_eq!(map.try_insert(10, "ten".to_string()).unwrap(), "ten");
+    assert_eq!(map.try_insert(30, "thirty".to_string()).unwrap(), "thirty");
+
+    {
+        let mut err = map.try_insert(20, "again".to_string()).unwrap_err();
+        assert_eq!(err.entry.key(), &20);
+        assert_eq!(err.entry.get(), "twenty!");
+        assert_eq!(err.to_string(), r#"failed to insert "again", key 20 already exists with value "twenty!""#);
+        err.entry.get_mut().push('?');
+        assert_eq!(err.into_inner(), (20, "again".to_string()));
+    }
+    assert_eq!(map.keys().cloned().collect::<Vec<_>>(), [10, 20, 30]);
+    assert_eq!(map[&20], "twenty!?");
+
+    let err = map.try_insert(10, "x".to_string()).unwrap_err();
+    assert_eq!(err.entry.remove(), "ten");
+    assert_eq!(map.len(), 2);
+}
+
+#[test]
+fn test_from_sorted_vec() {
+    let empty: FlatMap<u32, u32> = FlatMap::from_sorted_vec(Vec::new()).unwrap();
+    assert!(empty.is_empty());
+    let single: FlatMap<u32, u32> = FlatMap::from_sorted_vec(vec![(1, 1)]).unwrap();
+    assert_eq!(single[&1], 1);
+
+    let sorted: Vec<(u32, u32)> = (0..100).map(|k| (k * 2, k)).collect();
+    let adopted = sorted.clone();
+    let ptr = adopted.as_ptr();
+    let map: FlatMap<u32, u32, LinearBack> = FlatMap::from_sorted_vec(adopted).unwrap();
+    assert_eq!(map.get(&42), Some(&21));
+    let inner = map.into_inner();
+    assert_eq!(inner, sorted);
+    assert_eq!(inner.as_ptr(), ptr);
+
+    let err = FlatMap::<u32, u32>::from_sorted_vec(vec![(1, 0), (3, 0), (2, 0)]).unwrap_err();
+    assert_eq!(err.index(), 2);
+    assert_eq!(err.into_input(), [(1, 0), (3, 0), (2, 0)]);
+
+    let err = FlatMap::<u32, u32>::from_sorted_vec(vec![(1, 0), (2, 0), (2, 1)]).unwrap_err();
+    assert_eq!(err.index(), 2);
+
+    let err = FlatMap::<u32, u32>::from_sorted_vec(vec![(5, 0), (4, 0)]).unwrap_err();
+    assert_eq!(err.index(), 1);
+
+    let map: FlatMap<u32, u32> = FlatMap::from_sorted_vec_unchecked(sorted.clone());
+    assert_eq!(map.into_inner(), sorted);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "not strictly sorted")]
+fn test_from_sorted_vec_unchecked_debug_assert() {
+    let _: FlatMap<u32, u32> = FlatMap::from_sorted_vec_unchecked(vec![(2, 0), (1, 0)]);
+}
+
+#[test]
+fn test_sorted_constructors_follow_order() {
+    use flat_map::{CaseInsensitive, Descending, OrderBy};
+
+    type Desc = FlatMap<i32, char, OrderBy<Descending>>;
+    type Names = FlatMap<&'static str, u32, OrderBy<CaseInsensitive>>;
+
+    assert_eq!(Desc::from_sorted_vec(vec![(1, 'a'), (2, 'b')]).unwrap_err().index(), 1);
+    let desc = Desc::from_sorted_vec(vec![(3, 'c'), (1, 'a')]).unwrap();
+    assert_eq!(desc.get(&1), Some(&'a'));
+    assert_eq!(desc.get(&3), Some(&'c'));
+    let desc = Desc::from_sorted_iter(vec![(3, 'c'), (2, 'b')]);
+    assert_eq!(desc.get(&2), Some(&'b'));
+
+    let desc = Desc::try_from_iter(vec![(1, 'a'), (5, 'e'), (3, 'c')]).unwrap();
+    assert_eq!(desc.keys().cloned().collect::<Vec<_>>(), [5, 3, 1]);
+    assert!([1, 3, 5].iter().all(|k| desc.contains_key(k)));
+
+    // "B" sorts before "a" by `Ord`, but after it without case
+    let names = Names::from_sorted_vec(vec![("a", 1), ("B", 2)]).unwrap();
+    assert_eq!(names.get(&"b"), Some(&2));
+    assert_eq!(Names::from_sorted_vec(vec![("B", 2), ("a", 1)]).unwrap_err().index(), 1);
+}
+
+#[test]
+fn test_into_keys_values() {
+    struct Owned {
+        keys: flat_map::flat_map::IntoKeys<u32, String>,
+    }
+
+    let map: FlatMap<u32, String> = (0..5).map(|k| (k, k.to_string())).collect();
+    let mut owned = Owned { keys: map.clone().into_keys() };
+    assert_eq!(owned.keys.len(), 5);
+    assert_eq!(owned.keys.next(), Some(0));
+    assert_eq!(owned.keys.next_back(), Some(4));
+    assert_eq!(format!("{:?}", owned.keys), "[1, 2, 3]");
+    assert_eq!(owned.keys.collect::<Vec<_>>(), [1, 2, 3]);
+
+    let mut values = map.into_values();
+    assert_eq!(values.next_back(), Some("4".to_string()));
+    assert_eq!(format!("{:?}", values), r#"["0", "1", "2", "3"]"#);
+    assert_eq!(values.by_ref().count(), 4);
+    assert_eq!(values.next(), None);
+    assert_eq!(values.next(), None);
+}
+
+#[test]
+fn test_get_many_mut() {
+    let mut m: FlatMap<u32, u32> = (1..=5).map(|k| (k, k * 10)).collect();
+
+    // adjacent entries, requested in reverse order
+    {
+        let [b, a] = m.get_many_mut([&2, &1]).unwrap();
+        assert_eq!((*a, *b), (10, 20));
+        std::mem::swap(a, b);
+    }
+    assert_eq!(m.get(&1), Some(&20));
+    assert_eq!(m.get(&2), Some(&10));
+
+    {
+        let [e, a, c] = m.get_many_mut([&5, &1, &3]).unwrap();
+        *e += 1;
+        *a += 1;
+        *c += 1;
+    }
+    assert_eq!(m.values().cloned().collect::<Vec<_>>(), vec![21, 10, 31, 40, 51]);
+
+    assert!(m.get_many_mut([&1, &6]).is_none());
+    assert!(m.get_many_mut([&1, &6, &2]).is_none());
+    assert!(m.get_many_mut([&3, &3]).is_none());
+    assert!(m.get_many_mut([&3, &4, &3]).is_none());
+    assert_eq!(m.get_many_mut::<u32, 0>([]), Some([]));
+}
+
+// no Ord, no Hash
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Color {
+    Red,
+    Green,
+    Blue,
+}
+
+#[test]
+fn test_unsorted_linear() {
+    use Color::*;
+
+    let mut m: FlatMap<Color, u32, UnsortedLinear> = FlatMap::with_lookup(UnsortedLinear);
+    assert_eq!(m.insert(Blue, 1), None);
+    assert_eq!(m.insert(Red, 2), None);
+    assert_eq!(m.insert(Blue, 3), Some(1));
+    *m.entry(Green).or_insert(0) += 4;
+    *m.entry(Red).or_insert(0) += 4;
+    assert_eq!(m.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>(),
+               vec![(Blue, 3), (Red, 6), (Green, 4)]);
+    assert_eq!(m.get(&Red), Some(&6));
+    assert_eq!(m[&Green], 4);
+    assert_eq!(format!("{:?}", m), "{Blue: 3, Red: 6, Green: 4}");
+
+    assert_eq!(m.remove(&Blue), Some(3));
+    assert_eq!(m.remove(&Blue), None);
+    assert_eq!(m.keys().cloned().collect::<Vec<_>>(), vec![Red, Green]);
+
+    // collect keeps the first of equal keys, extend the last, each in the
+    // position the key first appeared
+    let c: FlatMap<Color, u32, UnsortedLinear> =
+        vec![(Green, 1), (Red, 2), (Green, 3)].into_iter().collect();
+    assert_eq!(c.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>(), vec![(Green, 1), (Red, 2)]);
+    let mut e = c.clone();
+    e.extend(vec![(Blue, 4), (Green, 5), (Blue, 6)]);
+    assert_eq!(e.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>(),
+               vec![(Green, 5), (Red, 2), (Blue, 6)]);
+
+    // equality ignores insertion order
+    let reordered: FlatMap<Color, u32, UnsortedLinear> =
+        vec![(Red, 2), (Green, 1)].into_iter().collect();
+    assert_eq!(c, reordered);
+    assert!(c != e);
+
+    let mut merged = c.clone();
+    merged.merge_with(reordered, |_, a, b| a + b);
+    assert_eq!(merged.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>(), vec![(Green, 2), (Red, 4)]);
+}
+
+#[test]
+fn test_unsorted_float_keys() {
+    // f64 is neither Ord nor Eq
+    let mut m: FlatMap<f64, &str, UnsortedLinear> = FlatMap::with_lookup(UnsortedLinear);
+    assert_eq!(m.insert(2.5, "two and a half"), None);
+    assert_eq!(m.insert(-1.0, "minus one"), None);
+    assert_eq!(m.insert(2.5, "2.5"), Some("two and a half"));
+    assert_eq!(m.get(&2.5), Some(&"2.5"));
+    assert!(m.contains_key(&-1.0));
+    assert!(m.get(&0.0).is_none());
+    *m.entry(0.0).or_insert("zero") = "nought";
+    assert_eq!(m.remove(&-1.0), Some("minus one"));
+    assert_eq!(m.into_inner(), vec![(2.5, "2.5"), (0.0, "nought")]);
+}
+
+#[test]
+fn test_unsorted_append_into_sorted() {
+    let mut unsorted: FlatMap<u32, char, UnsortedLinear> = FlatMap::with_lookup(UnsortedLinear);
+    for &(k, v) in &[(5, 'e'), (1, 'a'), (3, 'c')] {
+        unsorted.insert(k, v);
+    }
+    let mut sorted: FlatMap<u32, char> = vec![(2, 'b'), (3, 'x')].into_iter().collect();
+    sorted.append(&mut unsorted);
+    assert!(unsorted.is_empty());
+    assert_eq!(sorted.into_inner(), vec![(1, 'a'), (2, 'b'), (3, 'c'), (5, 'e')]);
+}
+
+#[test]
+fn test_insert_hint() {
+    let mut m: FlatMap<u32, u32> = FlatMap::new();
+    assert_eq!(m.last_insert_index(), None);
+    for k in (0..20).map(|k| k * 2) {
+        let hint = m.last_insert_index().map_or(0, |i| i + 1);
+        assert_eq!(m.insert_hint(hint, k, k), None);
+    }
+    assert_eq!(m.last_insert_index(), Some(19));
+
+    // right hint for a gap, for an existing key, and wrong hints
+    assert_eq!(m.insert_hint(3, 5, 5), None);
+    assert_eq!(m.last_insert_index(), Some(3));
+    assert_eq!(m.insert_hint(3, 5, 50), Some(5));
+    assert_eq!(m.insert_hint(0, 7, 7), None);
+    assert_eq!(m.last_insert_index(), Some(5));
+    assert_eq!(m.insert_hint(100, 1, 1), None);
+    assert_eq!(m.insert_hint(22, 38, 380), Some(38));
+    assert_eq!(m.last_insert_index(), Some(22));
+    assert_eq!(m.insert_hint(0, 100, 100), None);
+    assert_eq!(m.last_insert_index(), Some(23));
+
+    // try_insert and push_max remember where they put a new entry
+    let mut n: FlatMap<u32, u32> = FlatMap::new();
+    n.push_max(10, 10).unwrap();
+    assert_eq!(n.last_insert_index(), Some(0));
+    n.push_max(20, 20).unwrap();
+    assert_eq!(n.last_insert_index(), Some(1));
+    assert!(n.push_max(15, 15).is_err());
+    assert_eq!(n.last_insert_index(), Some(1));
+    n.try_insert(5, 5).unwrap();
+    assert_eq!(n.last_insert_index(), Some(0));
+    assert!(n.try_insert(20, 0).is_err());
+    assert_eq!(n.last_insert_index(), Some(0));
+    let hint = n.last_insert_index().map_or(0, |i| i + 1);
+    assert_eq!(n.insert_hint(hint, 7, 7), None);
+    assert_eq!(n.into_inner(), [(5, 5), (7, 7), (10, 10), (20, 20)]);
+
+    let expected: FlatMap<u32, u32> = (0..20).map(|k| (k * 2, k * 2))
+        .chain(vec![(1, 1), (5, 50), (7, 7), (38, 380), (100, 100)])
+        .fold(FlatMap::new(), |mut acc, (k, v)| {
+            acc.insert(k, v);
+            acc
+        });
+    assert_eq!(m, expected);
+
+    m.insert(3, 3);
+    assert_eq!(m.last_insert_index(), Some(3));
+    m.clear();
+    assert_eq!(m.last_insert_index(), None);
+}
+
+#[test]
+fn test_with_mut_slice() {
+    let mut m: FlatMap<i32, char> = vec![(1, 'a'), (2, 'b'), (3, 'c'), (4, 'd')].into_iter().collect();
+    assert_eq!(m.as_slice(), &[(1, 'a'), (2, 'b'), (3, 'c'), (4, 'd')][..]);
+
+    // reverse the key order and collide two keys
+    let n = m.with_mut_slice(|entries| {
+        for entry in entries.iter_mut() {
+            entry.0 = (5 - entry.0).min(3);
+        }
+        entries.len()
+    });
+    assert_eq!(n, 4);
+    assert_eq!(m.as_slice(), &[(1, 'd'), (2, 'c'), (3, 'a')][..]);
+    assert_eq!(m.get(&2), Some(&'c'));
+    assert_eq!(m.insert(0, 'z'), None);
+    assert_eq!(m.keys().cloned().collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+
+    // the order is restored when the closure panics, too
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        m.with_mut_slice(|entries| {
+            entries.swap(0, 3);
+            panic!("boom");
+        })
+    }));
+    assert!(result.is_err());
+    assert_eq!(m.keys().cloned().collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+    assert_eq!(m.get(&0), Some(&'z'));
+}
+
+#[test]
+fn test_eq_hash_across_strategies() {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    fn hash_of<T: Hash>(t: &T) -> u64 {
+        let mut h = DefaultHasher::new();
+        t.hash(&mut h);
+        h.finish()
+    }
+
+    let entries = [(3, 'c'), (1, 'a'), (2, 'b')];
+    let binary: FlatMap<u32, char> = entries.iter().cloned().collect();
+    let front: FlatMap<u32, char, LinearFront> = entries.iter().cloned().collect();
+    let back: FlatMap<u32, char, LinearBack> = entries.iter().rev().cloned().collect();
+    let unsorted: FlatMap<u32, char, UnsortedLinear> = entries.iter().cloned().collect();
+    let reordered: FlatMap<u32, char, UnsortedLinear> = entries.iter().rev().cloned().collect();
+
+    assert!(binary == front);
+    assert!(front == back);
+    assert!(back == binary);
+    assert_eq!(hash_of(&binary), hash_of(&front));
+    assert_eq!(hash_of(&binary), hash_of(&back));
+
+    assert!(unsorted == reordered);
+    assert!(unsorted == binary);
+    assert!(binary == unsorted);
+    assert!(reordered == back);
+
+    let mut other = binary.clone();
+    other.insert(2, 'x');
+    assert!(other != front);
+    assert!(unsorted != other);
+    assert!(other != unsorted);
+    assert!(hash_of(&other) != hash_of(&front));
+    other.insert(2, 'b');
+    other.insert(4, 'd');
+    assert!(other != front);
+    assert!(reordered != other);
+
+    assert!(binary.keys_eq(&reordered));
+    assert!(reordered.keys_eq(&binary));
+    let sets: (FlatSet<u32>, FlatSet<u32, UnsortedLinear>) =
+        (vec![2, 1].into_iter().collect(), vec![1, 2].into_iter().collect());
+    assert!(sets.0 == sets.1);
+}
+
+#[test]
+fn test_std_conversions() {
+    use std::collections::BTreeMap;
+
+    let btree: BTreeMap<u32, &str> = vec![(5, "e"), (1, "a"), (3, "c")].into_iter().collect();
+    let expected: Vec<_> = btree.iter().map(|(&k, &v)| (k, v)).collect();
+    let m = FlatMap::from(btree.clone());
+    assert_eq!(m.as_slice(), &expected[..]);
+    assert_eq!(m.get(&3), Some(&"c"));
+    assert_eq!(BTreeMap::from(m), btree);
+
+    let unsorted: FlatMap<u32, &str, UnsortedLinear> =
+        vec![(5, "e"), (1, "a")].into_iter().collect();
+    let back: BTreeMap<_, _> = unsorted.into();
+    assert_eq!(back.into_iter().collect::<Vec<_>>(), [(1, "a"), (5, "e")]);
+
+    let m: FlatMap<_, _> = FlatMap::from([(2, 'x'), (1, 'a'), (2, 'b')]);
+    assert_eq!(m.into_inner(), [(1, 'a'), (2, 'b')]);
+    let m: FlatMap<_, _> = vec![(2, 'x'), (1, 'a'), (2, 'b')].into();
+    assert_eq!(m.into_inner(), [(1, 'a'), (2, 'b')]);
+    let m: FlatMap<_, _, UnsortedLinear> = FlatMap::from([(2, 'x'), (1, 'a'), (2, 'b')]);
+    assert_eq!(m.into_inner(), [(2, 'b'), (1, 'a')]);
+
+    let m: FlatMap<_, _, LinearBack> = vec![(2, 'b'), (1, 'a')].into_iter().collect();
+    assert_eq!(m.get(&2), Some(&'b'));
+    assert_eq!(m.into_inner(), [(1, 'a'), (2, 'b')]);
+}
+
+#[test]
+fn test_entry_neighbors() {
+    let mut m: FlatMap<u32, char> = FlatMap::from([(10, 'a'), (20, 'b'), (30, 'c')]);
+    match m.entry(10) {
+        Occupied(e) => {
+            assert_eq!(e.index(), 0);
+            assert_eq!(e.peek_prev(), None);
+            assert_eq!(e.peek_next(), Some((&20, &'b')));
+        }
+        Vacant(_) => panic!(),
+    }
+    match m.entry(30) {
+        Occupied(e) => {
+            assert_eq!(e.index(), 2);
+            assert_eq!(e.peek_prev(), Some((&20, &'b')));
+            assert_eq!(e.peek_next(), None);
+        }
+        Vacant(_) => panic!(),
+    }
+    match m.entry(5) {
+        Vacant(e) => {
+            assert_eq!(e.index(), 0);
+            assert_eq!(e.peek_prev(), None);
+            assert_eq!(e.peek_next(), Some((&10, &'a')));
+        }
+        Occupied(_) => panic!(),
+    }
+    match m.entry(25) {
+        Vacant(e) => {
+            assert_eq!(e.index(), 2);
+            assert_eq!(e.peek_prev(), Some((&20, &'b')));
+            assert_eq!(e.peek_next(), Some((&30, &'c')));
+        }
+        Occupied(_) => panic!(),
+    }
+    match m.entry(40) {
+        Vacant(e) => {
+            assert_eq!(e.index(), 3);
+            assert_eq!(e.peek_prev(), Some((&30, &'c')));
+            assert_eq!(e.peek_next(), None);
+        }
+        Occupied(_) => panic!(),
+    }
+
+    let mut single: FlatMap<u32, char> = FlatMap::from([(1, 'x')]);
+    match single.entry(1) {
+        Occupied(e) => {
+            assert_eq!(e.index(), 0);
+            assert_eq!(e.peek_prev(), None);
+            assert_eq!(e.peek_next(), None);
+        }
+        Vacant(_) => panic!(),
+    }
+    match single.entry(2) {
+        Vacant(e) => {
+            assert_eq!(e.index(), 1);
+            assert_eq!(e.peek_prev(), Some((&1, &'x')));
+            assert_eq!(e.peek_next(), None);
+        }
+        Occupied(_) => panic!(),
+    }
+
+    let mut empty: FlatMap<u32, char> = FlatMap::new();
+    match empty.entry(1) {
+        Vacant(e) => {
+            assert_eq!(e.index(), 0);
+            assert_eq!(e.peek_prev(), None);
+            assert_eq!(e.peek_next(), None);
+        }
+        Occupied(_) => panic!(),
+    }
+}
+
+#[test]
+fn test_get_or_insert_with() {
+    use std::rc::Rc;
+
+    let stored: Rc<str> = Rc::from("alpha");
+    let mut m: FlatMap<Rc<str>, u32> = FlatMap::new();
+    m.insert(stored.clone(), 1);
+
+    let mut calls = 0;
+    {
+        let (key, value) = m.get_or_insert_with(Rc::from("alpha"), |_| {
+            calls += 1;
+            0
+        });
+        assert!(Rc::ptr_eq(key, &stored));
+        *value += 10;
+    }
+    assert_eq!(calls, 0);
+    assert_eq!(m[&*stored], 11);
+
+    {
+        let (key, value) = m.get_or_insert_with(Rc::from("beta"), |k| {
+            calls += 1;
+            k.len() as u32
+        });
+        assert_eq!(&**key, "beta");
+        assert_eq!(*value, 4);
+    }
+    assert_eq!(calls, 1);
+    m.get_or_insert_with(Rc::from("beta"), |_| {
+        calls += 1;
+        0
+    });
+    assert_eq!(calls, 1);
+    assert_eq!(m.len(), 2);
+
+    let (key, value) = m.entry(Rc::from("gamma")).get_or_insert_with(|_| 3);
+    assert_eq!((&**key, *value), ("gamma", 3));
+    assert_eq!(m.keys().map(|k| &**k).collect::<Vec<_>>(), ["alpha", "beta", "gamma"]);
+}
+
+#[test]
+fn test_small_flat_map() {
+    use flat_map::small::Entry;
+    use std::rc::Rc;
+
+    let mut m: SmallFlatMap<u32, &str, 3> = SmallFlatMap::new();
+    assert_eq!(m.capacity(), 3);
+    assert_eq!(m.insert(20, "b").unwrap(), None);
+    assert_eq!(m.insert(10, "a").unwrap(), None);
+    assert_eq!(m.insert(30, "c").unwrap(), None);
+    assert!(m.is_full());
+    assert_eq!(m.insert(20, "B").unwrap(), Some("b"));
+    assert_eq!(m.insert(40, "d").unwrap_err().into_inner(), (40, "d"));
+    assert_eq!(m.as_slice(), [(10, "a"), (20, "B"), (30, "c")]);
+    assert_eq!(m.get(&30), Some(&"c"));
+    assert_eq!(m.get(&40), None);
+    assert_eq!(format!("{:?}", m), r#"{10: "a", 20: "B", 30: "c"}"#);
+
+    match m.entry(40) {
+        Entry::Vacant(e) => {
+            assert_eq!(e.index(), 3);
+            assert_eq!(e.insert("d").unwrap_err().into_inner(), (40, "d"));
+        }
+        Entry::Occupied(_) => panic!(),
+    }
+    *m.entry(10).or_insert("x").unwrap() = "A";
+    assert_eq!(m.remove(&20), Some("B"));
+    assert_eq!(m.remove(&20), None);
+    assert_eq!(*m.entry(15).or_insert("y").unwrap(), "y");
+    for (_, v) in &mut m {
+        *v = "z";
+    }
+    assert_eq!(m.iter().collect::<Vec<_>>(), [(&10, &"z"), (&15, &"z"), (&30, &"z")]);
+
+    let other: SmallFlatMap<u32, &str, 8, BinarySearch> =
+        SmallFlatMap::try_from_iter(vec![(30, "z"), (15, "z"), (10, "z"), (15, "w")]).unwrap();
+    assert!(m == other);
+    assert!(m.clone() == m);
+    let err = SmallFlatMap::<u32, u32, 2>::try_from_iter(vec![(1, 1), (1, 2), (2, 2), (3, 3)]);
+    assert_eq!(err.unwrap_err().into_inner(), (3, 3));
+
+    let unsorted: SmallFlatMap<&str, u32, 4, UnsortedLinear> =
+        SmallFlatMap::try_from_iter(vec![("b", 1), ("a", 2)]).unwrap();
+    assert_eq!(unsorted.as_slice(), [("b", 1), ("a", 2)]);
+
+    // every stored value is dropped exactly once
+    let token = Rc::new(());
+    {
+        let mut m: SmallFlatMap<u32, Rc<()>, 4> = SmallFlatMap::new();
+        for i in 0..4 {
+            m.insert(i, token.clone()).unwrap();
+        }
+        m.insert(2, token.clone()).unwrap();
+        m.remove(&1);
+        assert_eq!(Rc::strong_count(&token), 4);
+        let copy = m.clone();
+        assert_eq!(Rc::strong_count(&token), 7);
+        drop(copy);
+        m.clear();
+        assert!(m.is_empty());
+        m.insert(9, token.clone()).unwrap();
+    }
+    assert_eq!(Rc::strong_count(&token), 1);
+}
+
+#[test]
+fn test_insert_many() {
+    use std::collections::BTreeMap;
+
+    // not commutative, so the order of the calls shows
+    let f = |_: &u32, old: u32, new: u32| old.wrapping_mul(31).wrapping_add(new);
+    let data: Vec<(u32, u32)> = rand_data(300).into_iter().map(|(k, v)| (k % 64, v)).collect();
+    let (initial, batch) = data.split_at(40);
+
+    let mut map: FlatMap<u32, u32> = initial.iter().cloned().collect();
+    let mut model: BTreeMap<u32, u32> = map.iter().map(|(&k, &v)| (k, v)).collect();
+    map.insert_many(batch.iter().cloned(), f);
+    for &(k, v) in batch {
+        let value = match model.remove(&k) {
+            Some(old) => f(&k, old, v),
+            None => v,
+        };
+        model.insert(k, value);
+    }
+    assert_eq!(map.iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>(),
+               model.into_iter().collect::<Vec<_>>());
+
+    let mut empty: FlatMap<u32, u32, LinearBack> = FlatMap::default();
+    empty.insert_many(None, f);
+    assert!(empty.is_empty());
+    empty.insert_many(vec![(2, 1), (1, 1), (2, 2)], f);
+    assert_eq!(empty.into_inner(), [(1, 1), (2, 33)]);
+
+    let mut totals: FlatMap<&str, u32> = vec![("a", 1), ("c", 3)].into_iter().collect();
+    let other: FlatMap<&str, u32> = vec![("b", 2), ("c", 4)].into_iter().collect();
+    totals.merge_sorted(other, |_, old, new| old + new);
+    assert_eq!(totals.into_inner(), [("a", 1), ("b", 2), ("c", 7)]);
+}
+
+#[test]
+fn test_builder() {
+    let data = rand_data(200);
+    let mut builder = FlatMapBuilder::with_capacity(data.len());
+    for &(k, v) in &data {
+        builder.push(k % 50, v);
+    }
+    assert_eq!(builder.len(), 200);
+    let first = builder.clone().build();
+    let last = builder.keep_last(true).build();
+    assert_eq!(first, data.iter().map(|&(k, v)| (k % 50, v)).collect::<FlatMap<_, _>>());
+    let mut inserted = FlatMap::new();
+    for &(k, v) in &data {
+        inserted.insert(k % 50, v);
+    }
+    assert_eq!(last, inserted);
+
+    let mut log = FlatMapBuilder::with_lookup(UnsortedLinear);
+    log.extend(vec![(3, 'a'), (1, 'b'), (3, 'c')]);
+    assert_eq!(log.build().into_inner(), [(3, 'a'), (1, 'b')]);
+    let empty: FlatMapBuilder<u32, u32> = FlatMapBuilder::default();
+    assert!(empty.is_empty() && empty.build().is_empty());
+}
+
+#[test]
+fn test_staged_flat_map() {
+    use std::collections::BTreeMap;
+
+    let mut map = StagedFlatMap::with_staging_limit(8);
+    let mut model = BTreeMap::new();
+    for (i, (k, v)) in rand_data(2000).into_iter().enumerate() {
+        let k = k % 300;
+        if i % 3 == 0 {
+            assert_eq!(map.remove(&k), model.remove(&k));
+        } else {
+            assert_eq!(map.insert(k, v), model.insert(k, v));
+        }
+        assert!(map.staged_len() < 8);
+        assert_eq!(map.len(), model.len());
+        if i % 7 == 0 {
+            assert_eq!(map.get(&(k + 1)), model.get(&(k + 1)));
+            assert_eq!(map.contains_key(&k), model.contains_key(&k));
+        }
+    }
+    if let Some((&k, _)) = model.iter().next() {
+        *map.get_mut(&k).unwrap() += 1;
+        *model.get_mut(&k).unwrap() += 1;
+    }
+    assert_eq!(map.flat_map().iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>(),
+               model.into_iter().collect::<Vec<_>>());
+    assert_eq!(map.staged_len(), 0);
+
+    let base: FlatMap<u32, char> = vec![(5, 'e')].into_iter().collect();
+    let mut map = StagedFlatMap::from(base);
+    map.extend(vec![(2, 'b'), (5, 'E'), (9, 'i')]);
+    assert_eq!(map.staged_len(), 2);
+    assert_eq!(map.clone().into_flat_map().into_inner(), [(2, 'b'), (5, 'E'), (9, 'i')]);
+    map.clear();
+    assert!(map.is_empty());
+}
+
+#[test]
+fn test_from_sorted_iter() {
+    let source: std::collections::BTreeMap<_, _> = rand_data(500).into_iter().collect();
+    let map: FlatMap<u32, u32, LinearFront> = FlatMap::from_sorted_iter(source.clone());
+    assert_eq!(map.check_invariants(), Ok(()));
+    assert!(map.iter().eq(source.iter()));
+    assert!(FlatMap::<u32, u32>::from_sorted_iter(None).is_empty());
+}
+
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic(expected = "from_sorted_iter input is not strictly sorted by key")]
+fn test_from_sorted_iter_unsorted() {
+    FlatMap::<u32, u32>::from_sorted_iter(vec![(1, 1), (3, 3), (3, 4)]);
+}
+
+#[test]
+fn test_push_max() {
+    let mut map: FlatMap<u32, char, LinearBack> = FlatMap::default();
+    for (k, v) in [(1, 'a'), (4, 'b'), (9, 'c')] {
+        map.push_max(k, v).unwrap();
+    }
+    let err = map.push_max(9, 'x').unwrap_err();
+    assert_eq!(err.index(), 3);
+    assert_eq!(err.into_input(), (9, 'x'));
+    assert!(map.push_max(2, 'y').is_err());
+    assert_eq!(map.into_inner(), [(1, 'a'), (4, 'b'), (9, 'c')]);
+}
+
+#[test]
+fn test_from_iter_duplicate_policies() {
+    let data = vec![(2, 'a'), (1, 'b'), (2, 'c'), (1, 'd'), (3, 'e')];
+    let first: FlatMap<u32, char> = FlatMap::from_iter_keep_first(data.clone());
+    assert_eq!(first.into_inner(), [(1, 'b'), (2, 'a'), (3, 'e')]);
+    let last: FlatMap<u32, char, UnsortedLinear> = FlatMap::from_iter_keep_last(data.clone());
+    assert_eq!(last.into_inner(), [(2, 'c'), (1, 'd'), (3, 'e')]);
+
+    let joined: FlatMap<u32, String, LinearFront> =
+        FlatMap::from_iter_merge(data.into_iter().map(|(k, c)| (k, c.to_string())),
+                                 |_, a, b| a + &b);
+    assert_eq!(joined.into_inner(),
+               [(1, "bd".to_string()), (2, "ac".to_string()), (3, "e".to_string())]);
+}
+
+#[test]
+fn test_try_from_iter() {
+    let map: FlatMap<u32, char> = FlatMap::try_from_iter(vec![(3, 'c'), (1, 'a'), (2, 'b')]).unwrap();
+    assert_eq!(map.into_inner(), [(1, 'a'), (2, 'b'), (3, 'c')]);
+    let log: FlatMap<u32, char, UnsortedLinear> =
+        FlatMap::try_from_iter(vec![(3, 'c'), (1, 'a')]).unwrap();
+    assert_eq!(log.into_inner(), [(3, 'c'), (1, 'a')]);
+
+    // 5 repeats first, although 2 is the smaller repeated key
+    let err = FlatMap::<u32, u32>::try_from_iter(vec![(5, 0), (2, 0), (5, 1), (2, 1)])
+        .unwrap_err();
+    assert_eq!(err.into_key(), 5);
+    assert!(FlatMap::<u32, u32>::try_from_iter(None).unwrap().is_empty());
+
+    // keys that differ only in case are the same key without case
+    type Names = FlatMap<&'static str, u32, flat_map::OrderBy<flat_map::CaseInsensitive>>;
+    let err = Names::try_from_iter(vec![("a", 1), ("b", 2), ("A", 3)]).unwrap_err();
+    assert_eq!(err.into_key(), "A");
+    let names = Names::try_from_iter(vec![("b", 2), ("A", 1)]).unwrap();
+    assert_eq!(names.check_invariants(), Ok(()));
+    let err = FlatMap::<u32, u32, UnsortedLinear>::try_from_iter(vec![(3, 0), (1, 0), (3, 1)])
+        .unwrap_err();
+    assert_eq!(err.into_key(), 3);
+}
+
+#[test]
+fn test_group_from_iter() {
+    let data: Vec<(u32, u32)> = rand_data(500).into_iter().map(|(k, v)| (k % 40, v)).collect();
+    let groups: FlatMap<u32, Vec<u32>, LinearBack> = FlatMap::group_from_iter(data.clone());
+    assert_eq!(groups.check_invariants(), Ok(()));
+    assert_eq!(groups.values().map(Vec::len).sum::<usize>(), data.len());
+    for (k, values) in &groups {
+        let expected: Vec<u32> = data.iter().filter(|e| e.0 == *k).map(|e| e.1).collect();
+        assert_eq!(*values, expected);
+    }
+    assert!(FlatMap::<u32, Vec<u32>>::group_from_iter(None).is_empty());
+}
+
+#[test]
+fn test_counts() {
+    let keys: Vec<u32> = rand_data(1000).into_iter().map(|(k, _)| k % 30).collect();
+    let counts: FlatMap<u32, usize> = FlatMap::counts(keys.iter().cloned());
+    assert_eq!(counts.check_invariants(), Ok(()));
+    for (k, &n) in &counts {
+        assert_eq!(n, keys.iter().filter(|&x| x == k).count());
+    }
+    assert_eq!(counts.values().sum::<usize>(), keys.len());
+    assert!(FlatMap::<u32, usize>::counts(None).is_empty());
+}
+
+#[test]
+fn test_map_set_operations() {
+    let a: FlatMap<u32, char> = (0..20).step_by(2).map(|k| (k, 'a')).collect();
+    let b: FlatMap<u32, char> = (0..20).step_by(3).map(|k| (k, 'b')).collect();
+    let ab = |k: &u32| if a.contains_key(k) { 'a' } else { 'b' };
+
+    let union: Vec<_> = a.union(&b).map(|(&k, &v)| (k, v)).collect();
+    let expected: Vec<_> = (0..20)
+        .filter(|k| a.contains_key(k) || b.contains_key(k))
+        .map(|k| (k, ab(&k)))
+        .collect();
+    assert_eq!(union, expected);
+
+    let intersection: Vec<_> = a.intersection(&b).map(|(&k, &v)| (k, v)).collect();
+    assert_eq!(intersection, [(0, 'a'), (6, 'a'), (12, 'a'), (18, 'a')]);
+
+    let difference: Vec<_> = b.difference(&a).map(|(&k, _)| k).collect();
+    assert_eq!(difference, [3, 9, 15]);
+
+    let symmetric: Vec<_> = a.symmetric_difference(&b).map(|(&k, &v)| (k, v)).collect();
+    let expected: Vec<_> = (0..20)
+        .filter(|k| a.contains_key(k) != b.contains_key(k))
+        .map(|k| (k, ab(&k)))
+        .collect();
+    assert_eq!(symmetric, expected);
+
+    let empty = FlatMap::new();
+    assert_eq!(a.union(&empty).count(), a.len());
+    assert_eq!(a.intersection(&empty).count(), 0);
+    assert_eq!(empty.difference(&a).count(), 0);
+    assert_eq!(empty.symmetric_difference(&b).count(), b.len());
+}
+
+#[test]
+fn test_joins() {
+    let a: FlatMap<u32, u32> = rand_data(200).into_iter().map(|(k, v)| (k % 300, v)).collect();
+    let b: FlatMap<u32, String> =
+        rand_data(200).into_iter().map(|(k, _)| (k % 300, k.to_string())).collect();
+
+    let inner: Vec<_> = a.join_inner(&b).collect();
+    let expected: Vec<_> = a.iter().filter_map(|(k, v)| b.get(k).map(|v2| (k, v, v2))).collect();
+    assert_eq!(inner, expected);
+
+    let left: Vec<_> = a.join_left(&b).collect();
+    let expected: Vec<_> = a.iter().map(|(k, v)| (k, v, b.get(k))).collect();
+    assert_eq!(left, expected);
+
+    let outer: Vec<_> = a.join_outer(&b).collect();
+    let keys: FlatMap<u32, ()> = a.keys().chain(b.keys()).map(|&k| (k, ())).collect();
+    let expected: Vec<_> = keys.keys().map(|k| (k, a.get(k), b.get(k))).collect();
+    assert_eq!(outer, expected);
+
+    let empty: FlatMap<u32, ()> = FlatMap::new();
+    assert_eq!(a.join_inner(&empty).count(), 0);
+    assert_eq!(a.join_left(&empty).count(), a.len());
+    assert_eq!(empty.join_outer(&b).count(), b.len());
+}
+
+#[test]
+fn test_diff() {
+    use flat_map::flat_map::DiffItem;
+
+    let old: FlatMap<u32, u32> =
+        rand_data(300).into_iter().map(|(k, v)| (k % 200, v % 3)).collect();
+    let new: FlatMap<u32, u32> =
+        rand_data(300).into_iter().map(|(k, v)| (k % 200, v % 3)).collect();
+    let mut patched = old.clone();
+    let mut last = None;
+    for item in old.diff(&new) {
+        let key = match item {
+            DiffItem::Added(k, v) => {
+                assert_eq!(patched.insert(*k, *v), None);
+                k
+            }
+            DiffItem::Removed(k, v) => {
+                assert_eq!(patched.remove(k), Some(*v));
+                k
+            }
+            DiffItem::Changed(k, old, new) => {
+                assert_ne!(old, new);
+                assert_eq!(patched.insert(*k, *new), Some(*old));
+                k
+            }
+        };
+        assert!(last < Some(key));
+        last = Some(key);
+    }
+    assert_eq!(patched, new);
+    assert_eq!(new.diff(&new).count(), 0);
+}
+
+#[test]
+fn test_apply() {
+    use flat_map::flat_map::DiffItem;
+
+    let old: FlatMap<u32, u32> =
+        rand_data(300).into_iter().map(|(k, v)| (k % 200, v % 3)).collect();
+    let new: FlatMap<u32, u32> =
+        rand_data(300).into_iter().map(|(k, v)| (k % 200, v % 3)).collect();
+    let mut replica = old.clone();
+    replica.apply(old.diff(&new));
+    assert_eq!(replica, new);
+
+    // unsorted, with repeated keys and changes that do not match the map
+    let mut map: FlatMap<u32, char> = vec![(1, 'a'), (3, 'c'), (5, 'e')].into_iter().collect();
+    let (k1, k2, k3, k4, k6) = (1, 2, 3, 4, 6);
+    let (x, y, z) = ('x', 'y', 'z');
+    map.apply(vec![DiffItem::Added(&k6, &x),
+                   DiffItem::Removed(&k3, &x),
+                   DiffItem::Changed(&k1, &x, &y),
+                   DiffItem::Removed(&k4, &x),
+                   DiffItem::Added(&k2, &x),
+                   DiffItem::Removed(&k2, &x),
+                   DiffItem::Changed(&k1, &x, &z)]);
+    assert_eq!(map.into_inner(), [(1, 'z'), (5, 'e'), (6, 'x')]);
+}
+
+#[test]
+fn test_remove_keys() {
+    let data = rand_data(500);
+    let keys: Vec<u32> = data.iter().step_by(3).map(|&(k, _)| k).chain(Some(u32::MAX)).collect();
+    let full: FlatMap<u32, u32> = data.into_iter().collect();
+
+    let mut removed = full.clone();
+    let mut expected = full.clone();
+    let mut count = 0;
+    for k in &keys {
+        count += expected.remove(k).is_some() as usize;
+    }
+    assert_eq!(removed.remove_keys(keys.clone()), count);
+    assert_eq!(removed, expected);
+
+    let mut retained = full.clone();
+    assert_eq!(retained.retain_keys(keys.iter().rev().cloned()), full.len() - count);
+    assert_eq!(retained.len(), count);
+    assert!(retained.keys().all(|k| keys.contains(k)));
+    assert_eq!(retained.remove_keys(None), 0);
+}
+
+#[test]
+fn test_remove_range() {
+    let full: FlatMap<u32, u32> = rand_data(500).into_iter().collect();
+    let range = u32::MAX / 4..u32::MAX / 2;
+    let mut map = full.clone();
+    let count = full.range(range.clone()).count();
+    assert!(count > 0);
+    assert_eq!(map.remove_range(range.clone()), count);
+    assert_eq!(map.len(), full.len() - count);
+    assert!(map.keys().all(|k| !range.contains(k)));
+    assert_eq!(map.remove_range(range), 0);
+    assert_eq!(map.remove_range(..), full.len() - count);
+    assert!(map.is_empty());
+}
+
+#[test]
+fn test_drain_range() {
+    let full: FlatMap<u32, u32> = rand_data(500).into_iter().collect();
+    let range = u32::MAX / 4..u32::MAX / 2;
+    let mut map = full.clone();
+    let drained: Vec<_> = map.drain_range(range.clone()).collect();
+    let expected: Vec<_> = full.range(range.clone()).map(|(&k, &v)| (k, v)).collect();
+    assert!(!expected.is_empty());
+    assert_eq!(drained, expected);
+    assert_eq!(map.len(), full.len() - drained.len());
+
+    // dropping the iterator early still removes the whole range
+    let mut map = full.clone();
+    assert_eq!(map.drain_range(range.clone()).next_back(), expected.last().cloned());
+    assert_eq!(map.range(range).count(), 0);
+    assert_eq!(map.len(), full.len() - expected.len());
+}
+
+#[test]
+fn test_truncate_before_after() {
+    let full: FlatMap<u32, u32> = rand_data(500).into_iter().collect();
+    let lo = *full.keys().nth(100).unwrap();
+    let hi = *full.keys().nth(400).unwrap();
+
+    let mut map = full.clone();
+    assert_eq!(map.truncate_before(&lo), 100);
+    assert_eq!(map.truncate_after(&hi), full.len() - 401);
+    assert_eq!(map.first_key_value(), Some((&lo, &full[&lo])));
+    assert_eq!(map.last_key_value(), Some((&hi, &full[&hi])));
+    assert_eq!(map.len(), 301);
+
+    // keys that are not present split the map at the same place
+    assert_eq!(map.truncate_before(&(lo + 1)), 1);
+    assert_eq!(map.truncate_after(&(hi - 1)), 1);
+    assert_eq!(map.truncate_after(&0), 299);
+    assert!(map.is_empty());
+}
+
+#[test]
+fn test_lower_upper_bound() {
+    let map: FlatMap<u32, u32> = rand_data(300).into_iter().map(|(k, v)| (k % 1000, v)).collect();
+    let linear: FlatMap<u32, u32, LinearFront> = map.iter().map(|(&k, &v)| (k, v)).collect();
+    for q in 0..1001 {
+        let lower = map.keys().filter(|&&k| k < q).count();
+        let upper = map.keys().filter(|&&k| k <= q).count();
+        assert_eq!(map.lower_bound(&q), lower);
+        assert_eq!(map.upper_bound(&q), upper);
+        assert_eq!(map.partition_point(|&k, _| k < q), lower);
+        assert_eq!(linear.lower_bound(&q), lower);
+        assert_eq!(linear.upper_bound(&q), upper);
+    }
+}
+
+#[test]
+fn test_cursors() {
+    let map: FlatMap<u32, char> = vec![(10, 'a'), (20, 'b'), (30, 'c')].into_iter().collect();
+    let mut cursor = map.upper_bound_cursor(&20);
+    assert_eq!(cursor.index(), Some(2));
+    assert_eq!(cursor.peek_prev(), Some((&20, &'b')));
+    cursor.move_next();
+    assert_eq!(cursor.index(), None);
+    assert_eq!(cursor.peek_next(), Some((&10, &'a')));
+    cursor.move_next();
+    assert_eq!(cursor.key(), Some(&10));
+    let forward: Vec<_> = std::iter::from_fn(|| {
+        let kv = cursor.key_value();
+        cursor.move_next();
+        kv
+    }).collect();
+    assert_eq!(forward, map.iter().collect::<Vec<_>>());
+    assert_eq!(map.lower_bound_cursor(&31).index(), None);
+
+    // merge a sorted batch into the map in place
+    let mut map = map;
+    let mut cursor = map.lower_bound_cursor_mut(&0);
+    for (k, v) in [(5, 'x'), (20, 'y'), (25, 'z'), (40, 'w')] {
+        while cursor.key().is_some_and(|&c| c < k) {
+            cursor.move_next();
+        }
+        if cursor.key() == Some(&k) {
+            *cursor.value_mut().unwrap() = v;
+        } else {
+            cursor.insert_before(k, v).unwrap();
+        }
+    }
+    assert_eq!(cursor.index(), None);
+    assert_eq!(cursor.insert_before(35, '!').unwrap_err().index(), 6);
+    assert!(cursor.insert_after(5, '!').is_err());
+    cursor.insert_after(1, 'q').unwrap();
+    assert_eq!(cursor.index(), None);
+    cursor.move_next();
+    assert_eq!(cursor.remove_current(), Some((1, 'q')));
+    assert_eq!(cursor.key(), Some(&5));
+    assert_eq!(map.into_inner(),
+               [(5, 'x'), (10, 'a'), (20, 'y'), (25, 'z'), (30, 'c'), (40, 'w')]);
+}
+
+#[test]
+fn test_floor_ceiling() {
+    let map: FlatMap<u32, u32> = rand_data(300).into_iter().map(|(k, v)| (k % 1000, v)).collect();
+    for q in 0..1001 {
+        assert_eq!(map.get_floor(&q), map.range(..=q).next_back());
+        assert_eq!(map.get_lower(&q), map.range(..q).next_back());
+        assert_eq!(map.get_ceiling(&q), map.range(q..).next());
+        assert_eq!(map.get_higher(&q), map.range(q + 1..).next());
+    }
+    let empty: FlatMap<u32, u32> = FlatMap::new();
+    assert_eq!(empty.get_floor(&0), None);
+    assert_eq!(empty.get_ceiling(&0), None);
+}
+
+#[test]
+fn test_nearest() {
+    use flat_map::TiePolicy;
+
+    let map: FlatMap<u32, u32> = rand_data(100).into_iter().map(|(k, v)| (k % 1000, v)).collect();
+    for q in 0..1100 {
+        let distance = |k: &u32| if *k < q { q - k } else { k - q };
+        let best = map.keys().map(distance).min();
+        let lower = map.nearest(&q).map(|(k, _)| *k);
+        let higher = map.nearest_with(&q, TiePolicy::PreferHigher).map(|(k, _)| *k);
+        assert_eq!(lower.as_ref().map(distance), best);
+        assert_eq!(higher.as_ref().map(distance), best);
+        assert!(lower <= higher);
+        if lower != higher {
+            assert!(lower < Some(q) && higher > Some(q));
+        }
+    }
+    let empty: FlatMap<u32, ()> = FlatMap::new();
+    assert_eq!(empty.nearest(&5), None);
+}
+
+#[test]
+fn test_range_prefix() {
+    let map: FlatMap<String, usize> = rand_data(500)
+        .into_iter()
+        .map(|(k, _)| (k % 5000).to_string())
+        .map(|k| (k.clone(), k.len()))
+        .collect();
+    for prefix in ["", "1", "12", "123", "4999", "5", "50", "9999", "x"] {
+        let found: Vec<_> = map.range_prefix(prefix).map(|(k, _)| k.as_str()).collect();
+        let expected: Vec<_> =
+            map.keys().map(|k| k.as_str()).filter(|k| k.starts_with(prefix)).collect();
+        assert_eq!(found, expected);
+    }
+
+    let bytes: FlatMap<Vec<u8>, ()> = map.keys().map(|k| (k.clone().into_bytes(), ())).collect();
+    assert_eq!(bytes.range_prefix_bytes(b"12").count(), map.range_prefix("12").count());
+    assert_eq!(bytes.range_prefix_bytes(b"").count(), bytes.len());
+}
+
+#[test]
+fn test_get_longest_prefix() {
+    let map: FlatMap<String, ()> = rand_data(300)
+        .into_iter()
+        .map(|(k, _)| ((k % 3000).to_string(), ()))
+        .chain(vec![("é".to_string(), ()), ("éa".to_string(), ())])
+        .collect();
+    let longest = |q: &str| {
+        map.keys().filter(|k| q.starts_with(k.as_str())).max_by_key(|k| k.len()).map(|k| k.as_str())
+    };
+    for n in (0..40000).step_by(7) {
+        let q = n.to_string();
+        assert_eq!(map.get_longest_prefix(&q).map(|(k, _)| k.as_str()), longest(&q));
+    }
+    for q in ["", "é", "éb", "éa!", "ée", "ê"] {
+        assert_eq!(map.get_longest_prefix(q).map(|(k, _)| k.as_str()), longest(q));
+    }
+
+    let bytes: FlatMap<Vec<u8>, ()> = map.keys().map(|k| (k.clone().into_bytes(), ())).collect();
+    for n in 0..4000 {
+        let q = n.to_string();
+        assert_eq!(bytes.get_longest_prefix_bytes(q.as_bytes()).map(|(k, _)| k.as_slice()),
+                   longest(&q).map(str::as_bytes));
+    }
+}
+
+#[test]
+fn test_order_by() {
+    use flat_map::{Compare, OrderBy};
+    use std::cmp::Ordering;
+
+    #[derive(Clone, Copy, Default)]
+    struct CaseInsensitive;
+
+    impl Compare<String> for CaseInsensitive {
+        fn compare(&self, a: &String, b: &String) -> Ordering {
+            a.to_lowercase().cmp(&b.to_lowercase())
+        }
+    }
+
+    let words = ["Pear", "apple", "PEAR", "banana", "Apple", "cherry"];
+    let map: FlatMap<String, usize, OrderBy<CaseInsensitive>> =
+        words.iter().enumerate().map(|(i, w)| (w.to_string(), i)).collect();
+    let keys: Vec<_> = map.keys().map(|k| k.as_str()).collect();
+    assert_eq!(keys, ["apple", "banana", "cherry", "Pear"]);
+    assert_eq!(map.get(&"APPLE".to_string()), Some(&1));
+
+    let mut map = map;
+    assert_eq!(map.insert("BANANA".to_string(), 10), Some(3));
+    *map.entry("Date".to_string()).or_insert(0) += 1;
+    *map.entry("date".to_string()).or_insert(0) += 1;
+    assert_eq!(map.get(&"DATE".to_string()), Some(&2));
+    let high = map.split_off(&"C".to_string());
+    let keys: Vec<_> = high.keys().map(|k| k.as_str()).collect();
+    assert_eq!(keys, ["cherry", "Date", "Pear"]);
+    assert_eq!(map.len(), 2);
+
+    let mut floats = FlatMap::with_lookup(OrderBy(|a: &f64, b: &f64| b.total_cmp(a)));
+    floats.extend(vec![(0.5, 'a'), (-2.0, 'b'), (f64::INFINITY, 'c'), (0.5, 'd')]);
+    assert_eq!(floats.into_inner(), [(f64::INFINITY, 'c'), (0.5, 'd'), (-2.0, 'b')]);
+}
+
+#[test]
+fn test_descending() {
+    use flat_map::{Descending, OrderBy};
+
+    let data = rand_data(300);
+    let asc: FlatMap<u32, u32> = data.iter().cloned().collect();
+    let desc: FlatMap<u32, u32, OrderBy<Descending>> = data.iter().cloned().collect();
+    assert!(desc.iter().eq(asc.iter().rev()));
+    assert_eq!(desc.first_key_value(), asc.last_key_value());
+
+    let mut map = FlatMap::new_desc();
+    for (k, v) in data.iter().cloned() {
+        map.insert(k, v);
+    }
+    assert_eq!(map, desc);
+    let mid = *asc.keys().nth(150).unwrap();
+    assert_eq!(map.get_floor(&mid), Some((&mid, &asc[&mid])));
+    let low = map.split_off(&mid);
+    assert_eq!(map.len(), 149);
+    assert_eq!(low.first_key_value().map(|(k, _)| *k), Some(mid));
+    assert!(low.keys().eq(asc.range(..=mid).rev().map(|(k, _)| k)));
+}
+
+#[test]
+fn test_case_insensitive() {
+    use flat_map::{AsciiCaseInsensitive, CaseInsensitive, OrderBy};
+
+    let mut headers: FlatMap<String, u32, OrderBy<AsciiCaseInsensitive>> = FlatMap::default();
+    for (i, name) in ["Host", "Accept", "X-Trace", "accept", "HOST"].iter().enumerate() {
+        *headers.entry(name.to_string()).or_insert(0) += i as u32;
+    }
+    let keys: Vec<_> = headers.keys().map(|k| k.as_str()).collect();
+    assert_eq!(keys, ["Accept", "Host", "X-Trace"]);
+    assert_eq!(headers.get("ACCEPT"), Some(&4));
+    assert_eq!(headers.get("host"), Some(&4));
+    assert!(headers.contains_key("x-trace"));
+    assert_eq!(headers.remove("x-TRACE"), Some(2));
+    // only ASCII letters are folded
+    headers.insert("Ärger".to_string(), 9);
+    assert_eq!(headers.get("äRGER"), None);
+
+    let names: FlatMap<&str, (), OrderBy<CaseInsensitive>> =
+        vec!["Ärger", "ärger", "Zoo", "apple"].into_iter().map(|n| (n, ())).collect();
+    let keys: Vec<_> = names.keys().cloned().collect();
+    assert_eq!(keys, ["apple", "Zoo", "Ärger"]);
+    assert!(names.contains_key(&"ÄRGER"));
+}
+
+#[test]
+fn test_eq_across_orders() {
+    use flat_map::{CaseInsensitive, Descending, OrderBy};
+
+    let asc: FlatMap<i32, char> = vec![(1, 'a'), (2, 'b')].into_iter().collect();
+    let desc: FlatMap<i32, char, OrderBy<Descending>> =
+        asc.iter().map(|(&k, &v)| (k, v)).collect();
+    assert_eq!(asc, desc);
+    assert_eq!(desc, asc);
+    assert!(asc.keys_eq(&desc));
+    let other: FlatMap<i32, char, OrderBy<Descending>> =
+        vec![(1, 'a'), (2, 'c')].into_iter().collect();
+    assert_ne!(desc, other);
+    assert_ne!(asc, other);
+
+    let names: FlatMap<&str, (), OrderBy<CaseInsensitive>> =
+        vec![("a", ()), ("B", ())].into_iter().collect();
+    let by_ord: FlatMap<&str, ()> = vec![("a", ()), ("B", ())].into_iter().collect();
+    assert_eq!(names, by_ord);
+    let sets = (FlatSet::<&str>::from_iter(vec!["a", "B"]),
+                FlatSet::<&str, OrderBy<CaseInsensitive>>::from_iter(vec!["a", "B"]));
+    assert!(sets.0 == sets.1);
+
+    // a comparator-sorted side is searched rather than scanned pairwise
+    let big: FlatMap<u32, u32, OrderBy<Descending>> = (0..100_000).map(|k| (k, k)).collect();
+    let mut changed = big.clone();
+    changed.insert(50_000, 0);
+    assert_ne!(big, changed);
+    assert!(big.keys_eq(&changed));
+    assert_eq!(big, big.clone());
+
+    let mut unsorted: FlatMap<&str, (), UnsortedLinear> = FlatMap::default();
+    unsorted.extend(vec![("B", ()), ("a", ())]);
+    assert_eq!(names, unsorted);
+    assert_eq!(unsorted, names);
+    unsorted.remove("B");
+    unsorted.insert("b", ());
+    assert_ne!(names, unsorted);
+    assert_ne!(unsorted, names);
+}
+
+#[test]
+fn test_comparable_lookup() {
+    use flat_map::{Comparable, Equivalent};
+    use std::cmp::Ordering;
+
+    // matches every key with the given prefix, for a first-or-last search
+    struct Prefix(u32);
+
+    impl Equivalent<(u32, u32)> for Prefix {
+        fn equivalent(&self, key: &(u32, u32)) -> bool {
+            self.0 == key.0
+        }
+    }
+
+    impl Comparable<(u32, u32)> for Prefix {
+        fn compare(&self, key: &(u32, u32)) -> Ordering {
+            self.0.cmp(&key.0)
+        }
+    }
+
+    fn check<L: Lookup<(u32, u32), char, Prefix>>(map: &FlatMap<(u32, u32), char, L>) {
+        assert_eq!(map.binary_search_key(&Prefix(3)), Err(3));
+        assert_eq!(map.get(&Prefix(1)), Some(&'a'));
+        assert_eq!(map.get(&Prefix(4)), Some(&'d'));
+        assert!(map.get(&Prefix(2)).is_some());
+        assert!(!map.contains_key(&Prefix(0)));
+    }
+
+    let entries = [((1, 0), 'a'), ((2, 0), 'b'), ((2, 1), 'c'), ((4, 0), 'd')];
+    let binary: FlatMap<_, _> = entries.iter().cloned().collect();
+    let front: FlatMap<_, _, LinearFront> = entries.iter().cloned().collect();
+    let back: FlatMap<_, _, LinearBack> = entries.iter().cloned().collect();
+    check(&binary);
+    check(&front);
+    check(&back);
+    assert_eq!(front.get_key_value(&Prefix(2)), Some((&(2, 0), &'b')));
+    assert_eq!(back.get_key_value(&Prefix(2)), Some((&(2, 1), &'c')));
+
+    let mut map = binary.clone();
+    assert_eq!(map.remove_entry(&Prefix(1)), Some(((1, 0), 'a')));
+    assert_eq!(map.get(&(2, 1)), Some(&'c'));
+
+    // indexing takes the same queries as get
+    assert_eq!(map[&Prefix(4)], 'd');
+    map[&Prefix(4)] = 'e';
+    assert_eq!(map[&(4, 0)], 'e');
+
+    // unsorted maps match queries through Equivalent alone
+    let mut unsorted: FlatMap<_, _, UnsortedLinear> = entries.iter().cloned().collect();
+    assert_eq!(unsorted.get_key_value(&Prefix(2)), Some((&(2, 0), &'b')));
+    assert!(!unsorted.contains_key(&Prefix(3)));
+    unsorted[&Prefix(1)] = 'z';
+    assert_eq!(unsorted.remove(&(1, 0)), Some('z'));
+}
+
+#[test]
+fn test_interpolation_search() {
+    use flat_map::{BinarySearch, InterpolationSearch};
+
+    fn check<K>(keys: &[K], probes: &[K])
+        where K: Copy + Ord + std::fmt::Debug + flat_map::InterpolationKey
+    {
+        let by_interpolation: FlatMap<K, usize, InterpolationSearch> =
+            keys.iter().enumerate().map(|(i, &k)| (k, i)).collect();
+        let by_binary: FlatMap<K, usize, BinarySearch> =
+            keys.iter().enumerate().map(|(i, &k)| (k, i)).collect();
+        assert_eq!(by_interpolation.as_slice(), by_binary.as_slice());
+        for q in keys.iter().chain(probes) {
+            assert_eq!(by_interpolation.get(q), by_binary.get(q), "{:?}", q);
+            assert_eq!(by_interpolation.lower_bound(q), by_binary.lower_bound(q), "{:?}", q);
+        }
+    }
+
+    let random: Vec<u32> = rand_data(1000).into_iter().map(|(k, _)| k).collect();
+    let missing: Vec<u32> = random.iter().map(|k| k.wrapping_add(1)).collect();
+    check(&random, &missing);
+
+    // exponentially spread keys make the guesses poor
+    let skewed: Vec<u64> = (0..64).map(|i| 1 << i).chain(0..100).collect();
+    check(&skewed, &[3, 1000, 1 << 40, u64::MAX]);
+
+    let signed: Vec<i64> = (-50..50).map(|i| i * i * i).collect();
+    check(&signed, &[i64::MIN, -1, 0, 7, i64::MAX]);
+
+    check::<u8>(&[], &[0, 255]);
+    check(&[5u8, 5, 5], &[4, 5, 6]);
+}
+
+#[test]
+fn test_exponential_search() {
+    use flat_map::{BinarySearch, ExponentialSearch, Lookup};
+
+    let keys: Vec<u32> = rand_data(500).into_iter().map(|(k, _)| k).collect();
+    let by_gallop: FlatMap<u32, (), ExponentialSearch> = keys.iter().map(|&k| (k, ())).collect();
+    let entries = by_gallop.as_slice();
+    let mut queries = keys.clone();
+    queries.extend(keys.iter().map(|k| k.wrapping_add(1)));
+    queries.extend(&[0, u32::MAX]);
+    for q in &queries {
+        let expected = BinarySearch.lookup(entries, q);
+        assert_eq!(by_gallop.binary_search_key(q), expected, "{}", q);
+        for &hint in &[0, 1, 7, 250, 498, 499, 500, 10_000] {
+            assert_eq!(ExponentialSearch.lookup_from(entries, q, hint), expected, "{} {}", q, hint);
+        }
+    }
+
+    let empty: &[(u32, ())] = &[];
+    assert_eq!(ExponentialSearch.lookup_from(empty, &5, 3), Err(0));
+}
+
+#[test]
+fn test_branchless_search() {
+    use flat_map::{BinarySearch, BranchlessSearch, Lookup};
+
+    for &len in &[0, 1, 2, 3, 8, 100, 1000] {
+        let keys: Vec<u32> = rand_data(len).into_iter().map(|(k, _)| k).collect();
+        let map: FlatMap<u32, (), BranchlessSearch> = keys.iter().map(|&k| (k, ())).collect();
+        let mut queries = keys.clone();
+        queries.extend(keys.iter().map(|k| k.wrapping_add(1)));
+        queries.extend(&[0, u32::MAX]);
+        for q in &queries {
+            let expected = BinarySearch.lookup(map.as_slice(), q);
+            assert_eq!(map.binary_search_key(q), expected, "{}", q);
+        }
+    }
+}
+
+#[test]
+fn test_eytzinger_flat_map() {
+    for &len in &[0, 1, 2, 3, 7, 8, 100, 1000] {
+        let mut flat: FlatMap<u32, u32> = rand_data(len).into_iter().collect();
+        let mut frozen = EytzingerFlatMap::from(flat.clone());
+        assert_eq!(frozen.len(), flat.len());
+        assert!(frozen.iter().eq(flat.iter()));
+        assert_eq!(frozen.iter().len(), flat.len());
+        for (k, v) in &flat {
+            assert_eq!(frozen.get_key_value(k), Some((k, v)));
+            let missing = k.wrapping_add(1);
+            assert_eq!(frozen.get(&missing), flat.get(&missing));
+        }
+        assert!(!frozen.contains_key(&u32::MAX) || flat.contains_key(&u32::MAX));
+        for (k, v) in flat.iter_mut().step_by(3) {
+            *v = 0;
+            *frozen.get_mut(k).unwrap() = 0;
+        }
+        assert_eq!(FlatMap::from(frozen.clone()), flat);
+    }
+
+    let mut frozen: EytzingerFlatMap<&str, i32> =
+        vec![("b", 2), ("a", 1), ("b", 3)].into_iter().collect();
+    assert_eq!(format!("{:?}", frozen), r#"{"a": 1, "b": 2}"#);
+    *frozen.get_mut("a").unwrap() += 10;
+    assert_eq!(frozen.get("a"), Some(&11));
+    assert_eq!(frozen.get("c"), None);
+}
+
+#[test]
+fn test_adaptive_lookup() {
+    use flat_map::Adaptive;
+
+    assert_eq!(Adaptive::default().threshold, Adaptive::DEFAULT_THRESHOLD);
+    let mut map = FlatMap::with_lookup(Adaptive::new(8));
+    // crosses the threshold from a scan to binary search
+    for k in (0..40).rev() {
+        map.insert(k * 2, k);
+        for j in k..40 {
+            assert_eq!(map.get(&(j * 2)), Some(&j));
+            assert_eq!(map.get(&(j * 2 + 1)), None);
+        }
+    }
+    assert_eq!(map.lower_bound(&7), 4);
+}
+
+#[test]
+fn test_cached_lookup() {
+    use flat_map::{CachedLookup, LinearBack};
+
+    let mut map = FlatMap::with_lookup(CachedLookup::new(LinearBack));
+    map.extend((0..50).map(|k| (k * 2, k)));
+    for &k in &[10u32, 12, 12, 10, 98, 0, 0, 1, 3, 99, 100, 2] {
+        let expected = (0..50).map(|j| j * 2).position(|j| j == k).map(|j| j as u32);
+        assert_eq!(map.get(&k).cloned(), expected, "{}", k);
+    }
+    // the remembered position may be past the end after a removal
+    map.get(&98);
+    map.truncate_after(&10);
+    assert_eq!(map.get(&10), Some(&5));
+    assert_eq!(map.get(&98), None);
+
+    let copy = map.clone();
+    assert_eq!(copy.get(&4), Some(&2));
+    assert_eq!(copy.len(), 6);
+}
+
+#[test]
+fn test_lookup_hint() {
+    use flat_map::{Adaptive, CachedLookup, InterpolationSearch, LinearBack, Lookup, UnsortedLinear};
+
+    fn check<L: Lookup<u32, u32, u32> + Default>() {
+        let map: FlatMap<u32, u32, L> = (0..200).map(|k| (k * 3, k)).collect();
+        for q in 0..610 {
+            for &hint in &[0, 1, 66, 67, 199, 200, 1000] {
+                let expected = if q % 3 == 0 && q < 600 { Some(&(q / 3)) } else { None };
+                assert_eq!(map.get_hint(hint, &q), expected, "{} {}", q, hint);
+            }
+        }
+    }
+    check::<flat_map::BinarySearch>();
+    check::<LinearBack>();
+    check::<Adaptive>();
+    check::<CachedLookup>();
+    check::<InterpolationSearch>();
+    check::<UnsortedLinear>();
+
+    // a merge-style workload: each key lands just past the last one
+    let mut map: FlatMap<u32, u32> = (0..100).map(|k| (k * 10, 0)).collect();
+    let mut hint = 0;
+    for k in 0..1000 {
+        map.insert_hint(hint, k, 1);
+        hint = map.last_insert_index().unwrap() + 1;
+    }
+    assert_eq!(map.len(), 1000);
+    assert!(map.values().all(|&v| v == 1));
+}
+
+#[test]
+fn test_boxed_lookup() {
+    use flat_map::{BoxedLookup, CachedLookup, ExponentialSearch, LinearBack};
+
+    let strategies: Vec<BoxedLookup<u32, u32>> = vec![
+        Box::new(flat_map::BinarySearch),
+        Box::new(LinearBack),
+        Box::new(ExponentialSearch),
+        Box::new(CachedLookup::new(LinearBack)),
+    ];
+    for l in strategies {
+        let mut map = FlatMap::with_lookup(l);
+        map.extend(vec![(5, 50), (1, 10), (3, 30), (1, 11)]);
+        assert_eq!(map.as_slice(), [(1, 11), (3, 30), (5, 50)]);
+        assert_eq!(map.get(&3), Some(&30));
+        assert_eq!(map.get_hint(2, &1), Some(&11));
+        assert_eq!(map.remove(&5), Some(50));
+
+        let copy = map.clone();
+        let tail = map.split_off(&2);
+        assert_eq!(tail.into_inner(), [(3, 30)]);
+        assert_eq!(copy.len(), 2);
+    }
+}
+
+#[test]
+fn test_set_lookup() {
+    use flat_map::{Adaptive, Descending, LinearBack, OrderBy, UnsortedLinear};
+
+    let map: FlatMap<u32, char, Adaptive> = FlatMap::with_lookup(Adaptive::new(3));
+    assert_eq!(map.lookup().threshold, 3);
+    let mut map = map.set_lookup(LinearBack);
+    map.extend(vec![(3, 'c'), (1, 'a'), (2, 'b')]);
+    map.set_growth_policy(flat_map::GrowthPolicy::Exact);
+
+    let mut unsorted = map.set_lookup(UnsortedLinear);
+    assert_eq!(unsorted.growth_policy(), flat_map::GrowthPolicy::Exact);
+    unsorted.insert(0, 'z');
+    unsorted.insert(4, 'd');
+    assert_eq!(unsorted.as_slice(), [(1, 'a'), (2, 'b'), (3, 'c'), (0, 'z'), (4, 'd')]);
+
+    let desc = unsorted.set_lookup(OrderBy(Descending));
+    assert_eq!(desc.keys().cloned().collect::<Vec<_>>(), [4, 3, 2, 1, 0]);
+    assert_eq!(desc.get(&2), Some(&'b'));
+
+    let (v, l) = desc.set_lookup(flat_map::BinarySearch).into_parts();
+    assert_eq!(v, [(0, 'z'), (1, 'a'), (2, 'b'), (3, 'c'), (4, 'd')]);
+    assert_eq!(l, flat_map::BinarySearch);
+}
+
+#[test]
+fn test_instrumented_lookup() {
+    use flat_map::{CachedLookup, Instrumented, LinearBack, LookupStats};
+
+    let mut map = FlatMap::with_lookup(Instrumented::new(LinearBack));
+    map.extend((0..10).map(|k| (k, k)));
+    assert_eq!(map.lookup().stats(), LookupStats::default());
+    assert_eq!(map.get(&9), Some(&9));
+    assert_eq!(map.get(&20), None);
+    assert!(map.contains_key(&0));
+    let stats = map.lookup().stats();
+    assert_eq!((stats.lookups, stats.hits, stats.misses), (3, 2, 1));
+    // 1 from the back for 9, 1 for 20, all 10 for 0
+    assert_eq!(stats.comparisons, 12);
+
+    // inserts and removals look keys up too
+    map.insert(10, 10);
+    map.remove(&3);
+    assert_eq!(map.lookup().stats().lookups, 5);
+    let copy = map.clone();
+    map.lookup().reset_stats();
+    assert_eq!(map.lookup().stats(), LookupStats::default());
+    assert_eq!(copy.lookup().stats().lookups, 5);
+
+    // wraps other wrappers
+    let mut cached = FlatMap::with_lookup(Instrumented::new(CachedLookup::new(LinearBack)));
+    cached.extend((0..100).map(|k| (k, k)));
+    cached.get(&20);
+    let before = cached.lookup().stats().comparisons;
+    cached.get(&21);
+    assert_eq!(cached.lookup().stats().comparisons - before, 2);
+}
+
+#[test]
+fn test_memory_usage() {
+    let mut map: FlatMap<u32, Vec<u8>> = FlatMap::with_capacity(8);
+    let shallow = std::mem::size_of::<FlatMap<u32, Vec<u8>>>()
+        + map.capacity() * std::mem::size_of::<(u32, Vec<u8>)>();
+    assert_eq!(map.memory_usage(), shallow);
+    assert_eq!(map.memory_usage_with(|_, v| v.capacity()), shallow);
+
+    map.insert(1, Vec::with_capacity(100));
+    map.insert(2, Vec::with_capacity(28));
+    assert_eq!(map.memory_usage(), shallow);
+    let deep = map.memory_usage_with(|_, v| v.capacity());
+    assert_eq!(deep - shallow, map.values().map(|v| v.capacity()).sum::<usize>());
+    assert!(deep >= shallow + 128);
+}
+
+#[test]
+fn test_try_reserve() {
+    let mut map: FlatMap<u32, u32> = vec![(1, 1), (2, 2)].into_iter().collect();
+    map.try_reserve_exact(10).unwrap();
+    assert_eq!(map.capacity(), 12);
+    map.try_reserve(20).unwrap();
+    assert!(map.capacity() >= 22);
+
+    let capacity = map.capacity();
+    assert!(map.try_reserve(usize::MAX).is_err());
+    assert!(map.try_reserve_exact(usize::MAX / 4).is_err());
+    assert_eq!(map.capacity(), capacity);
+    assert_eq!(map.into_inner(), [(1, 1), (2, 2)]);
+}
+
+#[test]
+fn test_write_to_read_from() {
+    use std::io::{self, Read, Write};
+
+    fn write_entry<W: Write>(w: &mut W, k: &u16, v: &String) -> io::Result<()> {
+        w.write_all(&k.to_le_bytes())?;
+        w.write_all(&[v.len() as u8])?;
+        w.write_all(v.as_bytes())
+    }
+    fn read_entry<R: Read>(r: &mut R) -> io::Result<(u16, String)> {
+        let (mut k, mut len) = ([0; 2], [0; 1]);
+        r.read_exact(&mut k)?;
+        r.read_exact(&mut len)?;
+        let mut v = vec![0; len[0] as usize];
+        r.read_exact(&mut v)?;
+        Ok((u16::from_le_bytes(k), String::from_utf8(v).unwrap()))
+    }
+
+    let map: FlatMap<u16, String> = (0..100).map(|i| (i * 7, i.to_string())).collect();
+    let mut bytes = Vec::new();
+    map.write_to(&mut bytes, write_entry).unwrap();
+    assert_eq!(FlatMap::<_, _>::read_from(&bytes[..], read_entry).unwrap(), map);
+
+    let empty: FlatMap<u16, String> = FlatMap::new();
+    let mut bytes = Vec::new();
+    empty.write_to(&mut bytes, write_entry).unwrap();
+    assert_eq!(bytes, [0; 8]);
+    assert!(FlatMap::<_, _>::read_from(&bytes[..], read_entry).unwrap().is_empty());
+
+    let err = FlatMap::<_, _>::read_from(&bytes[..4], read_entry).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    // a huge length fails on the missing entries rather than allocating
+    let err = FlatMap::<_, _>::read_from(&[0xff; 8][..], read_entry).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+    let mut unsorted = vec![2, 0, 0, 0, 0, 0, 0, 0];
+    unsorted.extend_from_slice(&[5, 0, 0, 3, 0, 0]);
+    let err = FlatMap::<_, _>::read_from(&unsorted[..], read_entry).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("index 1"), "{}", err);
+}
+
+#[test]
+fn test_append_sorted() {
+    use std::collections::BTreeMap;
+
+    // timestamps in order, with every 97th record late by up to 50 and
+    // every 1000th repeating the previous timestamp
+    let stream: Vec<(u64, u64)> = (0..100_000u64)
+        .map(|i| {
+            let ts = if i % 97 == 0 && i >= 50 {
+                i * 10 - (i % 50) * 10 - 5
+            } else if i % 1000 == 1 {
+                (i - 1) * 10
+            } else {
+                i * 10
+            };
+            (ts, i)
+        })
+        .collect();
+    let mut baseline = BTreeMap::new();
+    for &(k, v) in &stream {
+        baseline.insert(k, v);
+    }
+
+    let mut m = FlatMap::new();
+    let stragglers = m.append_sorted(stream.iter().cloned());
+    assert!(stragglers > 0 && stragglers < 1100);
+    assert!(m.iter().eq(baseline.iter()));
+
+    let mut sorted = FlatMap::new();
+    assert_eq!(sorted.append_sorted(baseline.iter().map(|(&k, &v)| (k, v))), 0);
+    assert!(sorted.iter().eq(baseline.iter()));
+    assert_eq!(sorted.append_sorted(vec![(0, 1), (5, 2)]), 2);
+    assert_eq!(sorted[&0], 1);
+    assert_eq!(sorted.len(), baseline.len() + 1);
+}
+
+#[test]
+fn test_remove_unordered() {
+    let mut m: FlatMap<u32, u32, UnsortedLinear> = (0..6).map(|k| (k, k * 10)).collect();
+    assert_eq!(m.remove_unordered(&1), Some(10));
+    assert_eq!(m.remove_unordered(&1), None);
+    assert_eq!(m.keys().cloned().collect::<Vec<_>>(), [0, 5, 2, 3, 4]);
+    match m.entry(0) {
+        Occupied(e) => assert_eq!(e.remove_unordered(), 0),
+        Vacant(_) => panic!(),
+    }
+    assert_eq!(m.keys().cloned().collect::<Vec<_>>(), [4, 5, 2, 3]);
+    for k in 2..6 {
+        assert_eq!(m.get(&k), Some(&(k * 10)));
+    }
+
+    let mut sorted: FlatMap<u32, u32> = (0..6).map(|k| (k, k * 10)).collect();
+    assert_eq!(sorted.remove_unordered(&1), Some(10));
+    match sorted.entry(0) {
+        Occupied(e) => assert_eq!(e.remove_unordered(), 0),
+        Vacant(_) => panic!(),
+    }
+    assert_eq!(sorted.keys().cloned().collect::<Vec<_>>(), [2, 3, 4, 5]);
+}
+
+#[test]
+fn test_index_mut() {
+    let mut m: FlatMap<&str, u32> = FlatMap::from([("a", 1), ("b", 2)]);
+    m[&"a"] = 10;
+    m[&"b"] += 5;
+    assert_eq!(m.into_inner(), [("a", 10), ("b", 7)]);
+
+    let mut names: FlatMap<String, Vec<u32>> = FlatMap::new();
+    names.insert("x".to_string(), vec![]);
+    names["x"].push(1);
+    *names.must_get_mut("x") = vec![2];
+    assert_eq!(names.must_get("x"), &[2]);
+}
+
+#[test]
+#[should_panic(expected = "no entry found for key")]
+fn test_index_mut_missing() {
+    let mut m: FlatMap<u32, u32> = FlatMap::new();
+    m[&1] = 1;
+}
+
+#[test]
+#[should_panic(expected = "no entry found for key \"missing-key\"")]
+fn test_must_get_message() {
+    let m: FlatMap<String, u32> = FlatMap::new();
+    m.must_get("missing-key");
+}
+
+#[test]
+#[should_panic(expected = "no entry found for key 42")]
+fn test_must_get_mut_message() {
+    let mut m: FlatMap<u32, u32, UnsortedLinear> = FlatMap::with_lookup(UnsortedLinear);
+    m.insert(7, 0);
+    *m.must_get_mut(&42) = 1;
+}
+
+// Checks that the sorted strategies agree with `BinarySearch` on `slice`:
+// `Err` results must be equal, and `Ok` results must point at a key equal to
+// the probe, possibly a different one of a run of equal keys.
+fn check_strategies_agree(slice: &[(u8, ())], probe: u8) {
+    let expected = BinarySearch.lookup(slice, &probe);
+    let strict = slice.windows(2).all(|w| w[0].0 < w[1].0);
+    for found in [LinearFront.lookup(slice, &probe), LinearBack.lookup(slice, &probe)] {
+        match (expected, found) {
+            (Err(_), _) | (_, Err(_)) => assert_eq!(found, expected, "{:?} {}", slice, probe),
+            (Ok(i), Ok(j)) => {
+                assert_eq!(slice[j].0, probe, "{:?} {}", slice, probe);
+                if strict {
+                    assert_eq!(i, j, "{:?} {}", slice, probe);
+                }
+            }
+        }
+    }
+    let first = slice.iter().position(|e| e.0 == probe);
+    let last = slice.iter().rposition(|e| e.0 == probe);
+    assert_eq!(LinearFront.lookup(slice, &probe).ok(), first);
+    assert_eq!(LinearBack.lookup(slice, &probe).ok(), last);
+}
+
+#[test]
+fn test_lookup_strategies_agree_exhaustive() {
+    // every sorted slice of up to 6 keys from 1..=4, duplicates included,
+    // probed with every key from 0 to 5
+    fn sorted_slices(len: usize, min: u8, prefix: &mut Vec<(u8, ())>, out: &mut Vec<Vec<(u8, ())>>) {
+        out.push(prefix.clone());
+        if len == 0 {
+            return;
+        }
+        for k in min..=4 {
+            prefix.push((k, ()));
+            sorted_slices(len - 1, k, prefix, out);
+            prefix.pop();
+        }
+    }
+    let mut slices = Vec::new();
+    sorted_slices(6, 1, &mut Vec::new(), &mut slices);
+    assert_eq!(slices.len(), 210);
+    for slice in &slices {
+        for probe in 0..=5 {
+            check_strategies_agree(slice, probe);
+        }
+    }
+}
+
+// Orders names case-insensitively, so keys that differ only in case are the
+// same key.
+#[derive(Clone, Debug)]
+struct Caseless(&'static str);
+
+impl PartialEq for Caseless {
+    fn eq(&self, other: &Caseless) -> bool {
+        self.cmp(other) == std::cmp::Ordering::Equal
+    }
+}
+
+impl Eq for Caseless {}
+
+impl PartialOrd for Caseless {
+    fn partial_cmp(&self, other: &Caseless) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Caseless {
+    fn cmp(&self, other: &Caseless) -> std::cmp::Ordering {
+        let a = self.0.bytes().map(|b| b.to_ascii_lowercase());
+        a.cmp(other.0.bytes().map(|b| b.to_ascii_lowercase()))
+    }
+}
+
+#[test]
+fn test_lookup_strategies_caseless_keys() {
+    fn run<L: Lookup<Caseless, u32, Caseless> + Sorted + Default>() -> Vec<(&'static str, u32)> {
+        let mut m: FlatMap<Caseless, u32, L> = FlatMap::default();
+        for (i, name) in ["delta", "Bravo", "alpha", "CHARLIE", "echo"].iter().enumerate() {
+            m.insert(Caseless(name), i as u32);
+        }
+        // a differently cased key finds the stored entry, and keeps its case
+        assert_eq!(m.insert(Caseless("BRAVO"), 10), Some(1));
+        match m.entry(Caseless("Charlie")) {
+            Occupied(mut e) => {
+                assert_eq!(e.key().0, "CHARLIE");
+                *e.get_mut() += 10;
+            }
+            Vacant(_) => panic!(),
+        }
+        match m.entry(Caseless("Cobra")) {
+            Vacant(e) => {
+                assert_eq!(e.index(), 3);
+                e.insert(20);
+            }
+            Occupied(_) => panic!(),
+        }
+        assert_eq!(m.remove(&Caseless("ECHO")), Some(4));
+        assert_eq!(m.get(&Caseless("Delta")), Some(&0));
+        let keys: Vec<_> = m.keys().cloned().collect();
+        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+        m.into_iter().map(|(k, v)| (k.0, v)).collect()
+    }
+    let expected = vec![("alpha", 2), ("Bravo", 10), ("CHARLIE", 13), ("Cobra", 20), ("delta", 0)];
+    assert_eq!(run::<BinarySearch>(), expected);
+    assert_eq!(run::<LinearFront>(), expected);
+    assert_eq!(run::<LinearBack>(), expected);
+
+    // a run of keys equal to the probe, as a slice that is not a valid map
+    let slice = [(Caseless("a"), 0), (Caseless("b"), 1), (Caseless("B"), 2), (Caseless("c"), 3)];
+    assert_eq!(LinearFront.lookup(&slice[..], &Caseless("b")), Ok(1));
+    assert_eq!(LinearBack.lookup(&slice[..], &Caseless("b")), Ok(2));
+    assert!(BinarySearch.lookup(&slice[..], &Caseless("b")).is_ok());
+    for strategy_result in [BinarySearch.lookup(&slice[..], &Caseless("bb")),
+                            LinearFront.lookup(&slice[..], &Caseless("bb")),
+                            LinearBack.lookup(&slice[..], &Caseless("bb"))] {
+        assert_eq!(strategy_result, Err(3));
+    }
+}
+
+#[test]
+fn test_entry_at_index() {
+    let mut m: FlatMap<u32, char> = FlatMap::from([(10, 'a'), (20, 'b'), (30, 'c')]);
+    let index = match m.entry(20) {
+        Occupied(e) => e.index(),
+        Vacant(_) => panic!(),
+    };
+    {
+        let mut e = m.entry_at_index(index).unwrap();
+        assert_eq!((e.index(), *e.key()), (1, 20));
+        *e.get_mut() = 'B';
+    }
+    assert_eq!(m[&20], 'B');
+    assert!(m.entry_at_index(3).is_none());
+    assert_eq!(m.entry_at_index(0).unwrap().remove_entry(), (10, 'a'));
+    assert_eq!(m.into_inner(), [(20, 'B'), (30, 'c')]);
+
+    let mut empty: FlatMap<u32, char> = FlatMap::new();
+    assert!(empty.entry_at_index(0).is_none());
+}
+
+#[test]
+fn test_insert_full() {
+    let mut m: FlatMap<u32, char> = FlatMap::new();
+    assert_eq!(m.insert_full(20, 'b'), (0, None));
+    assert_eq!(m.insert_full(10, 'a'), (0, None));
+    assert_eq!(m.insert_full(30, 'c'), (2, None));
+    assert_eq!(m.insert_full(20, 'B'), (1, Some('b')));
+    assert_eq!(m.last_insert_index(), Some(1));
+    assert_eq!(m.get_index(1), Some((&20, &'B')));
+
+    let mut unsorted: FlatMap<u32, char, UnsortedLinear> = FlatMap::with_lookup(UnsortedLinear);
+    assert_eq!(unsorted.insert_full(20, 'b'), (0, None));
+    assert_eq!(unsorted.insert_full(10, 'a'), (1, None));
+    assert_eq!(unsorted.insert_full(20, 'B'), (0, Some('b')));
+}
+
+#[test]
+fn test_flat_multiset() {
+    let mut rolls: FlatMultiSet<u32> = vec![3, 1, 3, 6, 3, 1].into_iter().collect();
+    assert_eq!(rolls.len(), 6);
+    assert_eq!(rolls.distinct_len(), 3);
+    assert_eq!(rolls.count(&3), 3);
+    assert_eq!(rolls.count(&2), 0);
+    assert_eq!(rolls.insert(2), 1);
+    assert_eq!(rolls.insert_many(6, 2), 3);
+    assert_eq!(rolls.insert_many(5, 0), 0);
+    assert!(!rolls.contains(&5));
+
+    assert!(rolls.remove_one(&2));
+    assert!(!rolls.remove_one(&2));
+    assert!(rolls.remove_one(&1));
+    assert_eq!(rolls.count(&1), 1);
+    assert_eq!(rolls.remove_all(&6), 3);
+    assert_eq!(rolls.remove_all(&6), 0);
+
+    let expanded = rolls.iter();
+    assert_eq!(expanded.len(), 4);
+    assert_eq!(expanded.collect::<Vec<_>>(), [&1, &3, &3, &3]);
+    assert_eq!(rolls.counts().rev().collect::<Vec<_>>(), [(&3, 3), (&1, 1)]);
+    assert_eq!(format!("{:?}", rolls), "{1: 1, 3: 3}");
+
+    let unsorted: FlatMultiSet<u32, UnsortedLinear> = vec![3, 3, 1, 3].into_iter().collect();
+    assert_eq!(unsorted.counts().collect::<Vec<_>>(), [(&3, 3), (&1, 1)]);
+    assert!(unsorted == rolls);
+    rolls.clear();
+    assert!(rolls.is_empty());
+    assert_eq!(rolls.iter().next(), None);
+}
+
+#[test]
+fn test_range_map() {
+    fn ranges(m: &RangeMap<u32, char>) -> Vec<(u32, u32, char)> {
+        m.iter().map(|(r, &v)| (*r.start, *r.end, v)).collect()
+    }
+
+    let mut m = RangeMap::new();
+    m.insert(10..20, 'a');
+    m.insert(30..40, 'b');
+    assert_eq!(m.get(&9), None);
+    assert_eq!(m.get(&10), Some(&'a'));
+    assert_eq!(m.get(&19), Some(&'a'));
+    assert_eq!(m.get(&20), None);
+    assert_eq!(m.get_key_value(&35), Some((&30..&40, &'b')));
+
+    // touching ranges with equal values merge, others stay apart
+    m.insert(20..25, 'a');
+    m.insert(25..30, 'c');
+    assert_eq!(ranges(&m), [(10, 25, 'a'), (25, 30, 'c'), (30, 40, 'b')]);
+
+    // overwriting the middle of one range splits it
+    m.insert(32..35, 'x');
+    assert_eq!(ranges(&m), [(10, 25, 'a'), (25, 30, 'c'), (30, 32, 'b'), (32, 35, 'x'), (35, 40, 'b')]);
+
+    // a range across several others replaces them and trims the ends
+    m.insert(15..38, 'y');
+    assert_eq!(ranges(&m), [(10, 15, 'a'), (15, 38, 'y'), (38, 40, 'b')]);
+
+    // filling the gaps with an equal value merges everything
+    m.insert(0..10, 'a');
+    m.insert(15..38, 'a');
+    m.insert(38..50, 'a');
+    assert_eq!(ranges(&m), [(0, 50, 'a')]);
+    assert_eq!(m.len(), 1);
+
+    m.remove(20..30);
+    assert_eq!(ranges(&m), [(0, 20, 'a'), (30, 50, 'a')]);
+    m.remove(10..40);
+    assert_eq!(ranges(&m), [(0, 10, 'a'), (40, 50, 'a')]);
+    m.remove(5..5);
+    m.remove(50..60);
+    m.remove(0..10);
+    assert_eq!(ranges(&m), [(40, 50, 'a')]);
+    assert!(!m.contains_key(&5));
+
+    let collected: RangeMap<u32, char> = vec![(0..10, 'a'), (5..15, 'b'), (15..20, 'b')].into_iter().collect();
+    assert_eq!(ranges(&collected), [(0, 5, 'a'), (5, 20, 'b')]);
+    assert_eq!(format!("{:?}", collected), "{0..5: 'a', 5..20: 'b'}");
+}
+
+#[test]
+fn test_range_map_against_model() {
+    // random inserts and removes over a small domain, checked point by point
+    let mut seed = 12345u32;
+    let mut next = |n: u32| {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        (seed >> 16) % n
+    };
+    let mut m = RangeMap::new();
+    let mut model = [None; 40];
+    for _ in 0..2000 {
+        let (a, b) = (next(40), next(40));
+        let (start, end) = (a.min(b), a.max(b) + 1);
+        if next(3) == 0 {
+            m.remove(start..end);
+            for p in start..end {
+                model[p as usize] = None;
+            }
+        } else {
+            let value = next(3);
+            m.insert(start..end, value);
+            for p in start..end {
+                model[p as usize] = Some(value);
+            }
+        }
+        for p in 0..40 {
+            assert_eq!(m.get(&p), model[p as usize].as_ref());
+        }
+        let spans: Vec<_> = m.iter().collect();
+        for w in spans.windows(2) {
+            assert!(w[0].0.end <= w[1].0.start);
+            assert!(w[0].0.end < w[1].0.start || w[0].1 != w[1].1);
+        }
+    }
+}
+
+#[test]
+#[should_panic(expected = "range is empty")]
+fn test_range_map_empty_insert() {
+    let mut m = RangeMap::new();
+    m.insert(3..3, ());
+}
+
+#[test]
+fn test_soa_flat_map() {
+    let mut m: SoaFlatMap<u32, &str> = vec![(3, "c"), (1, "a"), (3, "x"), (2, "b")].into_iter().collect();
+    assert_eq!(m.keys(), [1, 2, 3]);
+    assert_eq!(m.values(), ["a", "b", "c"]);
+    assert_eq!(m.insert(2, "B"), Some("b"));
+    assert_eq!(m.insert(0, "z"), None);
+    assert_eq!(m.keys(), [0, 1, 2, 3]);
+    assert_eq!(m.get(&2), Some(&"B"));
+    assert_eq!(m.binary_search_key(&5), Err(4));
+    assert_eq!(m.remove_entry(&1), Some((1, "a")));
+    assert_eq!(m.remove(&1), None);
+    for (_, v) in &mut m {
+        *v = "v";
+    }
+    assert_eq!(m.iter().next_back(), Some((&3, &"v")));
+
+    let flat: FlatMap<u32, &str> = m.clone().into();
+    assert_eq!(flat.as_slice(), [(0, "v"), (2, "v"), (3, "v")]);
+    assert_eq!(SoaFlatMap::from(flat), m);
+    assert_eq!(format!("{:?}", m), "{0: \"v\", 2: \"v\", 3: \"v\"}");
+}
+
+#[test]
+fn test_spill_flat_map() {
+    let mut m: SpillFlatMap<u32, Rc<u32>, 3> = SpillFlatMap::new();
+    let shared = Rc::new(0);
+    for k in [5, 1, 3] {
+        assert_eq!(m.insert(k, shared.clone()), None);
+    }
+    assert!(!m.spilled());
+    assert_eq!(m.capacity(), 3);
+    assert!(m.insert(3, shared.clone()).is_some());
+    assert!(!m.spilled());
+
+    assert_eq!(m.insert(2, shared.clone()), None);
+    assert!(m.spilled());
+    assert_eq!(m.iter().map(|(k, _)| *k).collect::<Vec<_>>(), [1, 2, 3, 5]);
+    assert_eq!(Rc::strong_count(&shared), 5);
+    assert!(m.remove(&5).is_some());
+    assert_eq!(m.remove_entry(&5), None);
+    assert!(m.contains_key(&2));
+    m.clear();
+    assert!(m.spilled() && m.is_empty());
+    assert_eq!(Rc::strong_count(&shared), 1);
+
+    // the strategy carries over, so an unsorted map keeps insertion order
+    let mut log: SpillFlatMap<u32, char, 2, UnsortedLinear> =
+        SpillFlatMap::with_lookup(UnsortedLinear);
+    log.extend(vec![(9, 'a'), (4, 'b'), (7, 'c'), (4, 'd')]);
+    assert!(log.spilled());
+    assert_eq!(log.as_slice(), [(9, 'a'), (4, 'd'), (7, 'c')]);
+    *log.get_mut(&7).unwrap() = 'e';
+    for (_, v) in &mut log {
+        *v = v.to_ascii_uppercase();
+    }
+    assert_eq!(format!("{:?}", log), "{9: 'A', 4: 'D', 7: 'E'}");
+
+    let small: SpillFlatMap<u32, char, 3> =
+        vec![(2, 'x'), (1, 'y'), (2, 'z')].into_iter().collect();
+    assert!(!small.spilled());
+    assert_eq!(small.as_slice(), [(1, 'y'), (2, 'x')]);
+    let large: SpillFlatMap<u32, char, 1> = small.iter().map(|(&k, &v)| (k, v)).collect();
+    assert!(large.spilled());
+    assert_eq!(small, large);
+    assert_eq!(small.clone(), small);
+}
+
+#[test]
+fn test_slice_flat_map() {
+    use std::mem::MaybeUninit;
+
+    let shared = Rc::new(());
+    let mut buf: Vec<MaybeUninit<(u32, Rc<()>)>> =
+        (0..3).map(|_| MaybeUninit::uninit()).collect();
+    {
+        let mut m = SliceFlatMap::new(&mut buf);
+        assert_eq!(m.capacity(), 3);
+        for k in [30, 10, 20] {
+            assert!(m.insert(k, shared.clone()).unwrap().is_none());
+        }
+        assert!(m.is_full());
+        assert!(m.insert(10, shared.clone()).unwrap().is_some());
+        let (k, _) = m.insert(40, shared.clone()).unwrap_err().into_inner();
+        assert_eq!(k, 40);
+        assert_eq!(m.iter().map(|(k, _)| *k).collect::<Vec<_>>(), [10, 20, 30]);
+        assert_eq!(Rc::strong_count(&shared), 4);
+
+        assert!(m.remove(&20).is_some());
+        assert_eq!(m.remove_entry(&20), None);
+        assert!(m.contains_key(&30) && m.get_mut(&10).is_some());
+        assert_eq!(Rc::strong_count(&shared), 3);
+    }
+    // dropping the map drops its entries, but not the buffer
+    assert_eq!(Rc::strong_count(&shared), 1);
+
+    let mut a = [MaybeUninit::uninit(); 4];
+    let mut b = [MaybeUninit::uninit(); 2];
+    let mut x = SliceFlatMap::with_lookup(&mut a, UnsortedLinear);
+    let mut y = SliceFlatMap::new(&mut b);
+    for (k, v) in [(2, 'b'), (1, 'a')] {
+        x.insert(k, v).unwrap();
+        y.insert(k, v).unwrap();
+    }
+    assert_eq!(x.as_slice(), [(2, 'b'), (1, 'a')]);
+    assert_eq!(x, y);
+    assert_eq!(format!("{:?}", y), "{1: 'a', 2: 'b'}");
+}
+
+#[test]
+fn test_flat_slice() {
+    static TABLE: FlatSlice<u32, &str> =
+        FlatSlice::from_sorted_slice_unchecked(&[(1, "one"), (3, "three"), (5, "five")]);
+    assert_eq!(TABLE.len(), 3);
+    assert_eq!(TABLE.get(&3), Some(&"three"));
+    assert_eq!(TABLE.get_key_value(&5), Some((&5, &"five")));
+    assert!(!TABLE.contains_key(&2));
+    assert_eq!(TABLE.range(2..=5).map(|(k, _)| *k).collect::<Vec<_>>(), [3, 5]);
+    assert_eq!(TABLE.first_key_value(), Some((&1, &"one")));
+    assert_eq!(TABLE.last_key_value(), Some((&5, &"five")));
+
+    let unsorted = [(1, 'a'), (1, 'b')];
+    assert_eq!(FlatSlice::from_sorted_slice(&unsorted).unwrap_err().index(), 1);
+
+    let map: FlatMap<u32, &str, LinearFront> = TABLE.iter().map(|(&k, &v)| (k, v)).collect();
+    let view = map.as_flat_slice();
+    assert_eq!(view, TABLE);
+    assert_eq!(view.range(..3).count(), 1);
+    assert_eq!(format!("{:?}", view), "{1: \"one\", 3: \"three\", 5: \"five\"}");
+    // the view borrows the map, not itself
+    let value = map.as_flat_slice().get(&1).unwrap();
+    assert_eq!(*value, "one");
+}
+
+#[test]
+fn test_check_invariants() {
+    use std::cell::Cell;
+    use std::cmp::Ordering;
+
+    // a key whose ordering can be changed while it is in a map
+    #[derive(Debug, PartialEq, Eq)]
+    struct Shifty(Cell<u32>);
+
+    impl PartialOrd for Shifty {
+        fn partial_cmp(&self, other: &Shifty) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl Ord for Shifty {
+        fn cmp(&self, other: &Shifty) -> Ordering {
+            self.0.get().cmp(&other.0.get())
+        }
+    }
+
+    let map: FlatMap<Shifty, ()> = (0..5).map(|k| (Shifty(Cell::new(k)), ())).collect();
+    assert_eq!(map.check_invariants(), Ok(()));
+    map.as_slice()[2].0 .0.set(9);
+    assert_eq!(map.check_invariants(), Err(InvariantError::out_of_order(3)));
+    map.as_slice()[2].0 .0.set(1);
+    let err = map.check_invariants().unwrap_err();
+    assert!(err.is_duplicate());
+    assert_eq!(err.to_string(), "duplicate key at index 2");
+
+    let mut log = FlatMap::with_lookup(UnsortedLinear);
+    for k in [9, 2, 7, 4] {
+        log.insert(Shifty(Cell::new(k)), ());
+    }
+    assert_eq!(log.check_invariants(), Ok(()));
+    log.as_slice()[3].0 .0.set(9);
+    log.as_slice()[2].0 .0.set(2);
+    assert_eq!(log.check_invariants(), Err(InvariantError::duplicate(2)));
+    assert_eq!(FlatMap::<u32, ()>::new().check_invariants(), Ok(()));
+
+    let mut desc = FlatMap::new_desc();
+    for k in 0..5 {
+        desc.insert(Shifty(Cell::new(k)), ());
+    }
+    assert_eq!(desc.check_invariants(), Ok(()));
+    desc.as_slice()[1].0 .0.set(0);
+    assert_eq!(desc.check_invariants(), Err(InvariantError::out_of_order(2)));
+    desc.as_slice()[1].0 .0.set(2);
+    assert_eq!(desc.check_invariants(), Err(InvariantError::duplicate(2)));
+}
+
+#[test]
+fn test_positional_access() {
+    let mut map: FlatMap<u32, u32, LinearFront> = (1..6).map(|k| (k * 10, k)).collect();
+    assert_eq!(map.get_index(0), Some((&10, &1)));
+    assert_eq!(map.get_index(4), Some((&50, &5)));
+    assert_eq!(map.get_index(5), None);
+
+    *map.get_index_mut(1).unwrap().1 += 100;
+    assert_eq!(map[&20], 102);
+    assert!(map.get_index_mut(5).is_none());
+
+    assert_eq!(map.binary_search_key(&30), Ok(2));
+    assert_eq!(map.binary_search_key(&35), Err(3));
+    assert_eq!(map.binary_search_key(&0), Err(0));
+    assert_eq!(map.binary_search_key(&99), Err(5));
+    assert_eq!(map.get_index_of(&30), Some(2));
+    assert_eq!(map.get_index_of(&35), None);
+
+    // step from a key to its neighbours
+    let i = map.binary_search_key(&30).unwrap();
+    assert_eq!(map.get_index(i - 1), Some((&20, &102)));
+    assert_eq!(map.get_index(i + 1), Some((&40, &4)));
+
+    assert_eq!(map.remove_index(5), None);
+    assert_eq!(map.remove_index(4), Some((50, 5)));
+    assert_eq!(map.remove_index(0), Some((10, 1)));
+    assert_eq!(map.keys().cloned().collect::<Vec<_>>(), [20, 30, 40]);
+    assert_eq!(map.remove_index(usize::MAX), None);
+}
+
+#[test]
+fn test_merge_with() {
+    // disjoint
+    let mut a: FlatMap<u32, u32> = (0..10).map(|k| (k * 2, k)).collect();
+    let b: FlatMap<u32, u32, LinearBack> = (0..10).map(|k| (k * 2 + 1, k)).collect();
+    a.merge_with(b, |_, _, _| unreachable!());
+    assert_eq!(a.keys().cloned().collect::<Vec<_>>(), (0..20).collect::<Vec<_>>());
+
+    // fully overlapping, keeping the max timestamp
+    let mut seen: FlatMap<&str, u32> = vec![("x", 5), ("y", 1), ("z", 7)].into_iter().collect();
+    let update: FlatMap<&str, u32> = vec![("x", 3), ("y", 4), ("z", 9)].into_iter().collect();
+    let mut calls = Vec::new();
+    seen.merge_with(update, |&k, old, new| {
+        calls.push((k, old, new));
+        old.max(new)
+    });
+    assert_eq!(calls, [("x", 5, 3), ("y", 1, 4), ("z", 7, 9)]);
+    assert_eq!(seen.into_inner(), [("x", 5), ("y", 4), ("z", 9)]);
+
+    // random, against insert
+    let mut rng = DeterministicRng::new();
+    let a: FlatMap<u32, u32> = (0..300).map(|_| (rng.next() % 500, rng.next() % 100)).collect();
+    let b: FlatMap<u32, u32> = (0..300).map(|_| (rng.next() % 500, rng.next() % 100)).collect();
+    let mut expected = a.clone();
+    let mut duplicates = 0;
+    for (&k, &v) in &b {
+        let sum = expected.get(&k).map(|&old| old + v);
+        if sum.is_some() {
+            duplicates += 1;
+        }
+        expected.insert(k, sum.unwrap_or(v));
+    }
+    let mut merged = a.clone();
+    let mut calls = 0;
+    merged.merge_with(b.clone(), |_, old, new| {
+        calls += 1;
+        old + new
+    });
+    assert_eq!(merged, expected);
+    assert_eq!(calls, duplicates);
+
+    // append is a last-writer-wins merge
+    let mut appended = a.clone();
+    let mut other = b.clone();
+    appended.append(&mut other);
+    assert!(other.is_empty());
+    let mut expected = a;
+    expected.extend(b);
+    assert_eq!(appended, expected);
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn test_rayon() {
+    use rayon::prelude::*;
+
+    let mut m: FlatMap<u32, u64> = (0..1000).map(|k| (k, u64::from(k))).collect();
+    assert_eq!(m.par_iter().map(|(_, v)| *v).sum::<u64>(), 499500);
+    assert_eq!(m.par_keys().cloned().collect::<Vec<_>>(), m.keys().cloned().collect::<Vec<_>>());
+    assert_eq!(m.par_values().len(), 1000);
+
+    m.par_values_mut().for_each(|v| *v *= 2);
+    (&mut m).into_par_iter().for_each(|(k, v)| *v += u64::from(*k));
+    assert_eq!(m.get(&10), Some(&30));
+
+    // indexed, so zip and enumerate line up with the sequential order
+    assert!(m.par_iter().enumerate().all(|(i, (k, _))| i as u32 == *k));
+    assert!(m.par_keys().zip(m.par_values()).all(|(k, v)| *v == 3 * u64::from(*k)));
+
+    let owned: Vec<(u32, u64)> = m.clone().into_par_iter().collect();
+    assert_eq!(owned, m.into_iter().collect::<Vec<_>>());
+
+    // later duplicates win, as with extend
+    let input: Vec<(u32, usize)> = (0..10_000).map(|i| ((i % 7) as u32, i)).rev().collect();
+    let m: FlatMap<u32, usize> = input.into_par_iter().collect();
+    assert_eq!(m.len(), 7);
+    assert_eq!(m.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>(),
+               (0..7).map(|k| (k as u32, k)).collect::<Vec<_>>());
+
+    let mut m = m;
+    m.par_extend((5..20_000u32).into_par_iter().map(|k| (k % 10, k as usize)));
+    let mut expected: FlatMap<u32, usize> = (0..7).map(|k| (k as u32, k)).collect();
+    expected.extend((5..20_000u32).map(|k| (k % 10, k as usize)));
+    assert_eq!(m, expected);
+
+    // the strategy's order decides which entries have equal keys, and the
+    // last of them in input order wins
+    type Names = FlatMap<String, usize, flat_map::OrderBy<flat_map::CaseInsensitive>>;
+    let names: Vec<(String, usize)> = ["b", "A", "a", "B", "c"].iter().cycle().take(5000)
+        .enumerate().map(|(i, n)| (n.to_string(), i)).collect();
+    let mut seq = Names::default();
+    seq.extend(names.clone());
+    let par: Names = names.clone().into_par_iter().collect();
+    assert_eq!(par.as_slice(), seq.as_slice());
+    let mut par = Names::default();
+    par.par_extend(names);
+    assert_eq!(par.as_slice(), seq.as_slice());
+
+    // keys need no `Ord` impl
+    let floats: FlatMap<f64, usize, UnsortedLinear> =
+        (0..100).into_par_iter().map(|i| ((i % 3) as f64 / 2.0, i)).collect();
+    assert_eq!(floats.len(), 3);
+}
+
+#[cfg(feature = "arbitrary")]
+#[test]
+fn test_arbitrary() {
+    use arbitrary::{Arbitrary, Unstructured};
+
+    let mut rng = DeterministicRng::new();
+    for len in 0..200 {
+        let bytes: Vec<u8> = (0..len).map(|_| rng.next() as u8).collect();
+        let mut u = Unstructured::new(&bytes);
+        let map = FlatMap::<u8, u16>::arbitrary(&mut u).unwrap();
+        assert!(map.keys().zip(map.keys().skip(1)).all(|(a, b)| a < b));
+        let map = FlatMap::<u8, u16>::arbitrary_take_rest(Unstructured::new(&bytes)).unwrap();
+        assert!(map.keys().zip(map.keys().skip(1)).all(|(a, b)| a < b));
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+#[test]
+fn test_arbitrary_companion_types() {
+    use arbitrary::{Arbitrary, Unstructured};
+
+    let mut rng = DeterministicRng::new();
+    for len in 0..200 {
+        let bytes: Vec<u8> = (0..len).map(|_| rng.next() as u8).collect();
+        let mut u = Unstructured::new(&bytes);
+        let set = FlatSet::<u8>::arbitrary(&mut u).unwrap();
+        assert!(set.iter().zip(set.iter().skip(1)).all(|(a, b)| a < b));
+        let words = FlatMultiSet::<u8>::arbitrary(&mut u).unwrap();
+        assert!(words.counts().all(|(_, n)| n > 0));
+        let soa = SoaFlatMap::<u8, u16>::arbitrary(&mut u).unwrap();
+        assert!(soa.keys().windows(2).all(|w| w[0] < w[1]));
+        let frozen = EytzingerFlatMap::<u8, u16>::arbitrary(&mut u).unwrap();
+        assert!(frozen.iter().zip(frozen.iter().skip(1)).all(|(a, b)| a.0 < b.0));
+        let staged = StagedFlatMap::<u8, u16>::arbitrary(&mut u).unwrap();
+        assert_eq!(staged.staged_len(), 0);
+        let small = SmallFlatMap::<u8, u16, 4>::arbitrary(&mut u).unwrap();
+        assert!(small.len() <= 4);
+        let spill = SpillFlatMap::<u8, u16, 4>::arbitrary(&mut u).unwrap();
+        assert_eq!(spill.spilled(), spill.len() > 4);
+        let bounded = BoundedFlatMap::<u8, u16, 4>::arbitrary(&mut u).unwrap();
+        assert!(bounded.len() <= 4);
+        let ranges = RangeMap::<u8, bool>::arbitrary_take_rest(u).unwrap();
+        let spans: Vec<_> = ranges.iter().map(|(r, _)| r.clone()).collect();
+        assert!(spans.iter().all(|r| r.start < r.end));
+        assert!(spans.windows(2).all(|w| w[0].end <= w[1].start));
+    }
+}
+
+#[cfg(feature = "proptest")]
+use proptest::strategy::Strategy;
+
+#[cfg(feature = "proptest")]
+#[derive(Clone, Debug)]
+enum ModelOp {
+    Insert(u8, u32),
+    Remove(u8),
+    SplitOff(u8),
+}
+
+#[cfg(feature = "proptest")]
+proptest! {
+    #[test]
+    fn test_prop_against_btree_model(
+        map in flat_map::prop::flat_map(0u8..64, proptest::num::u32::ANY, 0..32),
+        ops in proptest::collection::vec(prop_oneof![
+            (0u8..64, proptest::num::u32::ANY).prop_map(|(k, v)| ModelOp::Insert(k, v)),
+            (0u8..64).prop_map(ModelOp::Remove),
+            (0u8..64).prop_map(ModelOp::SplitOff),
+        ], 0..64)
+    ) {
+        use std::collections::BTreeMap;
+
+        let mut map: FlatMap<u8, u32> = map;
+        let mut model: BTreeMap<u8, u32> = map.iter().map(|(&k, &v)| (k, v)).collect();
+        for op in ops {
+            match op {
+                ModelOp::Insert(k, v) => prop_assert_eq!(map.insert(k, v), model.insert(k, v)),
+                ModelOp::Remove(k) => prop_assert_eq!(map.remove(&k), model.remove(&k)),
+                ModelOp::SplitOff(k) => {
+                    let tail = map.split_off(&k);
+                    let model_tail = model.split_off(&k);
+                    prop_assert!(tail.iter().eq(model_tail.iter()));
+                }
+            }
+            prop_assert!(map.iter().eq(model.iter()));
+        }
+    }
+}
+
+#[cfg(feature = "proptest")]
+proptest! {
+    #[test]
+    fn test_prop_remove_unordered(
+        keys in proptest::collection::vec(proptest::num::u16::ANY, 0..200),
+        removed in proptest::collection::vec(proptest::num::u16::ANY, 0..100)
+    ) {
+        use std::collections::BTreeSet;
+
+        let mut unsorted: FlatMap<u16, u16, UnsortedLinear> = FlatMap::with_lookup(UnsortedLinear);
+        let mut sorted: FlatMap<u16, u16> = FlatMap::new();
+        for &k in &keys {
+            unsorted.insert(k, !k);
+            sorted.insert(k, !k);
+        }
+        let mut model: BTreeSet<u16> = keys.iter().cloned().collect();
+        for k in removed.iter().chain(keys.iter().step_by(3)) {
+            let expected = if model.remove(k) { Some(!*k) } else { None };
+            prop_assert_eq!(unsorted.remove_unordered(k), expected);
+            prop_assert_eq!(sorted.remove_unordered(k), expected);
+        }
+        prop_assert_eq!(unsorted.len(), model.len());
+        prop_assert!(sorted.keys().eq(model.iter()));
+        for k in &model {
+            prop_assert_eq!(unsorted.get(k), Some(&!*k));
+        }
+    }
+}
+
+#[cfg(feature = "proptest")]
+proptest! {
+    #[test]
+    fn test_prop_lookup_strategies_agree(
+        mut keys in proptest::collection::vec(0u8..32, 0..64),
+        probe in 0u8..34
+    ) {
+        keys.sort();
+        let slice: Vec<(u8, ())> = keys.into_iter().map(|k| (k, ())).collect();
+        check_strategies_agree(&slice, probe);
+        let mut distinct = slice.clone();
+        distinct.dedup();
+        check_strategies_agree(&distinct, probe);
+    }
+}
+
+#[cfg(feature = "proptest")]
+proptest! {
+    #[test]
+    fn test_prop_arbitrary(
+        map in proptest::arbitrary::any::<FlatMap<u8, u16>>(),
+        set in flat_map::prop::flat_set(0u8..64, 0..32)
+    ) {
+        let set: FlatSet<u8> = set;
+        prop_assert!(map.keys().zip(map.keys().skip(1)).all(|(a, b)| a < b));
+        prop_assert!(set.iter().zip(set.iter().skip(1)).all(|(a, b)| a < b));
+        prop_assert!(set.len() < 32);
+    }
+}
+
+#[cfg(feature = "proptest")]
+#[test]
+fn test_prop_arbitrary_shrinks_sorted() {
+    use proptest::strategy::ValueTree;
+    use proptest::test_runner::TestRunner;
+
+    let mut runner = TestRunner::deterministic();
+    let strategy = proptest::arbitrary::any::<FlatSet<u16>>();
+    for _ in 0..20 {
+        let mut tree = strategy.new_tree(&mut runner).unwrap();
+        while tree.simplify() {
+            let set = tree.current();
+            assert!(set.iter().zip(set.iter().skip(1)).all(|(a, b)| a < b));
+        }
+    }
+}
+
+#[cfg(feature = "unstable")]
+#[test]
+fn test_trusted_len() {
+    use std::iter::TrustedLen;
+
+    fn check<I: TrustedLen>(mut iter: I, len: usize) {
+        for i in 0..len {
+            assert_eq!(iter.size_hint(), (len - i, Some(len - i)));
+            assert!(iter.next().is_some());
+        }
+        assert_eq!(iter.size_hint(), (0, Some(0)));
+        assert!(iter.next().is_none());
+    }
+
+    let size = 100;
+    let mut map: FlatMap<_, _> = (0..size).map(|i| (i, i)).collect();
+    check(map.iter(), size);
+    check(map.iter_mut(), size);
+    check(map.keys(), size);
+    check(map.values(), size);
+    check(map.values_mut(), size);
+    check(map.iter().rev(), size);
+    check(map.clone().into_iter(), size);
+
+    let values: Vec<_> = map.values().cloned().collect();
+    assert_eq!(values, (0..size).collect::<Vec<_>>());
+}
+
+#[cfg(feature = "unstable")]
+#[test]
+fn test_advance_by() {
+    let map: FlatMap<_, _> = (0..10).map(|i| (i, i)).collect();
+
+    let mut iter = map.iter();
+    assert!(iter.advance_by(3).is_ok());
+    assert!(iter.advance_back_by(3).is_ok());
+    assert_eq!(iter.len(), 4);
+    assert_eq!(iter.next(), Some((&3, &3)));
+    assert_eq!(iter.advance_by(5).unwrap_err().get(), 2);
+
+    let mut iter = map.into_iter();
+    assert!(iter.advance_by(9).is_ok());
+    assert_eq!(iter.next(), Some((9, 9)));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde() {
+    let mut map = FlatMap::new();
+    map.insert(18, 18);
+    let json = serde_json::to_string(&map).unwrap();
+    let new_map: FlatMap<u64, u64> = serde_json::from_str(&json).unwrap();
+    assert_eq!(new_map.get(&18), map.get(&18));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_in_place_growth_policy() {
+    use serde::Deserialize;
+
+    let json = r#"{"3": 3, "1": 1, "2": 2}"#;
+    let mut map: FlatMap<u64, u64> = FlatMap::new();
+    map.set_growth_policy(GrowthPolicy::Exact);
+    Deserialize::deserialize_in_place(&mut serde_json::Deserializer::from_str(json), &mut map)
+        .unwrap();
+    assert_eq!(map.growth_policy(), GrowthPolicy::Exact);
+    assert_eq!(map.capacity(), map.len());
+    assert_eq!(map.clone().into_inner(), [(1, 1), (2, 2), (3, 3)]);
+
+    // the existing buffer is reused when it has room
+    let mut map: FlatMap<u64, u64> = FlatMap::with_capacity(64);
+    map.set_growth_policy(GrowthPolicy::Exact);
+    Deserialize::deserialize_in_place(&mut serde_json::Deserializer::from_str(json), &mut map)
+        .unwrap();
+    assert_eq!(map.capacity(), 64);
+    assert_eq!(map.len(), 3);
+}
+
+#[cfg(feature = "serde")]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Payload {
+    Offsets {
+        #[serde(with = "flat_map::serde_seq")]
+        offsets: FlatMap<u64, String>,
+    },
+    Nothing(()),
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_seq() {
+    let offsets: FlatMap<u64, String> = vec![(u64::MAX, "max".to_string()), (0, "zero".to_string())]
+        .into_iter()
+        .collect();
+    let payload = Payload::Offsets { offsets };
+    let json = serde_json::to_string(&payload).unwrap();
+    assert_eq!(json, format!(r#"{{"offsets":[[0,"zero"],[{},"max"]]}}"#, u64::MAX));
+    assert_eq!(serde_json::from_str::<Payload>(&json).unwrap(), payload);
+
+    let empty = Payload::Offsets { offsets: FlatMap::new() };
+    let json = serde_json::to_string(&empty).unwrap();
+    assert_eq!(json, r#"{"offsets":[]}"#);
+    assert_eq!(serde_json::from_str::<Payload>(&json).unwrap(), empty);
+
+    // unsorted input with duplicates: the last pair wins
+    let back: Payload = serde_json::from_str(r#"{"offsets":[[5,"a"],[1,"b"],[5,"c"]]}"#).unwrap();
+    let expected: FlatMap<u64, String> = vec![(1, "b".to_string()), (5, "c".to_string())]
+        .into_iter()
+        .collect();
+    assert_eq!(back, Payload::Offsets { offsets: expected });
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_duplicates() {
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Policies {
+        #[serde(with = "flat_map::serde_dup::first")]
+        first: FlatMap<String, u32>,
+        #[serde(with = "flat_map::serde_dup::last")]
+        last: FlatMap<String, u32, LinearBack>,
+        #[serde(with = "flat_map::serde_dup::deny")]
+        deny: FlatMap<u32, u32>,
+    }
+
+    let json = r#"{"first":{"b":1,"a":2,"b":3},"last":{"b":1,"a":2,"b":3},"deny":[[2,0],[1,0]]}"#;
+    let p: Policies = serde_json::from_str(json).unwrap();
+    assert_eq!(p.first.as_slice(), [("a".to_string(), 2), ("b".to_string(), 1)]);
+    assert_eq!(p.last.as_slice(), [("a".to_string(), 2), ("b".to_string(), 3)]);
+    assert_eq!(p.deny.as_slice(), [(1, 0), (2, 0)]);
+    assert_eq!(serde_json::to_string(&p).unwrap(),
+               r#"{"first":{"a":2,"b":1},"last":{"a":2,"b":3},"deny":{"1":0,"2":0}}"#);
+
+    let json = r#"{"first":{},"last":{},"deny":[[2,0],[1,0],[2,1]]}"#;
+    let err = serde_json::from_str::<Policies>(json).unwrap_err();
+    assert!(err.to_string().contains("duplicate key"), "{}", err);
+}
+
+#[cfg(feature = "postcard")]
+#[test]
+fn test_serde_seq_postcard() {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Wrapper {
+        #[serde(with = "flat_map::serde_seq")]
+        map: FlatMap<u64, u8, LinearBack>,
+    }
+
+    let map: FlatMap<u64, u8, LinearBack> = vec![(300, 1), (2, 2)].into_iter().collect();
+    let wrapper = Wrapper { map };
+    let mut buf = [0u8; 16];
+    let bytes = postcard::to_slice(&wrapper, &mut buf).unwrap().to_vec();
+    // length, then each varint key and byte value
+    assert_eq!(bytes, [2, 2, 2, 0xac, 0x02, 1]);
+    assert_eq!(postcard::from_bytes::<Wrapper>(&bytes).unwrap(), wrapper);
+
+    let dup = [3, 7, 1, 2, 2, 7, 9];
+    let back: Wrapper = postcard::from_bytes(&dup).unwrap();
+    assert_eq!(back.map.into_inner(), vec![(2, 2), (7, 9)]);
+
+    // binary formats get the same pairs without the attribute
+    let plain = postcard::to_slice(&wrapper.map, &mut buf).unwrap();
+    assert_eq!(plain, &bytes[..]);
+    let back: FlatMap<u64, u8, LinearBack> = postcard::from_bytes(&dup).unwrap();
+    assert_eq!(back.into_inner(), vec![(2, 2), (7, 9)]);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_flat_set() {
+    let set: FlatSet<u32> = vec![3, 1, 2].into_iter().collect();
+    let json = serde_json::to_string(&set).unwrap();
+    assert_eq!(json, "[1,2,3]");
+    let back: FlatSet<u32> = serde_json::from_str("[3,1,2,1]").unwrap();
+    assert_eq!(back, set);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_companion_types() {
+    let json = r#"{"1":"a","3":"c","2":"b"}"#;
+    let sorted = r#"{"1":"a","2":"b","3":"c"}"#;
+
+    let soa: SoaFlatMap<u32, String> = serde_json::from_str(json).unwrap();
+    assert_eq!(soa.keys(), [1, 2, 3]);
+    assert_eq!(serde_json::to_string(&soa).unwrap(), sorted);
+
+    let frozen: EytzingerFlatMap<u32, String> = serde_json::from_str(json).unwrap();
+    assert_eq!(frozen.get(&2).map(|s| s.as_str()), Some("b"));
+    assert_eq!(serde_json::to_string(&frozen).unwrap(), sorted);
+
+    let small: SmallFlatMap<u32, String, 3> = serde_json::from_str(json).unwrap();
+    assert_eq!(serde_json::to_string(&small).unwrap(), sorted);
+    let err = serde_json::from_str::<SmallFlatMap<u32, String, 2>>(json).unwrap_err();
+    assert!(err.to_string().contains("more than 2 entries"), "{}", err);
+
+    let spill: SpillFlatMap<u32, String, 2> = serde_json::from_str(json).unwrap();
+    assert!(spill.spilled());
+    assert_eq!(serde_json::to_string(&spill).unwrap(), sorted);
+
+    let mut staged: StagedFlatMap<u32, String> = serde_json::from_str(json).unwrap();
+    staged.insert(0, "z".to_string());
+    assert_eq!(staged.staged_len(), 1);
+    assert_eq!(serde_json::to_string(&staged).unwrap(), r#"{"0":"z","1":"a","2":"b","3":"c"}"#);
+
+    let words: FlatMultiSet<String> = "b a b".split(' ').map(String::from).collect();
+    assert_eq!(serde_json::to_string(&words).unwrap(), r#"{"a":1,"b":2}"#);
+    let back: FlatMultiSet<String> = serde_json::from_str(r#"{"b":2,"a":1,"c":0}"#).unwrap();
+    assert_eq!(back, words);
+    assert_eq!(back.len(), 3);
+
+    let mut ranges = RangeMap::new();
+    ranges.insert(0..10, 'a');
+    ranges.insert(5..15, 'b');
+    let json = serde_json::to_string(&ranges).unwrap();
+    assert_eq!(json, r#"[[0,5,"a"],[5,15,"b"]]"#);
+    assert_eq!(serde_json::from_str::<RangeMap<u32, char>>(&json).unwrap(), ranges);
+    // later ranges overwrite earlier ones
+    let back: RangeMap<u32, char> = serde_json::from_str(r#"[[5,15,"b"],[0,10,"a"]]"#).unwrap();
+    assert_eq!(back.iter().count(), 2);
+    assert_eq!(back.get(&7), Some(&'a'));
+    assert!(serde_json::from_str::<RangeMap<u32, char>>(r#"[[3,3,"a"]]"#).is_err());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_bulk() {
+    let mut rng = DeterministicRng::new();
+    let entries: Vec<(u32, u32)> = (0..10000).map(|_| (rng.next() % 8000, rng.next())).collect();
+    let mut expected = FlatMap::new();
+    for &(k, v) in &entries {
+        expected.insert(k, v);
+    }
+
+    let json = format!("{{{}}}",
+                       entries.iter()
+                           .map(|(k, v)| format!("\"{}\":{}", k, v))
+                           .collect::<Vec<_>>()
+                           .join(","));
+    let map: FlatMap<u32, u32> = serde_json::from_str(&json).unwrap();
+    assert_eq!(map, expected);
+
+    let sorted = serde_json::to_string(&expected).unwrap();
+    assert_eq!(serde_json::from_str::<FlatMap<u32, u32>>(&sorted).unwrap(), expected);
+
+    let pairs = serde_json::to_string(&entries).unwrap();
+    let map: FlatMap<u32, u32> = serde_json::from_str(&pairs).unwrap();
+    assert_eq!(map, expected);
 }